@@ -0,0 +1,8 @@
+// The camera of the frame, a CameraBlock written once per frame.
+#pragma once
+
+layout(std140) uniform Camera {
+  mat4 view;
+  mat4 projection;
+  mat4 view_projection;
+};
@@ -0,0 +1,16 @@
+// The light and its shadow map, a LightBlock and a ShadowBlock written
+// once per frame. Both are in world space.
+#pragma once
+
+layout(std140) uniform Light {
+  // the direction towards a directional light, or where a spot light is
+  vec3 light_dir_or_loc;
+  int light_type; // 0 directional, 1 spot
+  vec3 light_color;
+  float light_cone_angle;
+};
+
+layout(std140) uniform Shadow {
+  // from world space to shadow texture space
+  mat4 shadow_transform;
+};
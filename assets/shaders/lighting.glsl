@@ -0,0 +1,14 @@
+// Blinn-Phong terms. The directions are normalized and in the same
+// space, light_dir and view_dir pointing away from the surface.
+#pragma once
+
+// the geometry term, 0 on the side facing away
+float lambert(vec3 n, vec3 light_dir) {
+  return max(dot(n, light_dir), 0.0);
+}
+
+float blinn_phong(vec3 n, vec3 light_dir, vec3 view_dir, float shininess) {
+  // half vector
+  vec3 h = normalize(light_dir + view_dir);
+  return pow(max(dot(n, h), 0.0), shininess);
+}
@@ -0,0 +1,41 @@
+// The material the meshes set from the .mtl files, a MaterialBlock
+// written once per material, and the colours of a point with the
+// textures applied.
+#pragma once
+
+layout(std140) uniform Material {
+  vec3 Kd; // diffuse
+  float Ns; // shininess
+  vec3 Ks; // specular
+  uint use_map_Kd;
+  vec3 Ka; // ambient
+  uint use_map_Ks;
+  uint use_map_Ka;
+  uint use_map_bump;
+  float d; // dissolve
+  float Ni; // optical density
+};
+
+// textures
+uniform sampler2D map_Kd, map_Ks, map_Ka, map_bump;
+
+vec3 material_Kd(vec2 uv) {
+  if (use_map_Kd == 1u) {
+    return texture(map_Kd, uv).rgb * Kd;
+  }
+  return Kd;
+}
+
+vec3 material_Ks(vec2 uv) {
+  if (use_map_Ks == 1u) {
+    return texture(map_Ks, uv).rgb * Ks;
+  }
+  return Ks;
+}
+
+vec3 material_Ka(vec2 uv) {
+  if (use_map_Ka == 1u) {
+    return texture(map_Ka, uv).rgb * Ka;
+  }
+  return Ka;
+}
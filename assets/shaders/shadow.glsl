@@ -0,0 +1,12 @@
+// Lookups in the shadow map of the light, with depth comparison on.
+#pragma once
+
+uniform sampler2DShadow shadow_map;
+
+// 1 lit, 0 in shadow. The bias moves the light space position towards
+// the light before the perspective divide.
+float shadow_lookup(vec4 shadow_pos, float bias) {
+  vec3 shadow_uv = shadow_pos.xyz;
+  shadow_uv.z -= bias;
+  return texture(shadow_map, shadow_uv / shadow_pos.w);
+}
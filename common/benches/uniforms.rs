@@ -0,0 +1,323 @@
+// Per-draw cost of putting uniforms together, as a mesh in 07 does:
+// the frame's uniforms merged with the model's, then with the material's
+// DynUniforms. The nested case merges one group at a time, five levels
+// deep, where looking names up again for every level would add up. The
+// HashSet and HashMap versions are what glium_helper had before, kept
+// here to compare against. Allocations are counted as well, the
+// current draws make none.
+//
+//   cargo bench -p common --bench uniforms
+//
+// Only plain values, so it runs without a GL context.
+
+use std::{
+  alloc::{GlobalAlloc, Layout, System},
+  borrow::Cow,
+  collections::{HashMap, HashSet},
+  hint::black_box,
+  sync::atomic::{AtomicUsize, Ordering},
+  time::{Duration, Instant},
+};
+
+use common::{DynUniforms, MergedUniform, OwnedMergedUniform};
+use glium::{
+  uniform,
+  uniforms::{AsUniformValue, UniformValue, Uniforms},
+};
+
+const DRAWS: u32 = 200_000;
+
+// the system allocator, counting
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    System.alloc(layout)
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    System.dealloc(ptr, layout)
+  }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+struct Material {
+  ns: f32,
+  ni: f32,
+  d: f32,
+  ka: [f32; 3],
+  kd: [f32; 3],
+  ks: [f32; 3],
+  use_map_kd: u32,
+}
+
+const MATERIAL: Material = Material {
+  ns: 32.0,
+  ni: 1.5,
+  d: 1.0,
+  ka: [0.1, 0.1, 0.1],
+  kd: [0.8, 0.6, 0.4],
+  ks: [1.0, 1.0, 1.0],
+  use_map_kd: 1,
+};
+
+const MAT4: [[f32; 4]; 4] = [
+  [1.0, 0.0, 0.0, 0.0],
+  [0.0, 1.0, 0.0, 0.0],
+  [0.0, 0.0, 1.0, 0.0],
+  [0.0, 0.0, 0.0, 1.0],
+];
+
+type DrawFn = fn(&Material) -> usize;
+
+fn main() {
+  let cases: [(&str, DrawFn, DrawFn, usize); 2] = [
+    ("flat", legacy_draw, draw, 16),
+    ("nested", legacy_nested_draw, nested_draw, 18),
+  ];
+  for (case, legacy_draw, draw, uniforms) in cases {
+    let (legacy, legacy_allocations) = time(legacy_draw, uniforms);
+    let (current, allocations) = time(draw, uniforms);
+    assert_eq!(allocations, 0.0, "{} allocates", case);
+    println!("{}:", case);
+    println!(
+      "  hashed:    {:>8.1} ns/draw, {:.1} allocations",
+      per_draw(legacy),
+      legacy_allocations
+    );
+    println!(
+      "  visited:   {:>8.1} ns/draw, {:.1} allocations",
+      per_draw(current),
+      allocations
+    );
+    println!(
+      "  speedup:   {:>8.1}x",
+      legacy.as_secs_f64() / current.as_secs_f64()
+    );
+  }
+}
+
+// the time and the allocations per draw
+fn time(draw: DrawFn, uniforms: usize) -> (Duration, f64) {
+  // warm up, and check both see the same uniforms
+  let mut count = 0;
+  for _ in 0..DRAWS / 10 {
+    count = draw(black_box(&MATERIAL));
+  }
+  assert_eq!(count, uniforms);
+
+  let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+  let start = Instant::now();
+  for _ in 0..DRAWS {
+    black_box(draw(black_box(&MATERIAL)));
+  }
+  let elapsed = start.elapsed();
+  let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+  (elapsed, allocations as f64 / DRAWS as f64)
+}
+
+fn per_draw(elapsed: Duration) -> f64 {
+  elapsed.as_nanos() as f64 / DRAWS as f64
+}
+
+// stands in for glium binding the uniforms
+fn bind(uniforms: &impl Uniforms) -> usize {
+  let mut count = 0;
+  uniforms.visit_values(|name, value| {
+    black_box((name, value));
+    count += 1;
+  });
+  count
+}
+
+fn draw(mtl: &Material) -> usize {
+  let camera = uniform! { view: MAT4, projection: MAT4 };
+  let light = uniform! {
+    light_position: [0.0f32, 5.0, 5.0],
+    light_color: [1.0f32, 1.0, 1.0],
+    shadow_transform: MAT4,
+  };
+  let frame = MergedUniform::new(&camera, &light);
+  let model = uniform! { m: MAT4, mvp: MAT4, mv: MAT4, mv_n: MAT4 };
+
+  let mut material = DynUniforms::new();
+  material.add("Ns", mtl.ns);
+  material.add("Ni", mtl.ni);
+  material.add("d", mtl.d);
+  material.add("Ka", mtl.ka);
+  material.add("Kd", mtl.kd);
+  material.add("Ks", mtl.ks);
+  material.add("use_map_Kd", mtl.use_map_kd);
+  // the model's, which the merge drops
+  material.add("m", MAT4);
+
+  bind(&OwnedMergedUniform::new(
+    MergedUniform::new(&frame, &model),
+    material,
+  ))
+}
+
+// the shadow's and the model's uniforms each merged in on their own
+fn nested_draw(mtl: &Material) -> usize {
+  let camera = uniform! { view: MAT4, projection: MAT4 };
+  let light = uniform! {
+    light_position: [0.0f32, 5.0, 5.0],
+    light_color: [1.0f32, 1.0, 1.0],
+  };
+  let shadow = uniform! {
+    shadow_transform: MAT4,
+    shadow_bias: 0.005f32,
+    shadow_size: [2048.0f32, 2048.0],
+  };
+  let model = uniform! { m: MAT4, mvp: MAT4, mv: MAT4, mv_n: MAT4 };
+  let frame = MergedUniform::new(&camera, &light);
+  let shadowed = MergedUniform::new(&frame, &shadow);
+  let placed = MergedUniform::new(&shadowed, &model);
+
+  let mut material = DynUniforms::new();
+  material.add("Ns", mtl.ns);
+  material.add("Ni", mtl.ni);
+  material.add("d", mtl.d);
+  material.add("Ka", mtl.ka);
+  material.add("Kd", mtl.kd);
+  material.add("Ks", mtl.ks);
+  material.add("use_map_Kd", mtl.use_map_kd);
+  // the model's and the camera's, which the merges drop
+  material.add("m", MAT4);
+  material.add("view", MAT4);
+
+  bind(&OwnedMergedUniform::new(placed, material))
+}
+
+fn legacy_draw(mtl: &Material) -> usize {
+  let camera = uniform! { view: MAT4, projection: MAT4 };
+  let light = uniform! {
+    light_position: [0.0f32, 5.0, 5.0],
+    light_color: [1.0f32, 1.0, 1.0],
+    shadow_transform: MAT4,
+  };
+  let frame = LegacyMerged::new(&camera, &light);
+  let model = uniform! { m: MAT4, mvp: MAT4, mv: MAT4, mv_n: MAT4 };
+
+  let mut material = LegacyDyn::default();
+  material.add("Ns", &mtl.ns);
+  material.add("Ni", &mtl.ni);
+  material.add("d", &mtl.d);
+  material.add("Ka", &mtl.ka);
+  material.add("Kd", &mtl.kd);
+  material.add("Ks", &mtl.ks);
+  material.add("use_map_Kd", &mtl.use_map_kd);
+  material.add("m", &MAT4);
+
+  bind(&LegacyOwnedMerged {
+    u1: LegacyMerged::new(&frame, &model),
+    u2: material,
+  })
+}
+
+fn legacy_nested_draw(mtl: &Material) -> usize {
+  let camera = uniform! { view: MAT4, projection: MAT4 };
+  let light = uniform! {
+    light_position: [0.0f32, 5.0, 5.0],
+    light_color: [1.0f32, 1.0, 1.0],
+  };
+  let shadow = uniform! {
+    shadow_transform: MAT4,
+    shadow_bias: 0.005f32,
+    shadow_size: [2048.0f32, 2048.0],
+  };
+  let model = uniform! { m: MAT4, mvp: MAT4, mv: MAT4, mv_n: MAT4 };
+  let frame = LegacyMerged::new(&camera, &light);
+  let shadowed = LegacyMerged::new(&frame, &shadow);
+  let placed = LegacyMerged::new(&shadowed, &model);
+
+  let mut material = LegacyDyn::default();
+  material.add("Ns", &mtl.ns);
+  material.add("Ni", &mtl.ni);
+  material.add("d", &mtl.d);
+  material.add("Ka", &mtl.ka);
+  material.add("Kd", &mtl.kd);
+  material.add("Ks", &mtl.ks);
+  material.add("use_map_Kd", &mtl.use_map_kd);
+  material.add("m", &MAT4);
+  material.add("view", &MAT4);
+
+  bind(&LegacyOwnedMerged {
+    u1: placed,
+    u2: material,
+  })
+}
+
+#[derive(Default)]
+struct LegacyDyn<'a> {
+  uniforms: HashMap<Cow<'static, str>, UniformValue<'a>>,
+}
+
+impl<'a> LegacyDyn<'a> {
+  fn add(&mut self, name: &'static str, value: &'a dyn AsUniformValue) {
+    if !self.uniforms.contains_key(name) {
+      self.uniforms.insert(name.into(), value.as_uniform_value());
+    }
+  }
+}
+
+impl Uniforms for LegacyDyn<'_> {
+  fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
+    for (name, value) in &self.uniforms {
+      f(name, *value);
+    }
+  }
+}
+
+struct LegacyOwnedMerged<U1, U2> {
+  u1: U1,
+  u2: U2,
+}
+
+impl<U1: Uniforms, U2: Uniforms> Uniforms for LegacyOwnedMerged<U1, U2> {
+  fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
+    let mut visited = HashSet::new();
+    self.u1.visit_values(|name, value| {
+      if visited.insert(name.to_owned()) {
+        f(name, value);
+      }
+    });
+    self.u2.visit_values(|name, value| {
+      if !visited.contains(name) && visited.insert(name.to_owned()) {
+        f(name, value);
+      }
+    });
+  }
+}
+
+struct LegacyMerged<'a, U1, U2> {
+  u1: &'a U1,
+  u2: &'a U2,
+}
+
+impl<'a, U1, U2> LegacyMerged<'a, U1, U2> {
+  fn new(u1: &'a U1, u2: &'a U2) -> Self {
+    Self { u1, u2 }
+  }
+}
+
+impl<U1: Uniforms, U2: Uniforms> Uniforms for LegacyMerged<'_, U1, U2> {
+  fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
+    let mut visited = HashSet::new();
+    self.u1.visit_values(|name, value| {
+      if visited.insert(name.to_owned()) {
+        f(name, value);
+      }
+    });
+    self.u2.visit_values(|name, value| {
+      if !visited.contains(name) && visited.insert(name.to_owned()) {
+        f(name, value);
+      }
+    });
+  }
+}
@@ -0,0 +1,71 @@
+// Repairs and subdivides an obj offline, for models that are too slow
+// to refine at load time:
+//
+//   cargo run -p common --release --example refine_obj -- \
+//     assets/teapot.obj teapot-2.obj --repair --loop=2
+//
+// --repair welds, removes degenerate faces and fixes the winding,
+// --fill-holes=N also closes boundary loops of up to N edges.
+// --loop=N and --catmull-clark=N subdivide N times, after the repair.
+
+use anyhow::{bail, Context as _};
+use common::{
+  mesh::{catmull_clark, loop_subdivide, repair},
+  Obj,
+};
+
+enum Subdivision {
+  Loop(usize),
+  CatmullClark(usize),
+}
+
+fn main() -> anyhow::Result<()> {
+  let (flags, paths): (Vec<_>, Vec<_>) = std::env::args()
+    .skip(1)
+    .partition(|arg| arg.starts_with("--"));
+  let [input, output] = paths.as_slice() else {
+    bail!("usage: refine_obj <input.obj> <output.obj> [flags]");
+  };
+
+  let mut fix = false;
+  let mut fill_holes = None;
+  let mut subdivision = None;
+  for flag in &flags {
+    let (name, value) = flag.split_once('=').unwrap_or((flag, ""));
+    let count = || -> anyhow::Result<usize> {
+      value
+        .parse()
+        .with_context(|| format!("{} needs a count", name))
+    };
+    match name {
+      "--repair" => fix = true,
+      "--fill-holes" => fill_holes = Some(count()?),
+      "--loop" => subdivision = Some(Subdivision::Loop(count()?)),
+      "--catmull-clark" => {
+        subdivision = Some(Subdivision::CatmullClark(count()?))
+      }
+      _ => bail!("unknown flag {}", flag),
+    }
+  }
+
+  let mut obj =
+    Obj::load_from(input).with_context(|| format!("loading {}", input))?;
+  let faces = |obj: &Obj| obj.groups.iter().map(|g| g.f.len()).sum::<usize>();
+  println!("{}: {} faces", input, faces(&obj));
+
+  if fix || fill_holes.is_some() {
+    let report = repair(&mut obj, fill_holes);
+    println!("Repaired: {}", report);
+  }
+  obj = match subdivision {
+    Some(Subdivision::Loop(levels)) => loop_subdivide(obj, levels),
+    Some(Subdivision::CatmullClark(levels)) => catmull_clark(obj, levels),
+    None => obj,
+  };
+
+  obj
+    .save_to(output)
+    .with_context(|| format!("writing {}", output))?;
+  println!("{}: {} faces", output, faces(&obj));
+  Ok(())
+}
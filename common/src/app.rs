@@ -0,0 +1,484 @@
+// The window, the event loop and the frame pacing every project needs.
+// A project implements App with the parts specific to its scene and
+// hands it to run(). Escape quits, F6 or 'r' reloads the shaders, F12
+// saves a screenshot and shift F12 starts and stops recording a frame
+// sequence, in every project alike. Programs loaded through a
+// ProgramHandle are also rebuilt as soon as their files change, and the
+// title shows what failed to build. With reversed-Z the frames are
+// drawn offscreen with float depth and copied to the window.
+
+use std::time::{Duration, Instant};
+
+use glam::Vec2;
+use glium::{
+  glutin::surface::WindowSurface, uniforms::MagnifySamplerFilter, Display,
+  Surface,
+};
+use winit::{
+  application::ApplicationHandler,
+  dpi::LogicalSize,
+  event::{
+    ElementState, KeyEvent, MouseButton, MouseScrollDelta, StartCause,
+    WindowEvent,
+  },
+  event_loop::{ActiveEventLoop, ControlFlow, EventLoop},
+  keyboard::{ModifiersState, NamedKey},
+  window::{Window, WindowAttributes, WindowId},
+};
+
+use crate::{
+  capture::{self, Sequence},
+  gl_boilerplate::{ContextOptions, OffscreenTarget},
+  program_registry,
+};
+
+const TARGET_UPS: u32 = 60;
+// the dt App::update gets when frames keep up
+pub const TARGET_FRAME_TIME: Duration =
+  Duration::from_micros(1_000_000 / TARGET_UPS as u64);
+// for mouse wheels that scroll by lines
+const PIXELS_PER_LINE: f32 = 30.0;
+// the frame rate of recorded sequences
+const SEQUENCE_FPS: u32 = 30;
+// how far the cursor may move between press and release of a click
+const CLICK_DISTANCE: f32 = 3.0;
+
+pub enum Input {
+  // presses and releases
+  Key(KeyEvent),
+  Button {
+    button: MouseButton,
+    pressed: bool,
+    pos: Vec2,
+  },
+  // released about where it was pressed, after the Button release
+  Click {
+    button: MouseButton,
+    pos: Vec2,
+    modifiers: ModifiersState,
+  },
+  // the cursor moved with a button held, once for every held button
+  Drag {
+    button: MouseButton,
+    from: Vec2,
+    to: Vec2,
+    modifiers: ModifiersState,
+  },
+  // in pixels
+  Scroll {
+    dx: f32,
+    dy: f32,
+    modifiers: ModifiersState,
+  },
+}
+
+pub trait App {
+  // once the display is there
+  fn init(&mut self, display: &Display<WindowSurface>) -> anyhow::Result<()>;
+
+  fn update(&mut self, dt: Duration);
+
+  // clearing is up to the app, the runner finishes the frame. any
+  // surface does, so apps can render offscreen as well.
+  fn render(&self, frame: &mut impl Surface) -> anyhow::Result<()>;
+
+  fn on_input(&mut self, input: Input);
+
+  fn resize(&mut self, _width: u32, _height: u32) {}
+
+  fn reload_shaders(
+    &mut self,
+    _display: &Display<WindowSurface>,
+  ) -> anyhow::Result<()> {
+    Ok(())
+  }
+
+  // shown in the title bar
+  fn help(&self) -> &str {
+    ""
+  }
+
+  // right before the window goes away
+  fn exit(&mut self) {}
+}
+
+pub fn run(app: impl App) -> anyhow::Result<()> {
+  run_with(app, ContextOptions::default())
+}
+
+// for the GL version, the framebuffer, vsync and the depth mode
+pub fn run_with(app: impl App, options: ContextOptions) -> anyhow::Result<()> {
+  let event_loop = EventLoop::new()?;
+  let mut runner = Runner {
+    app,
+    options,
+    window: None,
+    display: None,
+    offscreen: None,
+    last_update: Instant::now(),
+    held: Vec::new(),
+    cursor: Vec2::ZERO,
+    modifiers: ModifiersState::empty(),
+    screenshot: false,
+    sequence: None,
+    error: None,
+  };
+  event_loop.run_app(&mut runner)?;
+
+  match runner.error {
+    Some(e) => Err(e),
+    None => Ok(()),
+  }
+}
+
+struct Runner<A> {
+  app: A,
+  options: ContextOptions,
+  window: Option<Window>,
+  display: Option<Display<WindowSurface>>,
+  // what the frames are drawn into when the options draw offscreen, the
+  // size of the window
+  offscreen: Option<OffscreenTarget>,
+  last_update: Instant,
+  // mouse buttons held down and where, in the order they were pressed
+  held: Vec<(MouseButton, Vec2)>,
+  cursor: Vec2,
+  modifiers: ModifiersState,
+  // save the next frame
+  screenshot: bool,
+  // being recorded, every update is followed by a saved frame
+  sequence: Option<Sequence>,
+  // what stopped the event loop, returned from run
+  error: Option<anyhow::Error>,
+}
+
+impl<A: App> Runner<A> {
+  fn init(&mut self, event_loop: &ActiveEventLoop) -> anyhow::Result<()> {
+    let window_attrs = WindowAttributes::default()
+      .with_title("cs5610")
+      .with_inner_size(LogicalSize::new(800, 600));
+    let window = event_loop.create_window(window_attrs)?;
+    let display = self.options.init_display(&window)?;
+    // the app picks the same fallback with supported_depth_mode
+    self.options = self.options.supported_by(&display);
+    self.app.init(&display)?;
+
+    self.window = Some(window);
+    self.display = Some(display);
+    Ok(())
+  }
+
+  fn fail(&mut self, event_loop: &ActiveEventLoop, error: anyhow::Error) {
+    self.error = Some(error);
+    event_loop.exit();
+  }
+
+  fn request_redraw(&self) {
+    if let Some(window) = &self.window {
+      window.request_redraw();
+    }
+  }
+
+  fn handle_keyboard(&mut self, event: KeyEvent, event_loop: &ActiveEventLoop) {
+    if event.state.is_pressed() {
+      if event.logical_key == NamedKey::Escape {
+        event_loop.exit();
+        return;
+      }
+      if event.logical_key == NamedKey::F6 || event.logical_key == "r" {
+        self.reload_shaders();
+      }
+      if event.logical_key == NamedKey::F12 {
+        match self.modifiers.shift_key() {
+          true => self.toggle_sequence(),
+          false => self.screenshot = true,
+        }
+      }
+    }
+
+    self.app.on_input(Input::Key(event));
+    self.request_redraw();
+  }
+
+  fn reload_shaders(&mut self) {
+    let Some(display) = &self.display else {
+      return;
+    };
+    let reloaded = program_registry::reload_all(display)
+      .and(self.app.reload_shaders(display));
+    match reloaded {
+      Ok(()) => println!("Reloaded shaders"),
+      Err(e) => eprintln!("Failed to reload shader: {:#}", e),
+    }
+  }
+
+  fn toggle_sequence(&mut self) {
+    if let Some(sequence) = self.sequence.take() {
+      println!(
+        "Recorded {} frames to {} at {} fps",
+        sequence.frames(),
+        sequence.dir().display(),
+        SEQUENCE_FPS
+      );
+      return;
+    }
+
+    match Sequence::start(SEQUENCE_FPS) {
+      Ok(sequence) => {
+        println!("Recording to {}", sequence.dir().display());
+        self.sequence = Some(sequence);
+      }
+      Err(e) => eprintln!("Failed to start recording: {:#}", e),
+    }
+  }
+
+  fn handle_mouse_input(&mut self, state: ElementState, button: MouseButton) {
+    let pressed = state.is_pressed();
+    let index = self.held.iter().position(|&(b, _)| b == button);
+    let pressed_at = index.map(|i| self.held.remove(i).1);
+    if pressed {
+      self.held.push((button, self.cursor));
+    }
+
+    self.app.on_input(Input::Button {
+      button,
+      pressed,
+      pos: self.cursor,
+    });
+    let clicked =
+      pressed_at.is_some_and(|pos| pos.distance(self.cursor) <= CLICK_DISTANCE);
+    if !pressed && clicked {
+      self.app.on_input(Input::Click {
+        button,
+        pos: self.cursor,
+        modifiers: self.modifiers,
+      });
+    }
+  }
+
+  fn handle_cursor_moved(&mut self, to: Vec2) {
+    let from = std::mem::replace(&mut self.cursor, to);
+    for &(button, _) in &self.held {
+      self.app.on_input(Input::Drag {
+        button,
+        from,
+        to,
+        modifiers: self.modifiers,
+      });
+    }
+    if !self.held.is_empty() {
+      self.request_redraw();
+    }
+  }
+
+  fn handle_mouse_wheel(&mut self, delta: MouseScrollDelta) {
+    let (dx, dy) = match delta {
+      MouseScrollDelta::LineDelta(x, y) => {
+        (x * PIXELS_PER_LINE, y * PIXELS_PER_LINE)
+      }
+      MouseScrollDelta::PixelDelta(pos) => (pos.x as f32, pos.y as f32),
+    };
+    self.app.on_input(Input::Scroll {
+      dx,
+      dy,
+      modifiers: self.modifiers,
+    });
+  }
+
+  // follows the window size, made again when it changes
+  fn update_offscreen(&mut self) -> anyhow::Result<()> {
+    let Some(display) = &self.display else {
+      return Ok(());
+    };
+    if !self.options.draws_offscreen() {
+      return Ok(());
+    }
+    let size = display.get_framebuffer_dimensions();
+    // minimized, nothing to draw into
+    if size.0 == 0 || size.1 == 0 {
+      return Ok(());
+    }
+    if self.offscreen.as_ref().map(OffscreenTarget::dimensions) != Some(size) {
+      let target = self.options.offscreen_target(display, size.0, size.1)?;
+      self.offscreen = Some(target);
+    }
+    Ok(())
+  }
+
+  fn handle_redraw(&mut self, event_loop: &ActiveEventLoop) {
+    if let Err(e) = self.update_offscreen() {
+      self.fail(event_loop, e.context("resizing the offscreen target"));
+      return;
+    }
+    let Some(display) = &self.display else {
+      return;
+    };
+
+    let mut frame = display.draw();
+    let rendered = match &self.offscreen {
+      Some(target) => {
+        target.framebuffer(display).and_then(|mut framebuffer| {
+          self.app.render(&mut framebuffer)?;
+          framebuffer.fill(&frame, MagnifySamplerFilter::Nearest);
+          Ok(())
+        })
+      }
+      None => self.app.render(&mut frame),
+    };
+    // an unfinished frame panics when dropped, finish it either way
+    let finished = frame.finish().map_err(anyhow::Error::from);
+    if let Err(e) = rendered.and(finished) {
+      self.fail(event_loop, e.context("rendering"));
+      return;
+    }
+
+    if let Err(e) = self.capture() {
+      eprintln!("Failed to capture the frame: {:#}", e);
+      self.screenshot = false;
+      self.sequence = None;
+    }
+  }
+
+  // the frame just shown, with its depth for screenshots
+  fn capture(&mut self) -> anyhow::Result<()> {
+    let recording = self
+      .sequence
+      .as_ref()
+      .is_some_and(|sequence| sequence.pending());
+    if !self.screenshot && !recording {
+      return Ok(());
+    }
+    let Some(display) = &self.display else {
+      return Ok(());
+    };
+
+    // the window can't give its depth back, so the frame is drawn
+    // again offscreen unless it was drawn there to begin with
+    let redrawn;
+    let target = match &self.offscreen {
+      Some(target) => target,
+      None => {
+        let (width, height) = display.get_framebuffer_dimensions();
+        redrawn = self.options.offscreen_target(display, width, height)?;
+        self.app.render(&mut redrawn.framebuffer(display)?)?;
+        &redrawn
+      }
+    };
+    let color = capture::read_color(&target.color)?;
+
+    if let Some(sequence) = &mut self.sequence {
+      if recording {
+        sequence.save_frame(&color)?;
+      }
+    }
+
+    if std::mem::take(&mut self.screenshot) {
+      let path = capture::screenshot_path();
+      capture::save_color(&path, &color)?;
+      let depth = capture::read_depth(display, &target.depth)?;
+      let depth_path = path.with_extension("pfm");
+      capture::save_depth(&depth_path, &depth)?;
+      println!(
+        "Saved screenshot to {} and {}",
+        path.display(),
+        depth_path.display()
+      );
+    }
+    Ok(())
+  }
+
+  fn update(&mut self) {
+    // show the rebuilt programs right away
+    if self.display.as_ref().is_some_and(program_registry::poll) {
+      self.request_redraw();
+    }
+
+    let dt = self.last_update.elapsed();
+    match &mut self.sequence {
+      // simulated time, so the recording plays at the real speed
+      Some(sequence) => self.app.update(sequence.next_step()),
+      None => self.app.update(dt),
+    }
+
+    let ups = 1.0 / dt.as_secs_f32();
+    let mut title = match self.app.help() {
+      "" => format!("ups: {:.2}", ups),
+      help => format!("ups: {:.2} ({})", ups, help),
+    };
+    if let Some(errors) = program_registry::error_summary() {
+      title = format!("{} - {}", title, errors);
+    }
+
+    if let Some(window) = &self.window {
+      window.set_title(&title);
+      window.request_redraw();
+    }
+
+    self.last_update = Instant::now();
+  }
+
+  fn schedule_next_frame(&self, event_loop: &ActiveEventLoop) {
+    let wake_up_at = self.last_update + TARGET_FRAME_TIME;
+    event_loop.set_control_flow(ControlFlow::WaitUntil(wake_up_at));
+  }
+}
+
+impl<A: App> ApplicationHandler for Runner<A> {
+  fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+    if self.window.is_some() {
+      return;
+    }
+    if let Err(e) = self.init(event_loop) {
+      self.fail(event_loop, e.context("initializing"));
+    }
+  }
+
+  fn window_event(
+    &mut self,
+    event_loop: &ActiveEventLoop,
+    _window_id: WindowId,
+    event: WindowEvent,
+  ) {
+    match event {
+      WindowEvent::Resized(size) => {
+        self.app.resize(size.width, size.height);
+        self.request_redraw();
+      }
+      WindowEvent::KeyboardInput { event, .. } => {
+        self.handle_keyboard(event, event_loop);
+      }
+      WindowEvent::RedrawRequested => {
+        self.handle_redraw(event_loop);
+      }
+      WindowEvent::CloseRequested => {
+        event_loop.exit();
+      }
+      WindowEvent::MouseInput { state, button, .. } => {
+        self.handle_mouse_input(state, button);
+      }
+      WindowEvent::MouseWheel { delta, .. } => {
+        self.handle_mouse_wheel(delta);
+      }
+      WindowEvent::CursorMoved { position, .. } => {
+        self
+          .handle_cursor_moved(Vec2::new(position.x as f32, position.y as f32));
+      }
+      WindowEvent::ModifiersChanged(modifiers) => {
+        self.modifiers = modifiers.state();
+      }
+      _ => {}
+    }
+  }
+
+  fn new_events(&mut self, event_loop: &ActiveEventLoop, _cause: StartCause) {
+    self.schedule_next_frame(event_loop);
+
+    if self.last_update.elapsed() > TARGET_FRAME_TIME {
+      self.update();
+    }
+  }
+
+  fn exiting(&mut self, _event_loop: &ActiveEventLoop) {
+    self.app.exit();
+  }
+}
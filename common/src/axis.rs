@@ -1,8 +1,7 @@
 use glam::Mat4;
 use glium::{
   backend::Facade, implement_vertex, index::PrimitiveType, program::SourceCode,
-  uniform, DrawParameters, Frame, IndexBuffer, Program, Surface as _,
-  VertexBuffer,
+  uniform, DrawParameters, IndexBuffer, Program, Surface, VertexBuffer,
 };
 
 #[derive(Copy, Clone)]
@@ -103,7 +102,11 @@ impl Axis {
     })
   }
 
-  pub fn draw(&self, frame: &mut Frame, view_projection: &Mat4) -> Result<()> {
+  pub fn draw(
+    &self,
+    frame: &mut impl Surface,
+    view_projection: &Mat4,
+  ) -> Result<()> {
     let mvp: [[f32; 4]; 4] = view_projection.to_cols_array_2d();
 
     let uniforms = uniform! {
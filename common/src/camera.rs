@@ -0,0 +1,414 @@
+// A camera is a view matrix and a projection. Controllers turn mouse and
+// keyboard input into the view matrix, and can be swapped at runtime
+// by building the new one from the current camera.
+
+mod arcball;
+mod fly;
+mod orbit;
+mod path;
+
+use std::time::Duration;
+
+use glam::{Mat4, Vec2, Vec3};
+use glium::{
+  draw_parameters::{ClipControlDepth, DepthTest},
+  texture::DepthFormat,
+  Depth, DrawParameters,
+};
+use winit::keyboard::Key;
+
+use crate::{
+  math::{Aabb, DepthRange, Frustum, Sphere},
+  CameraLike,
+};
+
+pub use arcball::ArcballController;
+pub use fly::{FirstPersonController, FlyController};
+pub use orbit::OrbitController;
+pub use path::{
+  CameraPath, CameraPaths, Keyframe, PathPlayer, PathRecorder, Playback,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+  Perspective {
+    // vertical, in degrees
+    fov: f32,
+    near: f32,
+    // may be f32::INFINITY
+    far: f32,
+  },
+  // shows as much as the perspective projection with the same fov does
+  // at the focus distance, so zooming keeps working
+  Orthographic {
+    fov: f32,
+    near: f32,
+    far: f32,
+  },
+  Custom(Mat4),
+}
+
+impl Default for Projection {
+  fn default() -> Self {
+    Self::Perspective {
+      fov: 60.0,
+      near: 0.1,
+      far: 100.0,
+    }
+  }
+}
+
+impl Projection {
+  pub fn matrix(
+    &self,
+    aspect: f32,
+    focus_distance: f32,
+    depth: DepthRange,
+  ) -> Mat4 {
+    match *self {
+      Self::Perspective { fov, near, far } => {
+        // the z row is replaced below, any far plane does for now
+        let m =
+          Mat4::perspective_rh_gl(fov.to_radians(), aspect, near, 2.0 * near);
+        // ndc z = (a * z + b) / -z for view space z
+        let a = if far.is_infinite() {
+          -depth.far
+        } else {
+          (far * depth.far - near * depth.near) / (near - far)
+        };
+        let b = near * (depth.near + a);
+        with_depth_row(m, a, b)
+      }
+      Self::Orthographic { fov, near, far } => {
+        let h = focus_distance * (fov.to_radians() / 2.0).tan();
+        let w = h * aspect;
+        let m = Mat4::orthographic_rh_gl(-w, w, -h, h, -1.0, 1.0);
+        let far = far.min(INFINITE_ORTHOGRAPHIC_FAR);
+        // ndc z = a * z + b
+        let a = (depth.near - depth.far) / (far - near);
+        let b = depth.near + a * near;
+        with_depth_row(m, a, b)
+      }
+      Self::Custom(m) => m,
+    }
+  }
+
+  // between perspective and orthographic, custom stays as it is
+  pub fn toggled(&self) -> Self {
+    match *self {
+      Self::Perspective { fov, near, far } => {
+        Self::Orthographic { fov, near, far }
+      }
+      Self::Orthographic { fov, near, far } => {
+        Self::Perspective { fov, near, far }
+      }
+      Self::Custom(m) => Self::Custom(m),
+    }
+  }
+
+  // the same projection with other clip planes, custom stays as it is
+  pub fn with_near_far(&self, near: f32, far: f32) -> Self {
+    match *self {
+      Self::Perspective { fov, .. } => Self::Perspective { fov, near, far },
+      Self::Orthographic { fov, .. } => Self::Orthographic { fov, near, far },
+      Self::Custom(m) => Self::Custom(m),
+    }
+  }
+
+  // how far from its center a sphere fills the narrower side of the view
+  pub fn fit_distance(&self, radius: f32, aspect: f32) -> f32 {
+    // read back from the matrix so custom projections work as well
+    let m = self.matrix(aspect, 1.0, DepthRange::default());
+    let tan_half = (1.0 / m.x_axis.x).min(1.0 / m.y_axis.y);
+    if m.w_axis.w == 0.0 {
+      radius / tan_half.atan().sin()
+    } else {
+      // orthographic, the focus distance scales the view
+      radius / tan_half
+    }
+  }
+}
+
+fn with_depth_row(mut m: Mat4, a: f32, b: f32) -> Mat4 {
+  m.z_axis.z = a;
+  m.w_axis.z = b;
+  m
+}
+
+// orthographic projections have no perspective divide to squeeze
+// infinity into the depth range, so they stop here instead
+const INFINITE_ORTHOGRAPHIC_FAR: f32 = 1.0e4;
+
+// How depth is stored and compared. Reversed-Z puts the near plane at
+// depth 1 and the far plane at 0. Floats are most precise around 0,
+// which cancels out the perspective divide crowding far away depths
+// together, so with a float depth buffer the precision is about even
+// over the whole distance.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DepthMode {
+  // -1..1 clip space, cleared to 1, nearer is less
+  #[default]
+  Standard,
+  // 0..1 clip space, cleared to 0, nearer is greater. needs clip
+  // control, see gl_boilerplate::supported_depth_mode.
+  ReversedZ,
+}
+
+impl DepthMode {
+  pub fn range(self) -> DepthRange {
+    match self {
+      Self::Standard => DepthRange::NEG_ONE_TO_ONE,
+      Self::ReversedZ => DepthRange::REVERSED,
+    }
+  }
+
+  // the far plane, in the depth buffer
+  pub fn clear_depth(self) -> f32 {
+    match self {
+      Self::Standard => 1.0,
+      Self::ReversedZ => 0.0,
+    }
+  }
+
+  pub fn depth_test(self) -> DepthTest {
+    match self {
+      Self::Standard => DepthTest::IfLess,
+      Self::ReversedZ => DepthTest::IfMore,
+    }
+  }
+
+  // testing and writing, for opaque geometry
+  pub fn depth(self) -> Depth {
+    Depth {
+      test: self.depth_test(),
+      write: true,
+      ..Default::default()
+    }
+  }
+
+  // glium sets clip control per draw, so every draw with this mode has
+  // to ask for it
+  pub fn clip_control_depth(self) -> ClipControlDepth {
+    match self {
+      Self::Standard => ClipControlDepth::NegativeOneToOne,
+      Self::ReversedZ => ClipControlDepth::ZeroToOne,
+    }
+  }
+
+  // depth and clip space for opaque geometry, to fill in the rest of the
+  // draw parameters from
+  pub fn draw_parameters(self) -> DrawParameters<'static> {
+    DrawParameters {
+      depth: self.depth(),
+      clip_control_depth: self.clip_control_depth(),
+      ..Default::default()
+    }
+  }
+
+  // for depth textures rendered with this mode
+  pub fn depth_format(self) -> DepthFormat {
+    match self {
+      Self::Standard => DepthFormat::I24,
+      Self::ReversedZ => DepthFormat::F32,
+    }
+  }
+}
+
+// extra depth in front of and behind the bounds, relative to their depth
+const DEPTH_MARGIN: f32 = 0.01;
+// the smallest near plane as a fraction of the far plane, any closer
+// and the depth buffer runs out of precision
+const MIN_NEAR_RATIO: f32 = 0.001;
+
+#[derive(Clone, Debug)]
+pub struct Camera {
+  view: Mat4,
+  pub projection: Projection,
+  aspect: f32,
+  // distance to what the camera looks at, e.g. the orbit target
+  focus_distance: f32,
+  depth_mode: DepthMode,
+}
+
+impl Default for Camera {
+  fn default() -> Self {
+    Self::new(Mat4::IDENTITY, Projection::default())
+  }
+}
+
+impl Camera {
+  pub fn new(view: Mat4, projection: Projection) -> Self {
+    Self {
+      view,
+      projection,
+      aspect: 1.0,
+      focus_distance: 1.0,
+      depth_mode: DepthMode::default(),
+    }
+  }
+
+  pub fn with_depth_mode(mut self, depth_mode: DepthMode) -> Self {
+    self.depth_mode = depth_mode;
+    self
+  }
+
+  pub fn look_at(eye: Vec3, target: Vec3, projection: Projection) -> Self {
+    let mut camera =
+      Self::new(Mat4::look_at_rh(eye, target, Vec3::Y), projection);
+    camera.focus_distance = eye.distance(target);
+    camera
+  }
+
+  pub fn view(&self) -> Mat4 {
+    self.view
+  }
+
+  // any invertible matrix, e.g. a mirrored one for reflections
+  pub fn set_view(&mut self, view: Mat4) {
+    self.view = view;
+  }
+
+  // custom projections have to match the depth mode themselves
+  pub fn projection(&self) -> Mat4 {
+    let depth = self.depth_mode.range();
+    self
+      .projection
+      .matrix(self.aspect, self.focus_distance, depth)
+  }
+
+  pub fn view_projection(&self) -> Mat4 {
+    self.projection() * self.view
+  }
+
+  pub fn frustum(&self) -> Frustum {
+    Frustum::from_matrix_with_depth(
+      self.view_projection(),
+      self.depth_mode.range(),
+    )
+  }
+
+  pub fn depth_mode(&self) -> DepthMode {
+    self.depth_mode
+  }
+
+  pub fn aspect(&self) -> f32 {
+    self.aspect
+  }
+
+  pub fn handle_resize(&mut self, width: f32, height: f32) {
+    if height == 0.0 {
+      return;
+    }
+    self.aspect = width / height;
+  }
+
+  pub fn focus_distance(&self) -> f32 {
+    self.focus_distance
+  }
+
+  pub fn set_focus_distance(&mut self, distance: f32) {
+    self.focus_distance = distance;
+  }
+
+  pub fn toggle_projection(&mut self) {
+    self.projection = self.projection.toggled();
+  }
+
+  pub fn position(&self) -> Vec3 {
+    self.view.inverse().transform_point3(Vec3::ZERO)
+  }
+
+  pub fn forward(&self) -> Vec3 {
+    self
+      .view
+      .inverse()
+      .transform_vector3(Vec3::NEG_Z)
+      .normalize()
+  }
+
+  pub fn focus_point(&self) -> Vec3 {
+    self.position() + self.forward() * self.focus_distance
+  }
+
+  // the focus distance at which the bounds fill the view
+  pub fn fit_distance(&self, bounds: &Aabb) -> f32 {
+    let sphere = Sphere::from_aabb(bounds);
+    self.projection.fit_distance(sphere.radius, self.aspect)
+  }
+
+  // moves the near and far planes as close to the bounds as possible,
+  // for the current view. the near plane stays in front of the camera
+  // for perspective projections, and an infinite far plane stays.
+  pub fn fit_near_far(&mut self, bounds: &Aabb) {
+    if bounds.is_empty() {
+      return;
+    }
+    let (near, far) = bounds
+      .corners()
+      .iter()
+      .map(|&c| -self.view.transform_point3(c).z)
+      .fold((f32::INFINITY, f32::NEG_INFINITY), |(near, far), depth| {
+        (near.min(depth), far.max(depth))
+      });
+    if far <= 0.0 {
+      // everything is behind the camera
+      return;
+    }
+
+    let margin = (far - near) * DEPTH_MARGIN;
+    let (mut near, mut far) = (near - margin, far + margin);
+    if let Projection::Perspective { far: current, .. } = self.projection {
+      near = near.max(far * MIN_NEAR_RATIO);
+      if current.is_infinite() {
+        far = current;
+      }
+    }
+    self.projection = self.projection.with_near_far(near, far);
+  }
+}
+
+impl CameraLike for Camera {
+  fn view(&self) -> [[f32; 4]; 4] {
+    self.view.to_cols_array_2d()
+  }
+
+  fn projection(&self) -> [[f32; 4]; 4] {
+    Camera::projection(self).to_cols_array_2d()
+  }
+
+  fn depth_mode(&self) -> DepthMode {
+    self.depth_mode
+  }
+}
+
+pub trait CameraController {
+  // the cursor was dragged from `from` to `to`, in window pixels with
+  // the origin at the top left
+  fn drag(&mut self, from: Vec2, to: Vec2, viewport: Vec2);
+
+  // move towards the focus, negative to move away. one unit is roughly
+  // one notch of the scroll wheel.
+  fn zoom(&mut self, amount: f32);
+
+  // returns whether the key was used
+  fn key(&mut self, _key: &Key, _pressed: bool) -> bool {
+    false
+  }
+
+  // continuous movement, returns whether the camera moved
+  fn update(&mut self, _dt: Duration) -> bool {
+    false
+  }
+
+  // look at `target` from `distance` away, keeping the view direction
+  fn focus(&mut self, target: Vec3, distance: f32);
+
+  // move so the bounds fill the view ("frame selected")
+  fn frame(&mut self, camera: &Camera, bounds: &Aabb) {
+    if !bounds.is_empty() {
+      self.focus(bounds.center(), camera.fit_distance(bounds));
+    }
+  }
+
+  fn apply(&self, camera: &mut Camera);
+}
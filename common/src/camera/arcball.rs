@@ -0,0 +1,83 @@
+use glam::{Mat4, Quat, Vec2, Vec3};
+
+use super::{Camera, CameraController};
+
+// Rotates around a target like dragging a ball under the cursor
+// (Shoemake, 1992). The orientation is a quaternion, so there is no up
+// axis to lock against and the camera can roll over the poles.
+#[derive(Clone, Debug)]
+pub struct ArcballController {
+  pub target: Vec3,
+  pub distance: f32,
+  // camera to world
+  pub orientation: Quat,
+  pub min_distance: f32,
+}
+
+const ZOOM_SPEED: f32 = 0.1;
+
+impl ArcballController {
+  pub fn new(target: Vec3, distance: f32) -> Self {
+    Self {
+      target,
+      distance,
+      orientation: Quat::IDENTITY,
+      min_distance: 0.01,
+    }
+  }
+
+  pub fn from_camera(camera: &Camera) -> Self {
+    let (_, orientation, _) =
+      camera.view().inverse().to_scale_rotation_translation();
+    Self {
+      orientation,
+      ..Self::new(camera.focus_point(), camera.focus_distance())
+    }
+  }
+
+  pub fn eye(&self) -> Vec3 {
+    self.target + self.orientation * Vec3::Z * self.distance
+  }
+}
+
+// the point on the ball under the cursor, in view space. outside the
+// ball it continues on a hyperbolic sheet (Bell) so the rotation doesn't
+// jump at the silhouette.
+fn ball_point(cursor: Vec2, viewport: Vec2) -> Vec3 {
+  let radius = viewport.min_element() / 2.0;
+  let p = Vec2::new(cursor.x - viewport.x / 2.0, viewport.y / 2.0 - cursor.y)
+    / radius;
+  let d2 = p.length_squared();
+  let z = if d2 <= 0.5 {
+    (1.0 - d2).sqrt()
+  } else {
+    0.5 / d2.sqrt()
+  };
+  p.extend(z).normalize()
+}
+
+impl CameraController for ArcballController {
+  fn drag(&mut self, from: Vec2, to: Vec2, viewport: Vec2) {
+    let (a, b) = (ball_point(from, viewport), ball_point(to, viewport));
+    // the ball turns from a to b, the camera the opposite way
+    let rotation = Quat::from_rotation_arc(a, b);
+    self.orientation = (self.orientation * rotation.inverse()).normalize();
+  }
+
+  fn zoom(&mut self, amount: f32) {
+    self.distance =
+      (self.distance * (-amount * ZOOM_SPEED).exp()).max(self.min_distance);
+  }
+
+  fn focus(&mut self, target: Vec3, distance: f32) {
+    self.target = target;
+    self.distance = distance.max(self.min_distance);
+  }
+
+  fn apply(&self, camera: &mut Camera) {
+    let camera_to_world =
+      Mat4::from_rotation_translation(self.orientation, self.eye());
+    camera.set_view(camera_to_world.inverse());
+    camera.set_focus_distance(self.distance);
+  }
+}
@@ -0,0 +1,279 @@
+use std::time::Duration;
+
+use glam::{EulerRot, Mat4, Quat, Vec2, Vec3};
+use winit::keyboard::{Key, NamedKey};
+
+use super::{Camera, CameraController};
+
+const MAX_PITCH: f32 = std::f32::consts::FRAC_PI_2 - 0.01;
+// with shift held
+const FAST_FACTOR: f32 = 4.0;
+// how far a zoom step moves, in seconds of movement
+const ZOOM_STEP: f32 = 0.1;
+// zooming past the point of interest leaves it just in front
+const MIN_FOCUS_DISTANCE: f32 = 0.01;
+
+// Free flight: WASD or the arrow keys move along the view direction,
+// E/Q or page up/down move up and down, dragging looks around.
+#[derive(Clone, Debug)]
+pub struct FlyController {
+  pub position: Vec3,
+  // radians, 0 looks towards -z
+  pub yaw: f32,
+  pub pitch: f32,
+  // units per second
+  pub speed: f32,
+  // radians per pixel
+  pub sensitivity: f32,
+  // how far ahead the point of interest is, for orthographic
+  // projections and for switching back to orbiting
+  pub focus_distance: f32,
+  held: Held,
+}
+
+// Walking: like flying, but moving stays in the horizontal plane so
+// the eye height is kept when looking up or down.
+#[derive(Clone, Debug)]
+pub struct FirstPersonController {
+  pub position: Vec3,
+  pub yaw: f32,
+  pub pitch: f32,
+  pub speed: f32,
+  pub sensitivity: f32,
+  pub focus_distance: f32,
+  held: Held,
+}
+
+impl FlyController {
+  pub fn new(position: Vec3, yaw: f32, pitch: f32) -> Self {
+    Self {
+      position,
+      yaw,
+      pitch: pitch.clamp(-MAX_PITCH, MAX_PITCH),
+      speed: 1.0,
+      sensitivity: 0.005,
+      focus_distance: 1.0,
+      held: Held::default(),
+    }
+  }
+
+  pub fn from_camera(camera: &Camera) -> Self {
+    let (yaw, pitch) = angles(camera.forward());
+    Self {
+      focus_distance: camera.focus_distance(),
+      ..Self::new(camera.position(), yaw, pitch)
+    }
+  }
+
+  pub fn with_speed(mut self, speed: f32) -> Self {
+    self.speed = speed;
+    self
+  }
+}
+
+impl FirstPersonController {
+  pub fn new(position: Vec3, yaw: f32, pitch: f32) -> Self {
+    let FlyController {
+      speed,
+      sensitivity,
+      focus_distance,
+      ..
+    } = FlyController::new(position, yaw, pitch);
+    Self {
+      position,
+      yaw,
+      pitch: pitch.clamp(-MAX_PITCH, MAX_PITCH),
+      speed,
+      sensitivity,
+      focus_distance,
+      held: Held::default(),
+    }
+  }
+
+  pub fn from_camera(camera: &Camera) -> Self {
+    let (yaw, pitch) = angles(camera.forward());
+    Self {
+      focus_distance: camera.focus_distance(),
+      ..Self::new(camera.position(), yaw, pitch)
+    }
+  }
+
+  pub fn with_speed(mut self, speed: f32) -> Self {
+    self.speed = speed;
+    self
+  }
+}
+
+impl CameraController for FlyController {
+  fn drag(&mut self, from: Vec2, to: Vec2, _viewport: Vec2) {
+    look(
+      &mut self.yaw,
+      &mut self.pitch,
+      (to - from) * self.sensitivity,
+    );
+  }
+
+  fn zoom(&mut self, amount: f32) {
+    let forward = rotation(self.yaw, self.pitch) * Vec3::NEG_Z;
+    let step = amount * self.speed * ZOOM_STEP;
+    self.position += forward * step;
+    self.focus_distance = (self.focus_distance - step).max(MIN_FOCUS_DISTANCE);
+  }
+
+  fn key(&mut self, key: &Key, pressed: bool) -> bool {
+    self.held.set(key, pressed, true)
+  }
+
+  fn update(&mut self, dt: Duration) -> bool {
+    let axes = self.held.axes();
+    if axes == Vec3::ZERO {
+      return false;
+    }
+
+    let rotation = rotation(self.yaw, self.pitch);
+    let dir = rotation * Vec3::X * axes.x
+      + Vec3::Y * axes.y
+      + rotation * Vec3::NEG_Z * axes.z;
+    self.position +=
+      dir.normalize_or_zero() * self.held.speed(self.speed) * dt.as_secs_f32();
+    true
+  }
+
+  fn focus(&mut self, target: Vec3, distance: f32) {
+    let forward = rotation(self.yaw, self.pitch) * Vec3::NEG_Z;
+    self.position = target - forward * distance;
+    self.focus_distance = distance;
+  }
+
+  fn apply(&self, camera: &mut Camera) {
+    camera.set_view(view(self.position, self.yaw, self.pitch));
+    camera.set_focus_distance(self.focus_distance);
+  }
+}
+
+impl CameraController for FirstPersonController {
+  fn drag(&mut self, from: Vec2, to: Vec2, _viewport: Vec2) {
+    look(
+      &mut self.yaw,
+      &mut self.pitch,
+      (to - from) * self.sensitivity,
+    );
+  }
+
+  fn zoom(&mut self, amount: f32) {
+    let forward = rotation(self.yaw, 0.0) * Vec3::NEG_Z;
+    let step = amount * self.speed * ZOOM_STEP;
+    self.position += forward * step;
+    self.focus_distance = (self.focus_distance - step).max(MIN_FOCUS_DISTANCE);
+  }
+
+  fn key(&mut self, key: &Key, pressed: bool) -> bool {
+    // no flying up and down
+    self.held.set(key, pressed, false)
+  }
+
+  fn update(&mut self, dt: Duration) -> bool {
+    let axes = self.held.axes();
+    if axes == Vec3::ZERO {
+      return false;
+    }
+
+    let rotation = rotation(self.yaw, 0.0);
+    let dir = rotation * Vec3::X * axes.x + rotation * Vec3::NEG_Z * axes.z;
+    self.position +=
+      dir.normalize_or_zero() * self.held.speed(self.speed) * dt.as_secs_f32();
+    true
+  }
+
+  fn focus(&mut self, target: Vec3, distance: f32) {
+    let forward = rotation(self.yaw, self.pitch) * Vec3::NEG_Z;
+    self.position = target - forward * distance;
+    self.focus_distance = distance;
+  }
+
+  fn apply(&self, camera: &mut Camera) {
+    camera.set_view(view(self.position, self.yaw, self.pitch));
+    camera.set_focus_distance(self.focus_distance);
+  }
+}
+
+// camera to world
+fn rotation(yaw: f32, pitch: f32) -> Quat {
+  Quat::from_euler(EulerRot::YXZ, yaw, pitch, 0.0)
+}
+
+fn view(position: Vec3, yaw: f32, pitch: f32) -> Mat4 {
+  Mat4::from_rotation_translation(rotation(yaw, pitch), position).inverse()
+}
+
+// (yaw, pitch) looking along `forward`
+fn angles(forward: Vec3) -> (f32, f32) {
+  let pitch = forward
+    .y
+    .clamp(-1.0, 1.0)
+    .asin()
+    .clamp(-MAX_PITCH, MAX_PITCH);
+  ((-forward.x).atan2(-forward.z), pitch)
+}
+
+// the view follows the cursor
+fn look(yaw: &mut f32, pitch: &mut f32, delta: Vec2) {
+  *yaw -= delta.x;
+  *pitch = (*pitch - delta.y).clamp(-MAX_PITCH, MAX_PITCH);
+}
+
+// movement keys currently held down
+#[derive(Clone, Debug, Default)]
+struct Held {
+  forward: bool,
+  back: bool,
+  left: bool,
+  right: bool,
+  up: bool,
+  down: bool,
+  fast: bool,
+}
+
+impl Held {
+  fn set(&mut self, key: &Key, pressed: bool, vertical: bool) -> bool {
+    let flag = match key {
+      Key::Named(NamedKey::ArrowUp) => &mut self.forward,
+      Key::Named(NamedKey::ArrowDown) => &mut self.back,
+      Key::Named(NamedKey::ArrowLeft) => &mut self.left,
+      Key::Named(NamedKey::ArrowRight) => &mut self.right,
+      Key::Named(NamedKey::PageUp) if vertical => &mut self.up,
+      Key::Named(NamedKey::PageDown) if vertical => &mut self.down,
+      Key::Named(NamedKey::Shift) => &mut self.fast,
+      Key::Character(c) => match c.to_lowercase().as_str() {
+        "w" => &mut self.forward,
+        "s" => &mut self.back,
+        "a" => &mut self.left,
+        "d" => &mut self.right,
+        "e" if vertical => &mut self.up,
+        "q" if vertical => &mut self.down,
+        _ => return false,
+      },
+      _ => return false,
+    };
+    *flag = pressed;
+    true
+  }
+
+  // (right, up, forward), each -1, 0 or 1
+  fn axes(&self) -> Vec3 {
+    let axis = |pos: bool, neg: bool| pos as i32 as f32 - neg as i32 as f32;
+    Vec3::new(
+      axis(self.right, self.left),
+      axis(self.up, self.down),
+      axis(self.forward, self.back),
+    )
+  }
+
+  fn speed(&self, speed: f32) -> f32 {
+    if self.fast {
+      speed * FAST_FACTOR
+    } else {
+      speed
+    }
+  }
+}
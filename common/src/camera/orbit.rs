@@ -0,0 +1,96 @@
+use glam::{Mat4, Vec2, Vec3};
+
+use super::{Camera, CameraController};
+
+// Turntable around a target: yaw around the world up axis, pitch
+// clamped short of the poles so up never flips.
+#[derive(Clone, Debug)]
+pub struct OrbitController {
+  pub target: Vec3,
+  pub distance: f32,
+  // radians, 0 looks from +z towards -z
+  pub yaw: f32,
+  // radians, positive looks from above
+  pub pitch: f32,
+  pub min_distance: f32,
+  pub max_distance: f32,
+  // radians per pixel
+  pub sensitivity: f32,
+}
+
+const MAX_PITCH: f32 = std::f32::consts::FRAC_PI_2 - 0.01;
+const ZOOM_SPEED: f32 = 0.1;
+
+impl OrbitController {
+  pub fn new(target: Vec3, distance: f32) -> Self {
+    Self {
+      target,
+      distance,
+      yaw: 0.0,
+      pitch: 0.0,
+      min_distance: 0.01,
+      max_distance: f32::INFINITY,
+      sensitivity: 0.01,
+    }
+  }
+
+  pub fn looking_at(eye: Vec3, target: Vec3) -> Self {
+    let offset = eye - target;
+    let distance = offset.length();
+    let dir = offset / distance;
+
+    Self {
+      yaw: dir.x.atan2(dir.z),
+      pitch: dir.y.asin().clamp(-MAX_PITCH, MAX_PITCH),
+      ..Self::new(target, distance)
+    }
+  }
+
+  pub fn from_camera(camera: &Camera) -> Self {
+    Self::looking_at(camera.position(), camera.focus_point())
+  }
+
+  pub fn with_angles(mut self, yaw: f32, pitch: f32) -> Self {
+    self.yaw = yaw;
+    self.pitch = pitch.clamp(-MAX_PITCH, MAX_PITCH);
+    self
+  }
+
+  pub fn with_distance_range(mut self, min: f32, max: f32) -> Self {
+    self.min_distance = min;
+    self.max_distance = max;
+    self.distance = self.distance.clamp(min, max);
+    self
+  }
+
+  pub fn eye(&self) -> Vec3 {
+    let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+    let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+    let dir = Vec3::new(cos_pitch * sin_yaw, sin_pitch, cos_pitch * cos_yaw);
+    self.target + dir * self.distance
+  }
+}
+
+impl CameraController for OrbitController {
+  fn drag(&mut self, from: Vec2, to: Vec2, _viewport: Vec2) {
+    let delta = (to - from) * self.sensitivity;
+    // the scene follows the cursor, so the camera moves the other way
+    self.yaw -= delta.x;
+    self.pitch = (self.pitch + delta.y).clamp(-MAX_PITCH, MAX_PITCH);
+  }
+
+  fn zoom(&mut self, amount: f32) {
+    self.distance = (self.distance * (-amount * ZOOM_SPEED).exp())
+      .clamp(self.min_distance, self.max_distance);
+  }
+
+  fn focus(&mut self, target: Vec3, distance: f32) {
+    self.target = target;
+    self.distance = distance.clamp(self.min_distance, self.max_distance);
+  }
+
+  fn apply(&self, camera: &mut Camera) {
+    camera.set_view(Mat4::look_at_rh(self.eye(), self.target, Vec3::Y));
+    camera.set_focus_distance(self.distance);
+  }
+}
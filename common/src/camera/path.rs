@@ -0,0 +1,621 @@
+use std::{
+  path::{Path, PathBuf},
+  time::Duration,
+};
+
+use glam::{Mat4, Quat, Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+use winit::event::KeyEvent;
+
+use super::{Camera, CameraController};
+
+// A camera pose at a point in time. Position and target are what the
+// splines run through; the orientation only contributes the up vector,
+// so roll from the arcball survives.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Keyframe {
+  // seconds from the start of the path
+  pub time: f32,
+  pub position: Vec3,
+  pub target: Vec3,
+  // camera to world
+  pub orientation: Quat,
+}
+
+impl Keyframe {
+  pub fn from_camera(time: f32, camera: &Camera) -> Self {
+    let (_, orientation, _) =
+      camera.view().inverse().to_scale_rotation_translation();
+    Self {
+      time,
+      position: camera.position(),
+      target: camera.focus_point(),
+      orientation,
+    }
+  }
+
+  pub fn view(&self) -> Mat4 {
+    let up = self.orientation * Vec3::Y;
+    Mat4::look_at_rh(self.position, self.target, up)
+  }
+}
+
+// Keyframes on a timeline, sorted by time. In between, position and
+// target follow Catmull-Rom splines with the tangents scaled to the
+// spacing of the keyframes, so the speed doesn't jump where keyframes
+// are unevenly spaced.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CameraPath {
+  keyframes: Vec<Keyframe>,
+}
+
+impl CameraPath {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+    let text = std::fs::read_to_string(path)?;
+    let mut path: Self = ron::from_str(&text)?;
+    path.keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+    Ok(path)
+  }
+
+  pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+    let text = ron::ser::to_string_pretty(self, Default::default())?;
+    std::fs::write(path, text)?;
+    Ok(())
+  }
+
+  pub fn keyframes(&self) -> &[Keyframe] {
+    &self.keyframes
+  }
+
+  // replaces a keyframe at the same time
+  pub fn insert(&mut self, keyframe: Keyframe) {
+    let i = self.keyframes.partition_point(|k| k.time < keyframe.time);
+    match self.keyframes.get_mut(i) {
+      Some(k) if k.time == keyframe.time => *k = keyframe,
+      _ => self.keyframes.insert(i, keyframe),
+    }
+  }
+
+  pub fn clear(&mut self) {
+    self.keyframes.clear();
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.keyframes.is_empty()
+  }
+
+  // time of the last keyframe
+  pub fn duration(&self) -> f32 {
+    self.keyframes.last().map_or(0.0, |k| k.time)
+  }
+
+  // the pose at `time`, held at the first and last keyframe outside
+  // of the path
+  pub fn sample(&self, time: f32) -> Option<Keyframe> {
+    let keys = &self.keyframes;
+    let (first, last) = (keys.first()?, keys.last()?);
+    if time <= first.time {
+      return Some(Keyframe { time, ..*first });
+    }
+    if time >= last.time {
+      return Some(Keyframe { time, ..*last });
+    }
+
+    // keys[i].time <= time < keys[i + 1].time
+    let i = keys.partition_point(|k| k.time <= time) - 1;
+    let (a, b) = (&keys[i], &keys[i + 1]);
+    let h = b.time - a.time;
+    let s = (time - a.time) / h;
+
+    let tangent = |j: usize, f: fn(&Keyframe) -> Vec3| {
+      let prev = &keys[j.saturating_sub(1)];
+      let next = &keys[(j + 1).min(keys.len() - 1)];
+      (f(next) - f(prev)) / (next.time - prev.time)
+    };
+    let spline = |f: fn(&Keyframe) -> Vec3| {
+      hermite(f(a), tangent(i, f) * h, f(b), tangent(i + 1, f) * h, s)
+    };
+
+    Some(Keyframe {
+      time,
+      position: spline(|k| k.position),
+      target: spline(|k| k.target),
+      orientation: a.orientation.slerp(b.orientation, s),
+    })
+  }
+}
+
+// cubic hermite between p0 and p1 with tangents m0 and m1, s in 0..1
+fn hermite(p0: Vec3, m0: Vec3, p1: Vec3, m1: Vec3, s: f32) -> Vec3 {
+  let (s2, s3) = (s * s, s * s * s);
+  p0 * (2.0 * s3 - 3.0 * s2 + 1.0)
+    + m0 * (s3 - 2.0 * s2 + s)
+    + p1 * (-2.0 * s3 + 3.0 * s2)
+    + m1 * (s3 - s2)
+}
+
+// Plays a path back as a camera controller. With a fixed step every
+// update advances the same amount regardless of the frame time, so
+// runs are repeatable frame by frame, e.g. for screenshots.
+#[derive(Clone, Debug)]
+pub struct PathPlayer {
+  path: CameraPath,
+  time: f32,
+  pub looping: bool,
+  pub fixed_step: Option<Duration>,
+  pub paused: bool,
+}
+
+impl PathPlayer {
+  pub fn new(path: CameraPath) -> Self {
+    let time = path.keyframes().first().map_or(0.0, |k| k.time);
+    Self {
+      path,
+      time,
+      looping: false,
+      fixed_step: None,
+      paused: false,
+    }
+  }
+
+  pub fn looping(mut self, looping: bool) -> Self {
+    self.looping = looping;
+    self
+  }
+
+  pub fn with_fixed_step(mut self, step: Duration) -> Self {
+    self.fixed_step = Some(step);
+    self
+  }
+
+  pub fn path(&self) -> &CameraPath {
+    &self.path
+  }
+
+  pub fn time(&self) -> f32 {
+    self.time
+  }
+
+  pub fn seek(&mut self, time: f32) {
+    self.time = time;
+  }
+
+  pub fn is_finished(&self) -> bool {
+    !self.looping && self.time >= self.path.duration()
+  }
+}
+
+impl CameraController for PathPlayer {
+  // the path is in control, input is ignored
+  fn drag(&mut self, _from: Vec2, _to: Vec2, _viewport: Vec2) {}
+
+  fn zoom(&mut self, _amount: f32) {}
+
+  fn focus(&mut self, _target: Vec3, _distance: f32) {}
+
+  fn update(&mut self, dt: Duration) -> bool {
+    if self.paused || self.path.is_empty() || self.is_finished() {
+      return false;
+    }
+
+    self.time += self.fixed_step.unwrap_or(dt).as_secs_f32();
+    let duration = self.path.duration();
+    if self.looping && duration > 0.0 {
+      self.time %= duration;
+    }
+    true
+  }
+
+  fn apply(&self, camera: &mut Camera) {
+    let Some(key) = self.path.sample(self.time) else {
+      return;
+    };
+    camera.set_view(key.view());
+    camera.set_focus_distance(key.position.distance(key.target));
+  }
+}
+
+// Samples the camera while it is being moved around, e.g. with the
+// orbit controls. A keyframe is taken at most every `interval`, and
+// only if the camera moved since the last one; pauses are kept as
+// holds on the timeline.
+#[derive(Clone, Debug)]
+pub struct PathRecorder {
+  path: CameraPath,
+  time: f32,
+  interval: f32,
+  // when the camera was last checked for movement
+  last: Option<f32>,
+}
+
+impl PathRecorder {
+  pub fn new(interval: Duration) -> Self {
+    Self {
+      path: CameraPath::new(),
+      time: 0.0,
+      interval: interval.as_secs_f32(),
+      last: None,
+    }
+  }
+
+  pub fn update(&mut self, dt: Duration, camera: &Camera) {
+    let due = self.last.is_none_or(|t| self.time - t >= self.interval);
+    if due {
+      let key = Keyframe::from_camera(self.time, camera);
+      let moved =
+        self.path.keyframes().last().is_none_or(|last| {
+          !last.view().abs_diff_eq(key.view(), f32::EPSILON)
+        });
+      if moved {
+        // the camera stood still at the previous check, hold it there
+        // instead of drifting towards the new pose all along
+        if let (Some(&last), Some(checked)) =
+          (self.path.keyframes().last(), self.last)
+        {
+          if checked > last.time {
+            self.path.insert(Keyframe {
+              time: checked,
+              ..last
+            });
+          }
+        }
+        self.path.insert(key);
+      }
+      self.last = Some(self.time);
+    }
+    self.time += dt.as_secs_f32();
+  }
+
+  pub fn finish(self) -> CameraPath {
+    self.path
+  }
+}
+
+// how often a keyframe is taken while recording
+const RECORD_INTERVAL: Duration = Duration::from_millis(250);
+// one frame at the rate paths are recorded at, whatever the frame time
+// during playback, so every run shows the same frames
+const PLAYBACK_STEP: Duration = crate::app::TARGET_FRAME_TIME;
+
+// what CameraPaths::update did to the camera
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Playback {
+  // nothing plays, the controls have the camera
+  Idle,
+  Moved,
+  // the last step of the path, the controls take over from here
+  Ended,
+}
+
+// The 'k' and 'l' keys of the projects: 'k' starts and stops recording
+// the camera into a file, whatever controls move it, and 'l' plays the
+// file back. While a path plays it overrides the controls.
+pub struct CameraPaths {
+  file: PathBuf,
+  player: Option<PathPlayer>,
+  recorder: Option<PathRecorder>,
+}
+
+impl CameraPaths {
+  pub fn new(file: impl Into<PathBuf>) -> Self {
+    Self {
+      file: file.into(),
+      player: None,
+      recorder: None,
+    }
+  }
+
+  // returns whether the key was used
+  pub fn handle_key(&mut self, event: &KeyEvent) -> bool {
+    if !event.state.is_pressed() {
+      return false;
+    }
+    if event.logical_key == "k" {
+      self.toggle_recording();
+    } else if event.logical_key == "l" {
+      self.play();
+    } else {
+      return false;
+    }
+    true
+  }
+
+  pub fn toggle_recording(&mut self) {
+    let Some(recorder) = self.recorder.take() else {
+      println!("Recording camera path");
+      self.recorder = Some(PathRecorder::new(RECORD_INTERVAL));
+      return;
+    };
+
+    let path = recorder.finish();
+    match path.save(&self.file) {
+      Ok(()) => println!(
+        "Saved {} keyframes to {}",
+        path.keyframes().len(),
+        self.file.display()
+      ),
+      Err(e) => eprintln!("Failed to save camera path: {}", e),
+    }
+  }
+
+  pub fn play(&mut self) {
+    let path = match CameraPath::load(&self.file) {
+      Ok(path) => path,
+      Err(e) => {
+        eprintln!("Failed to load camera path: {}", e);
+        return;
+      }
+    };
+    println!("Playing camera path ({:.1}s)", path.duration());
+
+    self.player = Some(PathPlayer::new(path).with_fixed_step(PLAYBACK_STEP));
+  }
+
+  // hands the camera back to the controls
+  pub fn stop(&mut self) {
+    self.player = None;
+  }
+
+  // the controller to apply instead of the controls while a path plays
+  pub fn player(&self) -> Option<&PathPlayer> {
+    self.player.as_ref()
+  }
+
+  // before the camera moves for the frame: keyframes are taken at the
+  // start of the time they cover. a path that ended is let go the frame
+  // after Ended, so the camera is applied at its end once more.
+  pub fn update(&mut self, dt: Duration, camera: &Camera) -> Playback {
+    if let Some(recorder) = &mut self.recorder {
+      recorder.update(dt, camera);
+    }
+
+    let Some(player) = &mut self.player else {
+      return Playback::Idle;
+    };
+    if player.is_finished() {
+      self.player = None;
+      return Playback::Idle;
+    }
+    player.update(dt);
+    if player.is_finished() {
+      Playback::Ended
+    } else {
+      Playback::Moved
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::camera::Projection;
+
+  fn key(time: f32, position: Vec3) -> Keyframe {
+    Keyframe {
+      time,
+      position,
+      target: Vec3::ZERO,
+      orientation: Quat::IDENTITY,
+    }
+  }
+
+  // unevenly spaced, so the tangent scaling matters
+  fn uneven() -> CameraPath {
+    let mut path = CameraPath::new();
+    path.insert(key(0.0, Vec3::new(4.0, 1.0, 0.0)));
+    path.insert(key(0.5, Vec3::new(0.0, 2.0, 4.0)));
+    path.insert(key(2.0, Vec3::new(-4.0, 1.0, 0.0)));
+    path.insert(key(2.25, Vec3::new(0.0, 3.0, -4.0)));
+    path
+  }
+
+  fn is_finite(key: &Keyframe) -> bool {
+    key.position.is_finite()
+      && key.target.is_finite()
+      && key.orientation.is_finite()
+  }
+
+  #[test]
+  fn hermite_endpoints_and_tangents() {
+    let (p0, p1) = (Vec3::new(1.0, 2.0, 3.0), Vec3::new(-2.0, 0.0, 5.0));
+    let (m0, m1) = (Vec3::new(0.0, 4.0, 0.0), Vec3::new(1.0, 0.0, -1.0));
+    assert_eq!(hermite(p0, m0, p1, m1, 0.0), p0);
+    assert!(hermite(p0, m0, p1, m1, 1.0).abs_diff_eq(p1, 1e-6));
+
+    let e = 1e-3;
+    let start = (hermite(p0, m0, p1, m1, e) - p0) / e;
+    let end = (p1 - hermite(p0, m0, p1, m1, 1.0 - e)) / e;
+    assert!(start.abs_diff_eq(m0, 0.05), "{start}");
+    assert!(end.abs_diff_eq(m1, 0.05), "{end}");
+
+    // tangents along the chord give a straight, even line
+    let chord = p1 - p0;
+    let mid = hermite(p0, chord, p1, chord, 0.5);
+    assert!(mid.abs_diff_eq(p0.lerp(p1, 0.5), 1e-6));
+  }
+
+  #[test]
+  fn sample_hits_keyframes() {
+    let path = uneven();
+    for key in path.keyframes() {
+      let sampled = path.sample(key.time).unwrap();
+      assert!(sampled.position.abs_diff_eq(key.position, 1e-5));
+      assert!(sampled.target.abs_diff_eq(key.target, 1e-5));
+      assert_eq!(sampled.time, key.time);
+    }
+  }
+
+  #[test]
+  fn sample_clamps_outside_the_timeline() {
+    let path = uneven();
+    let keys = path.keyframes();
+    let (first, last) = (keys[0], keys[keys.len() - 1]);
+    assert_eq!(
+      path.sample(-1.0),
+      Some(Keyframe {
+        time: -1.0,
+        ..first
+      })
+    );
+    assert_eq!(path.sample(10.0), Some(Keyframe { time: 10.0, ..last }));
+    assert_eq!(CameraPath::new().sample(0.0), None);
+  }
+
+  #[test]
+  fn sample_is_continuous() {
+    let path = uneven();
+    let mut prev = path.sample(0.0).unwrap().position;
+    for i in 1..=225 {
+      let position = path.sample(i as f32 * 0.01).unwrap().position;
+      assert!(position.distance(prev) < 0.5, "jump at step {i}");
+      prev = position;
+    }
+  }
+
+  #[test]
+  fn loaded_paths_are_sorted_and_finite() {
+    // unsorted, with a pair and a triple of keyframes at the same time,
+    // as a hand edited file may have
+    let path = CameraPath {
+      keyframes: vec![
+        key(3.0, Vec3::new(0.0, 1.0, 5.0)),
+        key(1.0, Vec3::new(5.0, 1.0, 0.0)),
+        key(0.0, Vec3::new(4.0, 1.0, 4.0)),
+        key(1.0, Vec3::new(5.0, 2.0, 0.0)),
+        key(2.0, Vec3::new(-5.0, 1.0, 0.0)),
+        key(2.0, Vec3::new(-5.0, 2.0, 0.0)),
+        key(2.0, Vec3::new(-5.0, 3.0, 0.0)),
+        key(3.001, Vec3::new(0.0, 1.0, -5.0)),
+      ],
+    };
+    let file = std::env::temp_dir()
+      .join(format!("cs5610-camera-path-{}.ron", std::process::id()));
+    path.save(&file).unwrap();
+    let loaded = CameraPath::load(&file).unwrap();
+    std::fs::remove_file(&file).unwrap();
+
+    let times: Vec<f32> = loaded.keyframes().iter().map(|k| k.time).collect();
+    assert_eq!(times, [0.0, 1.0, 1.0, 2.0, 2.0, 2.0, 3.0, 3.001]);
+    assert_eq!(loaded.duration(), 3.001);
+    for i in -10..=320 {
+      let time = i as f32 * 0.01;
+      let key = loaded.sample(time).unwrap();
+      assert!(is_finite(&key), "{key:?} at {time}");
+    }
+  }
+
+  #[test]
+  fn recorder_holds_pauses() {
+    let step = Duration::from_millis(250);
+    let projection = Projection::default();
+    let still =
+      Camera::look_at(Vec3::new(0.0, 1.0, 5.0), Vec3::ZERO, projection);
+    let moved =
+      Camera::look_at(Vec3::new(5.0, 1.0, 0.0), Vec3::ZERO, projection);
+
+    let mut recorder = PathRecorder::new(step);
+    for _ in 0..3 {
+      recorder.update(step, &still);
+    }
+    recorder.update(step, &moved);
+    let path = recorder.finish();
+
+    let times: Vec<f32> = path.keyframes().iter().map(|k| k.time).collect();
+    assert_eq!(times, [0.0, 0.5, 0.75]);
+    let keys = path.keyframes();
+    assert!(keys[0].view().abs_diff_eq(keys[1].view(), 1e-6));
+    assert!(keys[2].view().abs_diff_eq(moved.view(), 1e-5));
+  }
+
+  #[test]
+  fn recorder_skips_holds_while_moving() {
+    let step = Duration::from_millis(250);
+    let mut recorder = PathRecorder::new(step);
+    for i in 0..4 {
+      let eye = Vec3::new(i as f32, 1.0, 5.0);
+      let camera = Camera::look_at(eye, Vec3::ZERO, Projection::default());
+      recorder.update(step, &camera);
+    }
+    let path = recorder.finish();
+
+    let times: Vec<f32> = path.keyframes().iter().map(|k| k.time).collect();
+    assert_eq!(times, [0.0, 0.25, 0.5, 0.75]);
+  }
+
+  #[test]
+  fn player_loops() {
+    let step = Duration::from_millis(500);
+    let mut player = PathPlayer::new(uneven()).looping(true);
+    for _ in 0..5 {
+      assert!(player.update(step));
+    }
+    // 2.5 seconds into a 2.25 second path
+    assert!((player.time() - 0.25).abs() < 1e-6, "{}", player.time());
+    assert!(!player.is_finished());
+  }
+
+  #[test]
+  fn player_stops_at_the_end() {
+    let mut player = PathPlayer::new(uneven());
+    let step = Duration::from_secs(1);
+    assert!(player.update(step));
+    assert!(player.update(step));
+    assert!(player.update(step));
+    assert!(player.is_finished());
+    assert!(!player.update(step));
+
+    let mut camera = Camera::default();
+    player.apply(&mut camera);
+    let last = player.path().keyframes().last().unwrap();
+    assert!(camera.position().abs_diff_eq(last.position, 1e-5));
+  }
+
+  #[test]
+  fn fixed_step_ignores_the_frame_time() {
+    let step = Duration::from_millis(40);
+    let mut a = PathPlayer::new(uneven()).with_fixed_step(step);
+    let mut b = PathPlayer::new(uneven()).with_fixed_step(step);
+    let (mut camera_a, mut camera_b) = (Camera::default(), Camera::default());
+    for i in 0..60 {
+      // one runs smoothly, the other stutters
+      a.update(Duration::from_millis(16));
+      b.update(Duration::from_millis(if i % 3 == 0 { 100 } else { 5 }));
+      a.apply(&mut camera_a);
+      b.apply(&mut camera_b);
+      assert_eq!(a.time(), b.time());
+      assert_eq!(camera_a.view(), camera_b.view());
+    }
+  }
+
+  #[test]
+  fn camera_paths_record_and_play() {
+    let file = std::env::temp_dir()
+      .join(format!("cs5610-camera-paths-{}.ron", std::process::id()));
+    let mut paths = CameraPaths::new(&file);
+    paths.toggle_recording();
+    for i in 0..4 {
+      let eye = Vec3::new(i as f32, 1.0, 5.0);
+      let camera = Camera::look_at(eye, Vec3::ZERO, Projection::default());
+      assert_eq!(paths.update(RECORD_INTERVAL, &camera), Playback::Idle);
+    }
+    paths.toggle_recording();
+
+    paths.play();
+    std::fs::remove_file(&file).unwrap();
+    let camera = Camera::default();
+    let mut frames = 0;
+    while paths.update(Duration::from_secs(1), &camera) == Playback::Moved {
+      frames += 1;
+    }
+    // 0.75 seconds at the fixed step, whatever the frame time. the steps
+    // add up to just under or over it, that decides on the last one.
+    assert!((44..=45).contains(&frames), "{frames}");
+    assert!(paths.player().is_some());
+    assert_eq!(paths.update(PLAYBACK_STEP, &camera), Playback::Idle);
+    assert!(paths.player().is_none());
+  }
+}
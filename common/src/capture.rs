@@ -0,0 +1,335 @@
+// Reading back what was rendered and writing it to image files, for bug
+// reports and demo videos. Color goes to PNG (or whatever the extension
+// says), depth to PFM or EXR to keep the floats as they are.
+
+use std::{
+  fs::File,
+  io::{BufWriter, Write},
+  path::{Path, PathBuf},
+  time::Duration,
+};
+
+use anyhow::{bail, Context as _};
+use glium::{
+  backend::Facade,
+  framebuffer::SimpleFrameBuffer,
+  texture::{
+    CubeLayer, Cubemap, DepthTexture2d, MipmapsOption, RawImage2d,
+    UncompressedFloatFormat,
+  },
+  uniform,
+  uniforms::{MagnifySamplerFilter, MinifySamplerFilter},
+  vertex::EmptyVertexAttributes,
+  BlitTarget, DrawParameters, Program, Surface, Texture2d,
+};
+use image::{ImageBuffer, Luma, Rgb32FImage, RgbaImage};
+
+// where the screenshots and sequences go, relative to where the project
+// was started
+pub const CAPTURE_DIR: &str = "captures";
+
+// one float per pixel, top row first like the color images
+pub type DepthImage = ImageBuffer<Luma<f32>, Vec<f32>>;
+
+// the order of the faces everywhere in the projects
+pub const CUBE_FACES: [(CubeLayer, &str); 6] = [
+  (CubeLayer::PositiveX, "posx"),
+  (CubeLayer::NegativeX, "negx"),
+  (CubeLayer::PositiveY, "posy"),
+  (CubeLayer::NegativeY, "negy"),
+  (CubeLayer::PositiveZ, "posz"),
+  (CubeLayer::NegativeZ, "negz"),
+];
+
+pub fn read_color(texture: &Texture2d) -> anyhow::Result<RgbaImage> {
+  from_raw(texture.read())
+}
+
+// glium only reads back 8 bit color, so the depth is drawn into a color
+// texture with the bits of each float spread over the four channels
+pub fn read_depth(
+  facade: &impl Facade,
+  depth: &DepthTexture2d,
+) -> anyhow::Result<DepthImage> {
+  let (width, height) = depth.dimensions();
+  let packed = Texture2d::empty_with_format(
+    facade,
+    UncompressedFloatFormat::U8U8U8U8,
+    MipmapsOption::NoMipmap,
+    width,
+    height,
+  )?;
+  let program = Program::from_source(
+    facade,
+    FULLSCREEN_VERT_SHADER,
+    PACK_DEPTH_FRAG_SHADER,
+    None,
+  )?;
+  let uniforms = uniform! {
+    depth: depth
+      .sampled()
+      .minify_filter(MinifySamplerFilter::Nearest)
+      .magnify_filter(MagnifySamplerFilter::Nearest),
+  };
+  SimpleFrameBuffer::new(facade, &packed)?.draw(
+    EmptyVertexAttributes { len: 4 },
+    glium::index::NoIndices(glium::index::PrimitiveType::TriangleStrip),
+    &program,
+    &uniforms,
+    &DrawParameters::default(),
+  )?;
+
+  let packed = read_color(&packed)?;
+  let data = packed.pixels().map(|pixel| unpack_depth(pixel.0)).collect();
+  DepthImage::from_raw(width, height, data).context("depth size mismatch")
+}
+
+// in the order of CUBE_FACES, through a 2d texture as glium can't read
+// cubemaps directly
+pub fn read_cubemap(
+  facade: &impl Facade,
+  cubemap: &Cubemap,
+) -> anyhow::Result<Vec<RgbaImage>> {
+  let size = cubemap.get_width();
+  let full_rect = BlitTarget {
+    left: 0,
+    bottom: 0,
+    width: size as i32,
+    height: size as i32,
+  };
+
+  CUBE_FACES
+    .iter()
+    .map(|&(layer, _)| {
+      let face = cubemap.main_level().image(layer);
+      let source = SimpleFrameBuffer::new(facade, face)?;
+      let texture = Texture2d::empty(facade, size, size)?;
+      source.blit_whole_color_to(
+        &texture.as_surface(),
+        &full_rect,
+        MagnifySamplerFilter::Nearest,
+      );
+      read_color(&texture)
+    })
+    .collect()
+}
+
+pub fn save_color(
+  path: impl AsRef<Path>,
+  image: &RgbaImage,
+) -> anyhow::Result<()> {
+  let path = path.as_ref();
+  create_parent(path)?;
+  image
+    .save(path)
+    .with_context(|| format!("writing {}", path.display()))
+}
+
+// .pfm or .exr
+pub fn save_depth(
+  path: impl AsRef<Path>,
+  image: &DepthImage,
+) -> anyhow::Result<()> {
+  let path = path.as_ref();
+  create_parent(path)?;
+  let result = match path.extension().and_then(|ext| ext.to_str()) {
+    Some("pfm") => write_pfm(path, image),
+    Some("exr") => {
+      // the EXR encoder only takes RGB or RGBA
+      let rgb = Rgb32FImage::from_fn(image.width(), image.height(), |x, y| {
+        let depth = image.get_pixel(x, y).0[0];
+        image::Rgb([depth; 3])
+      });
+      rgb.save(path).map_err(anyhow::Error::from)
+    }
+    _ => bail!("depth can only be saved as .pfm or .exr"),
+  };
+  result.with_context(|| format!("writing {}", path.display()))
+}
+
+// posx.png, negx.png and so on in the directory
+pub fn save_cubemap(
+  dir: impl AsRef<Path>,
+  facade: &impl Facade,
+  cubemap: &Cubemap,
+) -> anyhow::Result<()> {
+  let faces = read_cubemap(facade, cubemap)?;
+  for ((_, name), face) in CUBE_FACES.iter().zip(&faces) {
+    save_color(dir.as_ref().join(format!("{}.png", name)), face)?;
+  }
+  Ok(())
+}
+
+// A numbered image per frame, with the simulation stepped by the same
+// time for every frame however long rendering takes. Playing the frames
+// at the given rate gives the real speed back.
+pub struct Sequence {
+  dir: PathBuf,
+  step: Duration,
+  frame: u32,
+  // stepped but not saved yet
+  pending: bool,
+}
+
+impl Sequence {
+  // into the first sequence_N directory that doesn't exist yet
+  pub fn start(fps: u32) -> anyhow::Result<Self> {
+    if fps == 0 {
+      bail!("a sequence needs at least one frame per second");
+    }
+    let dir = next_free_path(|n| format!("sequence_{}", n));
+    std::fs::create_dir_all(&dir)
+      .with_context(|| format!("creating {}", dir.display()))?;
+    Ok(Self {
+      dir,
+      step: Duration::from_secs(1) / fps,
+      frame: 0,
+      pending: false,
+    })
+  }
+
+  // the time to advance the simulation by, once before every frame
+  pub fn next_step(&mut self) -> Duration {
+    self.pending = true;
+    self.step
+  }
+
+  // whether a frame is due since the last step
+  pub fn pending(&self) -> bool {
+    self.pending
+  }
+
+  pub fn dir(&self) -> &Path {
+    &self.dir
+  }
+
+  pub fn frames(&self) -> u32 {
+    self.frame
+  }
+
+  pub fn save_frame(&mut self, image: &RgbaImage) -> anyhow::Result<()> {
+    let path = self.dir.join(format!("{:05}.png", self.frame));
+    save_color(path, image)?;
+    self.frame += 1;
+    self.pending = false;
+    Ok(())
+  }
+}
+
+// screenshot_N.png, numbered so earlier ones are kept
+pub fn screenshot_path() -> PathBuf {
+  next_free_path(|n| format!("screenshot_{}.png", n))
+}
+
+fn next_free_path(name: impl Fn(u32) -> String) -> PathBuf {
+  (0..)
+    .map(|n| Path::new(CAPTURE_DIR).join(name(n)))
+    .find(|path| !path.exists())
+    .unwrap()
+}
+
+fn create_parent(path: &Path) -> anyhow::Result<()> {
+  match path.parent() {
+    Some(dir) if !dir.as_os_str().is_empty() => std::fs::create_dir_all(dir)
+      .with_context(|| format!("creating {}", dir.display())),
+    _ => Ok(()),
+  }
+}
+
+// GL reads bottom up, images go top down
+fn from_raw(raw: RawImage2d<u8>) -> anyhow::Result<RgbaImage> {
+  let image = RgbaImage::from_raw(raw.width, raw.height, raw.data.into_owned())
+    .context("unexpected pixel data size")?;
+  Ok(image::imageops::flip_vertical(&image))
+}
+
+// the bytes PACK_DEPTH_FRAG_SHADER spread a float over, red first
+fn unpack_depth(rgba: [u8; 4]) -> f32 {
+  f32::from_bits(u32::from_le_bytes(rgba))
+}
+
+fn write_pfm(path: &Path, image: &DepthImage) -> anyhow::Result<()> {
+  let mut out = BufWriter::new(File::create(path)?);
+  encode_pfm(&mut out, image)?;
+  out.flush()?;
+  Ok(())
+}
+
+// Portable float map: a text header and the rows bottom up. A negative
+// scale means little endian.
+fn encode_pfm(out: &mut impl Write, image: &DepthImage) -> std::io::Result<()> {
+  write!(out, "Pf\n{} {}\n-1.0\n", image.width(), image.height())?;
+  for y in (0..image.height()).rev() {
+    for x in 0..image.width() {
+      out.write_all(&image.get_pixel(x, y).0[0].to_le_bytes())?;
+    }
+  }
+  Ok(())
+}
+
+// a quad over the whole target, without any vertex buffer
+const FULLSCREEN_VERT_SHADER: &str = r#"
+#version 330 core
+
+void main() {
+  vec2 corner = vec2(gl_VertexID & 1, gl_VertexID >> 1);
+  gl_Position = vec4(corner * 2.0 - 1.0, 0.0, 1.0);
+}
+"#;
+
+const PACK_DEPTH_FRAG_SHADER: &str = r#"
+#version 330 core
+
+uniform sampler2D depth;
+
+layout(location = 0) out vec4 color;
+
+void main() {
+  float d = texelFetch(depth, ivec2(gl_FragCoord.xy), 0).r;
+  uint bits = floatBitsToUint(d);
+  uvec4 bytes = uvec4(bits, bits >> 8, bits >> 16, bits >> 24) & 0xFFu;
+  color = vec4(bytes) / 255.0;
+}
+"#;
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // what PACK_DEPTH_FRAG_SHADER writes for a depth value
+  fn pack_depth(d: f32) -> [u8; 4] {
+    let bits = d.to_bits();
+    [bits, bits >> 8, bits >> 16, bits >> 24].map(|b| (b & 0xFF) as u8)
+  }
+
+  #[test]
+  fn depth_round_trip() {
+    for d in [0.0, 1.0, 0.5, 1e-7, 0.999_999_9, -0.0, f32::MIN_POSITIVE] {
+      assert_eq!(unpack_depth(pack_depth(d)).to_bits(), d.to_bits());
+    }
+  }
+
+  #[test]
+  fn pfm_header_and_rows() {
+    // two columns, three rows, top row first
+    let image = DepthImage::from_fn(2, 3, |x, y| Luma([(y * 2 + x) as f32]));
+    let mut bytes = Vec::new();
+    encode_pfm(&mut bytes, &image).unwrap();
+
+    // the negative scale says little endian
+    let header = b"Pf\n2 3\n-1.0\n";
+    assert_eq!(&bytes[..header.len()], header);
+
+    let values: Vec<f32> = bytes[header.len()..]
+      .chunks_exact(4)
+      .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+      .collect();
+    // the bottom row comes first
+    assert_eq!(values, [4.0, 5.0, 2.0, 3.0, 0.0, 1.0]);
+  }
+
+  #[test]
+  fn sequence_needs_a_frame_rate() {
+    assert!(Sequence::start(0).is_err());
+  }
+}
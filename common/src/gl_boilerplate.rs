@@ -1,64 +1,629 @@
-use glium::{glutin::surface::WindowSurface, Display};
+use std::{
+  ffi::{c_void, CString},
+  fmt::Write as _,
+  num::NonZeroU32,
+  rc::Rc,
+};
+
+use anyhow::{anyhow, bail, Context as _};
+use glium::{
+  backend::{Backend, Context, Facade},
+  debug::DebugCallbackBehavior,
+  framebuffer::SimpleFrameBuffer,
+  glutin::{
+    api::egl::{self, device::Device},
+    config::{ConfigSurfaceTypes, ConfigTemplateBuilder, GlConfig},
+    context::{
+      ContextApi, ContextAttributes, ContextAttributesBuilder, GlProfile,
+      NotCurrentGlContext, PossiblyCurrentGlContext, Version,
+    },
+    display::{DisplayApiPreference, GetGlDisplay, GlDisplay},
+    surface::{
+      GlSurface, SurfaceAttributesBuilder, SwapInterval, WindowSurface,
+    },
+  },
+  texture::{
+    DepthFormat, DepthTexture2d, MipmapsOption, UncompressedFloatFormat,
+  },
+  Api, Display, IncompatibleOpenGl, SwapBuffersError, Texture2d,
+  Version as GlVersion,
+};
 use winit::{
-  raw_window_handle::{HasDisplayHandle, HasWindowHandle},
+  raw_window_handle::{HasDisplayHandle, HasWindowHandle, RawWindowHandle},
   window::Window,
 };
 
+use crate::camera::DepthMode;
+
 // I would use glutin::SimpleWindowBuilder but it has no way to turn
 // on debug with the public API.
 pub fn init_display(window: &Window) -> Display<WindowSurface> {
-  use glium::{
-    debug::DebugCallbackBehavior,
-    glutin::{
-      context::NotCurrentGlContext,
-      display::{DisplayApiPreference, GlDisplay as _},
-      surface::SurfaceAttributesBuilder,
-    },
-  };
-
-  let display_handle = window.display_handle().unwrap().as_raw();
-  let window_handle = window.window_handle().unwrap().as_raw();
+  init_display_with(window, DepthMode::Standard)
+}
 
-  let disp = unsafe {
-    glium::glutin::display::Display::new(
-      display_handle,
-      DisplayApiPreference::Egl,
-    )
+// the depth mode picks the depth buffer, the draws have to use
+// supported_depth_mode for the clip space to match
+pub fn init_display_with(
+  window: &Window,
+  depth_mode: DepthMode,
+) -> Display<WindowSurface> {
+  ContextOptions::new()
+    .depth_mode(depth_mode)
+    .init_display(window)
     .expect("Failed to create display")
-  };
+}
+
+// An OpenGL context without a window, for tests and batch jobs. Runs
+// on whatever EGL device there is, including Mesa's llvmpipe when
+// there is no GPU (LIBGL_ALWAYS_SOFTWARE=1 forces it). The target is
+// what the window would have been.
+pub fn init_headless(
+  width: u32,
+  height: u32,
+) -> anyhow::Result<(Headless, OffscreenTarget)> {
+  ContextOptions::headless().init_headless(width, height)
+}
+
+pub fn init_headless_with(
+  width: u32,
+  height: u32,
+  depth_mode: DepthMode,
+) -> anyhow::Result<(Headless, OffscreenTarget)> {
+  ContextOptions::headless()
+    .depth_mode(depth_mode)
+    .init_headless(width, height)
+}
+
+// A facade over a context that is current without any surface, the
+// way Display is one over a window. glium had its own until 0.32.
+pub struct Headless {
+  context: Rc<Context>,
+}
+
+impl Headless {
+  // the dimensions are those of the offscreen target, as there is no
+  // default framebuffer to take them from
+  fn new(
+    context: egl::context::PossiblyCurrentContext,
+    dimensions: (u32, u32),
+    debug: DebugCallbackBehavior,
+  ) -> Result<Self, IncompatibleOpenGl> {
+    let backend = SurfacelessBackend {
+      context,
+      dimensions,
+    };
+    let context = unsafe { Context::new(backend, true, debug)? };
+    Ok(Self { context })
+  }
+}
+
+impl Facade for Headless {
+  fn get_context(&self) -> &Rc<Context> {
+    &self.context
+  }
+}
+
+struct SurfacelessBackend {
+  context: egl::context::PossiblyCurrentContext,
+  dimensions: (u32, u32),
+}
+
+unsafe impl Backend for SurfacelessBackend {
+  // nothing is presented
+  fn swap_buffers(&self) -> Result<(), SwapBuffersError> {
+    Ok(())
+  }
+
+  unsafe fn get_proc_address(&self, symbol: &str) -> *const c_void {
+    let Ok(symbol) = CString::new(symbol) else {
+      return std::ptr::null();
+    };
+    self.context.display().get_proc_address(&symbol)
+  }
+
+  fn get_framebuffer_dimensions(&self) -> (u32, u32) {
+    self.dimensions
+  }
+
+  fn resize(&self, _new_size: (u32, u32)) {}
+
+  fn is_current(&self) -> bool {
+    self.context.is_current()
+  }
+
+  unsafe fn make_current(&self) {
+    if let Err(e) = self.context.make_current_surfaceless() {
+      eprintln!("Failed to make the headless context current: {}", e);
+    }
+  }
+}
+
+// The platform GL APIs to try, in order. The ones that don't exist on
+// this platform are skipped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GlApi {
+  Egl,
+  Glx,
+  Wgl,
+  Cgl,
+}
+
+impl GlApi {
+  #[allow(unused_variables)]
+  fn preference(self, window: RawWindowHandle) -> Option<DisplayApiPreference> {
+    match self {
+      Self::Egl => Some(DisplayApiPreference::Egl),
+      #[cfg(target_os = "linux")]
+      Self::Glx => Some(DisplayApiPreference::Glx(Box::new(
+        winit::platform::x11::register_xlib_error_hook,
+      ))),
+      #[cfg(windows)]
+      Self::Wgl => Some(DisplayApiPreference::Wgl(Some(window))),
+      #[cfg(target_os = "macos")]
+      Self::Cgl => Some(DisplayApiPreference::Cgl),
+      #[allow(unreachable_patterns)]
+      _ => None,
+    }
+  }
+}
+
+// What to ask of the GL context and its framebuffer. Configs rarely
+// match exactly, so the framebuffer wishes pick the closest config
+// instead of ruling configs out, and the choice is printed with what
+// it misses.
+#[derive(Clone, Debug)]
+pub struct ContextOptions {
+  version: Option<(u8, u8)>,
+  profile: Option<GlProfile>,
+  depth_bits: u8,
+  stencil_bits: u8,
+  samples: u8,
+  srgb: bool,
+  vsync: bool,
+  debug: bool,
+  apis: Vec<GlApi>,
+  depth_mode: DepthMode,
+}
+
+impl Default for ContextOptions {
+  fn default() -> Self {
+    Self {
+      version: None,
+      profile: None,
+      depth_bits: 24,
+      stencil_bits: 8,
+      samples: 0,
+      srgb: false,
+      vsync: true,
+      debug: true,
+      apis: vec![GlApi::Egl, GlApi::Glx, GlApi::Wgl, GlApi::Cgl],
+      depth_mode: DepthMode::Standard,
+    }
+  }
+}
+
+impl ContextOptions {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  // what init_headless can give: EGL, no stencil, no multisampling
+  pub fn headless() -> Self {
+    Self {
+      stencil_bits: 0,
+      apis: vec![GlApi::Egl],
+      ..Self::default()
+    }
+  }
+
+  // the newest the driver has if not given
+  pub fn gl_version(mut self, major: u8, minor: u8) -> Self {
+    self.version = Some((major, minor));
+    self
+  }
+
+  pub fn profile(mut self, profile: GlProfile) -> Self {
+    self.profile = Some(profile);
+    self
+  }
 
-  eprintln!("GL Version: {}", disp.version_string());
+  pub fn depth_bits(mut self, bits: u8) -> Self {
+    self.depth_bits = bits;
+    self
+  }
 
-  let config = unsafe {
-    disp
-      .find_configs(Default::default())
-      .expect("Failed to find configs")
+  pub fn stencil_bits(mut self, bits: u8) -> Self {
+    self.stencil_bits = bits;
+    self
+  }
+
+  // 0 turns multisampling off
+  pub fn samples(mut self, samples: u8) -> Self {
+    self.samples = samples;
+    self
+  }
+
+  pub fn srgb(mut self, srgb: bool) -> Self {
+    self.srgb = srgb;
+    self
+  }
+
+  pub fn vsync(mut self, vsync: bool) -> Self {
+    self.vsync = vsync;
+    self
+  }
+
+  // prints GL errors as they happen
+  pub fn debug(mut self, debug: bool) -> Self {
+    self.debug = debug;
+    self
+  }
+
+  // tried in order until one gives a context
+  pub fn apis(mut self, apis: &[GlApi]) -> Self {
+    self.apis = apis.to_vec();
+    self
+  }
+
+  pub fn depth_mode(mut self, depth_mode: DepthMode) -> Self {
+    self.depth_mode = depth_mode;
+    self
+  }
+
+  pub fn init_display(
+    &self,
+    window: &Window,
+  ) -> anyhow::Result<Display<WindowSurface>> {
+    let mut errors = String::new();
+    for &api in &self.apis {
+      match self.init_display_on(window, api) {
+        Ok(Some(display)) => return Ok(display),
+        Ok(None) => {}
+        Err(e) => writeln!(errors, "{:?}: {:#}", api, e)?,
+      }
+    }
+    Err(anyhow!("no GL API gave a context\n{}", errors))
+  }
+
+  // None if the API doesn't exist here
+  fn init_display_on(
+    &self,
+    window: &Window,
+    api: GlApi,
+  ) -> anyhow::Result<Option<Display<WindowSurface>>> {
+    let display_handle = window.display_handle()?.as_raw();
+    let window_handle = window.window_handle()?.as_raw();
+    let Some(preference) = api.preference(window_handle) else {
+      return Ok(None);
+    };
+
+    let disp = unsafe {
+      glium::glutin::display::Display::new(display_handle, preference)?
+    };
+    eprintln!("GL Version: {} ({:?})", disp.version_string(), api);
+
+    let template = ConfigTemplateBuilder::new()
+      .compatible_with_native_window(window_handle)
+      .build();
+    let configs = unsafe { disp.find_configs(template)? };
+    let config = self.choose_config(configs)?;
+
+    let context = unsafe {
+      disp.create_context(
+        &config,
+        &self.context_attributes(Some(window_handle)),
+      )?
+    };
+    let size = window.inner_size();
+    let surface_attr = SurfaceAttributesBuilder::<WindowSurface>::new()
+      .with_srgb(Some(self.srgb))
+      .build(
+        window_handle,
+        NonZeroU32::new(size.width).context("window has no width")?,
+        NonZeroU32::new(size.height).context("window has no height")?,
+      );
+    let surface =
+      unsafe { disp.create_window_surface(&config, &surface_attr)? };
+    let context = context.make_current(&surface)?;
+
+    let interval = match self.vsync {
+      true => SwapInterval::Wait(NonZeroU32::MIN),
+      false => SwapInterval::DontWait,
+    };
+    if let Err(e) = surface.set_swap_interval(&context, interval) {
+      eprintln!("Failed to set vsync to {}: {}", self.vsync, e);
+    }
+
+    let display = Display::with_debug(context, surface, self.debug_behavior())?;
+    Ok(Some(display))
+  }
+
+  // Headless contexts are always EGL and draw into the offscreen
+  // target, which takes the depth bits and the depth mode. What the
+  // target can't have is an error rather than quietly left out, and
+  // vsync doesn't matter with nothing presented.
+  pub fn init_headless(
+    &self,
+    width: u32,
+    height: u32,
+  ) -> anyhow::Result<(Headless, OffscreenTarget)> {
+    self.check_headless()?;
+    let mut error = anyhow!("no EGL device found");
+    for device in Device::query_devices().context("querying EGL devices")? {
+      match self.init_headless_on(&device, (width, height)) {
+        Ok(headless) => {
+          let target = self
+            .supported_by(&headless)
+            .offscreen_target(&headless, width, height)?;
+          return Ok((headless, target));
+        }
+        Err(e) => error = e,
+      }
+    }
+    Err(error)
+  }
+
+  fn check_headless(&self) -> anyhow::Result<()> {
+    if !self.apis.contains(&GlApi::Egl) {
+      bail!(
+        "headless contexts are EGL only, and EGL isn't in {:?}",
+        self.apis
+      );
+    }
+    if self.stencil_bits > 0 {
+      bail!(
+        "the offscreen target has no stencil buffer, {} stencil bits asked \
+         for",
+        self.stencil_bits
+      );
+    }
+    if self.samples > 0 {
+      bail!(
+        "the offscreen target isn't multisampled, {} samples asked for",
+        self.samples
+      );
+    }
+    if self.srgb {
+      bail!("the offscreen target isn't sRGB");
+    }
+    Ok(())
+  }
+
+  fn init_headless_on(
+    &self,
+    device: &Device,
+    dimensions: (u32, u32),
+  ) -> anyhow::Result<Headless> {
+    use glium::glutin::api::egl::display::Display as EglDisplay;
+
+    let disp = unsafe { EglDisplay::with_device(device, None)? };
+    eprintln!("GL Version: {}", disp.version_string());
+
+    // nothing is ever presented, any config that can render does
+    let template = ConfigTemplateBuilder::new()
+      .with_surface_type(ConfigSurfaceTypes::PBUFFER)
+      .build();
+    let config = unsafe { disp.find_configs(template)? }
       .next()
-      .expect("No config found")
-  };
-  let context = unsafe {
-    disp
-      .create_context(&config, &Default::default())
-      .expect("Failed to create context")
-  };
-  let surface_attr = SurfaceAttributesBuilder::<WindowSurface>::new().build(
-    window_handle,
-    window.inner_size().width.try_into().unwrap(),
-    window.inner_size().height.try_into().unwrap(),
-  );
-  let surface = unsafe {
-    disp
-      .create_window_surface(&config, &surface_attr)
-      .expect("Failed to create surface")
-  };
-  let context = context
-    .make_current(&surface)
-    .expect("Failed to make context current");
-
-  Display::with_debug(
-    context,
-    surface,
-    DebugCallbackBehavior::DebugMessageOnError,
-  )
-  .expect("Failed to create display")
+      .context("no EGL config found")?;
+    let context =
+      unsafe { disp.create_context(&config, &self.context_attributes(None))? };
+    let context = context.make_current_surfaceless()?;
+
+    Ok(Headless::new(context, dimensions, self.debug_behavior())?)
+  }
+
+  // what of these options the context can do, reversed-Z falls back to
+  // the standard depth mode without clip control
+  pub fn supported_by(&self, facade: &impl Facade) -> Self {
+    let depth_mode = supported_depth_mode(facade, self.depth_mode);
+    if depth_mode != self.depth_mode {
+      eprintln!(
+        "Reversed-Z needs OpenGL 4.5 or GL_ARB_clip_control, falling back \
+         to standard depth"
+      );
+    }
+    self.clone().depth_mode(depth_mode)
+  }
+
+  // window configs only come with integer depth, which reversed-Z gains
+  // nothing from, so its frames are drawn offscreen with float depth and
+  // copied to the window
+  pub fn draws_offscreen(&self) -> bool {
+    self.depth_mode == DepthMode::ReversedZ
+  }
+
+  // with the depth the context was made for
+  pub fn offscreen_target(
+    &self,
+    facade: &impl Facade,
+    width: u32,
+    height: u32,
+  ) -> anyhow::Result<OffscreenTarget> {
+    OffscreenTarget::with_depth_format(
+      facade,
+      width,
+      height,
+      self.depth_format(),
+    )
+  }
+
+  // the fewest bits that hold the depth bits, always float with
+  // reversed-Z
+  fn depth_format(&self) -> DepthFormat {
+    match (self.depth_mode, self.depth_bits) {
+      (DepthMode::ReversedZ, _) => DepthFormat::F32,
+      (_, 0..=16) => DepthFormat::I16,
+      (_, 17..=24) => DepthFormat::I24,
+      _ => DepthFormat::I32,
+    }
+  }
+
+  fn context_attributes(
+    &self,
+    window: Option<RawWindowHandle>,
+  ) -> ContextAttributes {
+    let version = self
+      .version
+      .map(|(major, minor)| Version::new(major, minor));
+    let mut builder = ContextAttributesBuilder::new()
+      .with_context_api(ContextApi::OpenGl(version))
+      .with_debug(self.debug);
+    if let Some(profile) = self.profile {
+      builder = builder.with_profile(profile);
+    }
+    builder.build(window)
+  }
+
+  fn debug_behavior(&self) -> DebugCallbackBehavior {
+    match self.debug {
+      true => DebugCallbackBehavior::DebugMessageOnError,
+      false => DebugCallbackBehavior::Ignore,
+    }
+  }
+
+  // Every wish weighs more than all the ones after it: depth, stencil,
+  // samples, sRGB. Among equals hardware acceleration wins, then the
+  // fewest bits beyond what was asked for.
+  fn choose_config<C: GlConfig>(
+    &self,
+    configs: impl Iterator<Item = C>,
+  ) -> anyhow::Result<C> {
+    let mut count = 0;
+    let (config, _) = configs
+      .inspect(|_| count += 1)
+      .map(|config| {
+        let misses = self.misses(&config);
+        let met = misses
+          .iter()
+          .enumerate()
+          .map(|(i, &missed)| if missed { 0 } else { 8 >> i })
+          .sum::<u32>();
+        let waste = (config.depth_size() as i32 - self.depth_bits as i32)
+          .max(0)
+          + (config.stencil_size() as i32 - self.stencil_bits as i32).max(0)
+          + (config.num_samples() as i32 - self.samples as i32).max(0);
+        let score = (met, config.hardware_accelerated(), -waste);
+        (config, score)
+      })
+      .max_by_key(|(_, score)| *score)
+      .context("no GL config found")?;
+
+    let misses = self.misses(&config);
+    let reason = if misses.iter().any(|&missed| missed) {
+      let names = ["depth bits", "stencil bits", "samples", "sRGB"];
+      let missed = names
+        .iter()
+        .zip(misses)
+        .filter(|(_, missed)| *missed)
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>();
+      format!("closest of {}, short on {}", count, missed.join(", "))
+    } else {
+      format!("best of {} with everything asked for", count)
+    };
+    eprintln!(
+      "GL config: depth {}, stencil {}, {} samples, sRGB {} ({})",
+      config.depth_size(),
+      config.stencil_size(),
+      config.num_samples(),
+      config.srgb_capable(),
+      reason
+    );
+    Ok(config)
+  }
+
+  // depth, stencil, samples and sRGB, true where the config falls short
+  fn misses(&self, config: &impl GlConfig) -> [bool; 4] {
+    [
+      config.depth_size() < self.depth_bits,
+      config.stencil_size() < self.stencil_bits,
+      config.num_samples() != self.samples,
+      self.srgb && !config.srgb_capable(),
+    ]
+  }
+}
+
+// A color and a depth texture to draw into instead of a window.
+pub struct OffscreenTarget {
+  pub color: Texture2d,
+  pub depth: DepthTexture2d,
+}
+
+impl OffscreenTarget {
+  pub fn new(
+    facade: &impl Facade,
+    width: u32,
+    height: u32,
+  ) -> anyhow::Result<Self> {
+    Self::with_depth_mode(facade, width, height, DepthMode::Standard)
+  }
+
+  pub fn with_depth_mode(
+    facade: &impl Facade,
+    width: u32,
+    height: u32,
+    depth_mode: DepthMode,
+  ) -> anyhow::Result<Self> {
+    Self::with_depth_format(facade, width, height, depth_mode.depth_format())
+  }
+
+  pub fn with_depth_format(
+    facade: &impl Facade,
+    width: u32,
+    height: u32,
+    depth_format: DepthFormat,
+  ) -> anyhow::Result<Self> {
+    let color = Texture2d::empty_with_format(
+      facade,
+      UncompressedFloatFormat::U8U8U8U8,
+      MipmapsOption::NoMipmap,
+      width,
+      height,
+    )?;
+    let depth = DepthTexture2d::empty_with_format(
+      facade,
+      depth_format,
+      MipmapsOption::NoMipmap,
+      width,
+      height,
+    )?;
+    Ok(Self { color, depth })
+  }
+
+  pub fn dimensions(&self) -> (u32, u32) {
+    self.color.dimensions()
+  }
+
+  // a surface to pass to the draw calls, like a frame
+  pub fn framebuffer(
+    &self,
+    facade: &impl Facade,
+  ) -> anyhow::Result<SimpleFrameBuffer<'_>> {
+    Ok(SimpleFrameBuffer::with_depth_buffer(
+      facade,
+      &self.color,
+      &self.depth,
+    )?)
+  }
+}
+
+// Reversed-Z wants depth in 0..1 instead of -1..1, which glium sets
+// per draw through the clip control draw parameter. Contexts without
+// clip control fall back to the standard depth mode.
+pub fn supported_depth_mode(
+  facade: &impl Facade,
+  depth_mode: DepthMode,
+) -> DepthMode {
+  if depth_mode == DepthMode::ReversedZ && !has_clip_control(facade) {
+    return DepthMode::Standard;
+  }
+  depth_mode
+}
+
+fn has_clip_control(facade: &impl Facade) -> bool {
+  let context = facade.get_context();
+  let GlVersion(api, major, minor) = *context.get_opengl_version();
+  (api == Api::Gl && (major, minor) >= (4, 5))
+    || context.get_extensions().gl_arb_clip_control
 }
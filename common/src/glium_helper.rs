@@ -1,67 +1,267 @@
 use std::{
   borrow::Cow,
+  cell::Ref,
   collections::{HashMap, HashSet},
   path::Path,
 };
 
+use anyhow::{anyhow, bail, Context};
+use glam::{Mat3, Mat4, Vec2, Vec3, Vec4};
 use glium::{
   backend::Facade,
+  program::SourceCode,
   texture::RawImage2d,
-  uniforms::{AsUniformValue, UniformValue, Uniforms},
+  uniforms::{UniformValue, Uniforms},
+  ProgramCreationError,
 };
 use image::RgbImage;
 
-#[derive(Default)]
+use crate::{
+  preprocessor::{Preprocessor, Source},
+  program_registry::ProgramHandle,
+};
+
+// as many as a material with all its maps has, with room to spare
+const INLINE_UNIFORMS: usize = 24;
+
+type Entry<'a> = (Cow<'a, str>, UniformValue<'a>);
+
+// Uniforms put together at run time. Names can be made up then, like
+// `lights[3].color`, and plain values are kept by value, so nothing has
+// to outlive the DynUniforms but textures and blocks. A name is kept
+// once, with the first value unless it is overridden. The first
+// INLINE_UNIFORMS are kept inline, so a draw that sets up its own
+// doesn't allocate, and only the ones past them go on the heap.
 pub struct DynUniforms<'a> {
-  uniforms: HashMap<Cow<'static, str>, UniformValue<'a>>,
+  inline: [Option<Entry<'a>>; INLINE_UNIFORMS],
+  spilled: Vec<Entry<'a>>,
+}
+
+impl Default for DynUniforms<'_> {
+  fn default() -> Self {
+    Self::new()
+  }
 }
 
 impl<'a> DynUniforms<'a> {
   pub fn new() -> Self {
     Self {
-      uniforms: Default::default(),
+      inline: [const { None }; INLINE_UNIFORMS],
+      spilled: Vec::new(),
     }
   }
 
-  pub fn add(&mut self, name: &'static str, value: &'a dyn AsUniformValue) {
-    if self.uniforms.contains_key(name) {
-      return;
+  // only what doesn't fit inline is reserved
+  pub fn with_capacity(capacity: usize) -> Self {
+    Self {
+      spilled: Vec::with_capacity(capacity.saturating_sub(INLINE_UNIFORMS)),
+      ..Self::new()
     }
-    self.add_override(name, value);
   }
 
-  pub fn add_raw(&mut self, name: &'static str, value: UniformValue<'a>) {
-    if self.uniforms.contains_key(name) {
-      return;
+  pub fn add(
+    &mut self,
+    name: impl Into<Cow<'a, str>>,
+    value: impl UniformData,
+  ) {
+    self.add_raw(name, value.to_uniform_value());
+  }
+
+  // for textures, samplers and blocks, which are borrowed
+  pub fn add_raw(
+    &mut self,
+    name: impl Into<Cow<'a, str>>,
+    value: UniformValue<'a>,
+  ) {
+    let name = name.into();
+    if self.value_mut(&name).is_none() {
+      self.push((name, value));
     }
-    self.add_raw_override(name, value);
   }
 
   pub fn add_override(
     &mut self,
-    name: &'static str,
-    value: &'a dyn AsUniformValue,
+    name: impl Into<Cow<'a, str>>,
+    value: impl UniformData,
   ) {
-    self.add_raw_override(name, value.as_uniform_value());
+    self.add_raw_override(name, value.to_uniform_value());
   }
 
   pub fn add_raw_override(
     &mut self,
-    name: &'static str,
+    name: impl Into<Cow<'a, str>>,
     value: UniformValue<'a>,
   ) {
-    self.uniforms.insert(Cow::Borrowed(name), value);
+    let name = name.into();
+    match self.value_mut(&name) {
+      Some(old) => *old = value,
+      None => self.push((name, value)),
+    }
+  }
+
+  fn entries(&self) -> impl Iterator<Item = &Entry<'a>> {
+    let inline = self.inline.iter().map_while(Option::as_ref);
+    inline.chain(&self.spilled)
+  }
+
+  // a few dozen at most, a hash wouldn't pay off
+  fn value_mut(&mut self, name: &str) -> Option<&mut UniformValue<'a>> {
+    let inline = self.inline.iter_mut().map_while(Option::as_mut);
+    inline
+      .chain(&mut self.spilled)
+      .find(|(n, _)| n == name)
+      .map(|(_, value)| value)
+  }
+
+  fn push(&mut self, entry: Entry<'a>) {
+    match self.inline.iter_mut().find(|slot| slot.is_none()) {
+      Some(slot) => *slot = Some(entry),
+      None => self.spilled.push(entry),
+    }
   }
 }
 
 impl Uniforms for DynUniforms<'_> {
   fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
-    for (name, value) in &self.uniforms {
+    for (name, value) in self.entries() {
       f(name, *value);
     }
   }
 }
 
+// Values that don't borrow anything, which DynUniforms keeps as they
+// are. A reference to one is one too, for fields set every draw.
+pub trait UniformData {
+  fn to_uniform_value(&self) -> UniformValue<'static>;
+}
+
+impl<T: UniformData + ?Sized> UniformData for &T {
+  fn to_uniform_value(&self) -> UniformValue<'static> {
+    (**self).to_uniform_value()
+  }
+}
+
+macro_rules! uniform_data {
+  ($($ty:ty => $variant:ident$(($convert:ident))?,)*) => {
+    $(
+      impl UniformData for $ty {
+        fn to_uniform_value(&self) -> UniformValue<'static> {
+          UniformValue::$variant(uniform_data!(@value self $($convert)?))
+        }
+      }
+    )*
+  };
+  (@value $self:ident) => {
+    *$self
+  };
+  (@value $self:ident $convert:ident) => {
+    $self.$convert()
+  };
+}
+
+uniform_data! {
+  f32 => Float,
+  i32 => SignedInt,
+  u32 => UnsignedInt,
+  bool => Bool,
+  [f32; 2] => Vec2,
+  [f32; 3] => Vec3,
+  [f32; 4] => Vec4,
+  [i32; 2] => IntVec2,
+  [i32; 3] => IntVec3,
+  [i32; 4] => IntVec4,
+  [u32; 2] => UnsignedIntVec2,
+  [u32; 3] => UnsignedIntVec3,
+  [u32; 4] => UnsignedIntVec4,
+  [[f32; 2]; 2] => Mat2,
+  [[f32; 3]; 3] => Mat3,
+  [[f32; 4]; 4] => Mat4,
+  Vec2 => Vec2(to_array),
+  Vec3 => Vec3(to_array),
+  Vec4 => Vec4(to_array),
+  Mat3 => Mat3(to_cols_array_2d),
+  Mat4 => Mat4(to_cols_array_2d),
+}
+
+// The first of two uniforms with the same name wins, u1 over u2. While
+// u1 is visited its names are hashed into a set on the stack, so a
+// draw doesn't allocate, and u2's names are checked against it. u1 is
+// only visited again to confirm a hash that matches; rescanning it for
+// every name of u2 would multiply with each level of nesting. Each side is taken to name a uniform once,
+// as DynUniforms and uniform! do.
+fn merge_visit<'a, U1: Uniforms, U2: Uniforms>(
+  u1: &'a U1,
+  u2: &'a U2,
+  mut f: impl FnMut(&str, UniformValue<'a>),
+) {
+  let mut names = NameSet::new();
+  u1.visit_values(|name, value| {
+    names.insert(name);
+    f(name, value);
+  });
+  u2.visit_values(|name, value| {
+    if !names.may_contain(name) || !visits(u1, name) {
+      f(name, value);
+    }
+  });
+}
+
+// stops comparing at the first match, the visit itself can't be cut
+// short
+fn visits(uniforms: &impl Uniforms, name: &str) -> bool {
+  let mut found = false;
+  uniforms.visit_values(|n, _| found = found || n == name);
+  found
+}
+
+// as many names as the materials, models and lights merged in a draw
+// have, with room to spare
+const NAME_SET_CAPACITY: usize = 64;
+
+// Hashes of uniform names in a fixed array, like an ArrayVec, and in a
+// HashSet past that, which only allocates when it is needed. A hash can
+// match another name, so a match only means maybe.
+struct NameSet {
+  hashes: [u64; NAME_SET_CAPACITY],
+  len: usize,
+  overflow: HashSet<u64>,
+}
+
+impl NameSet {
+  fn new() -> Self {
+    Self {
+      hashes: [0; NAME_SET_CAPACITY],
+      len: 0,
+      overflow: HashSet::new(),
+    }
+  }
+
+  fn insert(&mut self, name: &str) {
+    let hash = name_hash(name);
+    match self.hashes.get_mut(self.len) {
+      Some(slot) => {
+        *slot = hash;
+        self.len += 1;
+      }
+      None => {
+        self.overflow.insert(hash);
+      }
+    }
+  }
+
+  fn may_contain(&self, name: &str) -> bool {
+    let hash = name_hash(name);
+    self.hashes[..self.len].contains(&hash) || self.overflow.contains(&hash)
+  }
+}
+
+// FNV-1a, names are short
+fn name_hash(name: &str) -> u64 {
+  name.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+    (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+  })
+}
+
 pub struct OwnedMergedUniform<U1, U2> {
   u1: U1,
   u2: U2,
@@ -78,20 +278,8 @@ where
   U1: Uniforms,
   U2: Uniforms,
 {
-  fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
-    let mut visited = HashSet::new();
-
-    self.u1.visit_values(|name, value| {
-      if visited.insert(name.to_owned()) {
-        f(name, value);
-      }
-    });
-
-    self.u2.visit_values(|name, value| {
-      if !visited.contains(name) && visited.insert(name.to_owned()) {
-        f(name, value);
-      }
-    });
+  fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, f: F) {
+    merge_visit(&self.u1, &self.u2, f);
   }
 }
 
@@ -106,24 +294,22 @@ impl<'a, U1, U2> MergedUniform<'a, U1, U2> {
   }
 }
 
+// only references, whatever U1 and U2 are
+impl<U1, U2> Clone for MergedUniform<'_, U1, U2> {
+  fn clone(&self) -> Self {
+    *self
+  }
+}
+
+impl<U1, U2> Copy for MergedUniform<'_, U1, U2> {}
+
 impl<U1, U2> Uniforms for MergedUniform<'_, U1, U2>
 where
   U1: Uniforms,
   U2: Uniforms,
 {
-  fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
-    let mut visited = HashSet::new();
-
-    self.u1.visit_values(|name, value| {
-      if visited.insert(name.to_owned()) {
-        f(name, value);
-      }
-    });
-    self.u2.visit_values(|name, value| {
-      if !visited.contains(name) && visited.insert(name.to_owned()) {
-        f(name, value)
-      }
-    });
+  fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, f: F) {
+    merge_visit(self.u1, self.u2, f);
   }
 }
 
@@ -141,17 +327,196 @@ pub fn to_raw_image(image: &RgbImage) -> RawImage2d<'_, u8> {
   }
 }
 
+// path.vert and path.frag, through the preprocessor with the shared
+// includes
 pub fn load_program<P: AsRef<Path>>(
   path: P,
   facade: &impl Facade,
 ) -> Result<glium::Program, anyhow::Error> {
-  let vert_path = path.as_ref().with_extension("vert");
-  let frag_path = path.as_ref().with_extension("frag");
+  load_program_with(path, &Preprocessor::new(), facade)
+}
+
+pub fn load_program_with<P: AsRef<Path>>(
+  path: P,
+  preprocessor: &Preprocessor,
+  facade: &impl Facade,
+) -> Result<glium::Program, anyhow::Error> {
+  let path = path.as_ref();
+  let stages = [path.with_extension("vert"), path.with_extension("frag")];
+  load_stages(&stages, preprocessor, facade)
+}
 
-  let vert_src = std::fs::read_to_string(vert_path)?;
-  let frag_src = std::fs::read_to_string(frag_path)?;
+// A program from the files of its stages, the stage told by the
+// extension: vert, tcs, tes, geom or frag. Compile errors point at the
+// original files.
+pub fn load_stages<P: AsRef<Path>>(
+  files: &[P],
+  preprocessor: &Preprocessor,
+  facade: &impl Facade,
+) -> Result<glium::Program, anyhow::Error> {
+  let sources = preprocessor.process_stages(files)?;
+  compile_stages(files, &sources, facade)
+}
 
-  Ok(glium::Program::from_source(
-    facade, &vert_src, &frag_src, None,
-  )?)
+// the sources as processed from the files
+pub(crate) fn compile_stages<P: AsRef<Path>>(
+  files: &[P],
+  sources: &[Source],
+  facade: &impl Facade,
+) -> Result<glium::Program, anyhow::Error> {
+  let mut stages: HashMap<&str, &str> = HashMap::new();
+  for (file, source) in files.iter().zip(sources) {
+    let file = file.as_ref();
+    let stage = file
+      .extension()
+      .and_then(|ext| ext.to_str())
+      .filter(|ext| ["vert", "tcs", "tes", "geom", "frag"].contains(ext))
+      .ok_or_else(|| anyhow!("{}: unknown shader stage", file.display()))?;
+    if stages.insert(stage, &source.text).is_some() {
+      bail!("two {} shaders in {:?}", stage, files_list(files));
+    }
+  }
+  let (Some(&vertex), Some(&fragment)) =
+    (stages.get("vert"), stages.get("frag"))
+  else {
+    bail!(
+      "{:?} needs a vertex and a fragment shader",
+      files_list(files)
+    );
+  };
+
+  let input = SourceCode {
+    vertex_shader: vertex,
+    tessellation_control_shader: stages.get("tcs").copied(),
+    tessellation_evaluation_shader: stages.get("tes").copied(),
+    geometry_shader: stages.get("geom").copied(),
+    fragment_shader: fragment,
+  };
+  glium::Program::new(facade, input).map_err(|e| match e {
+    // the last source knows the files of all the stages
+    ProgramCreationError::CompilationError(log, _) => {
+      let log = sources.last().map_or(log.clone(), |s| s.map_log(&log));
+      anyhow!("compiling {:?}:\n{}", files_list(files), log)
+    }
+    e => e.into(),
+  })
+}
+
+fn files_list<P: AsRef<Path>>(files: &[P]) -> Vec<&Path> {
+  files.iter().map(AsRef::as_ref).collect()
+}
+
+// The same sources built with different defines, one program per named
+// variant, e.g. with and without shadows. The defines of a variant are
+// set to 1 on top of the preprocessor's. Each variant is rebuilt when
+// its files change, see program_registry.
+pub struct ProgramVariants {
+  programs: Vec<(String, ProgramHandle)>,
+}
+
+impl ProgramVariants {
+  pub fn load<P: AsRef<Path>>(
+    path: P,
+    preprocessor: &Preprocessor,
+    variants: &[(&str, &[&str])],
+    facade: &impl Facade,
+  ) -> Result<Self, anyhow::Error> {
+    let mut programs = Vec::new();
+    for (name, defines) in variants {
+      let preprocessor = defines
+        .iter()
+        .fold(preprocessor.clone(), |p, define| p.define(define, 1));
+      let program = ProgramHandle::load_with(&path, &preprocessor, facade)
+        .with_context(|| format!("variant {}", name))?;
+      programs.push((name.to_string(), program));
+    }
+    Ok(Self { programs })
+  }
+
+  pub fn get(&self, name: &str) -> Option<Ref<'_, glium::Program>> {
+    self
+      .programs
+      .iter()
+      .find_map(|(n, program)| (n == name).then(|| program.get()))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn uniforms(names: &[&str], value: f32) -> DynUniforms<'static> {
+    let mut uniforms = DynUniforms::new();
+    for name in names {
+      uniforms.add(name.to_string(), value);
+    }
+    uniforms
+  }
+
+  fn visited(uniforms: &impl Uniforms) -> Vec<(String, f32)> {
+    let mut visited = Vec::new();
+    uniforms.visit_values(|name, value| match value {
+      UniformValue::Float(value) => visited.push((name.to_owned(), value)),
+      _ => panic!("{} isn't a float", name),
+    });
+    visited
+  }
+
+  fn pairs(pairs: &[(&str, f32)]) -> Vec<(String, f32)> {
+    pairs.iter().map(|&(n, v)| (n.to_owned(), v)).collect()
+  }
+
+  #[test]
+  fn first_name_wins() {
+    let (a, b) = (uniforms(&["x", "y"], 1.0), uniforms(&["y", "z"], 2.0));
+    let expected = pairs(&[("x", 1.0), ("y", 1.0), ("z", 2.0)]);
+    assert_eq!(visited(&MergedUniform::new(&a, &b)), expected);
+    assert_eq!(visited(&OwnedMergedUniform::new(a, b)), expected);
+  }
+
+  #[test]
+  fn nested_merges() {
+    let a = uniforms(&["x"], 1.0);
+    let b = uniforms(&["x", "y"], 2.0);
+    let c = uniforms(&["x", "y", "z"], 3.0);
+    let d = uniforms(&["w", "z"], 4.0);
+    let ab = MergedUniform::new(&a, &b);
+    let abc = MergedUniform::new(&ab, &c);
+    let merged = OwnedMergedUniform::new(abc, d);
+    assert_eq!(
+      visited(&merged),
+      pairs(&[("x", 1.0), ("y", 2.0), ("z", 3.0), ("w", 4.0)])
+    );
+  }
+
+  #[test]
+  fn stays_inline() {
+    let names: Vec<String> =
+      (0..INLINE_UNIFORMS).map(|i| format!("u{}", i)).collect();
+    let mut uniforms = DynUniforms::new();
+    for name in &names {
+      uniforms.add(name.as_str(), 1.0);
+    }
+    uniforms.add_override("u0", 2.0);
+    assert_eq!(uniforms.spilled.capacity(), 0);
+
+    uniforms.add("extra", 3.0);
+    let visited = visited(&uniforms);
+    assert_eq!(visited.len(), INLINE_UNIFORMS + 1);
+    assert_eq!(visited[0], ("u0".to_owned(), 2.0));
+    assert_eq!(visited.last(), Some(&("extra".to_owned(), 3.0)));
+  }
+
+  #[test]
+  fn more_names_than_the_set_holds() {
+    let names: Vec<String> = (0..NAME_SET_CAPACITY + 8)
+      .map(|i| format!("u{}", i))
+      .collect();
+    let names: Vec<&str> = names.iter().map(String::as_str).collect();
+    let a = uniforms(&names, 1.0);
+    let b = uniforms(&["u3", "u70", "extra"], 2.0);
+    let merged = visited(&MergedUniform::new(&a, &b));
+    assert_eq!(merged.len(), names.len() + 1);
+    assert_eq!(merged.last(), Some(&("extra".to_owned(), 2.0)));
+  }
 }
@@ -0,0 +1,204 @@
+// Golden image tests: a scene is rendered offscreen and compared to a
+// reference image checked in next to the project. The references are
+// made with Mesa's llvmpipe, which every test run is forced onto, so the
+// results don't depend on the GPU. Small differences are tolerated to
+// survive Mesa updates.
+//
+// UPDATE_GOLDEN=1 writes the references. A missing reference fails
+// like a differing one, so a test can't pass without one.
+// Failures leave the output and a diff image in golden/failed.
+
+use std::{path::PathBuf, sync::Once};
+
+use anyhow::{bail, Context as _};
+use glium::{backend::Facade, framebuffer::SimpleFrameBuffer};
+use image::{Rgba, RgbaImage};
+
+use crate::{
+  camera::DepthMode,
+  capture,
+  gl_boilerplate::{ContextOptions, Headless, OffscreenTarget},
+};
+
+pub const WIDTH: u32 = 320;
+pub const HEIGHT: u32 = 240;
+
+// How far the output may be off. A pixel differs when any channel is
+// off by more than `channel`, and the image differs when more than
+// `pixels` of them do, as a fraction.
+#[derive(Clone, Copy, Debug)]
+pub struct Tolerance {
+  pub channel: u8,
+  pub pixels: f32,
+}
+
+impl Default for Tolerance {
+  fn default() -> Self {
+    Self {
+      channel: 2,
+      pixels: 0.001,
+    }
+  }
+}
+
+pub struct Golden {
+  headless: Headless,
+  target: OffscreenTarget,
+  dir: PathBuf,
+  tolerance: Tolerance,
+}
+
+impl Golden {
+  // references in dir, usually the project's golden directory
+  pub fn new(dir: impl Into<PathBuf>) -> anyhow::Result<Self> {
+    Self::with_depth_mode(dir, DepthMode::Standard)
+  }
+
+  pub fn with_depth_mode(
+    dir: impl Into<PathBuf>,
+    depth_mode: DepthMode,
+  ) -> anyhow::Result<Self> {
+    force_software_gl();
+    let (headless, target) = ContextOptions::headless()
+      .depth_mode(depth_mode)
+      .init_headless(WIDTH, HEIGHT)?;
+
+    let renderer = headless.get_context().get_opengl_renderer_string();
+    if !renderer.contains("llvmpipe") {
+      eprintln!(
+        "Rendering with {} instead of llvmpipe, the references may not match",
+        renderer
+      );
+    }
+
+    Ok(Self {
+      headless,
+      target,
+      dir: dir.into(),
+      tolerance: Tolerance::default(),
+    })
+  }
+
+  pub fn tolerance(mut self, tolerance: Tolerance) -> Self {
+    self.tolerance = tolerance;
+    self
+  }
+
+  // to create what the scene needs with
+  pub fn facade(&self) -> &Headless {
+    &self.headless
+  }
+
+  // renders with draw and compares with dir/name.png
+  pub fn check(
+    &self,
+    name: &str,
+    draw: impl FnOnce(&mut SimpleFrameBuffer) -> anyhow::Result<()>,
+  ) -> anyhow::Result<()> {
+    let mut framebuffer = self.target.framebuffer(&self.headless)?;
+    draw(&mut framebuffer).with_context(|| format!("rendering {}", name))?;
+    let actual = capture::read_color(&self.target.color)?;
+
+    let reference_path = self.dir.join(format!("{}.png", name));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+      return capture::save_color(&reference_path, &actual);
+    }
+    if !reference_path.exists() {
+      bail!(
+        "no reference image at {}, UPDATE_GOLDEN=1 records it",
+        reference_path.display()
+      );
+    }
+
+    let expected = image::open(&reference_path)
+      .with_context(|| format!("reading {}", reference_path.display()))?
+      .to_rgba8();
+    let diff = Diff::new(&actual, &expected, self.tolerance.channel)?;
+    if diff.fraction() <= self.tolerance.pixels {
+      return Ok(());
+    }
+
+    let failed = self.dir.join("failed");
+    let actual_path = failed.join(format!("{}.png", name));
+    let diff_path = failed.join(format!("{}.diff.png", name));
+    capture::save_color(&actual_path, &actual)?;
+    capture::save_color(&diff_path, &diff.image)?;
+    bail!(
+      "{} differs from {} in {} pixels ({:.3}%, at most {} apart), see {} \
+       and {}",
+      name,
+      reference_path.display(),
+      diff.differing,
+      diff.fraction() * 100.0,
+      diff.max_channel,
+      actual_path.display(),
+      diff_path.display()
+    )
+  }
+}
+
+// Where two images differ. The image shows the pixels out of tolerance
+// in red over a faded copy of the expected image.
+pub struct Diff {
+  pub differing: usize,
+  pub max_channel: u8,
+  pub image: RgbaImage,
+}
+
+impl Diff {
+  pub fn new(
+    actual: &RgbaImage,
+    expected: &RgbaImage,
+    channel_tolerance: u8,
+  ) -> anyhow::Result<Self> {
+    if actual.dimensions() != expected.dimensions() {
+      bail!(
+        "the output is {:?}, the reference {:?}",
+        actual.dimensions(),
+        expected.dimensions()
+      );
+    }
+
+    let mut differing = 0;
+    let mut max_channel = 0;
+    let image = RgbaImage::from_fn(actual.width(), actual.height(), |x, y| {
+      let a = actual.get_pixel(x, y);
+      let e = expected.get_pixel(x, y);
+      let off = (0..4).map(|i| a[i].abs_diff(e[i])).max().unwrap_or(0);
+      max_channel = max_channel.max(off);
+      if off > channel_tolerance {
+        differing += 1;
+        return Rgba([255, 0, 0, 255]);
+      }
+      let gray = luminance(e) / 4;
+      Rgba([gray, gray, gray, 255])
+    });
+
+    Ok(Self {
+      differing,
+      max_channel,
+      image,
+    })
+  }
+
+  pub fn fraction(&self) -> f32 {
+    let (width, height) = self.image.dimensions();
+    self.differing as f32 / (width * height) as f32
+  }
+}
+
+fn luminance(pixel: &Rgba<u8>) -> u8 {
+  let [r, g, b, _] = pixel.0.map(u32::from);
+  ((r * 299 + g * 587 + b * 114) / 1000) as u8
+}
+
+// Mesa reads these when the driver is loaded, so before the first
+// context. set_var isn't safe with other threads reading the
+// environment, hence only once, before any test touches GL.
+fn force_software_gl() {
+  static ONCE: Once = Once::new();
+  ONCE.call_once(|| {
+    std::env::set_var("LIBGL_ALWAYS_SOFTWARE", "1");
+    std::env::set_var("GALLIUM_DRIVER", "llvmpipe");
+  });
+}
@@ -1,19 +1,41 @@
+pub mod app;
 pub mod axis;
+pub mod camera;
+pub mod capture;
 pub mod gl_boilerplate;
 mod glium_helper;
+pub mod golden;
 pub mod math;
 pub mod mesh;
 pub mod obj_loader;
+pub mod preprocessor;
+pub mod program_registry;
 pub mod render;
+pub mod scene_file;
+pub mod scene_graph;
+pub mod session;
+#[cfg(feature = "shader-check")]
+pub mod shader_check;
+pub mod transform;
+pub mod uniform_blocks;
 
 use std::path::PathBuf;
 
 pub use axis::Axis;
 pub use glium_helper::{
-  load_program, to_raw_image, DynUniforms, MergedUniform, OwnedMergedUniform,
+  load_program, load_program_with, load_stages, to_raw_image, DynUniforms,
+  MergedUniform, OwnedMergedUniform, ProgramVariants, UniformData,
 };
 pub use obj_loader::{Group, Mtl, MtlLib, Obj, SimpleObj, VAIdx};
+pub use preprocessor::Preprocessor;
+pub use program_registry::ProgramHandle;
 pub use render::{CameraLike, Draw, HasProgram, HasShadow, ToUniforms};
+pub use scene_file::SceneFile;
+pub use scene_graph::{NodeId, SceneGraph};
+pub use transform::Transform;
+pub use uniform_blocks::{
+  BlockBuffer, CameraBlock, LightBlock, MaterialBlock, ShadowBlock, Std140,
+};
 
 pub fn teapot_path() -> PathBuf {
   asset_path("teapot.obj")
@@ -24,18 +46,7 @@ pub fn sphere_path() -> PathBuf {
 }
 
 pub fn asset_path(name: &str) -> PathBuf {
-  let path = PathBuf::from(format!("assets/{}", name));
-  if path.exists() {
-    return path;
-  }
-
-  let path = PathBuf::from(format!("../assets/{}", name));
-  if path.exists() {
-    return path;
-  }
-
-  let path = PathBuf::from(format!("../../assets/{}", name));
-  if path.exists() {
+  if let Some(path) = find_asset(name) {
     return path;
   }
 
@@ -47,6 +58,14 @@ pub fn asset_path(name: &str) -> PathBuf {
   panic!("Could not find {}", name)
 }
 
+// the shared assets seen from the workspace, a project or its src
+pub fn find_asset(name: &str) -> Option<PathBuf> {
+  ["assets", "../assets", "../../assets"]
+    .iter()
+    .map(|dir| PathBuf::from(format!("{}/{}", dir, name)))
+    .find(|path| path.exists())
+}
+
 #[macro_export]
 macro_rules! project_asset_path {
   ($name:literal) => {
@@ -1,4 +1,14 @@
-use glam::{Mat4, Vec3};
+mod bounds;
+mod frustum;
+mod plane;
+mod ray;
+
+use glam::{Mat4, Vec3, Vec4};
+
+pub use bounds::{max_scale, Aabb, Obb, Sphere};
+pub use frustum::{Containment, Frustum};
+pub use plane::{Plane, Side};
+pub use ray::{Ray, TriangleHit};
 
 pub fn reflect4x4(point: Vec3, normal: Vec3) -> Mat4 {
   let [x, y, z] = normal.into();
@@ -11,3 +21,145 @@ pub fn reflect4x4(point: Vec3, normal: Vec3) -> Mat4 {
     glam::vec4(2.0 * x * d, 2.0 * y * d, 2.0 * z * d, 1.0),
   )
 }
+
+// The radius in pixels of a sphere after projection, given its center
+// in view space. Infinite if the center is behind the camera.
+pub fn projected_radius(
+  center: Vec3,
+  radius: f32,
+  projection: Mat4,
+  viewport_height: f32,
+) -> f32 {
+  let w = (projection * center.extend(1.0)).w;
+  if w <= 0.0 {
+    return f32::INFINITY;
+  }
+
+  radius * projection.y_axis.y / w * viewport_height / 2.0
+}
+
+// Where the near and far planes end up in normalized device
+// coordinates, which depends on the clip control setting and on
+// whether depth is reversed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DepthRange {
+  pub near: f32,
+  pub far: f32,
+}
+
+impl DepthRange {
+  // the opengl default
+  pub const NEG_ONE_TO_ONE: Self = Self::new(-1.0, 1.0);
+  // with glClipControl(.., GL_ZERO_TO_ONE), like direct3d and vulkan
+  pub const ZERO_TO_ONE: Self = Self::new(0.0, 1.0);
+  pub const REVERSED: Self = Self::new(1.0, 0.0);
+
+  pub const fn new(near: f32, far: f32) -> Self {
+    Self { near, far }
+  }
+
+  pub fn is_reversed(&self) -> bool {
+    self.near > self.far
+  }
+}
+
+impl Default for DepthRange {
+  fn default() -> Self {
+    Self::NEG_ONE_TO_ONE
+  }
+}
+
+// Replace the near plane of `projection` with `clip_plane`, given in
+// view space and facing the geometry to keep (Lengyel, 2005). The far
+// plane gets tilted to still pass through the far corners, which costs
+// depth precision the more the planes differ. Only works with the
+// camera behind the plane, otherwise `projection` is returned as is.
+pub fn oblique_projection(
+  projection: Mat4,
+  clip_plane: Plane,
+  depth: DepthRange,
+) -> Mat4 {
+  let c = clip_plane.to_vec4();
+  if c.w >= 0.0 {
+    return projection;
+  }
+
+  // the corner of the frustum opposite to the plane, in view space.
+  // with an infinite far plane it is a direction (w = 0), which works
+  // just as well.
+  let corner = Vec4::new(c.x.signum(), c.y.signum(), depth.far, 1.0);
+  let q = projection.inverse() * corner;
+
+  // the new third row is a * c + b * (fourth row): the plane itself
+  // (c = 0) has to end up at the near depth, so b = near, and the
+  // corner at the far depth, which fixes a
+  let mut rows = projection.transpose();
+  let scale = (depth.far - depth.near) * rows.w_axis.dot(q) / c.dot(q);
+  rows.z_axis = c * scale + rows.w_axis * depth.near;
+  rows.transpose()
+}
+
+#[cfg(test)]
+mod tests {
+  use std::f32::consts::FRAC_PI_2;
+
+  use super::*;
+
+  fn projections() -> [(DepthRange, Mat4); 3] {
+    [
+      (
+        DepthRange::NEG_ONE_TO_ONE,
+        Mat4::perspective_rh_gl(FRAC_PI_2, 1.0, 1.0, 100.0),
+      ),
+      (
+        DepthRange::ZERO_TO_ONE,
+        Mat4::perspective_rh(FRAC_PI_2, 1.0, 1.0, 100.0),
+      ),
+      (
+        DepthRange::REVERSED,
+        Mat4::perspective_rh(FRAC_PI_2, 1.0, 100.0, 1.0),
+      ),
+    ]
+  }
+
+  fn ndc_depth(m: Mat4, p: Vec3) -> f32 {
+    m.project_point3(p).z
+  }
+
+  #[test]
+  fn oblique_projection_clips_at_the_plane() {
+    // tilted, 5 in front of the camera and facing away from it
+    let plane = Plane::from_point_normal(
+      Vec3::new(0.0, 0.0, -5.0),
+      Vec3::new(0.3, 0.2, -1.0),
+    );
+    for (depth, projection) in projections() {
+      let oblique = oblique_projection(projection, plane, depth);
+      for [x, y] in [[0.0, 0.0], [2.0, -1.0], [-3.0, 3.0]] {
+        let on_plane = plane.project(Vec3::new(x, y, -5.0));
+        let z = ndc_depth(oblique, on_plane);
+        assert!((z - depth.near).abs() < 1e-4, "{depth:?}: {on_plane} {z}");
+      }
+
+      // behind the plane is clipped, in front of it isn't
+      let (near, far) = (depth.near.min(depth.far), depth.near.max(depth.far));
+      let behind = ndc_depth(oblique, Vec3::new(0.0, 0.0, -3.0));
+      assert!(!(near..=far).contains(&behind), "{depth:?}: {behind}");
+      let in_front = ndc_depth(oblique, Vec3::new(0.0, 0.0, -20.0));
+      assert!((near..=far).contains(&in_front), "{depth:?}: {in_front}");
+
+      // x and y are unchanged
+      let p = Vec3::new(4.0, -7.0, -20.0);
+      let (a, b) = (oblique.project_point3(p), projection.project_point3(p));
+      assert!(a.truncate().abs_diff_eq(b.truncate(), 1e-6));
+    }
+  }
+
+  #[test]
+  fn oblique_projection_needs_the_camera_behind_the_plane() {
+    let plane = Plane::from_point_normal(Vec3::new(0.0, 0.0, -5.0), Vec3::Z);
+    for (depth, projection) in projections() {
+      assert_eq!(oblique_projection(projection, plane, depth), projection);
+    }
+  }
+}
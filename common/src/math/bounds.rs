@@ -0,0 +1,729 @@
+// Bounding volumes shared by the projects for culling, fitting shadow
+// frustums and framing the camera.
+
+use glam::{Mat3, Mat4, Vec3};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+  pub min: Vec3,
+  pub max: Vec3,
+}
+
+impl Default for Aabb {
+  fn default() -> Self {
+    Self::EMPTY
+  }
+}
+
+impl Aabb {
+  // contains nothing, and is the identity of `union`
+  pub const EMPTY: Self = Self {
+    min: Vec3::splat(f32::INFINITY),
+    max: Vec3::splat(f32::NEG_INFINITY),
+  };
+
+  pub fn new(min: Vec3, max: Vec3) -> Self {
+    Self { min, max }
+  }
+
+  pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Self {
+    let mut aabb = Self::EMPTY;
+    for p in points {
+      aabb.extend(p);
+    }
+    aabb
+  }
+
+  // the [[xmin, xmax], [ymin, ymax], [zmin, zmax]] layout used before
+  pub fn from_ranges([[x1, x2], [y1, y2], [z1, z2]]: [[f32; 2]; 3]) -> Self {
+    Self::new(Vec3::new(x1, y1, z1), Vec3::new(x2, y2, z2))
+  }
+
+  pub fn to_ranges(&self) -> [[f32; 2]; 3] {
+    let (min, max) = (self.min, self.max);
+    [[min.x, max.x], [min.y, max.y], [min.z, max.z]]
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.min.cmpgt(self.max).any()
+  }
+
+  pub fn extend(&mut self, p: Vec3) {
+    self.min = self.min.min(p);
+    self.max = self.max.max(p);
+  }
+
+  pub fn union(&self, other: &Self) -> Self {
+    Self::new(self.min.min(other.min), self.max.max(other.max))
+  }
+
+  pub fn center(&self) -> Vec3 {
+    (self.min + self.max) / 2.0
+  }
+
+  pub fn size(&self) -> Vec3 {
+    self.max - self.min
+  }
+
+  pub fn half_extents(&self) -> Vec3 {
+    self.size() / 2.0
+  }
+
+  // 0 for empty boxes
+  pub fn surface_area(&self) -> f32 {
+    if self.is_empty() {
+      return 0.0;
+    }
+    let s = self.size();
+    2.0 * (s.x * s.y + s.y * s.z + s.z * s.x)
+  }
+
+  pub fn contains(&self, p: Vec3) -> bool {
+    p.cmpge(self.min).all() && p.cmple(self.max).all()
+  }
+
+  pub fn intersects(&self, other: &Self) -> bool {
+    self.min.cmple(other.max).all() && other.min.cmple(self.max).all()
+  }
+
+  pub fn corners(&self) -> [Vec3; 8] {
+    let (a, b) = (self.min, self.max);
+    [
+      Vec3::new(a.x, a.y, a.z),
+      Vec3::new(a.x, a.y, b.z),
+      Vec3::new(a.x, b.y, a.z),
+      Vec3::new(a.x, b.y, b.z),
+      Vec3::new(b.x, a.y, a.z),
+      Vec3::new(b.x, a.y, b.z),
+      Vec3::new(b.x, b.y, a.z),
+      Vec3::new(b.x, b.y, b.z),
+    ]
+  }
+
+  // the box in normalized device coordinates covered by the box after
+  // the view-projection `m`, clamped to the ndc cube. parts behind the
+  // camera can't be divided by w, the whole cube is returned then.
+  pub fn project(&self, m: Mat4) -> Self {
+    let ndc = Self::new(Vec3::NEG_ONE, Vec3::ONE);
+    let clip = self.corners().map(|c| m * c.extend(1.0));
+    if clip.iter().any(|c| c.w <= 0.0) {
+      return ndc;
+    }
+
+    let projected = Self::from_points(clip.map(|c| c.truncate() / c.w));
+    Self::new(projected.min.max(ndc.min), projected.max.min(ndc.max))
+  }
+
+  // the box around the transformed box, `m` must be affine (Arvo, 1990)
+  pub fn transform(&self, m: Mat4) -> Self {
+    if self.is_empty() {
+      return *self;
+    }
+
+    let center = m.transform_point3(self.center());
+    let m3 = Mat3::from_mat4(m);
+    let abs =
+      Mat3::from_cols(m3.x_axis.abs(), m3.y_axis.abs(), m3.z_axis.abs());
+    let extents = abs * self.half_extents();
+    Self::new(center - extents, center + extents)
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sphere {
+  pub center: Vec3,
+  pub radius: f32,
+}
+
+impl Sphere {
+  pub const EMPTY: Self = Self {
+    center: Vec3::ZERO,
+    radius: f32::NEG_INFINITY,
+  };
+
+  pub fn new(center: Vec3, radius: f32) -> Self {
+    Self { center, radius }
+  }
+
+  // Ritter's sphere, at most a few percent larger than the minimal
+  // one, followed by a pass that shrinks it again where possible.
+  pub fn from_points(points: &[Vec3]) -> Self {
+    let Some(&first) = points.first() else {
+      return Self::EMPTY;
+    };
+
+    let farthest = |from: Vec3| {
+      points
+        .iter()
+        .copied()
+        .max_by(|a, b| {
+          from
+            .distance_squared(*a)
+            .total_cmp(&from.distance_squared(*b))
+        })
+        .unwrap()
+    };
+
+    let a = farthest(first);
+    let b = farthest(a);
+    let mut sphere = Self::new((a + b) / 2.0, a.distance(b) / 2.0);
+    for &p in points {
+      sphere.extend(p);
+    }
+
+    // the box around the points is sometimes the tighter fit
+    let aabb = Aabb::from_points(points.iter().copied());
+    let center = aabb.center();
+    let radius = points
+      .iter()
+      .map(|p| p.distance(center))
+      .fold(0.0, f32::max);
+    if radius < sphere.radius {
+      sphere = Self::new(center, radius);
+    }
+
+    sphere
+  }
+
+  pub fn from_aabb(aabb: &Aabb) -> Self {
+    Self::new(aabb.center(), aabb.half_extents().length())
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.radius < 0.0
+  }
+
+  // grow just enough to contain the point
+  pub fn extend(&mut self, p: Vec3) {
+    if self.is_empty() {
+      *self = Self::new(p, 0.0);
+      return;
+    }
+
+    let d = p.distance(self.center);
+    if d > self.radius {
+      let radius = (self.radius + d) / 2.0;
+      self.center += (p - self.center) * ((radius - self.radius) / d);
+      self.radius = radius;
+    }
+  }
+
+  pub fn union(&self, other: &Self) -> Self {
+    if self.is_empty() {
+      return *other;
+    }
+    if other.is_empty() {
+      return *self;
+    }
+
+    let d = self.center.distance(other.center);
+    if d + other.radius <= self.radius {
+      return *self;
+    }
+    if d + self.radius <= other.radius {
+      return *other;
+    }
+
+    let radius = (d + self.radius + other.radius) / 2.0;
+    let t = (radius - self.radius) / d;
+    Self::new(self.center.lerp(other.center, t), radius)
+  }
+
+  pub fn contains(&self, p: Vec3) -> bool {
+    p.distance_squared(self.center) <= self.radius * self.radius
+  }
+
+  // scaled by the largest axis of `m`, so it stays conservative under
+  // non-uniform scaling
+  pub fn transform(&self, m: Mat4) -> Self {
+    Self::new(m.transform_point3(self.center), self.radius * max_scale(m))
+  }
+
+  pub fn aabb(&self) -> Aabb {
+    let r = Vec3::splat(self.radius);
+    Aabb::new(self.center - r, self.center + r)
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Obb {
+  pub center: Vec3,
+  // orthonormal columns
+  pub axes: Mat3,
+  pub half_extents: Vec3,
+}
+
+impl Obb {
+  pub fn from_aabb(aabb: &Aabb) -> Self {
+    Self {
+      center: aabb.center(),
+      axes: Mat3::IDENTITY,
+      half_extents: aabb.half_extents(),
+    }
+  }
+
+  // aligned to the principal axes of the points. falls back to the
+  // axis aligned box when that is smaller, e.g. for symmetric shapes
+  // where the principal axes are arbitrary.
+  pub fn from_points(points: &[Vec3]) -> Self {
+    let aabb = Aabb::from_points(points.iter().copied());
+    if points.len() < 4 {
+      return Self::from_aabb(&aabb);
+    }
+
+    let mean = points.iter().copied().sum::<Vec3>() / points.len() as f32;
+    let mut cov = [[0.0f64; 3]; 3];
+    for p in points {
+      let d = (*p - mean).to_array();
+      for i in 0..3 {
+        for j in 0..3 {
+          cov[i][j] += d[i] as f64 * d[j] as f64;
+        }
+      }
+    }
+
+    let axes = symmetric_eigenvectors(cov);
+    let obb = Self::fit(axes, points);
+
+    if obb.volume() < Self::from_aabb(&aabb).volume() {
+      obb
+    } else {
+      Self::from_aabb(&aabb)
+    }
+  }
+
+  // the box along the given axes enclosing the points
+  fn fit(axes: Mat3, points: &[Vec3]) -> Self {
+    let local = Aabb::from_points(points.iter().map(|p| axes.transpose() * *p));
+    Self {
+      center: axes * local.center(),
+      axes,
+      half_extents: local.half_extents(),
+    }
+  }
+
+  pub fn volume(&self) -> f32 {
+    8.0 * self.half_extents.x * self.half_extents.y * self.half_extents.z
+  }
+
+  pub fn contains(&self, p: Vec3) -> bool {
+    let local = self.axes.transpose() * (p - self.center);
+    local.abs().cmple(self.half_extents).all()
+  }
+
+  pub fn corners(&self) -> [Vec3; 8] {
+    let local = Aabb::new(-self.half_extents, self.half_extents);
+    local.corners().map(|c| self.center + self.axes * c)
+  }
+
+  pub fn aabb(&self) -> Aabb {
+    let abs = Mat3::from_cols(
+      self.axes.x_axis.abs(),
+      self.axes.y_axis.abs(),
+      self.axes.z_axis.abs(),
+    );
+    let extents = abs * self.half_extents;
+    Aabb::new(self.center - extents, self.center + extents)
+  }
+
+  // `m` must be affine. shear is not representable and gets absorbed
+  // by refitting around the transformed corners, as does an axis scaled
+  // to nothing, which has no direction left to normalize.
+  pub fn transform(&self, m: Mat4) -> Self {
+    let m3 = Mat3::from_mat4(m);
+    let axes = m3 * self.axes;
+    let scale = Vec3::new(
+      axes.x_axis.length(),
+      axes.y_axis.length(),
+      axes.z_axis.length(),
+    );
+    if scale.min_element() < 1e-6 {
+      let corners = self.corners().map(|c| m.transform_point3(c));
+      return Self::from_points(&corners);
+    }
+
+    let normalized = Mat3::from_cols(
+      axes.x_axis / scale.x,
+      axes.y_axis / scale.y,
+      axes.z_axis / scale.z,
+    );
+
+    let orthogonal = normalized.x_axis.dot(normalized.y_axis).abs() < 1e-4
+      && normalized.y_axis.dot(normalized.z_axis).abs() < 1e-4
+      && normalized.z_axis.dot(normalized.x_axis).abs() < 1e-4;
+    if !orthogonal {
+      let corners = self.corners().map(|c| m.transform_point3(c));
+      return Self::from_points(&corners);
+    }
+
+    Self {
+      center: m.transform_point3(self.center),
+      axes: normalized,
+      half_extents: self.half_extents * scale,
+    }
+  }
+
+  pub fn union(&self, other: &Self) -> Self {
+    let mut corners = self.corners().to_vec();
+    corners.extend_from_slice(&other.corners());
+    Self::from_points(&corners)
+  }
+}
+
+// the largest factor `m` scales a length by, ignoring shear
+pub fn max_scale(m: Mat4) -> f32 {
+  let m3 = Mat3::from_mat4(m);
+  m3.x_axis
+    .length()
+    .max(m3.y_axis.length())
+    .max(m3.z_axis.length())
+}
+
+// cyclic jacobi rotations, returns the eigenvectors as columns
+fn symmetric_eigenvectors(mut a: [[f64; 3]; 3]) -> Mat3 {
+  let mut v = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+  for _ in 0..32 {
+    let off = a[0][1].powi(2) + a[0][2].powi(2) + a[1][2].powi(2);
+    if off < 1e-18 {
+      break;
+    }
+
+    for (p, q) in [(0, 1), (0, 2), (1, 2)] {
+      if a[p][q].abs() < 1e-30 {
+        continue;
+      }
+
+      let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+      let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+      let c = 1.0 / (t * t + 1.0).sqrt();
+      let s = t * c;
+
+      // a = J^T a J, v = v J
+      for row in &mut a {
+        let (ap, aq) = (row[p], row[q]);
+        row[p] = c * ap - s * aq;
+        row[q] = s * ap + c * aq;
+      }
+      let (row_p, row_q) = (a[p], a[q]);
+      a[p] = std::array::from_fn(|k| c * row_p[k] - s * row_q[k]);
+      a[q] = std::array::from_fn(|k| s * row_p[k] + c * row_q[k]);
+      for row in &mut v {
+        let (vp, vq) = (row[p], row[q]);
+        row[p] = c * vp - s * vq;
+        row[q] = s * vp + c * vq;
+      }
+    }
+  }
+
+  let column = |i: usize| {
+    Vec3::new(v[0][i] as f32, v[1][i] as f32, v[2][i] as f32).normalize()
+  };
+  let (x, y) = (column(0), column(1));
+  // keep it right-handed and exactly orthogonal
+  let y = (y - x * x.dot(y)).normalize();
+  Mat3::from_cols(x, y, x.cross(y))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn assert_aabb_eq(a: Aabb, b: Aabb) {
+    assert!(
+      a.min.abs_diff_eq(b.min, 1e-5) && a.max.abs_diff_eq(b.max, 1e-5),
+      "{a:?} != {b:?}"
+    );
+  }
+
+  #[test]
+  fn transform_translated_and_scaled() {
+    let aabb = Aabb::new(Vec3::ZERO, Vec3::ONE);
+    let m = Mat4::from_translation(Vec3::new(1.0, 2.0, 3.0));
+    assert_aabb_eq(
+      aabb.transform(m),
+      Aabb::new(Vec3::new(1.0, 2.0, 3.0), Vec3::new(2.0, 3.0, 4.0)),
+    );
+
+    let m = Mat4::from_scale(Vec3::new(2.0, -1.0, 0.5));
+    assert_aabb_eq(
+      aabb.transform(m),
+      Aabb::new(Vec3::new(0.0, -1.0, 0.0), Vec3::new(2.0, 0.0, 0.5)),
+    );
+  }
+
+  #[test]
+  fn transform_rotated() {
+    let aabb = Aabb::new(Vec3::NEG_ONE, Vec3::ONE);
+    let m = Mat4::from_rotation_z(std::f32::consts::FRAC_PI_4);
+    let extents = Vec3::new(2f32.sqrt(), 2f32.sqrt(), 1.0);
+    assert_aabb_eq(aabb.transform(m), Aabb::new(-extents, extents));
+
+    // the same as the box around the transformed corners
+    let m = Mat4::from_translation(Vec3::X)
+      * Mat4::from_rotation_y(0.3)
+      * Mat4::from_scale(Vec3::splat(2.0));
+    let corners = aabb.corners().map(|c| m.transform_point3(c));
+    assert_aabb_eq(aabb.transform(m), Aabb::from_points(corners));
+  }
+
+  #[test]
+  fn transform_empty() {
+    let m = Mat4::from_translation(Vec3::ONE);
+    assert!(Aabb::EMPTY.transform(m).is_empty());
+  }
+
+  // deterministic points in the unit cube
+  fn scattered(n: usize) -> Vec<Vec3> {
+    let mut state = 12345u32;
+    let mut next = move || {
+      state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+      (state >> 8) as f32 / (1 << 24) as f32
+    };
+    (0..n).map(|_| Vec3::new(next(), next(), next())).collect()
+  }
+
+  fn assert_sphere_contains(sphere: &Sphere, points: &[Vec3]) {
+    for p in points {
+      assert!(
+        p.distance(sphere.center) <= sphere.radius + 1e-4,
+        "{p} outside {sphere:?}"
+      );
+    }
+  }
+
+  fn assert_obb_contains(obb: &Obb, points: &[Vec3]) {
+    for p in points {
+      let local = obb.axes.transpose() * (*p - obb.center);
+      assert!(
+        local.abs().cmple(obb.half_extents + 1e-4).all(),
+        "{p} outside {obb:?}"
+      );
+    }
+  }
+
+  fn assert_finite_obb(obb: &Obb) {
+    assert!(
+      obb.center.is_finite()
+        && obb.axes.is_finite()
+        && obb.half_extents.is_finite(),
+      "{obb:?}"
+    );
+  }
+
+  fn box_corners(half_extents: Vec3, m: Mat4) -> [Vec3; 8] {
+    Aabb::new(-half_extents, half_extents)
+      .corners()
+      .map(|c| m.transform_point3(c))
+  }
+
+  #[test]
+  fn sphere_contains_points() {
+    let points = scattered(200);
+    let sphere = Sphere::from_points(&points);
+    assert_sphere_contains(&sphere, &points);
+    // no bigger than the sphere around the box
+    let aabb = Aabb::from_points(points.iter().copied());
+    assert!(sphere.radius <= Sphere::from_aabb(&aabb).radius + 1e-5);
+  }
+
+  #[test]
+  fn sphere_tight() {
+    // the corners of a cube, the diagonal is the diameter
+    let cube = Aabb::new(Vec3::NEG_ONE, Vec3::ONE).corners();
+    let sphere = Sphere::from_points(&cube);
+    assert!(sphere.center.abs_diff_eq(Vec3::ZERO, 1e-5));
+    assert!((sphere.radius - 3f32.sqrt()).abs() < 1e-5);
+
+    // an octahedron, where Ritter alone starts from two opposite tips
+    let octahedron = [
+      Vec3::X,
+      Vec3::NEG_X,
+      Vec3::Y,
+      Vec3::NEG_Y,
+      Vec3::Z,
+      Vec3::NEG_Z,
+    ];
+    let sphere = Sphere::from_points(&octahedron);
+    assert!(sphere.center.abs_diff_eq(Vec3::ZERO, 1e-5));
+    assert!((sphere.radius - 1.0).abs() < 1e-5);
+
+    // points on a unit sphere, within a few percent of it
+    let golden_angle = std::f32::consts::PI * (3.0 - 5f32.sqrt());
+    let on_sphere: Vec<Vec3> = (0..100)
+      .map(|i| {
+        let y = 1.0 - 2.0 * (i as f32 + 0.5) / 100.0;
+        let r = (1.0 - y * y).sqrt();
+        let phi = golden_angle * i as f32;
+        Vec3::new(r * phi.cos(), y, r * phi.sin()) + Vec3::splat(5.0)
+      })
+      .collect();
+    let sphere = Sphere::from_points(&on_sphere);
+    assert_sphere_contains(&sphere, &on_sphere);
+    assert!(sphere.radius < 1.05);
+  }
+
+  #[test]
+  fn sphere_degenerate() {
+    assert!(Sphere::from_points(&[]).is_empty());
+
+    let single = Sphere::from_points(&[Vec3::ONE]);
+    assert_eq!(single, Sphere::new(Vec3::ONE, 0.0));
+
+    let repeated = Sphere::from_points(&[Vec3::ONE; 5]);
+    assert_eq!(repeated, Sphere::new(Vec3::ONE, 0.0));
+
+    let collinear: Vec<Vec3> = (0..5).map(|i| Vec3::X * i as f32).collect();
+    let sphere = Sphere::from_points(&collinear);
+    assert!(sphere.center.abs_diff_eq(Vec3::X * 2.0, 1e-5));
+    assert!((sphere.radius - 2.0).abs() < 1e-5);
+  }
+
+  #[test]
+  fn sphere_union() {
+    let a = Sphere::new(Vec3::ZERO, 1.0);
+    let b = Sphere::new(Vec3::new(4.0, 0.0, 0.0), 1.0);
+    let union = a.union(&b);
+    assert!(union.center.abs_diff_eq(Vec3::new(2.0, 0.0, 0.0), 1e-5));
+    assert!((union.radius - 3.0).abs() < 1e-5);
+    assert_eq!(b.union(&a), union);
+
+    // one inside the other
+    let inner = Sphere::new(Vec3::new(0.5, 0.0, 0.0), 0.25);
+    assert_eq!(a.union(&inner), a);
+    assert_eq!(inner.union(&a), a);
+    // the same center
+    assert_eq!(a.union(&Sphere::new(Vec3::ZERO, 2.0)).radius, 2.0);
+
+    assert_eq!(Sphere::EMPTY.union(&a), a);
+    assert_eq!(a.union(&Sphere::EMPTY), a);
+  }
+
+  #[test]
+  fn eigenvectors() {
+    // R diag(5, 2, 1) R^T
+    let r = Mat3::from_quat(glam::Quat::from_euler(
+      glam::EulerRot::YXZ,
+      0.4,
+      -0.7,
+      1.1,
+    ));
+    let a = r * Mat3::from_diagonal(Vec3::new(5.0, 2.0, 1.0)) * r.transpose();
+    let cov =
+      std::array::from_fn(|i| std::array::from_fn(|j| a.col(j)[i] as f64));
+
+    let v = symmetric_eigenvectors(cov);
+    assert!((v.determinant() - 1.0).abs() < 1e-5);
+    for i in 0..3 {
+      let (col, av) = (v.col(i), a * v.col(i));
+      // only scaled by a
+      assert!(av.abs_diff_eq(col * col.dot(av), 1e-4), "{col} {av}");
+      assert!((col.length() - 1.0).abs() < 1e-5);
+    }
+    let mut eigenvalues = [0, 1, 2].map(|i| v.col(i).dot(a * v.col(i)));
+    eigenvalues.sort_by(f32::total_cmp);
+    assert!(Vec3::from(eigenvalues).abs_diff_eq(Vec3::new(1.0, 2.0, 5.0), 1e-4));
+
+    // already diagonal
+    let v =
+      symmetric_eigenvectors([[3.0, 0.0, 0.0], [0.0, 2.0, 0.0], [0.0; 3]]);
+    assert!(v.abs_diff_eq(Mat3::IDENTITY, 1e-6));
+  }
+
+  #[test]
+  fn obb_beats_aabb_on_rotated_box() {
+    let half_extents = Vec3::new(3.0, 1.0, 0.5);
+    let m = Mat4::from_translation(Vec3::new(1.0, -2.0, 0.5))
+      * Mat4::from_euler(glam::EulerRot::YXZ, 0.6, 0.3, -0.9);
+    let corners = box_corners(half_extents, m);
+
+    let obb = Obb::from_points(&corners);
+    assert_obb_contains(&obb, &corners);
+    assert!((obb.volume() - 12.0).abs() < 1e-3, "{}", obb.volume());
+
+    let aabb = Obb::from_aabb(&Aabb::from_points(corners));
+    assert!(obb.volume() < aabb.volume() * 0.5);
+    assert!(obb.aabb().min.abs_diff_eq(aabb.aabb().min, 1e-4));
+  }
+
+  #[test]
+  fn obb_contains_points() {
+    // squashed and turned, so the principal axes are well defined
+    let m =
+      Mat4::from_rotation_z(0.5) * Mat4::from_scale(Vec3::new(4.0, 1.0, 2.0));
+    let points: Vec<Vec3> = scattered(100)
+      .iter()
+      .map(|&p| m.transform_point3(p))
+      .collect();
+    let obb = Obb::from_points(&points);
+    assert_obb_contains(&obb, &points);
+    assert!(
+      obb.volume()
+        <= Obb::from_aabb(&Aabb::from_points(points.clone())).volume()
+    );
+  }
+
+  #[test]
+  fn obb_degenerate() {
+    // coplanar, in a tilted plane
+    let m = Mat4::from_rotation_x(0.8) * Mat4::from_rotation_y(0.2);
+    let plane: Vec<Vec3> = (0..16)
+      .map(|i| {
+        m.transform_point3(Vec3::new((i % 4) as f32, (i / 4) as f32, 0.0))
+      })
+      .collect();
+    // collinear, and a single point repeated
+    let line: Vec<Vec3> = (0..6).map(|i| Vec3::ONE * i as f32).collect();
+    let point = [Vec3::new(1.0, 2.0, 3.0); 6];
+
+    for points in [&plane[..], &line, &point] {
+      let obb = Obb::from_points(points);
+      assert_finite_obb(&obb);
+      assert_obb_contains(&obb, points);
+    }
+  }
+
+  #[test]
+  fn obb_transform() {
+    let obb = Obb::from_points(&box_corners(
+      Vec3::new(2.0, 1.0, 0.5),
+      Mat4::from_rotation_y(0.7),
+    ));
+
+    // rigid and uniformly scaled, the box just moves along
+    let m = Mat4::from_translation(Vec3::new(1.0, 2.0, 3.0))
+      * Mat4::from_rotation_x(0.3)
+      * Mat4::from_scale(Vec3::splat(2.0));
+    let moved = obb.transform(m);
+    assert!((moved.volume() - obb.volume() * 8.0).abs() < 1e-3);
+    assert_obb_contains(&moved, &obb.corners().map(|c| m.transform_point3(c)));
+
+    // non-uniform scale on a turned box shears it, refitted
+    let m = Mat4::from_scale(Vec3::new(3.0, 1.0, 1.0));
+    let sheared = obb.transform(m);
+    assert_finite_obb(&sheared);
+    assert_obb_contains(
+      &sheared,
+      &obb.corners().map(|c| m.transform_point3(c)),
+    );
+
+    // flattened to a plane and to a point
+    for scale in [Vec3::new(1.0, 0.0, 1.0), Vec3::ZERO] {
+      let m = Mat4::from_scale(scale);
+      let flat = obb.transform(m);
+      assert_finite_obb(&flat);
+      assert_obb_contains(&flat, &obb.corners().map(|c| m.transform_point3(c)));
+    }
+  }
+
+  #[test]
+  fn obb_union() {
+    let a =
+      Obb::from_points(&box_corners(Vec3::ONE, Mat4::from_rotation_z(0.4)));
+    let b = a.transform(Mat4::from_translation(Vec3::new(5.0, 0.0, 0.0)));
+    let union = a.union(&b);
+    assert_obb_contains(&union, &a.corners());
+    assert_obb_contains(&union, &b.corners());
+    assert!(union.volume() >= a.volume());
+  }
+}
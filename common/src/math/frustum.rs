@@ -0,0 +1,198 @@
+use glam::{Mat4, Vec3};
+
+use super::{Aabb, DepthRange, Plane, Side, Sphere};
+
+// the volume visible through a view-projection matrix
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frustum {
+  // left, right, bottom, top, near, far, facing inward
+  pub planes: [Plane; 6],
+  corners: [Vec3; 8],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Containment {
+  Outside,
+  Inside,
+  Intersecting,
+}
+
+impl Frustum {
+  // with the opengl default clip space, i.e. -w <= x, y, z <= w
+  pub fn from_matrix(view_projection: Mat4) -> Self {
+    Self::from_matrix_with_depth(view_projection, DepthRange::NEG_ONE_TO_ONE)
+  }
+
+  // planes from the rows of the matrix (Gribb & Hartmann, 2001)
+  pub fn from_matrix_with_depth(
+    view_projection: Mat4,
+    depth: DepthRange,
+  ) -> Self {
+    let m = view_projection.transpose();
+    let (x, y, z, w) = (m.x_axis, m.y_axis, m.z_axis, m.w_axis);
+    // z between near * w and far * w, in whichever order
+    let sign = (depth.far - depth.near).signum();
+    let near = (z - w * depth.near) * sign;
+    let far = (w * depth.far - z) * sign;
+    let planes = [w + x, w - x, w + y, w - y, near, far].map(Plane::from_vec4);
+
+    let inverse = view_projection.inverse();
+    let ndc = Aabb::new(Vec3::NEG_ONE, Vec3::ONE);
+    let corners = ndc.corners().map(|c| {
+      let z = if c.z < 0.0 { depth.near } else { depth.far };
+      inverse.project_point3(c.with_z(z))
+    });
+
+    Self { planes, corners }
+  }
+
+  // in the order of Aabb::corners of the ndc cube, i.e. the near plane
+  // corners are the even ones. infinitely far away for an infinite far
+  // plane.
+  pub fn corners(&self) -> [Vec3; 8] {
+    self.corners
+  }
+
+  pub fn aabb(&self) -> Aabb {
+    Aabb::from_points(self.corners)
+  }
+
+  pub fn contains(&self, p: Vec3) -> bool {
+    self
+      .planes
+      .iter()
+      .all(|plane| plane.signed_distance(p) >= 0.0)
+  }
+
+  // conservative: large boxes just outside a corner of the frustum are
+  // reported as intersecting
+  pub fn classify_aabb(&self, aabb: &Aabb) -> Containment {
+    self.classify(|plane| plane.classify_aabb(aabb))
+  }
+
+  pub fn classify_sphere(&self, sphere: &Sphere) -> Containment {
+    self.classify(|plane| plane.classify_sphere(sphere))
+  }
+
+  pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+    self.classify_aabb(aabb) != Containment::Outside
+  }
+
+  pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+    self.classify_sphere(sphere) != Containment::Outside
+  }
+
+  fn classify(&self, side: impl Fn(&Plane) -> Side) -> Containment {
+    let mut containment = Containment::Inside;
+    for plane in &self.planes {
+      match side(plane) {
+        Side::Back => return Containment::Outside,
+        Side::Intersecting => containment = Containment::Intersecting,
+        Side::Front => {}
+      }
+    }
+    containment
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::f32::consts::FRAC_PI_2;
+
+  use super::*;
+
+  // 90 degrees wide and high, from z = -1 to z = -10, for each depth
+  // range
+  fn frustums() -> [(DepthRange, Frustum); 3] {
+    let projections = [
+      (
+        DepthRange::NEG_ONE_TO_ONE,
+        Mat4::perspective_rh_gl(FRAC_PI_2, 1.0, 1.0, 10.0),
+      ),
+      (
+        DepthRange::ZERO_TO_ONE,
+        Mat4::perspective_rh(FRAC_PI_2, 1.0, 1.0, 10.0),
+      ),
+      // swapping near and far reverses depth
+      (
+        DepthRange::REVERSED,
+        Mat4::perspective_rh(FRAC_PI_2, 1.0, 10.0, 1.0),
+      ),
+    ];
+    projections
+      .map(|(depth, m)| (depth, Frustum::from_matrix_with_depth(m, depth)))
+  }
+
+  #[test]
+  fn planes_for_each_depth_range() {
+    for (depth, frustum) in frustums() {
+      let [left, right, bottom, top, near, far] = frustum.planes;
+      let diagonal = std::f32::consts::FRAC_1_SQRT_2;
+      let expected = [
+        (left, Vec3::new(diagonal, 0.0, -diagonal), 0.0),
+        (right, Vec3::new(-diagonal, 0.0, -diagonal), 0.0),
+        (bottom, Vec3::new(0.0, diagonal, -diagonal), 0.0),
+        (top, Vec3::new(0.0, -diagonal, -diagonal), 0.0),
+        (near, Vec3::NEG_Z, -1.0),
+        (far, Vec3::Z, 10.0),
+      ];
+      for (plane, normal, d) in expected {
+        assert!(
+          plane.normal.abs_diff_eq(normal, 1e-5) && (plane.d - d).abs() < 1e-4,
+          "{depth:?}: {plane:?}, expected {normal} {d}"
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn from_matrix_is_neg_one_to_one() {
+    let m = Mat4::perspective_rh_gl(FRAC_PI_2, 1.0, 1.0, 10.0);
+    assert_eq!(Frustum::from_matrix(m), frustums()[0].1);
+  }
+
+  #[test]
+  fn corners_and_contains() {
+    for (depth, frustum) in frustums() {
+      for (i, corner) in frustum.corners().into_iter().enumerate() {
+        let z = if i % 2 == 0 { -1.0 } else { -10.0 };
+        assert!((corner.z - z).abs() < 1e-4, "{depth:?}: {i} {corner}");
+        assert!((corner.x.abs() + z).abs() < 1e-4, "{depth:?}: {i} {corner}");
+      }
+
+      assert!(frustum.contains(Vec3::new(0.0, 0.0, -5.0)));
+      assert!(frustum.contains(Vec3::new(4.9, -4.9, -5.0)));
+      assert!(!frustum.contains(Vec3::new(5.1, 0.0, -5.0)));
+      assert!(!frustum.contains(Vec3::new(0.0, 0.0, -0.5)));
+      assert!(!frustum.contains(Vec3::new(0.0, 0.0, -10.5)));
+      assert!(!frustum.contains(Vec3::new(0.0, 0.0, 5.0)));
+    }
+  }
+
+  #[test]
+  fn classify_aabb() {
+    let cube_at = |center: Vec3| Aabb::new(center - 0.5, center + 0.5);
+    for (depth, frustum) in frustums() {
+      let classify = |center| frustum.classify_aabb(&cube_at(center));
+      let inside = classify(Vec3::new(0.0, 0.0, -5.0));
+      assert_eq!(inside, Containment::Inside, "{depth:?}");
+      // behind the camera, beyond the far plane and off to the side
+      for center in [
+        Vec3::new(0.0, 0.0, 5.0),
+        Vec3::new(0.0, 0.0, -12.0),
+        Vec3::new(0.0, 8.0, -5.0),
+      ] {
+        assert_eq!(classify(center), Containment::Outside, "{depth:?}");
+      }
+      // across the near, far and left planes
+      for center in [
+        Vec3::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 0.0, -10.0),
+        Vec3::new(-5.0, 0.0, -5.0),
+      ] {
+        let containment = classify(center);
+        assert_eq!(containment, Containment::Intersecting, "{depth:?}");
+      }
+    }
+  }
+}
@@ -0,0 +1,159 @@
+use glam::{Mat4, Vec3, Vec4};
+
+use super::{Aabb, Sphere};
+
+// the points p with normal.dot(p) + d == 0. the side the normal points
+// to is the front.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Plane {
+  pub normal: Vec3,
+  pub d: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+  Front,
+  Back,
+  // touching or crossing the plane
+  Intersecting,
+}
+
+impl Plane {
+  pub fn new(normal: Vec3, d: f32) -> Self {
+    Self { normal, d }
+  }
+
+  pub fn from_point_normal(point: Vec3, normal: Vec3) -> Self {
+    let normal = normal.normalize();
+    Self::new(normal, -normal.dot(point))
+  }
+
+  // the front is where the points are counter-clockwise
+  pub fn from_points(a: Vec3, b: Vec3, c: Vec3) -> Self {
+    Self::from_point_normal(a, (b - a).cross(c - a))
+  }
+
+  // (a, b, c, d) of ax + by + cz + d = 0, normalized
+  // a zero normal is the plane at infinity, with everything in front
+  // of it for positive w, e.g. the far plane of an infinite projection
+  pub fn from_vec4(v: Vec4) -> Self {
+    let length = v.truncate().length();
+    if length == 0.0 {
+      return Self::new(Vec3::ZERO, v.w.signum() * f32::INFINITY);
+    }
+    Self::new(v.truncate() / length, v.w / length)
+  }
+
+  pub fn to_vec4(&self) -> Vec4 {
+    self.normal.extend(self.d)
+  }
+
+  pub fn flip(&self) -> Self {
+    Self::new(-self.normal, -self.d)
+  }
+
+  // positive in front. in world units as long as the normal has unit
+  // length, which all the constructors ensure.
+  pub fn signed_distance(&self, p: Vec3) -> f32 {
+    self.normal.dot(p) + self.d
+  }
+
+  pub fn project(&self, p: Vec3) -> Vec3 {
+    p - self.normal * self.signed_distance(p)
+  }
+
+  // `m` maps points into the space the plane should end up in, e.g. the
+  // view matrix to move a world space plane into view space
+  pub fn transform(&self, m: Mat4) -> Self {
+    Self::from_vec4(m.inverse().transpose() * self.to_vec4())
+  }
+
+  pub fn classify_point(&self, p: Vec3) -> Side {
+    side(self.signed_distance(p), 0.0)
+  }
+
+  pub fn classify_sphere(&self, sphere: &Sphere) -> Side {
+    side(self.signed_distance(sphere.center), sphere.radius)
+  }
+
+  pub fn classify_aabb(&self, aabb: &Aabb) -> Side {
+    // the extent of the box along the normal
+    let radius = aabb.half_extents().dot(self.normal.abs());
+    side(self.signed_distance(aabb.center()), radius)
+  }
+}
+
+fn side(distance: f32, radius: f32) -> Side {
+  if distance > radius {
+    Side::Front
+  } else if distance < -radius {
+    Side::Back
+  } else {
+    Side::Intersecting
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // y = 0, facing up
+  fn ground() -> Plane {
+    Plane::from_point_normal(Vec3::ZERO, Vec3::Y)
+  }
+
+  fn cube_at(center: Vec3) -> Aabb {
+    Aabb::new(center - 1.0, center + 1.0)
+  }
+
+  #[test]
+  fn from_points_is_counter_clockwise() {
+    let plane = Plane::from_points(Vec3::ZERO, Vec3::X, Vec3::NEG_Z);
+    assert_eq!(plane, ground());
+    assert_eq!(
+      Plane::from_points(Vec3::ZERO, Vec3::NEG_Z, Vec3::X).normal,
+      Vec3::NEG_Y
+    );
+  }
+
+  #[test]
+  fn classify_aabb() {
+    let plane = ground();
+    assert_eq!(plane.classify_aabb(&cube_at(Vec3::Y * 2.0)), Side::Front);
+    assert_eq!(plane.classify_aabb(&cube_at(Vec3::Y * -2.0)), Side::Back);
+    assert_eq!(
+      plane.classify_aabb(&cube_at(Vec3::Y * 0.5)),
+      Side::Intersecting
+    );
+    // touching counts as intersecting
+    assert_eq!(plane.classify_aabb(&cube_at(Vec3::Y)), Side::Intersecting);
+  }
+
+  #[test]
+  fn classify_aabb_on_a_tilted_plane() {
+    let plane = Plane::from_point_normal(Vec3::ZERO, Vec3::new(1.0, 1.0, 0.0));
+    // the corner nearest to the plane is 0.1 * sqrt(2) in front of it
+    let aabb = Aabb::new(Vec3::splat(0.1), Vec3::splat(2.0));
+    assert_eq!(plane.classify_aabb(&aabb), Side::Front);
+    let aabb = Aabb::new(Vec3::splat(-0.1), Vec3::splat(2.0));
+    assert_eq!(plane.classify_aabb(&aabb), Side::Intersecting);
+    assert_eq!(
+      plane.flip().classify_aabb(&cube_at(Vec3::splat(3.0))),
+      Side::Back
+    );
+  }
+
+  #[test]
+  fn transform() {
+    let m = Mat4::from_translation(Vec3::new(0.0, 3.0, 0.0))
+      * Mat4::from_rotation_z(std::f32::consts::FRAC_PI_2);
+    // the ground turned to face -x, then moved up, which doesn't move it
+    let plane = ground().transform(m);
+    assert!(plane.normal.abs_diff_eq(Vec3::NEG_X, 1e-6));
+    assert!(plane.d.abs() < 1e-6);
+
+    let plane = ground().transform(Mat4::from_translation(Vec3::Y * 3.0));
+    assert_eq!(plane.signed_distance(Vec3::new(5.0, 3.0, -2.0)), 0.0);
+    assert_eq!(plane.signed_distance(Vec3::ZERO), -3.0);
+  }
+}
@@ -0,0 +1,203 @@
+use glam::{Mat4, Vec3};
+
+use super::{Aabb, Plane, Sphere};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ray {
+  pub origin: Vec3,
+  // not necessarily normalized, t is measured in multiples of it
+  pub dir: Vec3,
+}
+
+// where a ray crosses a triangle
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TriangleHit {
+  pub t: f32,
+  // weights of the second and third vertex, the first one gets
+  // 1 - u - v
+  pub u: f32,
+  pub v: f32,
+}
+
+impl Ray {
+  pub fn new(origin: Vec3, dir: Vec3) -> Self {
+    Self { origin, dir }
+  }
+
+  // the ray from `from` through `to`, reaching `to` at t = 1
+  pub fn between(from: Vec3, to: Vec3) -> Self {
+    Self::new(from, to - from)
+  }
+
+  pub fn at(&self, t: f32) -> Vec3 {
+    self.origin + self.dir * t
+  }
+
+  // into another space, e.g. a model's. the direction isn't normalized
+  // again, so t is the same in both.
+  pub fn transform(&self, m: Mat4) -> Self {
+    Self::new(
+      m.transform_point3(self.origin),
+      m.transform_vector3(self.dir),
+    )
+  }
+
+  // the range of t inside the box, clipped to [0, t_max] (slab test)
+  pub fn intersect_aabb(&self, aabb: &Aabb, t_max: f32) -> Option<(f32, f32)> {
+    self.intersect_aabb_inv(aabb, self.dir.recip(), t_max)
+  }
+
+  // with the reciprocal of the direction precomputed, for traversing
+  // many boxes
+  pub(crate) fn intersect_aabb_inv(
+    &self,
+    aabb: &Aabb,
+    inv_dir: Vec3,
+    t_max: f32,
+  ) -> Option<(f32, f32)> {
+    let t1 = (aabb.min - self.origin) * inv_dir;
+    let t2 = (aabb.max - self.origin) * inv_dir;
+    // a ray running exactly in a slab boundary gets 0 * inf = nan and
+    // misses, like it misses triangles it's parallel to
+    let near = t1.min(t2).max_element().max(0.0);
+    let far = t1.max(t2).min_element().min(t_max);
+    (near <= far).then_some((near, far))
+  }
+
+  // from either side of the plane
+  pub fn intersect_plane(&self, plane: &Plane, t_max: f32) -> Option<f32> {
+    let t = -plane.signed_distance(self.origin) / plane.normal.dot(self.dir);
+    (0.0..=t_max).contains(&t).then_some(t)
+  }
+
+  // where the ray enters the sphere, or 0 if it starts inside
+  pub fn intersect_sphere(&self, sphere: &Sphere, t_max: f32) -> Option<f32> {
+    let oc = self.origin - sphere.center;
+    let a = self.dir.length_squared();
+    let b = oc.dot(self.dir);
+    let c = oc.length_squared() - sphere.radius * sphere.radius;
+    let discriminant = b * b - a * c;
+    if discriminant < 0.0 {
+      return None;
+    }
+
+    let sqrt = discriminant.sqrt();
+    let (near, far) = ((-b - sqrt) / a, (-b + sqrt) / a);
+    if far < 0.0 || near > t_max {
+      return None;
+    }
+    Some(near.max(0.0))
+  }
+
+  // Möller-Trumbore, hits from both sides
+  pub fn intersect_triangle(
+    &self,
+    [a, b, c]: [Vec3; 3],
+    t_max: f32,
+  ) -> Option<TriangleHit> {
+    let (e1, e2) = (b - a, c - a);
+    let p = self.dir.cross(e2);
+    let det = e1.dot(p);
+    if det.abs() < f32::EPSILON * e1.length() * e2.length() {
+      return None;
+    }
+
+    let inv_det = 1.0 / det;
+    let s = self.origin - a;
+    let u = s.dot(p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+      return None;
+    }
+
+    let q = s.cross(e1);
+    let v = self.dir.dot(q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+      return None;
+    }
+
+    let t = e2.dot(q) * inv_det;
+    (0.0..=t_max)
+      .contains(&t)
+      .then_some(TriangleHit { t, u, v })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn unit_box() -> Aabb {
+    Aabb::new(Vec3::NEG_ONE, Vec3::ONE)
+  }
+
+  #[test]
+  fn aabb_hit() {
+    let ray = Ray::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::X);
+    assert_eq!(ray.intersect_aabb(&unit_box(), 100.0), Some((4.0, 6.0)));
+    // clipped to t_max
+    assert_eq!(ray.intersect_aabb(&unit_box(), 5.0), Some((4.0, 5.0)));
+    assert_eq!(ray.intersect_aabb(&unit_box(), 3.0), None);
+  }
+
+  #[test]
+  fn aabb_miss() {
+    let ray = Ray::new(Vec3::new(-5.0, 3.0, 0.0), Vec3::X);
+    assert_eq!(ray.intersect_aabb(&unit_box(), 100.0), None);
+    // pointing away
+    let ray = Ray::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::NEG_X);
+    assert_eq!(ray.intersect_aabb(&unit_box(), 100.0), None);
+  }
+
+  #[test]
+  fn transform_keeps_t() {
+    let ray = Ray::new(Vec3::new(-5.0, 1.0, 0.0), Vec3::X);
+    let m = Mat4::from_scale(Vec3::splat(0.5))
+      * Mat4::from_rotation_y(1.0)
+      * Mat4::from_translation(Vec3::new(1.0, 2.0, 3.0));
+    let moved = ray.transform(m);
+    for t in [0.0, 1.0, 7.5] {
+      assert!(moved.at(t).abs_diff_eq(m.transform_point3(ray.at(t)), 1e-5));
+    }
+  }
+
+  #[test]
+  fn aabb_from_inside() {
+    let ray = Ray::new(Vec3::new(0.5, 0.0, 0.0), Vec3::X);
+    assert_eq!(ray.intersect_aabb(&unit_box(), 100.0), Some((0.0, 0.5)));
+  }
+
+  // facing +z
+  const TRIANGLE: [Vec3; 3] = [Vec3::ZERO, Vec3::X, Vec3::Y];
+
+  #[test]
+  fn triangle_hit() {
+    let ray = Ray::new(Vec3::new(0.25, 0.5, 2.0), Vec3::NEG_Z);
+    let hit = ray.intersect_triangle(TRIANGLE, 100.0).unwrap();
+    assert_eq!((hit.t, hit.u, hit.v), (2.0, 0.25, 0.5));
+    assert!(ray.intersect_triangle(TRIANGLE, 1.0).is_none());
+
+    let ray = Ray::new(Vec3::new(0.75, 0.5, 2.0), Vec3::NEG_Z);
+    assert!(ray.intersect_triangle(TRIANGLE, 100.0).is_none());
+  }
+
+  #[test]
+  fn triangle_edge() {
+    // on the edge from the first to the second vertex
+    let ray = Ray::new(Vec3::new(0.5, 0.0, 1.0), Vec3::NEG_Z);
+    let hit = ray.intersect_triangle(TRIANGLE, 100.0).unwrap();
+    assert_eq!((hit.t, hit.u, hit.v), (1.0, 0.5, 0.0));
+    // and on a vertex
+    let ray = Ray::new(Vec3::new(0.0, 1.0, 1.0), Vec3::NEG_Z);
+    assert!(ray.intersect_triangle(TRIANGLE, 100.0).is_some());
+  }
+
+  #[test]
+  fn triangle_back_face() {
+    let ray = Ray::new(Vec3::new(0.25, 0.5, -2.0), Vec3::Z);
+    let hit = ray.intersect_triangle(TRIANGLE, 100.0).unwrap();
+    assert_eq!((hit.t, hit.u, hit.v), (2.0, 0.25, 0.5));
+    // parallel to it
+    let ray = Ray::new(Vec3::new(-1.0, 0.25, 0.0), Vec3::X);
+    assert!(ray.intersect_triangle(TRIANGLE, 100.0).is_none());
+  }
+}
@@ -1,4 +1,24 @@
-use std::collections::{BTreeMap, BTreeSet};
+mod adjacency;
+mod bvh;
+mod lod_cache;
+mod repair;
+mod simplify;
+mod subdivide;
+
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  ops::Range,
+};
+
+pub use adjacency::{adjacency_indices, BoundaryAdjacency};
+pub use bvh::{Bvh, Hit};
+pub use lod_cache::{cached_lod_chain, lod_cache_path};
+pub use repair::{repair, RepairReport};
+pub use simplify::{
+  lod_chain, select_lod, simplify, split_by_group, Lod, LodStrips, Simplified,
+  DEFAULT_LOD_RATIOS,
+};
+pub use subdivide::{catmull_clark, loop_subdivide};
 
 pub fn tear_into_strips(indices: &[u32]) -> Vec<Vec<u32>> {
   let mut trigs: BTreeSet<[u32; 3]> = BTreeSet::new();
@@ -47,11 +67,7 @@ pub fn tear_into_strips(indices: &[u32]) -> Vec<Vec<u32>> {
       }
     }
 
-    loop {
-      let Some((t, d)) = next_vert(&mut trigs, b, c) else {
-        break;
-      };
-
+    while let Some((t, d)) = next_vert(&mut trigs, b, c) {
       // add vertex to strip and mark the triangle as processed
       trigs.remove(t);
       strip.push(d);
@@ -88,6 +104,23 @@ pub fn concat_strips(strips: &[Vec<u32>]) -> Vec<u32> {
   indices
 }
 
+// strips the triangles of each group and appends them to indices,
+// returning the range of indices each group ended up in
+pub fn append_strips(
+  indices: &mut Vec<u32>,
+  group_trigs: &[Vec<u32>],
+) -> Vec<Range<u32>> {
+  group_trigs
+    .iter()
+    .map(|trigs| {
+      let strips = tear_into_strips(trigs);
+      let begin = indices.len() as u32;
+      indices.extend_from_slice(&concat_strips(&strips));
+      begin..(indices.len() as u32)
+    })
+    .collect()
+}
+
 #[allow(unused)]
 fn validate_trig_strips(indices: &[u32], strips: &[Vec<u32>]) {
   let mut original_trigs: Vec<[u32; 3]> = indices
@@ -127,3 +160,51 @@ fn rotate3<T: Ord>(mut a: [T; 3]) -> [T; 3] {
 
   a
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // the triangles of a strip, each rotated to start at its lowest
+  // index, the degenerate ones left out
+  fn strip_trigs(strip: &[u32]) -> Vec<[u32; 3]> {
+    let mut trigs: Vec<[u32; 3]> = strip
+      .windows(3)
+      .enumerate()
+      .map(|(i, w)| {
+        let t = [w[0], w[1], w[2]];
+        // every other triangle is wound the other way
+        rotate3(if i % 2 == 1 { [t[1], t[0], t[2]] } else { t })
+      })
+      .filter(|t| t[0] != t[1] && t[1] != t[2] && t[2] != t[0])
+      .collect();
+    trigs.sort();
+    trigs
+  }
+
+  fn list_trigs(indices: &[u32]) -> Vec<[u32; 3]> {
+    let mut trigs: Vec<[u32; 3]> = indices
+      .chunks(3)
+      .map(|t| rotate3(t.try_into().unwrap()))
+      .collect();
+    trigs.sort();
+    trigs
+  }
+
+  #[test]
+  fn strips_keep_the_triangles() {
+    // a fan and a strip of quads, sharing no edges
+    let fan = vec![0, 1, 2, 0, 2, 3, 0, 3, 4, 0, 4, 5];
+    let quads = vec![6, 7, 8, 8, 7, 9, 8, 9, 10, 10, 9, 11];
+    let mut indices = vec![42];
+    let ranges = append_strips(&mut indices, &[fan.clone(), quads.clone()]);
+
+    assert_eq!(ranges[0].start, 1);
+    assert_eq!(ranges[0].end, ranges[1].start);
+    assert_eq!(ranges[1].end as usize, indices.len());
+    for (range, trigs) in ranges.iter().zip([fan, quads]) {
+      let strip = &indices[range.start as usize..range.end as usize];
+      assert_eq!(strip_trigs(strip), list_trigs(&trigs));
+    }
+  }
+}
@@ -0,0 +1,158 @@
+// Index buffers for PrimitiveType::TrianglesListAdjacency. Each
+// triangle (v0, v1, v2) becomes six indices
+//
+//   v0, a01, v1, a12, v2, a20
+//
+// where aXY is the vertex of the neighbouring triangle opposite to the
+// edge XY. Triangles are matched by position, so neighbours across
+// uv/normal seams are still found.
+
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BoundaryAdjacency {
+  // the triangle's own opposite vertex, the boundary edge then looks
+  // like an edge between two coplanar faces and is never a silhouette.
+  Opposite,
+  // the first vertex of the edge, giving a degenerate neighbour with
+  // zero area that the geometry shader can test for. boundary edges
+  // are usually silhouettes for outlines and shadow volumes.
+  Degenerate,
+  // a fixed index, e.g. a vertex placed far away.
+  Index(u32),
+}
+
+// `indices` is a triangle list into `positions`.
+pub fn adjacency_indices(
+  positions: &[[f32; 3]],
+  indices: &[u32],
+  boundary: BoundaryAdjacency,
+) -> Vec<u32> {
+  // vertex index => index of the first vertex at the same position
+  let mut point_ids: HashMap<[u32; 3], u32> = HashMap::new();
+  let point_of: Vec<u32> = positions
+    .iter()
+    .enumerate()
+    .map(|(i, p)| *point_ids.entry(p.map(f32::to_bits)).or_insert(i as u32))
+    .collect();
+
+  // directed edge (from, to) => vertices opposite of it, more than one
+  // on non-manifold edges
+  let mut opposite: HashMap<[u32; 2], Vec<u32>> = HashMap::new();
+  for trig in indices.chunks_exact(3) {
+    for i in 0..3 {
+      let (a, b, c) = (trig[i], trig[(i + 1) % 3], trig[(i + 2) % 3]);
+      let edge = [point_of[a as usize], point_of[b as usize]];
+      opposite.entry(edge).or_default().push(c);
+    }
+  }
+
+  let mut adjacency = Vec::with_capacity(indices.len() * 2);
+  for trig in indices.chunks_exact(3) {
+    for i in 0..3 {
+      let (a, b, c) = (trig[i], trig[(i + 1) % 3], trig[(i + 2) % 3]);
+      // the neighbour with consistent winding runs the edge backwards
+      let edge = [point_of[a as usize], point_of[b as usize]];
+      let twin = [edge[1], edge[0]];
+      let adjacent = opposite
+        .get(&twin)
+        .and_then(|o| o.first().copied())
+        .or_else(|| {
+          // or forwards if the winding of the neighbour is flipped
+          opposite[&edge]
+            .iter()
+            .copied()
+            .find(|&d| point_of[d as usize] != point_of[c as usize])
+        });
+
+      adjacency.push(a);
+      adjacency.push(adjacent.unwrap_or(match boundary {
+        BoundaryAdjacency::Opposite => c,
+        BoundaryAdjacency::Degenerate => a,
+        BoundaryAdjacency::Index(index) => index,
+      }));
+    }
+  }
+
+  adjacency
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // a unit square split along the diagonal 0-2
+  const SQUARE: [[f32; 3]; 4] = [
+    [0.0, 0.0, 0.0],
+    [1.0, 0.0, 0.0],
+    [1.0, 1.0, 0.0],
+    [0.0, 1.0, 0.0],
+  ];
+
+  #[test]
+  fn neighbours_across_the_diagonal() {
+    let adjacency = adjacency_indices(
+      &SQUARE,
+      &[0, 1, 2, 0, 2, 3],
+      BoundaryAdjacency::Degenerate,
+    );
+    assert_eq!(adjacency, [0, 0, 1, 1, 2, 3, 0, 1, 2, 2, 3, 3]);
+  }
+
+  #[test]
+  fn boundary_edges() {
+    let indices = [0, 1, 2, 0, 2, 3];
+    let adjacency =
+      adjacency_indices(&SQUARE, &indices, BoundaryAdjacency::Opposite);
+    assert_eq!(adjacency, [0, 2, 1, 0, 2, 3, 0, 1, 2, 0, 3, 2]);
+
+    let adjacency =
+      adjacency_indices(&SQUARE, &indices, BoundaryAdjacency::Index(99));
+    assert_eq!(adjacency, [0, 99, 1, 99, 2, 3, 0, 1, 2, 99, 3, 99]);
+  }
+
+  #[test]
+  fn neighbours_across_seams() {
+    // the second triangle uses copies of the diagonal's vertices
+    let positions = [SQUARE.to_vec(), vec![SQUARE[0], SQUARE[2]]].concat();
+    let adjacency = adjacency_indices(
+      &positions,
+      &[0, 1, 2, 4, 5, 3],
+      BoundaryAdjacency::Degenerate,
+    );
+    assert_eq!(adjacency, [0, 0, 1, 1, 2, 3, 4, 1, 5, 5, 3, 3]);
+  }
+
+  #[test]
+  fn neighbours_with_flipped_winding() {
+    let adjacency = adjacency_indices(
+      &SQUARE,
+      &[0, 1, 2, 0, 3, 2],
+      BoundaryAdjacency::Degenerate,
+    );
+    assert_eq!(adjacency, [0, 0, 1, 1, 2, 3, 0, 0, 3, 3, 2, 1]);
+  }
+
+  #[test]
+  fn non_manifold_edges() {
+    // three triangles on the edge 0-1
+    let positions = [
+      [0.0, 0.0, 0.0],
+      [0.0, 0.0, 1.0],
+      [1.0, 0.0, 0.0],
+      [0.0, 1.0, 0.0],
+      [-1.0, 0.0, 0.0],
+    ];
+    let adjacency = adjacency_indices(
+      &positions,
+      &[0, 1, 2, 1, 0, 3, 1, 0, 4],
+      BoundaryAdjacency::Degenerate,
+    );
+    assert_eq!(adjacency.len(), 18);
+    // one of the other two is picked
+    assert!([3, 4].contains(&adjacency[1]));
+    // both see the first one
+    assert_eq!(adjacency[7], 2);
+    assert_eq!(adjacency[13], 2);
+  }
+}
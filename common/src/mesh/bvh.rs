@@ -0,0 +1,572 @@
+// Bounding volume hierarchy over the triangles of a mesh, for ray
+// casts on the cpu: picking, baking and reference renders.
+//
+// Built top-down with the surface area heuristic evaluated on a fixed
+// number of bins per axis. Triangles are copied into leaf order so a
+// traversal touches contiguous memory.
+
+use std::ops::{Add, Mul};
+
+use glam::{Mat4, Vec3};
+
+use crate::math::{Aabb, Ray, TriangleHit};
+use crate::obj_loader::{Obj, SimpleObj};
+
+const BINS: usize = 16;
+// leaves never get bigger than this, even if the heuristic says so
+const MAX_LEAF_TRIGS: usize = 8;
+// cost of visiting a node relative to intersecting a triangle
+const TRAVERSAL_COST: f32 = 1.0;
+// past this depth nodes are split in half, which bounds the depth of
+// the tree and the stack needed to traverse it by 2 * MAX_SAH_DEPTH
+const MAX_SAH_DEPTH: usize = 32;
+const STACK_SIZE: usize = 2 * MAX_SAH_DEPTH;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hit {
+  pub t: f32,
+  pub point: Vec3,
+  // weights of the triangle's three vertices at `point`
+  pub barycentric: Vec3,
+  // index of the triangle in the source, i.e. indices[3 * triangle..]
+  // or the n-th triangle of the obj
+  pub triangle: usize,
+  // the trig group, or the obj group the triangle belongs to
+  pub group: usize,
+}
+
+impl Hit {
+  // e.g. the uv or normal at the hit, from the triangle's vertices
+  pub fn interpolate<T>(&self, [a, b, c]: [T; 3]) -> T
+  where
+    T: Mul<f32, Output = T> + Add<Output = T>,
+  {
+    let w = self.barycentric;
+    a * w.x + b * w.y + c * w.z
+  }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Node {
+  aabb: Aabb,
+  // leaf: the first triangle. inner node: the second child, the first
+  // one directly follows the node.
+  index: u32,
+  // number of triangles, 0 for inner nodes
+  count: u32,
+}
+
+#[derive(Clone)]
+pub struct Bvh {
+  nodes: Vec<Node>,
+  // in leaf order
+  trigs: Vec<[Vec3; 3]>,
+  trig_ids: Vec<u32>,
+  trig_groups: Vec<u32>,
+}
+
+// a triangle while building
+struct BuildTrig {
+  aabb: Aabb,
+  centroid: Vec3,
+  id: u32,
+}
+
+impl Bvh {
+  // `indices` is a triangle list into `positions`, `trig_groups` holds
+  // the group of each triangle and may be empty.
+  pub fn new(
+    positions: &[[f32; 3]],
+    indices: &[u32],
+    trig_groups: &[u32],
+  ) -> Self {
+    assert_eq!(indices.len() % 3, 0);
+    assert!(trig_groups.is_empty() || trig_groups.len() * 3 == indices.len());
+
+    let trigs = indices
+      .chunks_exact(3)
+      .map(|t| [0, 1, 2].map(|i| Vec3::from(positions[t[i] as usize])))
+      .collect();
+    let trig_groups = if trig_groups.is_empty() {
+      vec![0; indices.len() / 3]
+    } else {
+      trig_groups.to_vec()
+    };
+
+    Self::build(trigs, trig_groups)
+  }
+
+  pub fn from_simple_obj(obj: &SimpleObj) -> Self {
+    let trigs: Vec<_> = obj
+      .trigs()
+      .map(|t| t.map(|[v, _, _]| Vec3::from(obj.v[v - 1])))
+      .collect();
+    let trig_groups = vec![0; trigs.len()];
+
+    Self::build(trigs, trig_groups)
+  }
+
+  // the group of a hit is the index into `obj.groups`, which also
+  // tells the material
+  pub fn from_obj(obj: &Obj) -> Self {
+    let mut trigs = Vec::new();
+    let mut trig_groups = Vec::new();
+    for (g, group) in obj.groups.iter().enumerate() {
+      for t in group.trigs() {
+        trigs.push(t.map(|[v, _, _]| Vec3::from(obj.v[v - 1])));
+        trig_groups.push(g as u32);
+      }
+    }
+
+    Self::build(trigs, trig_groups)
+  }
+
+  fn build(trigs: Vec<[Vec3; 3]>, trig_groups: Vec<u32>) -> Self {
+    let mut build_trigs: Vec<BuildTrig> = trigs
+      .iter()
+      .enumerate()
+      .map(|(i, t)| {
+        let aabb = Aabb::from_points(*t);
+        BuildTrig {
+          aabb,
+          centroid: aabb.center(),
+          id: i as u32,
+        }
+      })
+      .collect();
+
+    let mut nodes = Vec::with_capacity(trigs.len() * 2);
+    if !build_trigs.is_empty() {
+      build_node(&mut nodes, &mut build_trigs, 0, 0);
+    }
+
+    let trig_ids: Vec<u32> = build_trigs.iter().map(|t| t.id).collect();
+    Self {
+      nodes,
+      trigs: trig_ids.iter().map(|&i| trigs[i as usize]).collect(),
+      trig_ids,
+      trig_groups,
+    }
+  }
+
+  pub fn aabb(&self) -> Aabb {
+    self.nodes.first().map_or(Aabb::EMPTY, |n| n.aabb)
+  }
+
+  pub fn trig_count(&self) -> usize {
+    self.trigs.len()
+  }
+
+  // the nearest hit within [0, t_max]
+  pub fn closest_hit(&self, ray: &Ray, t_max: f32) -> Option<Hit> {
+    self.traverse(ray, t_max, false)
+  }
+
+  // for the mesh placed in the world by `model`, with the ray in world
+  // space: t is along that ray, the point is in model space, e.g. for
+  // picking
+  pub fn closest_hit_placed(
+    &self,
+    ray: &Ray,
+    model: Mat4,
+    t_max: f32,
+  ) -> Option<Hit> {
+    self.closest_hit(&ray.transform(model.inverse()), t_max)
+  }
+
+  // any hit within [0, t_max], cheaper when only occlusion matters,
+  // e.g. for shadow rays
+  pub fn any_hit(&self, ray: &Ray, t_max: f32) -> Option<Hit> {
+    self.traverse(ray, t_max, true)
+  }
+
+  fn traverse(&self, ray: &Ray, mut t_max: f32, any: bool) -> Option<Hit> {
+    let root = self.nodes.first()?;
+    ray.intersect_aabb(&root.aabb, t_max)?;
+
+    let inv_dir = ray.dir.recip();
+    let mut closest: Option<(usize, TriangleHit)> = None;
+    // (node, where the ray enters it)
+    let mut stack = [(0u32, 0.0f32); STACK_SIZE];
+    let mut len = 0;
+    let mut node = 0;
+
+    loop {
+      let Node { index, count, .. } = self.nodes[node];
+
+      if count > 0 {
+        let (start, end) = (index as usize, (index + count) as usize);
+        for (i, trig) in self.trigs[start..end].iter().enumerate() {
+          if let Some(hit) = ray.intersect_triangle(*trig, t_max) {
+            t_max = hit.t;
+            closest = Some((start + i, hit));
+            if any {
+              return closest.map(|(i, hit)| self.hit(ray, i, hit));
+            }
+          }
+        }
+      } else {
+        // descend into the nearer child, the other one goes on the
+        // stack
+        let children = [node + 1, index as usize];
+        let [near, far] = children.map(|c| {
+          ray
+            .intersect_aabb_inv(&self.nodes[c].aabb, inv_dir, t_max)
+            .map(|(t, _)| (t, c))
+        });
+        let (near, far) = match (near, far) {
+          (Some(a), Some(b)) if b.0 < a.0 => (Some(b), Some(a)),
+          (None, b) => (b, None),
+          pair => pair,
+        };
+
+        if let Some((_, c)) = near {
+          if let Some((t, f)) = far {
+            stack[len] = (f as u32, t);
+            len += 1;
+          }
+          node = c;
+          continue;
+        }
+      }
+
+      // skipping nodes that are behind the closest hit by now
+      loop {
+        let Some(next) = len.checked_sub(1) else {
+          return closest.map(|(i, hit)| self.hit(ray, i, hit));
+        };
+        len = next;
+        if stack[len].1 <= t_max {
+          break;
+        }
+      }
+      node = stack[len].0 as usize;
+    }
+  }
+
+  fn hit(&self, ray: &Ray, i: usize, hit: TriangleHit) -> Hit {
+    let id = self.trig_ids[i] as usize;
+    Hit {
+      t: hit.t,
+      point: ray.at(hit.t),
+      barycentric: Vec3::new(1.0 - hit.u - hit.v, hit.u, hit.v),
+      triangle: id,
+      group: self.trig_groups[id] as usize,
+    }
+  }
+}
+
+// appends the node for `trigs` and its subtree, `offset` is where the
+// slice starts in the final triangle order
+fn build_node(
+  nodes: &mut Vec<Node>,
+  trigs: &mut [BuildTrig],
+  offset: usize,
+  depth: usize,
+) {
+  let aabb = trigs
+    .iter()
+    .fold(Aabb::EMPTY, |aabb, t| aabb.union(&t.aabb));
+  let node = nodes.len();
+  nodes.push(Node {
+    aabb,
+    index: offset as u32,
+    count: trigs.len() as u32,
+  });
+
+  if trigs.len() == 1 {
+    return;
+  }
+
+  let leaf_cost = trigs.len() as f32 * aabb.surface_area();
+  let best = if depth < MAX_SAH_DEPTH {
+    find_split(trigs)
+  } else {
+    None
+  };
+  let split = match best {
+    Some((cost, split)) if cost < leaf_cost => split,
+    Some((_, split)) if trigs.len() > MAX_LEAF_TRIGS => split,
+    // too deep, or all centroids in the same spot
+    None if trigs.len() > MAX_LEAF_TRIGS => Split::Median,
+    _ => return,
+  };
+
+  let mid = match split {
+    Split::Bin {
+      axis,
+      bin,
+      min,
+      scale,
+    } => partition(trigs, |t| bin_of(t.centroid[axis], min, scale) < bin),
+    Split::Median => {
+      let bounds = Aabb::from_points(trigs.iter().map(|t| t.centroid));
+      let size = bounds.size();
+      let axis = if size.x >= size.y.max(size.z) {
+        0
+      } else if size.y >= size.z {
+        1
+      } else {
+        2
+      };
+      let mid = trigs.len() / 2;
+      trigs.select_nth_unstable_by(mid, |a, b| {
+        a.centroid[axis].total_cmp(&b.centroid[axis])
+      });
+      mid
+    }
+  };
+
+  let (left, right) = trigs.split_at_mut(mid);
+  build_node(nodes, left, offset, depth + 1);
+  let second = nodes.len();
+  build_node(nodes, right, offset + mid, depth + 1);
+
+  nodes[node].index = second as u32;
+  nodes[node].count = 0;
+}
+
+#[derive(Clone, Copy)]
+enum Split {
+  // the triangles with centroids in bins below `bin` go left
+  Bin {
+    axis: usize,
+    bin: usize,
+    min: f32,
+    scale: f32,
+  },
+  // half of the triangles on each side, along the longest axis
+  Median,
+}
+
+fn bin_of(c: f32, min: f32, scale: f32) -> usize {
+  (((c - min) * scale) as usize).min(BINS - 1)
+}
+
+// the cheapest binned split over all axes and its SAH cost
+fn find_split(trigs: &[BuildTrig]) -> Option<(f32, Split)> {
+  let bounds = Aabb::from_points(trigs.iter().map(|t| t.centroid));
+  let mut best: Option<(f32, Split)> = None;
+
+  for axis in 0..3 {
+    let (min, max) = (bounds.min[axis], bounds.max[axis]);
+    if max <= min {
+      continue;
+    }
+    let scale = BINS as f32 / (max - min);
+
+    let mut bins = [(Aabb::EMPTY, 0usize); BINS];
+    for t in trigs {
+      let bin = &mut bins[bin_of(t.centroid[axis], min, scale)];
+      bin.0 = bin.0.union(&t.aabb);
+      bin.1 += 1;
+    }
+
+    // sweep from the right, then from the left
+    let mut right_cost = [0.0; BINS];
+    let (mut aabb, mut count) = (Aabb::EMPTY, 0);
+    for bin in (1..BINS).rev() {
+      aabb = aabb.union(&bins[bin].0);
+      count += bins[bin].1;
+      right_cost[bin] = aabb.surface_area() * count as f32;
+    }
+
+    let (mut aabb, mut count) = (Aabb::EMPTY, 0);
+    for bin in 1..BINS {
+      aabb = aabb.union(&bins[bin - 1].0);
+      count += bins[bin - 1].1;
+      if count == 0 || count == trigs.len() {
+        continue;
+      }
+
+      let cost = TRAVERSAL_COST * bounds.surface_area()
+        + aabb.surface_area() * count as f32
+        + right_cost[bin];
+      if best.is_none_or(|(c, _)| cost < c) {
+        best = Some((
+          cost,
+          Split::Bin {
+            axis,
+            bin,
+            min,
+            scale,
+          },
+        ));
+      }
+    }
+  }
+
+  best
+}
+
+// moves the matching triangles to the front, returns how many there are
+fn partition(
+  trigs: &mut [BuildTrig],
+  pred: impl Fn(&BuildTrig) -> bool,
+) -> usize {
+  let mut mid = 0;
+  for i in 0..trigs.len() {
+    if pred(&trigs[i]) {
+      trigs.swap(i, mid);
+      mid += 1;
+    }
+  }
+  mid
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // xorshift, the same soup on every run
+  struct Rng(u64);
+
+  impl Rng {
+    fn next(&mut self) -> f32 {
+      self.0 ^= self.0 << 13;
+      self.0 ^= self.0 >> 7;
+      self.0 ^= self.0 << 17;
+      (self.0 >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    fn vec3(&mut self, scale: f32) -> Vec3 {
+      (Vec3::new(self.next(), self.next(), self.next()) * 2.0 - 1.0) * scale
+    }
+  }
+
+  // small triangles scattered over a cube, some of them overlapping
+  fn soup(rng: &mut Rng, count: usize) -> (Vec<[f32; 3]>, Vec<u32>) {
+    let mut positions = Vec::new();
+    for _ in 0..count {
+      let center = rng.vec3(10.0);
+      for _ in 0..3 {
+        positions.push((center + rng.vec3(1.0)).to_array());
+      }
+    }
+    let indices = (0..positions.len() as u32).collect();
+    (positions, indices)
+  }
+
+  fn linear_closest(
+    positions: &[[f32; 3]],
+    indices: &[u32],
+    ray: &Ray,
+  ) -> Option<(usize, f32)> {
+    let mut closest = None;
+    let mut t_max = f32::INFINITY;
+    for (i, t) in indices.chunks_exact(3).enumerate() {
+      let trig = [0, 1, 2].map(|j| Vec3::from(positions[t[j] as usize]));
+      if let Some(hit) = ray.intersect_triangle(trig, t_max) {
+        t_max = hit.t;
+        closest = Some((i, hit.t));
+      }
+    }
+    closest
+  }
+
+  #[test]
+  fn matches_a_linear_scan() {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    let (positions, indices) = soup(&mut rng, 500);
+    let groups: Vec<u32> = (0..500).map(|i| i % 7).collect();
+    let bvh = Bvh::new(&positions, &indices, &groups);
+    assert_eq!(bvh.trig_count(), 500);
+
+    let mut hits = 0;
+    for _ in 0..2000 {
+      // from outside towards somewhere inside, or from inside anywhere
+      let origin = rng.vec3(15.0);
+      let ray = Ray::between(origin, rng.vec3(10.0));
+
+      let expected = linear_closest(&positions, &indices, &ray);
+      let hit = bvh.closest_hit(&ray, f32::INFINITY);
+      assert_eq!(hit.is_some(), expected.is_some(), "{:?}", ray);
+      assert_eq!(
+        bvh.any_hit(&ray, f32::INFINITY).is_some(),
+        expected.is_some()
+      );
+
+      let (Some(hit), Some((triangle, t))) = (hit, expected) else {
+        continue;
+      };
+      hits += 1;
+      assert_eq!(hit.t, t);
+      // a tie only with triangles crossing at the very same point
+      if hit.triangle != triangle {
+        assert_eq!(hit.point, ray.at(t));
+      }
+      assert_eq!(hit.group, groups[hit.triangle] as usize);
+      assert!((hit.barycentric.element_sum() - 1.0).abs() < 1e-5);
+      assert!(hit.barycentric.min_element() >= -1e-6);
+    }
+    // enough of them hit for the comparison to mean something
+    assert!(hits > 200, "{} hits", hits);
+  }
+
+  #[test]
+  fn respects_t_max() {
+    let mut rng = Rng(7);
+    let (positions, indices) = soup(&mut rng, 200);
+    let bvh = Bvh::new(&positions, &indices, &[]);
+    for _ in 0..500 {
+      let ray = Ray::between(rng.vec3(15.0), rng.vec3(10.0));
+      if let Some(hit) = bvh.closest_hit(&ray, 1.0) {
+        assert!(hit.t <= 1.0);
+        assert_eq!(
+          linear_closest(&positions, &indices, &ray).map(|(_, t)| t),
+          Some(hit.t)
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn empty_mesh() {
+    let bvh = Bvh::new(&[], &[], &[]);
+    assert_eq!(bvh.trig_count(), 0);
+    assert!(bvh.aabb().is_empty());
+    let ray = Ray::new(Vec3::ZERO, Vec3::X);
+    assert_eq!(bvh.closest_hit(&ray, f32::INFINITY), None);
+    assert_eq!(bvh.any_hit(&ray, f32::INFINITY), None);
+  }
+
+  #[test]
+  fn single_triangle() {
+    let positions = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+    let bvh = Bvh::new(&positions, &[0, 1, 2], &[3]);
+
+    let ray = Ray::new(Vec3::new(0.25, 0.5, 2.0), Vec3::NEG_Z);
+    let hit = bvh.closest_hit(&ray, f32::INFINITY).unwrap();
+    assert_eq!(hit.t, 2.0);
+    assert_eq!(hit.point, Vec3::new(0.25, 0.5, 0.0));
+    assert_eq!(hit.barycentric, Vec3::new(0.25, 0.25, 0.5));
+    assert_eq!((hit.triangle, hit.group), (0, 3));
+    assert_eq!(hit.interpolate([0.0, 4.0, 8.0]), 5.0);
+
+    // too short, beside it, and pointing away
+    assert_eq!(bvh.closest_hit(&ray, 1.0), None);
+    let beside = Ray::new(Vec3::new(0.75, 0.5, 2.0), Vec3::NEG_Z);
+    assert_eq!(bvh.closest_hit(&beside, f32::INFINITY), None);
+    let away = Ray::new(Vec3::new(0.25, 0.5, 2.0), Vec3::Z);
+    assert_eq!(bvh.any_hit(&away, f32::INFINITY), None);
+  }
+
+  #[test]
+  fn placed_in_the_world() {
+    let positions = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+    let bvh = Bvh::new(&positions, &[0, 1, 2], &[]);
+    // twice the size and 3 units further down -z
+    let model = Mat4::from_translation(Vec3::new(0.0, 0.0, -3.0))
+      * Mat4::from_scale(Vec3::splat(2.0));
+
+    let ray = Ray::new(Vec3::new(0.5, 1.0, 2.0), Vec3::NEG_Z);
+    let hit = bvh.closest_hit_placed(&ray, model, f32::INFINITY).unwrap();
+    assert!((hit.t - 5.0).abs() < 1e-5);
+    assert!(hit.point.abs_diff_eq(Vec3::new(0.25, 0.5, 0.0), 1e-5));
+    // only where it was moved to
+    let beside = Ray::new(Vec3::new(1.0, 0.8, 2.0), Vec3::NEG_Z);
+    assert_eq!(bvh.closest_hit(&beside, f32::INFINITY), None);
+    assert!(bvh.closest_hit_placed(&beside, model, 4.0).is_none());
+    assert!(bvh.closest_hit_placed(&beside, model, 6.0).is_some());
+  }
+}
@@ -0,0 +1,215 @@
+// LOD chains of big models take a while to build, the yoda model has
+// about a million triangles, so they are kept on disk between runs.
+// A cache file holds the chain for one set of inputs, keyed by a hash
+// of the positions, the triangles and the ratios, and is rebuilt when
+// any of them changes. Reading a stale or broken file is not an error,
+// the chain is just built again.
+
+use std::{
+  fs::File,
+  io::{BufWriter, Write},
+  path::{Path, PathBuf},
+};
+
+use super::{lod_chain, Lod};
+
+const MAGIC: &[u8; 4] = b"LODS";
+// part of the key, bump it whenever simplify changes its output
+const VERSION: u32 = 1;
+
+// next to the model, e.g. yoda.obj.lods
+pub fn lod_cache_path(model_path: &Path) -> PathBuf {
+  let mut name = model_path.as_os_str().to_owned();
+  name.push(".lods");
+  PathBuf::from(name)
+}
+
+// lod_chain, read from `cache` when it was built from the same inputs
+// and written to it otherwise
+pub fn cached_lod_chain(
+  cache: &Path,
+  positions: &[[f32; 3]],
+  indices: &[u32],
+  trig_groups: &[u32],
+  ratios: &[f32],
+) -> Vec<Lod> {
+  let key = cache_key(positions, indices, trig_groups, ratios);
+  if let Some(lods) = std::fs::read(cache)
+    .ok()
+    .and_then(|bytes| read_lods(&bytes, key))
+  {
+    return lods;
+  }
+
+  let lods = lod_chain(positions, indices, trig_groups, ratios);
+  if let Err(e) = write_lods(cache, key, &lods) {
+    eprintln!("Failed to cache the lods in {}: {}", cache.display(), e);
+  }
+  lods
+}
+
+// FNV-1a over everything the chain is built from
+fn cache_key(
+  positions: &[[f32; 3]],
+  indices: &[u32],
+  trig_groups: &[u32],
+  ratios: &[f32],
+) -> u64 {
+  let words = std::iter::once(VERSION)
+    .chain([positions.len(), indices.len(), ratios.len()].map(|n| n as u32))
+    .chain(positions.iter().flatten().map(|x| x.to_bits()))
+    .chain(indices.iter().copied())
+    .chain(trig_groups.iter().copied())
+    .chain(ratios.iter().map(|r| r.to_bits()));
+  words
+    .flat_map(u32::to_le_bytes)
+    .fold(0xcbf29ce484222325, |hash, byte| {
+      (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+// magic, key, level count, then each level as ratio, error, triangle
+// count, indices and groups. all little endian.
+fn write_lods(path: &Path, key: u64, lods: &[Lod]) -> std::io::Result<()> {
+  let mut out = BufWriter::new(File::create(path)?);
+  out.write_all(MAGIC)?;
+  out.write_all(&key.to_le_bytes())?;
+  out.write_all(&(lods.len() as u32).to_le_bytes())?;
+  for lod in lods {
+    out.write_all(&lod.ratio.to_le_bytes())?;
+    out.write_all(&lod.error.to_le_bytes())?;
+    out.write_all(&(lod.trig_groups.len() as u32).to_le_bytes())?;
+    for word in lod.indices.iter().chain(&lod.trig_groups) {
+      out.write_all(&word.to_le_bytes())?;
+    }
+  }
+  out.flush()
+}
+
+// None unless the file is complete and for `key`
+fn read_lods(bytes: &[u8], key: u64) -> Option<Vec<Lod>> {
+  let mut reader = Reader { bytes };
+  if reader.take(4)? != MAGIC || reader.u64()? != key {
+    return None;
+  }
+
+  let count = reader.u32()?;
+  let mut lods = Vec::new();
+  for _ in 0..count {
+    let ratio = f32::from_bits(reader.u32()?);
+    let error = f32::from_bits(reader.u32()?);
+    let trig_count = reader.u32()? as usize;
+    let indices = reader.u32s(trig_count.checked_mul(3)?)?;
+    let trig_groups = reader.u32s(trig_count)?;
+    lods.push(Lod {
+      ratio,
+      indices,
+      trig_groups,
+      error,
+    });
+  }
+  reader.bytes.is_empty().then_some(lods)
+}
+
+struct Reader<'a> {
+  bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+  fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+    if n > self.bytes.len() {
+      return None;
+    }
+    let (taken, rest) = self.bytes.split_at(n);
+    self.bytes = rest;
+    Some(taken)
+  }
+
+  fn u32(&mut self) -> Option<u32> {
+    Some(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+  }
+
+  fn u64(&mut self) -> Option<u64> {
+    Some(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+  }
+
+  fn u32s(&mut self, n: usize) -> Option<Vec<u32>> {
+    let bytes = self.take(n.checked_mul(4)?)?;
+    Some(
+      bytes
+        .chunks_exact(4)
+        .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+        .collect(),
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // a 4x4 grid of quads, two triangles each
+  fn grid() -> (Vec<[f32; 3]>, Vec<u32>) {
+    let positions = (0..25)
+      .map(|i| [(i % 5) as f32, (i / 5) as f32, 0.0])
+      .collect();
+    let indices = (0..4)
+      .flat_map(|y| (0..4).map(move |x| y * 5 + x))
+      .flat_map(|i| [i, i + 1, i + 6, i, i + 6, i + 5])
+      .collect();
+    (positions, indices)
+  }
+
+  fn cache_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+      "cs5610-{}-{}.lods",
+      name,
+      std::process::id()
+    ))
+  }
+
+  fn flatten(lods: &[Lod]) -> Vec<(f32, f32, Vec<u32>, Vec<u32>)> {
+    lods
+      .iter()
+      .map(|l| (l.ratio, l.error, l.indices.clone(), l.trig_groups.clone()))
+      .collect()
+  }
+
+  #[test]
+  fn reads_back_what_it_built() {
+    let (positions, indices) = grid();
+    let groups = vec![0; indices.len() / 3];
+    let ratios = [0.5, 0.25];
+    let file = cache_file("lods-read-back");
+
+    let built = cached_lod_chain(&file, &positions, &indices, &groups, &ratios);
+    let bytes = std::fs::read(&file).unwrap();
+    let key = cache_key(&positions, &indices, &groups, &ratios);
+    let read = read_lods(&bytes, key).unwrap();
+    let cached =
+      cached_lod_chain(&file, &positions, &indices, &groups, &ratios);
+    std::fs::remove_file(&file).unwrap();
+
+    assert_eq!(flatten(&read), flatten(&built));
+    assert_eq!(flatten(&cached), flatten(&built));
+    // a truncated file is as good as none
+    assert!(read_lods(&bytes[..bytes.len() - 1], key).is_none());
+  }
+
+  #[test]
+  fn rebuilds_for_other_inputs() {
+    let (positions, indices) = grid();
+    let groups = vec![0; indices.len() / 3];
+    let file = cache_file("lods-rebuild");
+
+    cached_lod_chain(&file, &positions, &indices, &groups, &[0.5]);
+    let lods = cached_lod_chain(&file, &positions, &indices, &groups, &[0.25]);
+    let bytes = std::fs::read(&file).unwrap();
+    std::fs::remove_file(&file).unwrap();
+
+    assert_eq!(lods[0].ratio, 0.25);
+    let key = |ratios: &[f32]| cache_key(&positions, &indices, &groups, ratios);
+    assert!(read_lods(&bytes, key(&[0.25])).is_some());
+    assert!(read_lods(&bytes, key(&[0.5])).is_none());
+  }
+}
@@ -0,0 +1,559 @@
+// Cleanup for obj meshes that don't render well with back-face
+// culling: vertices at the same position are welded, degenerate and
+// duplicate faces are removed, the winding is made consistent within
+// each connected component and turned outward, and small holes can be
+// closed. Edges with more than two faces are left alone and counted.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+
+use glam::{DVec3, Vec3};
+
+use super::sorted;
+use crate::obj_loader::{Obj, VAIdx};
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RepairReport {
+  // positions that repeat an earlier one, their faces now use that
+  pub vertices_welded: usize,
+  pub degenerate_removed: usize,
+  pub duplicates_removed: usize,
+  // faces with reversed winding in the end
+  pub faces_flipped: usize,
+  // components flipped to face outward
+  pub components_flipped: usize,
+  pub components: usize,
+  pub holes_filled: usize,
+  // boundary loops left open
+  pub holes_remaining: usize,
+  // edges shared by more than two faces, which orient can't cross
+  pub non_manifold_edges: usize,
+}
+
+impl RepairReport {
+  pub fn is_clean(&self) -> bool {
+    self.vertices_welded == 0
+      && self.degenerate_removed == 0
+      && self.duplicates_removed == 0
+      && self.faces_flipped == 0
+      && self.components_flipped == 0
+      && self.holes_filled == 0
+  }
+}
+
+impl fmt::Display for RepairReport {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "welded {} vertices, removed {} degenerate and {} duplicate \
+       faces, flipped {} faces and {}/{} components, filled {} holes \
+       ({} left open), {} non-manifold edges",
+      self.vertices_welded,
+      self.degenerate_removed,
+      self.duplicates_removed,
+      self.faces_flipped,
+      self.components_flipped,
+      self.components,
+      self.holes_filled,
+      self.holes_remaining,
+      self.non_manifold_edges,
+    )
+  }
+}
+
+// Boundary loops with at most `fill_holes` edges are closed with a
+// new face, None leaves all holes open.
+pub fn repair(obj: &mut Obj, fill_holes: Option<usize>) -> RepairReport {
+  let mut report = RepairReport::default();
+
+  weld(obj, &mut report);
+  remove_degenerate(obj, &mut report);
+  remove_duplicates(obj, &mut report);
+  orient(obj, &mut report);
+  fill(obj, fill_holes.unwrap_or(0), &mut report);
+
+  report
+}
+
+// (group, face) of every face in the obj
+fn face_ids(obj: &Obj) -> Vec<(usize, usize)> {
+  obj
+    .groups
+    .iter()
+    .enumerate()
+    .flat_map(|(g, group)| (0..group.f.len()).map(move |f| (g, f)))
+    .collect()
+}
+
+fn face_normal(obj: &Obj, face: &[VAIdx]) -> DVec3 {
+  let pos = |[v, _, _]: VAIdx| Vec3::from(obj.v[v - 1]).as_dvec3();
+  let n = face.len();
+  // newell's method, the length is twice the area
+  (0..n)
+    .map(|i| pos(face[i]).cross(pos(face[(i + 1) % n])))
+    .sum()
+}
+
+// positions given more than once, e.g. split along a uv seam, so
+// that the faces on either side connect
+fn weld(obj: &mut Obj, report: &mut RepairReport) {
+  let mut first: HashMap<[u32; 3], usize> = HashMap::new();
+  let remap: Vec<usize> = obj
+    .v
+    .iter()
+    .enumerate()
+    .map(|(i, p)| *first.entry(p.map(f32::to_bits)).or_insert(i + 1))
+    .collect();
+  report.vertices_welded = obj.v.len() - first.len();
+
+  for group in &mut obj.groups {
+    for face in &mut group.f {
+      for [v, _, _] in face {
+        *v = remap[*v - 1];
+      }
+    }
+  }
+}
+
+fn remove_degenerate(obj: &mut Obj, report: &mut RepairReport) {
+  let (min, max) = obj.v.iter().fold(
+    (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+    |(min, max), &p| (min.min(p.into()), max.max(p.into())),
+  );
+  let min_area = (max - min).length_squared() as f64 * 1e-12;
+
+  let mut groups = std::mem::take(&mut obj.groups);
+  for group in &mut groups {
+    let before = group.f.len();
+
+    group.f.retain_mut(|face| {
+      // drop repeated positions, e.g. from collapsed edges
+      face.dedup_by_key(|[v, _, _]| *v);
+      if face.len() > 1 && face[0][0] == face[face.len() - 1][0] {
+        face.pop();
+      }
+
+      face.len() >= 3 && face_normal(obj, face).length() / 2.0 > min_area
+    });
+
+    report.degenerate_removed += before - group.f.len();
+  }
+  obj.groups = groups;
+}
+
+// faces covering the same positions, regardless of the winding
+fn remove_duplicates(obj: &mut Obj, report: &mut RepairReport) {
+  let mut seen: HashSet<Vec<usize>> = HashSet::new();
+
+  for group in &mut obj.groups {
+    let before = group.f.len();
+
+    group.f.retain(|face| seen.insert(face_key(face)));
+
+    report.duplicates_removed += before - group.f.len();
+  }
+}
+
+fn face_key(face: &[VAIdx]) -> Vec<usize> {
+  let mut key: Vec<usize> = face.iter().map(|[v, _, _]| *v).collect();
+  key.sort();
+  key
+}
+
+// directed edge (from, to) => faces containing it
+fn half_edges(
+  obj: &Obj,
+  faces: &[(usize, usize)],
+) -> HashMap<[usize; 2], Vec<usize>> {
+  let mut edges: HashMap<[usize; 2], Vec<usize>> = HashMap::new();
+
+  for (i, &(g, f)) in faces.iter().enumerate() {
+    let face = &obj.groups[g].f[f];
+    let n = face.len();
+    for j in 0..n {
+      let edge = [face[j][0], face[(j + 1) % n][0]];
+      edges.entry(edge).or_default().push(i);
+    }
+  }
+
+  edges
+}
+
+fn orient(obj: &mut Obj, report: &mut RepairReport) {
+  let faces = face_ids(obj);
+
+  // undirected edge => faces, only manifold edges connect faces
+  let mut edge_faces: HashMap<[usize; 2], Vec<usize>> = HashMap::new();
+  for (i, &(g, f)) in faces.iter().enumerate() {
+    let face = &obj.groups[g].f[f];
+    let n = face.len();
+    for j in 0..n {
+      let edge = sorted([face[j][0], face[(j + 1) % n][0]]);
+      edge_faces.entry(edge).or_default().push(i);
+    }
+  }
+
+  report.non_manifold_edges =
+    edge_faces.values().filter(|faces| faces.len() > 2).count();
+
+  // whether the face contains the directed edge a -> b
+  let has_edge = |face: &[VAIdx], a: usize, b: usize| {
+    let n = face.len();
+    (0..n).any(|j| face[j][0] == a && face[(j + 1) % n][0] == b)
+  };
+
+  let mut visited = vec![false; faces.len()];
+  let mut flipped = vec![false; faces.len()];
+
+  for seed in 0..faces.len() {
+    if visited[seed] {
+      continue;
+    }
+
+    let mut component = vec![seed];
+    let mut queue = VecDeque::from([seed]);
+    visited[seed] = true;
+
+    while let Some(i) = queue.pop_front() {
+      let (g, f) = faces[i];
+      let face = obj.groups[g].f[f].clone();
+      let n = face.len();
+
+      for j in 0..n {
+        let (a, b) = (face[j][0], face[(j + 1) % n][0]);
+        let neighbours = &edge_faces[&sorted([a, b])];
+        if neighbours.len() != 2 {
+          continue;
+        }
+
+        let k = if neighbours[0] == i {
+          neighbours[1]
+        } else {
+          neighbours[0]
+        };
+        if visited[k] {
+          continue;
+        }
+        visited[k] = true;
+
+        // a consistent neighbour runs the shared edge the other way
+        let (h, l) = faces[k];
+        if has_edge(&obj.groups[h].f[l], a, b) {
+          obj.groups[h].f[l].reverse();
+          flipped[k] = true;
+        }
+
+        component.push(k);
+        queue.push_back(k);
+      }
+    }
+
+    report.components += 1;
+    if inward(obj, &faces, &component) {
+      report.components_flipped += 1;
+      for &i in &component {
+        let (g, f) = faces[i];
+        obj.groups[g].f[f].reverse();
+        flipped[i] = !flipped[i];
+      }
+    }
+  }
+
+  report.faces_flipped = flipped.iter().filter(|&&f| f).count();
+}
+
+// the faces mostly disagree with the normals in the obj. if the normals
+// don't tell, e.g. they were recomputed from the bad winding, see if the
+// signed volume is negative. open components like planes have no volume
+// to decide with.
+fn inward(obj: &Obj, faces: &[(usize, usize)], component: &[usize]) -> bool {
+  let mut votes = 0i64;
+  for &i in component {
+    let (g, f) = faces[i];
+    let face = &obj.groups[g].f[f];
+    let normal = face_normal(obj, face);
+    let vn: DVec3 = face
+      .iter()
+      .map(|[_, _, vn]| Vec3::from(obj.vn[vn - 1]).as_dvec3())
+      .sum();
+    let d = normal.dot(vn);
+    votes += (d > 0.0) as i64 - (d < 0.0) as i64;
+  }
+
+  if votes != 0 {
+    return votes < 0;
+  }
+
+  let corners = component.iter().flat_map(|&i| {
+    let (g, f) = faces[i];
+    obj.groups[g].f[f].iter()
+  });
+  let (sum, count) = corners.fold((DVec3::ZERO, 0), |(sum, count), [v, ..]| {
+    (sum + Vec3::from(obj.v[v - 1]).as_dvec3(), count + 1)
+  });
+  let center = sum / count as f64;
+
+  let mut volume = 0.0;
+  let mut area: f64 = 0.0;
+  for &i in component {
+    let (g, f) = faces[i];
+    let face = &obj.groups[g].f[f];
+    let normal = face_normal(obj, face);
+    let p = Vec3::from(obj.v[face[0][0] - 1]).as_dvec3() - center;
+    volume += p.dot(normal) / 6.0;
+    area += normal.length() / 2.0;
+  }
+
+  volume < -1e-3 * area.powf(1.5)
+}
+
+fn fill(obj: &mut Obj, max_edges: usize, report: &mut RepairReport) {
+  let faces = face_ids(obj);
+  let edges = half_edges(obj, &faces);
+
+  // boundary half edges have no twin. from => (to, vt at from, face)
+  let mut boundary: HashMap<usize, Vec<(usize, usize, usize)>> = HashMap::new();
+  for (i, &(g, f)) in faces.iter().enumerate() {
+    let face = &obj.groups[g].f[f];
+    let n = face.len();
+    for j in 0..n {
+      let (a, b) = (face[j][0], face[(j + 1) % n][0]);
+      if !edges.contains_key(&[b, a]) && edges[&[a, b]].len() == 1 {
+        boundary.entry(a).or_default().push((b, face[j][1], i));
+      }
+    }
+  }
+
+  let face_keys: HashSet<Vec<usize>> = faces
+    .iter()
+    .map(|&(g, f)| face_key(&obj.groups[g].f[f]))
+    .collect();
+
+  let mut visited: HashSet<usize> = HashSet::new();
+  let mut starts: Vec<usize> = boundary.keys().copied().collect();
+  starts.sort();
+
+  for start in starts {
+    if visited.contains(&start) {
+      continue;
+    }
+
+    // walk the loop, giving up on vertices where loops touch
+    let mut hole = Vec::new();
+    let mut v = start;
+    let closed = loop {
+      let Some(next) = boundary.get(&v) else {
+        break false;
+      };
+      if next.len() != 1 || !visited.insert(v) {
+        break false;
+      }
+
+      hole.push((v, next[0].1, next[0].2));
+      v = next[0].0;
+      if v == start {
+        break true;
+      }
+    };
+
+    if !closed || hole.len() > max_edges {
+      report.holes_remaining += 1;
+      continue;
+    }
+
+    // the hole runs the boundary edges in the opposite direction. it's
+    // just the back of a lone face like plane.obj if they match up.
+    let face: Vec<VAIdx> =
+      hole.iter().rev().map(|&(v, vt, _)| [v, vt, 0]).collect();
+    if face_keys.contains(&face_key(&face)) {
+      report.holes_remaining += 1;
+      continue;
+    }
+
+    let normal = face_normal(obj, &face).normalize_or_zero().as_vec3();
+    obj.vn.push(normal.to_array());
+    let vn = obj.vn.len();
+
+    let group = faces[hole[0].2].0;
+    let face = face.into_iter().map(|[v, vt, _]| [v, vt, vn]).collect();
+    obj.groups[group].f.push(face);
+    report.holes_filled += 1;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::obj_loader::{Group, MtlLib};
+
+  const TETRAHEDRON: [[f32; 3]; 4] = [
+    [0.0, 0.0, 0.0],
+    [1.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0],
+    [0.0, 0.0, 1.0],
+  ];
+  // counter-clockwise seen from outside
+  const TETRAHEDRON_FACES: [[usize; 3]; 4] =
+    [[0, 2, 1], [0, 1, 3], [0, 3, 2], [1, 2, 3]];
+
+  // faces by 0-based index, with normals that don't tell inside from
+  // outside, so the volume decides
+  fn obj(v: &[[f32; 3]], faces: &[&[usize]]) -> Obj {
+    let f = faces
+      .iter()
+      .map(|face| face.iter().map(|&i| [i + 1, 1, 1]).collect())
+      .collect();
+    Obj {
+      v: v.to_vec(),
+      vn: vec![[0.0; 3]],
+      vt: vec![[0.0; 3]],
+      mtl_lib: MtlLib::default(),
+      groups: vec![Group {
+        f,
+        ..Group::default()
+      }],
+    }
+  }
+
+  fn tetrahedron(faces: &[[usize; 3]]) -> Obj {
+    let faces: Vec<&[usize]> = faces.iter().map(|f| &f[..]).collect();
+    obj(&TETRAHEDRON, &faces)
+  }
+
+  fn faces(obj: &Obj) -> Vec<Vec<usize>> {
+    obj.groups[0]
+      .f
+      .iter()
+      .map(|face| face.iter().map(|[v, _, _]| v - 1).collect())
+      .collect()
+  }
+
+  // every edge run once each way, and the normals pointing away from
+  // the middle of the tetrahedron
+  fn assert_outward(obj: &Obj) {
+    let edges = half_edges(obj, &face_ids(obj));
+    assert!(edges.values().all(|faces| faces.len() == 1));
+    assert!(edges.keys().all(|[a, b]| edges.contains_key(&[*b, *a])));
+
+    let center = DVec3::splat(0.25);
+    for face in &obj.groups[0].f {
+      let p = Vec3::from(obj.v[face[0][0] - 1]).as_dvec3();
+      assert!(face_normal(obj, face).dot(p - center) > 0.0);
+    }
+  }
+
+  #[test]
+  fn clean_mesh_is_untouched() {
+    let mut mesh = tetrahedron(&TETRAHEDRON_FACES);
+    let report = repair(&mut mesh, Some(4));
+    assert!(report.is_clean(), "{}", report);
+    assert_eq!(report.components, 1);
+    assert_eq!(faces(&mesh), faces(&tetrahedron(&TETRAHEDRON_FACES)));
+  }
+
+  #[test]
+  fn welds_duplicate_vertices() {
+    // a square split along its diagonal, the second triangle with
+    // copies of the diagonal's vertices and wound the other way
+    let mut mesh = obj(
+      &[
+        [0.0, 0.0, 0.0],
+        [1.0, 0.0, 0.0],
+        [1.0, 1.0, 0.0],
+        [0.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0],
+        [1.0, 1.0, 0.0],
+      ],
+      &[&[0, 1, 2], &[4, 3, 5]],
+    );
+    let report = repair(&mut mesh, None);
+    assert_eq!(report.vertices_welded, 2);
+    assert_eq!(faces(&mesh), vec![vec![0, 1, 2], vec![2, 3, 0]]);
+    // only connected through the welded edge
+    assert_eq!(report.components, 1);
+    assert_eq!(report.faces_flipped, 1);
+    assert_eq!(report.holes_remaining, 1);
+  }
+
+  #[test]
+  fn removes_degenerate_and_duplicate_faces() {
+    let mut mesh = obj(
+      &[TETRAHEDRON.to_vec(), vec![[0.5, 0.0, 0.0]]].concat(),
+      &[
+        &[0, 2, 1],
+        &[0, 1, 3],
+        &[0, 3, 2],
+        &[1, 2, 3],
+        // a repeated vertex, and three in a line
+        &[0, 0, 2],
+        &[0, 4, 1],
+        // twice the same face, once wound the other way
+        &[1, 2, 3],
+        &[3, 2, 1],
+      ],
+    );
+    let report = repair(&mut mesh, None);
+    assert_eq!(report.degenerate_removed, 2);
+    assert_eq!(report.duplicates_removed, 2);
+    assert_eq!(faces(&mesh).len(), 4);
+    assert_outward(&mesh);
+  }
+
+  #[test]
+  fn flips_inconsistent_faces() {
+    let mut faces = TETRAHEDRON_FACES;
+    faces[3].reverse();
+    let mut mesh = tetrahedron(&faces);
+    let report = repair(&mut mesh, None);
+    assert_eq!(report.faces_flipped, 1);
+    assert_eq!(report.components_flipped, 0);
+    assert_outward(&mesh);
+  }
+
+  #[test]
+  fn turns_inside_out_components_outward() {
+    let mut faces = TETRAHEDRON_FACES;
+    for face in &mut faces {
+      face.reverse();
+    }
+    let mut mesh = tetrahedron(&faces);
+    let report = repair(&mut mesh, None);
+    assert_eq!(report.components_flipped, 1);
+    assert_eq!(report.faces_flipped, 4);
+    assert_outward(&mesh);
+  }
+
+  #[test]
+  fn fills_small_holes() {
+    let mut mesh = tetrahedron(&TETRAHEDRON_FACES[..3]);
+    let report = repair(&mut mesh, Some(3));
+    assert_eq!(report.holes_filled, 1);
+    assert_eq!(report.holes_remaining, 0);
+    assert_outward(&mesh);
+
+    let mut mesh = tetrahedron(&TETRAHEDRON_FACES[..3]);
+    let report = repair(&mut mesh, Some(2));
+    assert_eq!(report.holes_filled, 0);
+    assert_eq!(report.holes_remaining, 1);
+  }
+
+  #[test]
+  fn reports_non_manifold_edges() {
+    // three triangles on the edge 0-1, like pages of a book
+    let mut mesh = obj(
+      &[
+        [0.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0],
+        [1.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0],
+        [-1.0, 0.0, 0.0],
+      ],
+      &[&[0, 1, 2], &[0, 1, 3], &[1, 0, 4]],
+    );
+    let report = repair(&mut mesh, Some(4));
+    assert_eq!(report.non_manifold_edges, 1);
+    // nothing connects them
+    assert_eq!(report.components, 3);
+    assert_eq!(faces(&mesh).len(), 3);
+  }
+}
@@ -0,0 +1,1020 @@
+// Quadric error metric simplification (Garland & Heckbert, 1997).
+//
+// Edges are removed with half-edge collapses: a vertex is always
+// merged into one of its neighbours and never moved to a new
+// position. That way every LOD keeps indexing into the original
+// vertex buffer and the uv/normal of the surviving vertices stay
+// valid, at the cost of a slightly higher error than the optimal
+// placement.
+//
+// Seams are handled on the "wedge" level: all vertices sharing the
+// same position form a single topological vertex, and the different
+// vertex indices (wedges) at that position are carried along when
+// collapsing. A vertex lying on a uv/normal seam, an open border or
+// a material boundary can only slide along that feature, and
+// vertices where features meet are locked.
+
+use std::{
+  cmp::Ordering,
+  collections::{BinaryHeap, HashMap},
+  ops::Range,
+  path::Path,
+};
+
+use glam::{DVec3, Mat4, Vec3};
+
+use super::{append_strips, cached_lod_chain};
+use crate::math::{projected_radius, Sphere};
+
+// extra weight for the planes perpendicular to feature edges,
+// relative to the face planes.
+const FEATURE_WEIGHT: f64 = 10.0;
+
+pub const DEFAULT_LOD_RATIOS: [f32; 4] = [0.5, 0.25, 0.1, 0.03];
+
+pub struct Simplified {
+  // triangle list into the original vertex buffer
+  pub indices: Vec<u32>,
+  // the group of each triangle in `indices`
+  pub trig_groups: Vec<u32>,
+  // geometric error relative to the radius of the mesh
+  pub error: f32,
+}
+
+pub struct Lod {
+  // ratio of the original triangle count this level aimed for
+  pub ratio: f32,
+  pub indices: Vec<u32>,
+  pub trig_groups: Vec<u32>,
+  // geometric error relative to the radius of the mesh
+  pub error: f32,
+}
+
+impl Lod {
+  // the triangle list of each group, in the original order
+  pub fn split_by_group(&self, group_count: usize) -> Vec<Vec<u32>> {
+    split_by_group(&self.indices, &self.trig_groups, group_count)
+  }
+}
+
+pub fn split_by_group(
+  indices: &[u32],
+  trig_groups: &[u32],
+  group_count: usize,
+) -> Vec<Vec<u32>> {
+  let mut groups = vec![Vec::new(); group_count];
+  for (trig, group) in indices.chunks(3).zip(trig_groups) {
+    groups[*group as usize].extend_from_slice(trig);
+  }
+  groups
+}
+
+// Generate a chain of LODs at the given ratios of the original
+// triangle count. Each level is simplified from the previous one,
+// the ratios are expected in decreasing order.
+pub fn lod_chain(
+  positions: &[[f32; 3]],
+  indices: &[u32],
+  trig_groups: &[u32],
+  ratios: &[f32],
+) -> Vec<Lod> {
+  let trig_count = indices.len() / 3;
+  let mut lods: Vec<Lod> = Vec::with_capacity(ratios.len());
+
+  for &ratio in ratios {
+    let (src_indices, src_groups, src_error) = match lods.last() {
+      Some(lod) => (&lod.indices[..], &lod.trig_groups[..], lod.error),
+      None => (indices, trig_groups, 0.0),
+    };
+
+    let target = (trig_count as f32 * ratio) as usize;
+    let simplified =
+      simplify(positions, src_indices, src_groups, target, f32::INFINITY);
+
+    lods.push(Lod {
+      ratio,
+      // the error of each step is measured against the previous
+      // level, so the sum bounds the error against the original.
+      error: src_error + simplified.error,
+      indices: simplified.indices,
+      trig_groups: simplified.trig_groups,
+    });
+  }
+
+  lods
+}
+
+// Pick the coarsest LOD whose error stays below `max_pixel_error`
+// when the mesh covers a circle of `screen_radius` pixels. Returns 0
+// (the full mesh) if none of the levels is good enough. `errors`
+// lists the relative error of each LOD, the full mesh included.
+pub fn select_lod(
+  errors: impl IntoIterator<Item = f32>,
+  screen_radius: f32,
+  max_pixel_error: f32,
+) -> usize {
+  let mut selected = 0;
+  for (i, error) in errors.into_iter().enumerate() {
+    if error * screen_radius <= max_pixel_error {
+      selected = i;
+    }
+  }
+  selected
+}
+
+// The groups of a mesh as triangle strips in one index buffer, for the
+// full mesh and for each level of detail of lod_chain. All levels index
+// into the same vertices.
+#[derive(Clone, Debug)]
+pub struct LodStrips {
+  // the index range of each group, level 0 is the full mesh
+  levels: Vec<Vec<Range<u32>>>,
+  // relative to the radius of the mesh, 0 for the full mesh
+  errors: Vec<f32>,
+  // model space
+  bounding_sphere: Sphere,
+}
+
+impl LodStrips {
+  // Strips the triangle list of each group and of each generated
+  // level, appending them to indices. No levels for empty ratios. The
+  // levels are read from and kept in `cache` if there is one, see
+  // cached_lod_chain.
+  pub fn build(
+    indices: &mut Vec<u32>,
+    positions: &[[f32; 3]],
+    group_trigs: &[Vec<u32>],
+    ratios: &[f32],
+    cache: Option<&Path>,
+  ) -> Self {
+    let mut levels = vec![append_strips(indices, group_trigs)];
+    let mut errors = vec![0.0];
+    if !ratios.is_empty() {
+      let trig_groups: Vec<u32> = group_trigs
+        .iter()
+        .enumerate()
+        .flat_map(|(i, trigs)| std::iter::repeat_n(i as u32, trigs.len() / 3))
+        .collect();
+      let trigs = group_trigs.concat();
+      let lods = match cache {
+        Some(cache) => {
+          cached_lod_chain(cache, positions, &trigs, &trig_groups, ratios)
+        }
+        None => lod_chain(positions, &trigs, &trig_groups, ratios),
+      };
+      for lod in lods {
+        let lod_trigs = lod.split_by_group(group_trigs.len());
+        levels.push(append_strips(indices, &lod_trigs));
+        errors.push(lod.error);
+      }
+    }
+    Self {
+      levels,
+      errors,
+      bounding_sphere: bounding_sphere(positions),
+    }
+  }
+
+  // a single group of all the indices, without lods
+  pub fn single(positions: &[[f32; 3]], index_count: u32) -> Self {
+    let group = Range {
+      start: 0,
+      end: index_count,
+    };
+    Self {
+      levels: vec![vec![group]],
+      errors: vec![0.0],
+      bounding_sphere: bounding_sphere(positions),
+    }
+  }
+
+  // the number of levels, the full mesh included
+  pub fn len(&self) -> usize {
+    self.levels.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.levels.is_empty()
+  }
+
+  // the index range of each group at a level
+  pub fn groups(&self, lod: usize) -> &[Range<u32>] {
+    &self.levels[lod]
+  }
+
+  pub fn bounding_sphere(&self) -> Sphere {
+    self.bounding_sphere
+  }
+
+  // pick the coarsest lod that stays within a pixel of the full mesh
+  // when the mesh is projected to a circle of `screen_radius` pixels
+  pub fn select(&self, screen_radius: f32) -> usize {
+    const MAX_PIXEL_ERROR: f32 = 1.0;
+
+    select_lod(self.errors.iter().copied(), screen_radius, MAX_PIXEL_ERROR)
+  }
+
+  // the lod for the projected size of the bounding sphere
+  pub fn select_projected(
+    &self,
+    model_view: Mat4,
+    projection: Mat4,
+    viewport_height: f32,
+  ) -> usize {
+    let Sphere { center, radius } = self.bounding_sphere.transform(model_view);
+    self.select(projected_radius(
+      center,
+      radius,
+      projection,
+      viewport_height,
+    ))
+  }
+}
+
+fn bounding_sphere(positions: &[[f32; 3]]) -> Sphere {
+  let points: Vec<Vec3> = positions.iter().copied().map(Vec3::from).collect();
+  Sphere::from_points(&points)
+}
+
+// Simplify the triangle list until at most `target_trigs` triangles
+// are left or the next collapse would exceed `max_error` (relative
+// to the mesh radius).
+pub fn simplify(
+  positions: &[[f32; 3]],
+  indices: &[u32],
+  trig_groups: &[u32],
+  target_trigs: usize,
+  max_error: f32,
+) -> Simplified {
+  assert_eq!(indices.len() % 3, 0);
+  assert_eq!(indices.len() / 3, trig_groups.len());
+
+  let mut simplifier = Simplifier::new(positions, indices, trig_groups);
+  let error = simplifier.run(target_trigs, max_error);
+  let (indices, trig_groups) = simplifier.output();
+
+  Simplified {
+    indices,
+    trig_groups,
+    error,
+  }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Quadric {
+  // upper triangle of the symmetric 4x4 matrix, row by row
+  a: [f64; 10],
+  // accumulated area weight, used to turn the error into a distance
+  w: f64,
+}
+
+impl Quadric {
+  fn from_plane(n: DVec3, d: f64, w: f64) -> Self {
+    let (x, y, z) = (n.x, n.y, n.z);
+    let a = [
+      x * x,
+      x * y,
+      x * z,
+      x * d,
+      y * y,
+      y * z,
+      y * d,
+      z * z,
+      z * d,
+      d * d,
+    ];
+    Self {
+      a: a.map(|v| v * w),
+      w,
+    }
+  }
+
+  fn add(&mut self, other: &Quadric) {
+    for (a, b) in self.a.iter_mut().zip(other.a) {
+      *a += b;
+    }
+    self.w += other.w;
+  }
+
+  fn eval(&self, p: DVec3) -> f64 {
+    let [a00, a01, a02, a03, a11, a12, a13, a22, a23, a33] = self.a;
+    let (x, y, z) = (p.x, p.y, p.z);
+    a00 * x * x
+      + a11 * y * y
+      + a22 * z * z
+      + 2.0 * (a01 * x * y + a02 * x * z + a12 * y * z)
+      + 2.0 * (a03 * x + a13 * y + a23 * z)
+      + a33
+  }
+
+  // the error expressed as a distance
+  fn distance(&self, p: DVec3) -> f64 {
+    if self.w <= 0.0 {
+      return 0.0;
+    }
+    (self.eval(p).max(0.0) / self.w).sqrt()
+  }
+}
+
+// Kinds of feature edges. A vertex with exactly two feature edges of
+// the same kind can slide along them; any other combination locks it.
+const EDGE_BORDER: u8 = 1;
+const EDGE_SEAM: u8 = 2;
+const EDGE_MATERIAL: u8 = 4;
+const EDGE_NON_MANIFOLD: u8 = 8;
+
+struct Collapse {
+  cost: f64,
+  from: u32,
+  to: u32,
+  stamp: u32,
+}
+
+impl PartialEq for Collapse {
+  fn eq(&self, other: &Self) -> bool {
+    self.cmp(other) == Ordering::Equal
+  }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Ord for Collapse {
+  // reversed so that the BinaryHeap pops the cheapest collapse
+  fn cmp(&self, other: &Self) -> Ordering {
+    other.cost.total_cmp(&self.cost)
+  }
+}
+
+struct Simplifier {
+  // position of each topological vertex
+  points: Vec<DVec3>,
+  // topological vertex of each wedge (index into the vertex buffer)
+  point_of: Vec<u32>,
+  quadrics: Vec<Quadric>,
+  // incident triangles of each point, may contain removed ones
+  point_trigs: Vec<Vec<u32>>,
+  point_alive: Vec<bool>,
+  // bumped every time the neighbourhood of a point changes
+  stamps: Vec<u32>,
+
+  trigs: Vec<[u32; 3]>,
+  trig_groups: Vec<u32>,
+  trig_alive: Vec<bool>,
+  alive_count: usize,
+
+  radius: f64,
+  heap: BinaryHeap<Collapse>,
+
+  // reused between candidate collapses, which happen millions of times
+  // for a big mesh. lists of points come from a pool as some calls need
+  // more than one at a time.
+  buffers: Vec<Vec<u32>>,
+  features: Vec<(u32, u8)>,
+  candidates: Vec<(f64, u32)>,
+  wedges: Vec<(u32, u32)>,
+}
+
+impl Simplifier {
+  fn new(positions: &[[f32; 3]], indices: &[u32], trig_groups: &[u32]) -> Self {
+    // weld the wedges by position
+    let mut point_ids: HashMap<[u32; 3], u32> = HashMap::new();
+    let mut point_of = vec![u32::MAX; positions.len()];
+    let mut points = Vec::new();
+
+    for &i in indices {
+      let p = positions[i as usize];
+      let id = *point_ids.entry(p.map(f32::to_bits)).or_insert_with(|| {
+        points.push(Vec3::from(p).as_dvec3());
+        points.len() as u32 - 1
+      });
+      point_of[i as usize] = id;
+    }
+
+    let trigs: Vec<[u32; 3]> =
+      indices.chunks(3).map(|t| t.try_into().unwrap()).collect();
+    let mut point_trigs = vec![Vec::new(); points.len()];
+    for (t, trig) in trigs.iter().enumerate() {
+      for w in trig {
+        point_trigs[point_of[*w as usize] as usize].push(t as u32);
+      }
+    }
+
+    let (min, max) = points
+      .iter()
+      .fold((DVec3::INFINITY, DVec3::NEG_INFINITY), |(min, max), p| {
+        (min.min(*p), max.max(*p))
+      });
+    let radius = if points.is_empty() {
+      1.0
+    } else {
+      (max - min).length().max(f64::EPSILON) / 2.0
+    };
+
+    let n_points = points.len();
+    let alive_count = trigs.len();
+    let mut simplifier = Self {
+      points,
+      point_of,
+      quadrics: vec![Quadric::default(); n_points],
+      point_trigs,
+      point_alive: vec![true; n_points],
+      stamps: vec![0; n_points],
+      trig_alive: vec![true; trigs.len()],
+      trig_groups: trig_groups.to_vec(),
+      trigs,
+      alive_count,
+      radius,
+      heap: BinaryHeap::new(),
+      buffers: Vec::new(),
+      features: Vec::new(),
+      candidates: Vec::new(),
+      wedges: Vec::new(),
+    };
+
+    simplifier.init_quadrics();
+    for p in 0..n_points as u32 {
+      simplifier.push_best_collapse(p);
+    }
+    simplifier
+  }
+
+  fn run(&mut self, target_trigs: usize, max_error: f32) -> f32 {
+    let max_error = max_error as f64 * self.radius;
+    let mut error: f64 = 0.0;
+
+    while self.alive_count > target_trigs {
+      let Some(collapse) = self.heap.pop() else {
+        break;
+      };
+
+      let from = collapse.from as usize;
+      if !self.point_alive[from] || self.stamps[from] != collapse.stamp {
+        // outdated, a fresh candidate has been pushed since
+        continue;
+      }
+
+      if collapse.cost > max_error {
+        break;
+      }
+
+      if !self.collapse(collapse.from, collapse.to) {
+        // the neighbourhood changed in a way the stamp can't catch,
+        // try again with a fresh candidate
+        self.stamps[from] += 1;
+        self.push_best_collapse(collapse.from);
+        continue;
+      }
+
+      error = error.max(collapse.cost);
+    }
+
+    (error / self.radius) as f32
+  }
+
+  fn output(&self) -> (Vec<u32>, Vec<u32>) {
+    let mut indices = Vec::with_capacity(self.alive_count * 3);
+    let mut groups = Vec::with_capacity(self.alive_count);
+
+    for (t, trig) in self.trigs.iter().enumerate() {
+      if self.trig_alive[t] {
+        indices.extend_from_slice(trig);
+        groups.push(self.trig_groups[t]);
+      }
+    }
+
+    (indices, groups)
+  }
+
+  fn point(&self, wedge: u32) -> u32 {
+    self.point_of[wedge as usize]
+  }
+
+  fn trig_points(&self, t: u32) -> [u32; 3] {
+    self.trigs[t as usize].map(|w| self.point(w))
+  }
+
+  fn alive_trigs(&self, p: u32) -> impl Iterator<Item = u32> + '_ {
+    self.point_trigs[p as usize]
+      .iter()
+      .copied()
+      .filter(|t| self.trig_alive[*t as usize])
+  }
+
+  fn init_quadrics(&mut self) {
+    for t in 0..self.trigs.len() as u32 {
+      let [a, b, c] = self.trig_points(t);
+      let [pa, pb, pc] = [a, b, c].map(|p| self.points[p as usize]);
+      let cross = (pb - pa).cross(pc - pa);
+      let area = cross.length() / 2.0;
+      if area <= 0.0 {
+        continue;
+      }
+
+      let n = cross.normalize();
+      let q = Quadric::from_plane(n, -n.dot(pa), area);
+      for p in [a, b, c] {
+        self.quadrics[p as usize].add(&q);
+      }
+    }
+
+    // keep the features in place with planes perpendicular to them
+    let mut features = std::mem::take(&mut self.features);
+    for p in 0..self.points.len() as u32 {
+      self.feature_edges(p, &mut features);
+      for &(other, kind) in &features {
+        if kind & EDGE_NON_MANIFOLD != 0 || p > other {
+          continue;
+        }
+
+        let Some(t) = self.edge_trigs(p, other).next() else {
+          continue;
+        };
+        let [a, b, c] = self.trig_points(t).map(|p| self.points[p as usize]);
+        let face_n = (b - a).cross(c - a).normalize_or_zero();
+
+        let (p0, p1) = (self.points[p as usize], self.points[other as usize]);
+        let edge = p1 - p0;
+        let n = edge.cross(face_n).normalize_or_zero();
+        if n == DVec3::ZERO {
+          continue;
+        }
+
+        let w = edge.length_squared() * FEATURE_WEIGHT;
+        let q = Quadric::from_plane(n, -n.dot(p0), w);
+        self.quadrics[p as usize].add(&q);
+        self.quadrics[other as usize].add(&q);
+      }
+    }
+    self.features = features;
+  }
+
+  fn edge_trigs(&self, a: u32, b: u32) -> impl Iterator<Item = u32> + '_ {
+    self
+      .alive_trigs(a)
+      .filter(move |t| self.trig_points(*t).contains(&b))
+  }
+
+  // an empty list from the pool, to be given back with recycle
+  fn buffer(&mut self) -> Vec<u32> {
+    self.buffers.pop().unwrap_or_default()
+  }
+
+  fn recycle(&mut self, mut buffer: Vec<u32>) {
+    buffer.clear();
+    self.buffers.push(buffer);
+  }
+
+  // the neighbours of p, sorted
+  fn neighbours(&mut self, p: u32) -> Vec<u32> {
+    let mut neighbours = self.buffer();
+    neighbours.extend(
+      self
+        .alive_trigs(p)
+        .flat_map(|t| self.trig_points(t))
+        .filter(|q| *q != p),
+    );
+    neighbours.sort_unstable();
+    neighbours.dedup();
+    neighbours
+  }
+
+  fn edge_kind(&self, a: u32, b: u32) -> u8 {
+    let mut trigs = self.edge_trigs(a, b);
+    match (trigs.next(), trigs.next(), trigs.next()) {
+      (Some(_), None, _) => EDGE_BORDER,
+      (Some(t1), Some(t2), None) => {
+        let mut kind = 0;
+        if self.trig_groups[t1 as usize] != self.trig_groups[t2 as usize] {
+          kind |= EDGE_MATERIAL;
+        }
+        if self.edge_wedges(t1, a, b) != self.edge_wedges(t2, a, b) {
+          kind |= EDGE_SEAM;
+        }
+        kind
+      }
+      _ => EDGE_NON_MANIFOLD,
+    }
+  }
+
+  // the wedges used by triangle t at the points a and b
+  fn edge_wedges(&self, t: u32, a: u32, b: u32) -> (u32, u32) {
+    let trig = self.trigs[t as usize];
+    let find = |p| *trig.iter().find(|w| self.point(**w) == p).unwrap();
+    (find(a), find(b))
+  }
+
+  // into `features`, replacing what was there
+  fn feature_edges(&mut self, p: u32, features: &mut Vec<(u32, u8)>) {
+    let neighbours = self.neighbours(p);
+    features.clear();
+    features.extend(
+      neighbours
+        .iter()
+        .map(|&q| (q, self.edge_kind(p, q)))
+        .filter(|(_, kind)| *kind != 0),
+    );
+    self.recycle(neighbours);
+  }
+
+  // the points `p` may collapse into, by their feature edges. from the
+  // pool.
+  fn collapse_targets(&mut self, p: u32) -> Vec<u32> {
+    let mut features = std::mem::take(&mut self.features);
+    self.feature_edges(p, &mut features);
+    let targets = match features[..] {
+      [] => self.neighbours(p),
+      [(q1, k1), (q2, k2)] if k1 == k2 && k1 & EDGE_NON_MANIFOLD == 0 => {
+        let mut targets = self.buffer();
+        targets.extend([q1, q2]);
+        targets
+      }
+      _ => self.buffer(),
+    };
+    self.features = features;
+    targets
+  }
+
+  fn push_best_collapse(&mut self, p: u32) {
+    if !self.point_alive[p as usize] {
+      return;
+    }
+
+    let targets = self.collapse_targets(p);
+    let mut candidates = std::mem::take(&mut self.candidates);
+    candidates.clear();
+    candidates.extend(targets.iter().map(|&q| (self.collapse_cost(p, q), q)));
+    self.recycle(targets);
+    candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let best = candidates
+      .iter()
+      .copied()
+      .find(|&(_, q)| self.collapse_valid(p, q));
+    self.candidates = candidates;
+
+    if let Some((cost, q)) = best {
+      self.heap.push(Collapse {
+        cost,
+        from: p,
+        to: q,
+        stamp: self.stamps[p as usize],
+      });
+    }
+  }
+
+  fn collapse_cost(&self, from: u32, to: u32) -> f64 {
+    let mut q = self.quadrics[from as usize];
+    q.add(&self.quadrics[to as usize]);
+    q.distance(self.points[to as usize])
+  }
+
+  // map each wedge of `from` to the wedge of `to` across the
+  // collapsed edge, into `map`. false if a wedge would get lost on the
+  // way, which means the collapse tears a seam apart.
+  fn wedge_map(&self, from: u32, to: u32, map: &mut Vec<(u32, u32)>) -> bool {
+    map.clear();
+    for t in self.edge_trigs(from, to) {
+      let (wf, wt) = self.edge_wedges(t, from, to);
+      match map.iter().find(|(w, _)| *w == wf) {
+        Some((_, existing)) if *existing != wt => return false,
+        Some(_) => {}
+        None => map.push((wf, wt)),
+      }
+    }
+
+    self.alive_trigs(from).all(|t| {
+      let wedge = *self.trigs[t as usize]
+        .iter()
+        .find(|w| self.point(**w) == from)
+        .unwrap();
+      map.iter().any(|(w, _)| *w == wedge)
+    })
+  }
+
+  fn collapse_valid(&mut self, from: u32, to: u32) -> bool {
+    // link condition: the only shared neighbours of the two points
+    // are the opposite vertices of the triangles on the edge,
+    // otherwise the collapse creates a non-manifold fold.
+    let (nf, nt) = (self.neighbours(from), self.neighbours(to));
+    let shared = count_shared(&nf, &nt);
+    self.recycle(nf);
+    self.recycle(nt);
+    if shared != self.edge_trigs(from, to).count() {
+      return false;
+    }
+
+    let mut wedges = std::mem::take(&mut self.wedges);
+    let mapped = self.wedge_map(from, to, &mut wedges);
+    self.wedges = wedges;
+    if !mapped {
+      return false;
+    }
+
+    // no triangle may flip or degenerate when `from` moves to `to`
+    let target = self.points[to as usize];
+    for t in self.alive_trigs(from) {
+      let pts = self.trig_points(t);
+      if pts.contains(&to) {
+        continue;
+      }
+
+      let old = pts.map(|p| self.points[p as usize]);
+      let new = pts.map(|p| {
+        if p == from {
+          target
+        } else {
+          self.points[p as usize]
+        }
+      });
+      let n_old = (old[1] - old[0]).cross(old[2] - old[0]);
+      let n_new = (new[1] - new[0]).cross(new[2] - new[0]);
+      if n_old.dot(n_new) <= 0.0 {
+        return false;
+      }
+    }
+
+    true
+  }
+
+  fn collapse(&mut self, from: u32, to: u32) -> bool {
+    if !self.point_alive[to as usize] || !self.collapse_valid(from, to) {
+      return false;
+    }
+    let mut map = std::mem::take(&mut self.wedges);
+    if !self.wedge_map(from, to, &mut map) {
+      self.wedges = map;
+      return false;
+    }
+
+    let mut trigs = self.buffer();
+    trigs.extend(self.alive_trigs(from));
+    for &t in &trigs {
+      let pts = self.trig_points(t);
+      if pts.contains(&to) {
+        self.trig_alive[t as usize] = false;
+        self.alive_count -= 1;
+        continue;
+      }
+
+      for w in &mut self.trigs[t as usize] {
+        if let Some((_, new)) = map.iter().find(|(old, _)| old == w) {
+          *w = *new;
+        }
+      }
+      self.point_trigs[to as usize].push(t);
+    }
+    self.recycle(trigs);
+    self.wedges = map;
+
+    let q = self.quadrics[from as usize];
+    self.quadrics[to as usize].add(&q);
+    self.point_alive[from as usize] = false;
+    self.point_trigs[from as usize].clear();
+
+    let trig_alive = &self.trig_alive;
+    self.point_trigs[to as usize].retain(|t| trig_alive[*t as usize]);
+
+    // everything around `to` has a new neighbourhood now
+    let mut ring = self.neighbours(to);
+    ring.push(to);
+    for &p in &ring {
+      self.stamps[p as usize] += 1;
+      self.push_best_collapse(p);
+    }
+    self.recycle(ring);
+
+    true
+  }
+}
+
+// how many values two sorted lists without duplicates have in common
+fn count_shared(a: &[u32], b: &[u32]) -> usize {
+  let (mut i, mut j, mut shared) = (0, 0, 0);
+  while i < a.len() && j < b.len() {
+    match a[i].cmp(&b[j]) {
+      Ordering::Less => i += 1,
+      Ordering::Greater => j += 1,
+      Ordering::Equal => {
+        shared += 1;
+        i += 1;
+        j += 1;
+      }
+    }
+  }
+  shared
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use super::*;
+
+  const N: u32 = 16;
+
+  // an N x N grid of quads over [0, N]², one position per vertex, with
+  // bumps so that collapses have a cost
+  fn grid() -> (Vec<[f32; 3]>, Vec<u32>) {
+    let mut positions = Vec::new();
+    for y in 0..=N {
+      for x in 0..=N {
+        let (fx, fy) = (x as f32, y as f32);
+        positions.push([fx, fy, 0.3 * (fx * 0.7).sin() * (fy * 0.5).cos()]);
+      }
+    }
+    let mut indices = Vec::new();
+    for y in 0..N {
+      for x in 0..N {
+        let i = y * (N + 1) + x;
+        let j = i + N + 1;
+        indices.extend_from_slice(&[i, i + 1, j, i + 1, j + 1, j]);
+      }
+    }
+    (positions, indices)
+  }
+
+  fn on_border(p: [f32; 3]) -> bool {
+    let n = N as f32;
+    p[0] == 0.0 || p[0] == n || p[1] == 0.0 || p[1] == n
+  }
+
+  // the edges only one triangle uses
+  fn border_edges(indices: &[u32]) -> Vec<[u32; 2]> {
+    let mut count: HashMap<[u32; 2], u32> = HashMap::new();
+    for t in indices.chunks(3) {
+      for (a, b) in [(t[0], t[1]), (t[1], t[2]), (t[2], t[0])] {
+        *count.entry([a.min(b), a.max(b)]).or_default() += 1;
+      }
+    }
+    count
+      .into_iter()
+      .filter(|(_, n)| *n == 1)
+      .map(|(edge, _)| edge)
+      .collect()
+  }
+
+  #[test]
+  fn reaches_the_target() {
+    let (positions, indices) = grid();
+    let groups = vec![0; indices.len() / 3];
+    let target = indices.len() / 3 / 4;
+    let simplified =
+      simplify(&positions, &indices, &groups, target, f32::INFINITY);
+    assert!(simplified.indices.len() / 3 <= target);
+    assert!(!simplified.indices.is_empty());
+    assert_eq!(simplified.trig_groups.len(), simplified.indices.len() / 3);
+    assert!(simplified.error > 0.0);
+  }
+
+  #[test]
+  fn stops_at_the_error_limit() {
+    let (mut positions, indices) = grid();
+    for p in &mut positions {
+      p[2] = 0.0;
+    }
+    let groups = vec![0; indices.len() / 3];
+    let simplified = simplify(&positions, &indices, &groups, 0, 1e-4);
+    // a flat grid loses its inner vertices for free, then the border
+    // would have to move
+    assert!(simplified.error <= 1e-4);
+    assert!(simplified.indices.len() < indices.len() / 4);
+    assert!(!simplified.indices.is_empty());
+  }
+
+  #[test]
+  fn keeps_the_border() {
+    let (positions, indices) = grid();
+    let groups = vec![0; indices.len() / 3];
+    let simplified = simplify(&positions, &indices, &groups, 8, f32::INFINITY);
+
+    // the corners, where the border turns, are locked
+    let used: Vec<u32> = simplified.indices.clone();
+    for corner in [0, N, N * (N + 1), (N + 1) * (N + 1) - 1] {
+      assert!(used.contains(&corner), "corner {} removed", corner);
+    }
+    // border vertices only slide along the border, so the outline
+    // stays on the original one
+    for [a, b] in border_edges(&simplified.indices) {
+      let (pa, pb) = (positions[a as usize], positions[b as usize]);
+      assert!(on_border(pa) && on_border(pb));
+      assert!(
+        pa[0] == pb[0] || pa[1] == pb[1],
+        "{:?} cuts a corner",
+        [a, b]
+      );
+    }
+  }
+
+  #[test]
+  fn keeps_material_boundaries() {
+    let (positions, indices) = grid();
+    // left and right half
+    let groups: Vec<u32> = indices
+      .chunks(3)
+      .map(|t| {
+        let x = t
+          .iter()
+          .map(|w| positions[*w as usize][0])
+          .fold(N as f32, f32::min);
+        (x >= (N / 2) as f32) as u32
+      })
+      .collect();
+    let simplified = simplify(&positions, &indices, &groups, 8, f32::INFINITY);
+    for (t, group) in simplified.indices.chunks(3).zip(&simplified.trig_groups)
+    {
+      let half = N as f32 / 2.0;
+      for &w in t {
+        let x = positions[w as usize][0];
+        assert!(if *group == 0 { x <= half } else { x >= half });
+      }
+    }
+  }
+
+  #[test]
+  fn lod_levels_shrink() {
+    let (positions, indices) = grid();
+    let groups = vec![0; indices.len() / 3];
+    let lods = lod_chain(&positions, &indices, &groups, &DEFAULT_LOD_RATIOS);
+    assert_eq!(lods.len(), DEFAULT_LOD_RATIOS.len());
+
+    let mut previous = (indices.len() / 3, 0.0);
+    for lod in &lods {
+      let trigs = lod.indices.len() / 3;
+      assert!(trigs < previous.0, "{} after {}", trigs, previous.0);
+      assert!(lod.error >= previous.1);
+      previous = (trigs, lod.error);
+    }
+  }
+
+  #[test]
+  fn selects_the_coarsest_good_enough_lod() {
+    let errors = [0.0, 0.01, 0.05, 0.2];
+    assert_eq!(select_lod(errors, 100.0, 1.0), 1);
+    assert_eq!(select_lod(errors, 10.0, 1.0), 2);
+    assert_eq!(select_lod(errors, 1000.0, 1.0), 0);
+  }
+
+  #[test]
+  fn strips_every_level() {
+    let (positions, indices) = grid();
+    let half = indices.len() / 2;
+    let group_trigs = vec![indices[..half].to_vec(), indices[half..].to_vec()];
+    let mut strips = vec![7];
+    let lods = LodStrips::build(
+      &mut strips,
+      &positions,
+      &group_trigs,
+      &DEFAULT_LOD_RATIOS,
+      None,
+    );
+    assert_eq!(lods.len(), DEFAULT_LOD_RATIOS.len() + 1);
+
+    // one range per group, back to back after what was there
+    let mut end = 1;
+    for lod in 0..lods.len() {
+      let groups = lods.groups(lod);
+      assert_eq!(groups.len(), 2);
+      for range in groups {
+        assert_eq!(range.start, end);
+        end = range.end;
+      }
+    }
+    assert_eq!(end as usize, strips.len());
+
+    let sphere = lods.bounding_sphere();
+    assert!(sphere.radius >= N as f32 / 2.0_f32.sqrt());
+    assert_eq!(lods.select(f32::INFINITY), 0);
+    assert_eq!(lods.select(0.0), lods.len() - 1);
+    // further away, coarser
+    let projection = Mat4::perspective_rh(1.0, 1.0, 0.1, 1000.0);
+    let at = |z: f32| {
+      let model_view = Mat4::from_translation(Vec3::new(0.0, 0.0, -z));
+      lods.select_projected(model_view, projection, 1000.0)
+    };
+    assert!(at(10.0) <= at(100.0) && at(100.0) <= at(900.0));
+    assert_eq!(at(900.0), lods.len() - 1);
+  }
+
+  #[test]
+  fn a_single_level() {
+    let (positions, indices) = grid();
+    let count = indices.len() as u32;
+    let lods = LodStrips::single(&positions, count);
+    assert_eq!(lods.len(), 1);
+    assert_eq!(lods.groups(0).to_vec(), vec![0..count; 1]);
+    assert_eq!(lods.select(0.0), 0);
+  }
+
+  #[test]
+  fn splits_by_group() {
+    let indices = [0, 1, 2, 2, 1, 3, 3, 1, 4];
+    let groups = split_by_group(&indices, &[1, 0, 1], 3);
+    assert_eq!(groups, vec![vec![2, 1, 3], vec![0, 1, 2, 3, 1, 4], vec![]]);
+  }
+}
@@ -0,0 +1,666 @@
+// CPU subdivision of obj meshes, for comparing with the tessellation
+// shaders of project-08.
+//
+// Both schemes work on the position topology of the whole obj, so
+// faces in different groups are smoothed together. Edges on the
+// boundary, edges shared by more than two faces, and edges where the
+// obj normals differ by more than CREASE_ANGLE are treated as creases,
+// vertices with only one face are kept as corners. The uvs
+// are interpolated linearly per face, which keeps uv seams intact.
+// Normals are recomputed after subdivision, split along the creases.
+
+use std::collections::{HashMap, HashSet};
+
+use glam::Vec3;
+
+use super::sorted;
+use crate::obj_loader::{Group, Obj, VAIdx};
+
+// flat shaded meshes like sphere.obj split the normals on every edge,
+// only the sharper ones are considered hard edges.
+const CREASE_ANGLE: f32 = std::f32::consts::FRAC_PI_3;
+
+// Loop subdivision, faces with more than three vertices are
+// triangulated first.
+pub fn loop_subdivide(obj: Obj, levels: usize) -> Obj {
+  let mut mesh = ControlMesh::from_obj(&obj, true);
+  for _ in 0..levels {
+    mesh = mesh.loop_step();
+  }
+  mesh.into_obj(obj)
+}
+
+// Catmull-Clark subdivision, the result contains only quads.
+pub fn catmull_clark(obj: Obj, levels: usize) -> Obj {
+  let mut mesh = ControlMesh::from_obj(&obj, false);
+  for _ in 0..levels {
+    mesh = mesh.catmull_clark_step();
+  }
+  mesh.into_obj(obj)
+}
+
+struct Face {
+  // indices into ControlMesh::pos
+  v: Vec<usize>,
+  // indices into ControlMesh::uv
+  vt: Vec<usize>,
+  group: usize,
+}
+
+struct ControlMesh {
+  pos: Vec<Vec3>,
+  uv: Vec<Vec3>,
+  faces: Vec<Face>,
+  // sorted position index pairs
+  creases: HashSet<[usize; 2]>,
+}
+
+// the faces around an edge
+struct Edge {
+  // (face, index of the first vertex of the edge in the face)
+  faces: Vec<(usize, usize)>,
+  crease: bool,
+}
+
+impl ControlMesh {
+  fn from_obj(obj: &Obj, triangulate: bool) -> Self {
+    let pos = obj.v.iter().copied().map(Vec3::from).collect();
+    let uv = obj.vt.iter().copied().map(Vec3::from).collect();
+    let mut faces = Vec::new();
+    // edge => normals at (lower, higher) vertex of first face
+    let mut edge_normals: HashMap<[usize; 2], [Vec3; 2]> = HashMap::new();
+    let mut creases = HashSet::new();
+    let hard = |[a, b]: [Vec3; 2]| a.angle_between(b) > CREASE_ANGLE;
+
+    for (group, g) in obj.groups.iter().enumerate() {
+      let polygons: Box<dyn Iterator<Item = Vec<VAIdx>>> = if triangulate {
+        Box::new(g.trigs().map(|t| t.to_vec()))
+      } else {
+        Box::new(g.f.iter().cloned())
+      };
+
+      for face in polygons {
+        let n = face.len();
+        for i in 0..n {
+          let (a, b) = (face[i], face[(i + 1) % n]);
+          let (lo, hi) = if a[0] < b[0] { (a, b) } else { (b, a) };
+          let edge = [lo[0] - 1, hi[0] - 1];
+          let normal = |[_, _, vn]: VAIdx| Vec3::from(obj.vn[vn - 1]);
+          let normals = [normal(lo), normal(hi)];
+
+          match edge_normals.get(&edge) {
+            Some(&[a, b]) => {
+              if hard([a, normals[0]]) || hard([b, normals[1]]) {
+                creases.insert(edge);
+              }
+            }
+            None => {
+              edge_normals.insert(edge, normals);
+            }
+          }
+        }
+
+        faces.push(Face {
+          v: face.iter().map(|[v, _, _]| v - 1).collect(),
+          vt: face.iter().map(|[_, vt, _]| vt - 1).collect(),
+          group,
+        });
+      }
+    }
+
+    let mut mesh = Self {
+      pos,
+      uv,
+      faces,
+      creases,
+    };
+    mesh.remove_unused_positions();
+    mesh
+  }
+
+  // unreferenced vertices would otherwise be left with zero valence
+  fn remove_unused_positions(&mut self) {
+    let mut remap = vec![usize::MAX; self.pos.len()];
+    let mut pos = Vec::new();
+
+    for face in &mut self.faces {
+      for v in &mut face.v {
+        if remap[*v] == usize::MAX {
+          remap[*v] = pos.len();
+          pos.push(self.pos[*v]);
+        }
+        *v = remap[*v];
+      }
+    }
+
+    self.creases = self
+      .creases
+      .iter()
+      .map(|[a, b]| sorted([remap[*a], remap[*b]]))
+      .collect();
+    self.pos = pos;
+  }
+
+  fn edges(&self) -> HashMap<[usize; 2], Edge> {
+    let mut edges: HashMap<[usize; 2], Edge> = HashMap::new();
+
+    for (f, face) in self.faces.iter().enumerate() {
+      let n = face.v.len();
+      for i in 0..n {
+        let key = sorted([face.v[i], face.v[(i + 1) % n]]);
+        edges
+          .entry(key)
+          .or_insert_with(|| Edge {
+            faces: Vec::new(),
+            crease: self.creases.contains(&key),
+          })
+          .faces
+          .push((f, i));
+      }
+    }
+
+    for edge in edges.values_mut() {
+      edge.crease |= edge.faces.len() != 2;
+    }
+
+    edges
+  }
+
+  // neighbouring vertices of each vertex, and those connected by a
+  // crease edge
+  fn vertex_rings(
+    &self,
+    edges: &HashMap<[usize; 2], Edge>,
+  ) -> (Vec<Vec<usize>>, Vec<Vec<usize>>) {
+    let mut ring = vec![Vec::new(); self.pos.len()];
+    let mut crease_ring = vec![Vec::new(); self.pos.len()];
+
+    for (&[a, b], edge) in edges {
+      ring[a].push(b);
+      ring[b].push(a);
+      if edge.crease {
+        crease_ring[a].push(b);
+        crease_ring[b].push(a);
+      }
+    }
+
+    (ring, crease_ring)
+  }
+
+  fn loop_step(&self) -> Self {
+    let edges = self.edges();
+    let (ring, crease_ring) = self.vertex_rings(&edges);
+
+    // even vertices keep their indices
+    let mut pos: Vec<Vec3> = (0..self.pos.len())
+      .map(|v| {
+        let p = self.pos[v];
+        match crease_ring[v].len() {
+          0 => {
+            let n = ring[v].len();
+            let beta = if n == 3 {
+              3.0 / 16.0
+            } else {
+              3.0 / (8.0 * n as f32)
+            };
+            let sum: Vec3 = ring[v].iter().map(|&u| self.pos[u]).sum();
+            p * (1.0 - n as f32 * beta) + sum * beta
+          }
+          2 if ring[v].len() > 2 => {
+            let [a, b] = [crease_ring[v][0], crease_ring[v][1]];
+            p * 0.75 + (self.pos[a] + self.pos[b]) * 0.125
+          }
+          // corners stay in place
+          _ => p,
+        }
+      })
+      .collect();
+
+    let mut edge_points = HashMap::new();
+    for (&[a, b], edge) in &edges {
+      let p = if edge.crease {
+        (self.pos[a] + self.pos[b]) * 0.5
+      } else {
+        let opposite =
+          |&(f, i): &(usize, usize)| self.pos[self.faces[f].v[(i + 2) % 3]];
+        let [c, d] = [opposite(&edge.faces[0]), opposite(&edge.faces[1])];
+        (self.pos[a] + self.pos[b]) * 0.375 + (c + d) * 0.125
+      };
+      edge_points.insert([a, b], pos.len());
+      pos.push(p);
+    }
+
+    let mut uv = self.uv.clone();
+    let mut uv_midpoints = HashMap::new();
+    let mut faces = Vec::with_capacity(self.faces.len() * 4);
+
+    for face in &self.faces {
+      let v = |i: usize| face.v[i % 3];
+      let e = |i: usize| edge_points[&sorted([v(i), v(i + 1)])];
+      let vt = |i: usize| face.vt[i % 3];
+      let mut et =
+        |i: usize| midpoint_uv(&mut uv, &mut uv_midpoints, vt(i), vt(i + 1));
+      let et = [et(0), et(1), et(2)];
+
+      for i in 0..3 {
+        faces.push(Face {
+          v: vec![v(i), e(i), e(i + 2)],
+          vt: vec![vt(i), et[i], et[(i + 2) % 3]],
+          group: face.group,
+        });
+      }
+      faces.push(Face {
+        v: vec![e(0), e(1), e(2)],
+        vt: et.to_vec(),
+        group: face.group,
+      });
+    }
+
+    Self {
+      pos,
+      uv,
+      faces,
+      creases: split_creases(&edges, &edge_points),
+    }
+  }
+
+  fn catmull_clark_step(&self) -> Self {
+    let edges = self.edges();
+    let (ring, crease_ring) = self.vertex_rings(&edges);
+
+    let face_points: Vec<Vec3> = self
+      .faces
+      .iter()
+      .map(|face| {
+        face.v.iter().map(|&v| self.pos[v]).sum::<Vec3>() / face.v.len() as f32
+      })
+      .collect();
+
+    // faces around each vertex
+    let mut vertex_faces = vec![Vec::new(); self.pos.len()];
+    for (f, face) in self.faces.iter().enumerate() {
+      for &v in &face.v {
+        vertex_faces[v].push(f);
+      }
+    }
+
+    let mut pos: Vec<Vec3> = (0..self.pos.len())
+      .map(|v| {
+        let p = self.pos[v];
+        match crease_ring[v].len() {
+          0 => {
+            let n = ring[v].len() as f32;
+            let q = vertex_faces[v]
+              .iter()
+              .map(|&f| face_points[f])
+              .sum::<Vec3>()
+              / vertex_faces[v].len() as f32;
+            let r = ring[v]
+              .iter()
+              .map(|&u| (p + self.pos[u]) * 0.5)
+              .sum::<Vec3>()
+              / n;
+            (q + r * 2.0 + p * (n - 3.0)) / n
+          }
+          2 if ring[v].len() > 2 => {
+            let [a, b] = [crease_ring[v][0], crease_ring[v][1]];
+            p * 0.75 + (self.pos[a] + self.pos[b]) * 0.125
+          }
+          _ => p,
+        }
+      })
+      .collect();
+
+    let mut edge_points = HashMap::new();
+    for (&[a, b], edge) in &edges {
+      let p = if edge.crease {
+        (self.pos[a] + self.pos[b]) * 0.5
+      } else {
+        let [(f, _), (g, _)] = [edge.faces[0], edge.faces[1]];
+        (self.pos[a] + self.pos[b] + face_points[f] + face_points[g]) * 0.25
+      };
+      edge_points.insert([a, b], pos.len());
+      pos.push(p);
+    }
+
+    let mut uv = self.uv.clone();
+    let mut uv_midpoints = HashMap::new();
+    let mut faces = Vec::with_capacity(self.faces.len() * 4);
+
+    for (f, face) in self.faces.iter().enumerate() {
+      let n = face.v.len();
+      let center = pos.len();
+      pos.push(face_points[f]);
+      let center_uv = uv.len();
+      uv.push(face.vt.iter().map(|&vt| self.uv[vt]).sum::<Vec3>() / n as f32);
+
+      let e =
+        |i: usize| edge_points[&sorted([face.v[i % n], face.v[(i + 1) % n]])];
+      let et: Vec<usize> = (0..n)
+        .map(|i| {
+          let (a, b) = (face.vt[i], face.vt[(i + 1) % n]);
+          midpoint_uv(&mut uv, &mut uv_midpoints, a, b)
+        })
+        .collect();
+
+      for i in 0..n {
+        let prev = (i + n - 1) % n;
+        faces.push(Face {
+          v: vec![face.v[i], e(i), center, e(prev)],
+          vt: vec![face.vt[i], et[i], center_uv, et[prev]],
+          group: face.group,
+        });
+      }
+    }
+
+    Self {
+      pos,
+      uv,
+      faces,
+      creases: split_creases(&edges, &edge_points),
+    }
+  }
+
+  // smooth normals, one per group of faces around a vertex that are
+  // not separated by creases. returns the normals and their index for
+  // each face vertex.
+  fn normals(&self) -> (Vec<Vec3>, Vec<Vec<usize>>) {
+    let edges = self.edges();
+
+    // each face vertex (corner) is a node of the union-find
+    let mut corner_base = Vec::with_capacity(self.faces.len());
+    let mut corner_count = 0;
+    for face in &self.faces {
+      corner_base.push(corner_count);
+      corner_count += face.v.len();
+    }
+
+    let mut parent: Vec<usize> = (0..corner_count).collect();
+    for edge in edges.values() {
+      if edge.crease {
+        continue;
+      }
+
+      let [(f, i), (g, j)] = [edge.faces[0], edge.faces[1]];
+      let (n, m) = (self.faces[f].v.len(), self.faces[g].v.len());
+      for a in [i, (i + 1) % n] {
+        // the same vertex is either end of the edge in the other face
+        let b = if self.faces[g].v[j] == self.faces[f].v[a] {
+          j
+        } else {
+          (j + 1) % m
+        };
+        union(&mut parent, corner_base[f] + a, corner_base[g] + b);
+      }
+    }
+
+    let mut normal_of_root = HashMap::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+    let mut face_normals = Vec::with_capacity(self.faces.len());
+
+    for (f, face) in self.faces.iter().enumerate() {
+      // newell's method, weighted by area
+      let n = face.v.len();
+      let normal: Vec3 = (0..n)
+        .map(|i| self.pos[face.v[i]].cross(self.pos[face.v[(i + 1) % n]]))
+        .sum();
+
+      let indices = (0..n)
+        .map(|i| {
+          let root = find(&mut parent, corner_base[f] + i);
+          let index = *normal_of_root.entry(root).or_insert_with(|| {
+            normals.push(Vec3::ZERO);
+            normals.len() - 1
+          });
+          normals[index] += normal;
+          index
+        })
+        .collect();
+      face_normals.push(indices);
+    }
+
+    for n in &mut normals {
+      *n = n.normalize_or_zero();
+    }
+
+    (normals, face_normals)
+  }
+
+  fn into_obj(self, obj: Obj) -> Obj {
+    let (normals, face_normals) = self.normals();
+
+    let mut groups: Vec<Group> = obj
+      .groups
+      .into_iter()
+      .map(|g| Group { f: Vec::new(), ..g })
+      .collect();
+
+    for (face, vn) in self.faces.iter().zip(face_normals) {
+      let f = (0..face.v.len())
+        .map(|i| [face.v[i] + 1, face.vt[i] + 1, vn[i] + 1])
+        .collect();
+      groups[face.group].f.push(f);
+    }
+
+    Obj {
+      v: self.pos.iter().map(|p| p.to_array()).collect(),
+      vn: normals.iter().map(|n| n.to_array()).collect(),
+      vt: self.uv.iter().map(|t| t.to_array()).collect(),
+      mtl_lib: obj.mtl_lib,
+      groups,
+    }
+  }
+}
+
+// each crease edge becomes two crease edges through its edge point
+fn split_creases(
+  edges: &HashMap<[usize; 2], Edge>,
+  edge_points: &HashMap<[usize; 2], usize>,
+) -> HashSet<[usize; 2]> {
+  edges
+    .iter()
+    .filter(|(_, edge)| edge.crease)
+    .flat_map(|(&[a, b], _)| {
+      let e = edge_points[&[a, b]];
+      [sorted([a, e]), sorted([e, b])]
+    })
+    .collect()
+}
+
+// shared between the faces on both sides of an edge unless it's a uv
+// seam
+fn midpoint_uv(
+  uv: &mut Vec<Vec3>,
+  midpoints: &mut HashMap<[usize; 2], usize>,
+  a: usize,
+  b: usize,
+) -> usize {
+  *midpoints.entry(sorted([a, b])).or_insert_with(|| {
+    uv.push((uv[a] + uv[b]) * 0.5);
+    uv.len() - 1
+  })
+}
+
+fn find(parent: &mut [usize], mut x: usize) -> usize {
+  while parent[x] != x {
+    parent[x] = parent[parent[x]];
+    x = parent[x];
+  }
+  x
+}
+
+fn union(parent: &mut [usize], a: usize, b: usize) {
+  let (a, b) = (find(parent, a), find(parent, b));
+  parent[a] = b;
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::obj_loader::MtlLib;
+
+  // faces by 0-based index, the normals pointing away from the origin
+  // so that no edge is a crease
+  fn obj(v: &[[f32; 3]], faces: &[&[usize]]) -> Obj {
+    let f = faces
+      .iter()
+      .map(|face| face.iter().map(|&i| [i + 1, i + 1, i + 1]).collect())
+      .collect();
+    Obj {
+      v: v.to_vec(),
+      vn: v
+        .iter()
+        .map(|p| Vec3::from(*p).normalize().into())
+        .collect(),
+      vt: v.to_vec(),
+      mtl_lib: MtlLib::default(),
+      groups: vec![Group {
+        f,
+        ..Group::default()
+      }],
+    }
+  }
+
+  fn tetrahedron() -> Obj {
+    obj(
+      &[
+        [1.0, 1.0, 1.0],
+        [1.0, -1.0, -1.0],
+        [-1.0, 1.0, -1.0],
+        [-1.0, -1.0, 1.0],
+      ],
+      &[&[0, 1, 2], &[0, 3, 1], &[0, 2, 3], &[1, 3, 2]],
+    )
+  }
+
+  fn cube() -> Obj {
+    let v: Vec<[f32; 3]> = (0..8)
+      .map(|i| {
+        [i & 1, (i >> 1) & 1, (i >> 2) & 1].map(|c| c as f32 * 2.0 - 1.0)
+      })
+      .collect();
+    obj(
+      &v,
+      &[
+        &[0, 2, 3, 1],
+        &[4, 5, 7, 6],
+        &[0, 1, 5, 4],
+        &[2, 6, 7, 3],
+        &[0, 4, 6, 2],
+        &[1, 3, 7, 5],
+      ],
+    )
+  }
+
+  // a square with its middle raised, open all around
+  fn tent() -> Obj {
+    obj(
+      &[
+        [1.0, 1.0, 0.0],
+        [-1.0, 1.0, 0.0],
+        [-1.0, -1.0, 0.0],
+        [1.0, -1.0, 0.0],
+        [0.0, 0.0, 1.0],
+      ],
+      &[&[0, 1, 4], &[1, 2, 4], &[2, 3, 4], &[3, 0, 4]],
+    )
+  }
+
+  fn faces(obj: &Obj) -> Vec<&Vec<VAIdx>> {
+    obj.groups.iter().flat_map(|g| &g.f).collect()
+  }
+
+  // position index pairs with the number of faces on them
+  fn edges(obj: &Obj) -> HashMap<[usize; 2], usize> {
+    let mut edges = HashMap::new();
+    for face in faces(obj) {
+      let n = face.len();
+      for i in 0..n {
+        let edge = sorted([face[i][0], face[(i + 1) % n][0]]);
+        *edges.entry(edge).or_default() += 1;
+      }
+    }
+    edges
+  }
+
+  // vertices, edges and faces
+  fn counts(obj: &Obj) -> [usize; 3] {
+    [obj.v.len(), edges(obj).len(), faces(obj).len()]
+  }
+
+  fn closed(obj: &Obj) -> bool {
+    edges(obj).values().all(|&n| n == 2)
+  }
+
+  fn face_sizes(obj: &Obj) -> HashSet<usize> {
+    faces(obj).iter().map(|f| f.len()).collect()
+  }
+
+  #[test]
+  fn loop_counts() {
+    let subdivided = loop_subdivide(tetrahedron(), 1);
+    assert_eq!(counts(&subdivided), [10, 24, 16]);
+    assert_eq!(face_sizes(&subdivided), HashSet::from([3]));
+
+    // triangulated first, 12 triangles and 18 edges
+    let subdivided = loop_subdivide(cube(), 1);
+    assert_eq!(counts(&subdivided), [26, 72, 48]);
+  }
+
+  #[test]
+  fn catmull_clark_counts() {
+    let subdivided = catmull_clark(tetrahedron(), 1);
+    assert_eq!(counts(&subdivided), [14, 24, 12]);
+    assert_eq!(face_sizes(&subdivided), HashSet::from([4]));
+
+    let subdivided = catmull_clark(cube(), 1);
+    assert_eq!(counts(&subdivided), [26, 48, 24]);
+    assert_eq!(face_sizes(&subdivided), HashSet::from([4]));
+
+    // no levels, no change
+    assert_eq!(counts(&catmull_clark(cube(), 0)), [8, 12, 6]);
+  }
+
+  #[test]
+  fn closed_meshes_stay_closed() {
+    for obj in [
+      loop_subdivide(tetrahedron(), 2),
+      loop_subdivide(cube(), 2),
+      catmull_clark(tetrahedron(), 2),
+      catmull_clark(cube(), 2),
+    ] {
+      assert!(closed(&obj));
+      let [v, e, f] = counts(&obj);
+      assert_eq!(v + f, e + 2, "euler characteristic");
+    }
+  }
+
+  #[test]
+  fn smooth_meshes_shrink() {
+    // the smooth rules pull the corners in
+    let subdivided = catmull_clark(cube(), 1);
+    assert!(subdivided
+      .v
+      .iter()
+      .all(|p| Vec3::from(*p).length() < 3f32.sqrt()));
+  }
+
+  // the boundary follows the curve rule, 3/4 of the vertex and 1/8 of
+  // each boundary neighbour, and edge points halve the boundary edges,
+  // so the apex doesn't pull the border up
+  #[test]
+  fn boundary_creases() {
+    for obj in [loop_subdivide(tent(), 1), catmull_clark(tent(), 1)] {
+      assert!(!closed(&obj));
+      for ([a, b], n) in edges(&obj) {
+        if n == 1 {
+          assert_eq!(obj.v[a - 1][2], 0.0);
+          assert_eq!(obj.v[b - 1][2], 0.0);
+        }
+      }
+      // (1, 1) between (-1, 1) and (1, -1)
+      assert!(obj.v.contains(&[0.75, 0.75, 0.0]));
+      assert!(obj.v.contains(&[0.0, 1.0, 0.0]));
+    }
+  }
+}
@@ -1,10 +1,13 @@
 use std::{
-  io::BufRead,
+  io::{BufRead, BufWriter, Write},
   path::{Path, PathBuf},
 };
 
+use glam::Vec3;
 use image::RgbImage;
 
+use crate::math::Aabb;
+
 #[derive(Default)]
 struct ObjLoader {
   base: PathBuf,
@@ -32,6 +35,42 @@ impl Obj {
     let obj = loader.parse(&mut reader)?;
     Ok(obj)
   }
+
+  // for meshes refined offline. The materials aren't written, the
+  // groups keep their usemtl names.
+  pub fn save_to<P: AsRef<Path>>(&self, path: &P) -> Result<()> {
+    let file = std::fs::File::create(path)?;
+    let mut writer = BufWriter::new(file);
+    self.write_to(&mut writer)?;
+    writer.flush()?;
+    Ok(())
+  }
+
+  pub fn write_to<W: Write>(&self, out: &mut W) -> std::io::Result<()> {
+    for [x, y, z] in &self.v {
+      writeln!(out, "v {} {} {}", x, y, z)?;
+    }
+    for [u, v, w] in &self.vt {
+      writeln!(out, "vt {} {} {}", u, v, w)?;
+    }
+    for [x, y, z] in &self.vn {
+      writeln!(out, "vn {} {} {}", x, y, z)?;
+    }
+    for group in &self.groups {
+      writeln!(out, "g {}", group.name)?;
+      if let Some(mtl) = &group.usemtl {
+        writeln!(out, "usemtl {}", mtl)?;
+      }
+      for face in &group.f {
+        write!(out, "f")?;
+        for [v, vt, vn] in face {
+          write!(out, " {}/{}/{}", v, vt, vn)?;
+        }
+        writeln!(out)?;
+      }
+    }
+    Ok(())
+  }
 }
 
 #[derive(PartialEq)]
@@ -132,25 +171,12 @@ impl SimpleObj {
     Ok(obj)
   }
 
-  pub fn bounding_box(&self) -> [(f32, f32); 3] {
-    let mut min = [f32::INFINITY; 3];
-    let mut max = [f32::NEG_INFINITY; 3];
-    for v in self.v.iter() {
-      for i in 0..3 {
-        min[i] = min[i].min(v[i]);
-        max[i] = max[i].max(v[i]);
-      }
-    }
-    [(min[0], max[0]), (min[1], max[1]), (min[2], max[2])]
+  pub fn bounding_box(&self) -> Aabb {
+    Aabb::from_points(self.v.iter().map(|&v| Vec3::from(v)))
   }
 
   pub fn center(&self) -> [f32; 3] {
-    let bbox = self.bounding_box();
-    [
-      (bbox[0].0 + bbox[0].1) / 2.0,
-      (bbox[1].0 + bbox[1].1) / 2.0,
-      (bbox[2].0 + bbox[2].1) / 2.0,
-    ]
+    self.bounding_box().center().to_array()
   }
 
   pub fn trigs(&self) -> impl Iterator<Item = [VAIdx; 3]> + '_ {
@@ -368,3 +394,38 @@ impl ObjLoader {
     Ok(MtlLib { mtls })
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn writes_what_it_reads() {
+    let obj = Obj {
+      v: vec![[0.0, 0.0, 0.0], [1.5, 0.0, 0.0], [0.0, -1e-7, 2.0]],
+      vn: vec![[0.0, 0.0, 1.0]],
+      vt: vec![[0.0, 0.0, 0.0], [1.0, 0.25, 0.0]],
+      mtl_lib: MtlLib::default(),
+      groups: vec![
+        Group {
+          name: "front".into(),
+          f: vec![vec![[1, 1, 1], [2, 2, 1], [3, 1, 1]]],
+          usemtl: Some("red".into()),
+        },
+        Group {
+          name: "back".into(),
+          f: vec![vec![[3, 1, 1], [2, 2, 1], [1, 1, 1]]],
+          usemtl: None,
+        },
+      ],
+    };
+    let mut bytes = Vec::new();
+    obj.write_to(&mut bytes).unwrap();
+    let read = ObjLoader::default().parse(&mut &bytes[..]).unwrap();
+
+    assert_eq!(read.v, obj.v);
+    assert_eq!(read.vn, obj.vn);
+    assert_eq!(read.vt, obj.vt);
+    assert!(read.groups == obj.groups);
+  }
+}
@@ -0,0 +1,878 @@
+// A preprocessor run on the GLSL sources before the driver sees them:
+// - `#include "file"` looks next to the including file first, then in
+//   the search paths, `#include <file>` only in the search paths
+// - `#pragma once` keeps a file from being included twice, #ifndef
+//   guards work as they always do
+// - defines set from Rust go in right after #version
+// - `#line` directives keep the driver's messages pointing at the
+//   original files and lines, see Source::map_log
+// Everything else is left to the driver's own preprocessor, unless the
+// conditionals are resolved here, for tools that see the text only.
+
+use std::{
+  collections::{HashMap, HashSet},
+  fmt::Write,
+  path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Context};
+
+// the includes shared by the projects
+pub const SHARED_SHADERS: &str =
+  concat!(env!("CARGO_MANIFEST_DIR"), "/../assets/shaders");
+
+#[derive(Clone, Debug)]
+pub struct Preprocessor {
+  search_paths: Vec<PathBuf>,
+  defines: Vec<(String, String)>,
+  line_directives: bool,
+  resolve_conditionals: bool,
+}
+
+impl Default for Preprocessor {
+  fn default() -> Self {
+    Self {
+      search_paths: vec![PathBuf::from(SHARED_SHADERS)],
+      defines: Vec::new(),
+      line_directives: true,
+      resolve_conditionals: false,
+    }
+  }
+}
+
+impl Preprocessor {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  // searched in the order added, after the shared includes
+  pub fn search_path(mut self, path: impl Into<PathBuf>) -> Self {
+    self.search_paths.push(path.into());
+    self
+  }
+
+  // `#define name value`, an empty value just defines the name
+  pub fn define(mut self, name: &str, value: impl ToString) -> Self {
+    let value = value.to_string();
+    match self.defines.iter_mut().find(|(n, _)| n == name) {
+      Some((_, existing)) => *existing = value,
+      None => self.defines.push((name.to_owned(), value)),
+    }
+    self
+  }
+
+  pub fn line_directives(mut self, line_directives: bool) -> Self {
+    self.line_directives = line_directives;
+    self
+  }
+
+  // drop the lines of the #if branches not taken, and the directives
+  pub fn resolve_conditionals(mut self, resolve: bool) -> Self {
+    self.resolve_conditionals = resolve;
+    self
+  }
+
+  pub fn process(&self, path: impl AsRef<Path>) -> anyhow::Result<Source> {
+    Ok(self.process_stages(&[path])?.remove(0))
+  }
+
+  // The stages of one program. They share the file numbers, so the
+  // last source maps the positions in the program's whole log.
+  pub fn process_stages<P: AsRef<Path>>(
+    &self,
+    paths: &[P],
+  ) -> anyhow::Result<Vec<Source>> {
+    let mut files = Vec::new();
+    let mut sources = Vec::new();
+    for path in paths {
+      let mut state = State::new(self, files);
+      state.file(path.as_ref(), true)?;
+      if !state.conditions.is_empty() {
+        bail!("{}: unterminated #if", path.as_ref().display());
+      }
+      files = state.files.clone();
+      sources.push(Source {
+        text: state.text,
+        files: state.files.into_iter().map(|(path, _)| path).collect(),
+        origins: state.origins,
+        line_directives: self.line_directives,
+      });
+    }
+    Ok(sources)
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct Source {
+  pub text: String,
+  // every file read; #line directives refer to them by index
+  pub files: Vec<PathBuf>,
+  // the file index and line of each line of the text
+  origins: Vec<(usize, usize)>,
+  line_directives: bool,
+}
+
+impl Source {
+  // the file and line a line of the text, from 1, came from
+  pub fn origin(&self, line: usize) -> Option<(&Path, usize)> {
+    let &(file, line) = self.origins.get(line.checked_sub(1)?)?;
+    Some((&self.files[file], line))
+  }
+
+  // The driver's log with "0:12(5): error", "ERROR: 0:12:" and
+  // "0(12) : error" positions replaced by the file and line.
+  pub fn map_log(&self, log: &str) -> String {
+    let mut mapped = String::new();
+    for line in log.lines() {
+      let start = ["ERROR: ", "WARNING: "]
+        .iter()
+        .find_map(|prefix| line.starts_with(prefix).then_some(prefix.len()))
+        .unwrap_or(0);
+      let position =
+        log_position(&line[start..]).and_then(|(string, number, len)| {
+          let (path, number) = if self.line_directives {
+            (self.files.get(string)?.as_path(), number)
+          } else {
+            self.origin(number)?
+          };
+          Some((path, number, len))
+        });
+      match position {
+        Some((path, number, len)) => writeln!(
+          mapped,
+          "{}{}:{}{}",
+          &line[..start],
+          path.display(),
+          number,
+          &line[start + len..]
+        ),
+        None => writeln!(mapped, "{}", line),
+      }
+      .unwrap();
+    }
+    mapped
+  }
+}
+
+// "string:line" or "string(line)" at the start, with its length
+fn log_position(text: &str) -> Option<(usize, usize, usize)> {
+  let digits = |s: &str| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(0);
+  let string_len = digits(text);
+  if string_len == 0 {
+    return None;
+  }
+  let string = text[..string_len].parse().ok()?;
+  let rest = &text[string_len..];
+  let (open, close) = match rest.chars().next()? {
+    ':' => (1, 0),
+    '(' => (1, 1),
+    _ => return None,
+  };
+  let line_len = digits(&rest[open..]);
+  if line_len == 0 {
+    return None;
+  }
+  let line = rest[open..open + line_len].parse().ok()?;
+  if close == 1 && !rest[open + line_len..].starts_with(')') {
+    return None;
+  }
+  Some((string, line, string_len + open + line_len + close))
+}
+
+struct State<'a> {
+  preprocessor: &'a Preprocessor,
+  text: String,
+  origins: Vec<(usize, usize)>,
+  // as given and canonical, shared by the stages
+  files: Vec<(PathBuf, PathBuf)>,
+  once: HashSet<PathBuf>,
+  // the files being included, for catching cycles
+  stack: Vec<PathBuf>,
+  // the string and line the driver counts the next line as, and
+  // whether #version is still to come, as nothing but comments can go
+  // before it
+  driver: (usize, usize),
+  version_pending: bool,
+  // when resolving conditionals
+  defines: HashMap<String, String>,
+  conditions: Vec<Condition>,
+}
+
+struct Condition {
+  // whether the enclosing branch is taken
+  parent: bool,
+  active: bool,
+  // whether any branch so far was
+  taken: bool,
+  after_else: bool,
+}
+
+impl<'a> State<'a> {
+  fn new(
+    preprocessor: &'a Preprocessor,
+    files: Vec<(PathBuf, PathBuf)>,
+  ) -> Self {
+    Self {
+      preprocessor,
+      text: String::new(),
+      origins: Vec::new(),
+      files,
+      once: HashSet::new(),
+      stack: Vec::new(),
+      driver: (0, 1),
+      version_pending: false,
+      defines: preprocessor.defines.iter().cloned().collect(),
+      conditions: Vec::new(),
+    }
+  }
+
+  fn file(&mut self, path: &Path, root: bool) -> anyhow::Result<()> {
+    let canonical = path
+      .canonicalize()
+      .with_context(|| format!("reading {}", path.display()))?;
+    if self.once.contains(&canonical) {
+      return Ok(());
+    }
+    if self.stack.contains(&canonical) {
+      let cycle: Vec<String> = self
+        .stack
+        .iter()
+        .skip_while(|file| **file != canonical)
+        .chain([&canonical])
+        .map(|file| file.display().to_string())
+        .collect();
+      bail!("include cycle: {}", cycle.join(" -> "));
+    }
+    let source = std::fs::read_to_string(path)
+      .with_context(|| format!("reading {}", path.display()))?;
+    let index = match self.files.iter().position(|(_, c)| *c == canonical) {
+      Some(index) => index,
+      None => {
+        self.files.push((path.to_owned(), canonical.clone()));
+        self.files.len() - 1
+      }
+    };
+    // the defines go at the very top without a #version
+    let mut version_seen = !root || !has_version(&source);
+    if root {
+      self.version_pending = !version_seen;
+      if version_seen {
+        self.inject_defines((index, 1));
+      }
+    }
+
+    self.stack.push(canonical.clone());
+    for (i, line) in source.lines().enumerate() {
+      let origin = (index, i + 1);
+      let at = || format!("{}:{}", path.display(), i + 1);
+      let Some((directive, rest)) = directive(line) else {
+        if self.active() {
+          self.emit(origin, line);
+        }
+        continue;
+      };
+      if self.preprocessor.resolve_conditionals {
+        let conditional = self.conditional(directive, rest).with_context(at)?;
+        if conditional {
+          continue;
+        }
+      }
+      if !self.active() {
+        continue;
+      }
+      match directive {
+        "include" => {
+          let included = self.find_include(rest, path).with_context(at)?;
+          self.file(&included, false)?;
+        }
+        "pragma" if rest == "once" => {
+          self.once.insert(canonical.clone());
+        }
+        "version" if !version_seen => {
+          version_seen = true;
+          self.emit(origin, line);
+          self.version_pending = false;
+          self.inject_defines(origin);
+          if let Ok(version) =
+            rest.split_whitespace().next().unwrap_or("").parse::<u32>()
+          {
+            self
+              .defines
+              .insert("__VERSION__".to_owned(), version.to_string());
+          }
+        }
+        "version" => {
+          bail!("{}: #version in an included file", at());
+        }
+        "define" => {
+          let name_len = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+          let (name, value) = rest.split_at(name_len);
+          self
+            .defines
+            .insert(name.to_owned(), value.trim().to_owned());
+          self.emit(origin, line);
+        }
+        "undef" => {
+          self.defines.remove(rest);
+          self.emit(origin, line);
+        }
+        _ => self.emit(origin, line),
+      }
+    }
+    self.stack.pop();
+    Ok(())
+  }
+
+  fn emit(&mut self, origin: (usize, usize), line: &str) {
+    if self.preprocessor.line_directives
+      && !self.version_pending
+      && self.driver != origin
+    {
+      // GLSL 3.30 and up number the line after #line as given
+      self.push(origin, &format!("#line {} {}", origin.1, origin.0));
+      self.driver = origin;
+    }
+    self.push(origin, line);
+  }
+
+  fn push(&mut self, origin: (usize, usize), line: &str) {
+    self.text.push_str(line);
+    self.text.push('\n');
+    self.origins.push(origin);
+    self.driver.1 += 1;
+  }
+
+  fn inject_defines(&mut self, origin: (usize, usize)) {
+    for (name, value) in &self.preprocessor.defines {
+      let line = format!("#define {} {}", name, value);
+      self.push(origin, line.trim_end());
+    }
+  }
+
+  fn active(&self) -> bool {
+    self.conditions.last().is_none_or(|c| c.active)
+  }
+
+  fn find_include(&self, rest: &str, from: &Path) -> anyhow::Result<PathBuf> {
+    let quoted = rest.strip_prefix('"').and_then(|r| r.strip_suffix('"'));
+    let angled = rest.strip_prefix('<').and_then(|r| r.strip_suffix('>'));
+    let (name, local) = match (quoted, angled) {
+      (Some(name), _) => (name, true),
+      (_, Some(name)) => (name, false),
+      _ => bail!("expected \"file\" or <file> after #include"),
+    };
+    let local = local
+      .then(|| from.parent().map(|dir| dir.join(name)))
+      .flatten();
+    let searched = self.preprocessor.search_paths.iter().map(|p| p.join(name));
+    local
+      .into_iter()
+      .chain(searched)
+      .find(|path| path.is_file())
+      .ok_or_else(|| {
+        anyhow!(
+          "can't find {} in {}",
+          name,
+          self
+            .preprocessor
+            .search_paths
+            .iter()
+            .map(|p| p.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+        )
+      })
+  }
+
+  // false for the directives that aren't conditionals
+  fn conditional(
+    &mut self,
+    directive: &str,
+    rest: &str,
+  ) -> anyhow::Result<bool> {
+    let parent = self.active();
+    match directive {
+      "if" | "ifdef" | "ifndef" => {
+        let taken = parent
+          && match directive {
+            "ifdef" => self.defines.contains_key(rest),
+            "ifndef" => !self.defines.contains_key(rest),
+            _ => self.eval(rest)? != 0,
+          };
+        self.conditions.push(Condition {
+          parent,
+          active: taken,
+          taken,
+          after_else: false,
+        });
+      }
+      "elif" => {
+        let condition = self
+          .conditions
+          .last()
+          .ok_or_else(|| anyhow!("#elif without #if"))?;
+        if condition.after_else {
+          bail!("#elif after #else");
+        }
+        let taken =
+          condition.parent && !condition.taken && self.eval(rest)? != 0;
+        let condition = self.conditions.last_mut().unwrap();
+        condition.active = taken;
+        condition.taken |= taken;
+      }
+      "else" => {
+        let condition = self
+          .conditions
+          .last_mut()
+          .ok_or_else(|| anyhow!("#else without #if"))?;
+        if condition.after_else {
+          bail!("second #else");
+        }
+        condition.active = condition.parent && !condition.taken;
+        condition.taken = true;
+        condition.after_else = true;
+      }
+      "endif" => {
+        self
+          .conditions
+          .pop()
+          .ok_or_else(|| anyhow!("#endif without #if"))?;
+      }
+      _ => return Ok(false),
+    }
+    Ok(true)
+  }
+
+  fn eval(&self, expression: &str) -> anyhow::Result<i64> {
+    let tokens = expression_tokens(expression)?;
+    let mut parser = Expression {
+      tokens: &tokens,
+      at: 0,
+      defines: &self.defines,
+    };
+    let value = parser.or()?;
+    match tokens.get(parser.at) {
+      None => Ok(value),
+      Some(token) => bail!("unexpected `{}` in #if {}", token, expression),
+    }
+  }
+}
+
+// the directive name and the rest without a trailing comment
+fn directive(line: &str) -> Option<(&str, &str)> {
+  let line = line.trim_start().strip_prefix('#')?.trim_start();
+  let line = match line.find("//") {
+    Some(comment) => &line[..comment],
+    None => line,
+  };
+  let name_len = line
+    .find(|c: char| !c.is_ascii_alphabetic())
+    .unwrap_or(line.len());
+  Some((&line[..name_len], line[name_len..].trim()))
+}
+
+fn has_version(source: &str) -> bool {
+  source
+    .lines()
+    .any(|line| directive(line).is_some_and(|(name, _)| name == "version"))
+}
+
+fn expression_tokens(expression: &str) -> anyhow::Result<Vec<String>> {
+  let mut tokens = Vec::new();
+  let mut chars = expression.char_indices().peekable();
+  while let Some((start, c)) = chars.next() {
+    if c.is_whitespace() {
+      continue;
+    }
+    let mut end = start + c.len_utf8();
+    if c.is_alphanumeric() || c == '_' {
+      while let Some(&(i, c)) = chars.peek() {
+        if !(c.is_alphanumeric() || c == '_') {
+          break;
+        }
+        end = i + c.len_utf8();
+        chars.next();
+      }
+    } else if let Some(&(i, next)) = chars.peek() {
+      let pair = [c, next].iter().collect::<String>();
+      if ["&&", "||", "==", "!=", "<=", ">="].contains(&pair.as_str()) {
+        end = i + next.len_utf8();
+        chars.next();
+      }
+    }
+    let token = &expression[start..end];
+    if !(token.chars().all(|c| c.is_alphanumeric() || c == '_')
+      || "()!<>+-*/%&&||==!=<=>=".contains(token))
+    {
+      bail!("unexpected `{}` in #if {}", token, expression);
+    }
+    tokens.push(token.to_owned());
+  }
+  Ok(tokens)
+}
+
+// The #if expressions, on integers as in C: defined, the comparisons,
+// the logic and arithmetic operators. A name that isn't defined, or
+// isn't defined as a number, is 0.
+struct Expression<'a> {
+  tokens: &'a [String],
+  at: usize,
+  defines: &'a HashMap<String, String>,
+}
+
+impl Expression<'_> {
+  fn peek(&self) -> Option<&str> {
+    self.tokens.get(self.at).map(String::as_str)
+  }
+
+  fn eat(&mut self, token: &str) -> bool {
+    let found = self.peek() == Some(token);
+    if found {
+      self.at += 1;
+    }
+    found
+  }
+
+  fn or(&mut self) -> anyhow::Result<i64> {
+    let mut value = self.and()?;
+    while self.eat("||") {
+      let right = self.and()?;
+      value = (value != 0 || right != 0) as i64;
+    }
+    Ok(value)
+  }
+
+  fn and(&mut self) -> anyhow::Result<i64> {
+    let mut value = self.binary(0)?;
+    while self.eat("&&") {
+      let right = self.binary(0)?;
+      value = (value != 0 && right != 0) as i64;
+    }
+    Ok(value)
+  }
+
+  // the binary operators from the loosest binding
+  fn binary(&mut self, level: usize) -> anyhow::Result<i64> {
+    const LEVELS: [&[&str]; 4] = [
+      &["==", "!="],
+      &["<", ">", "<=", ">="],
+      &["+", "-"],
+      &["*", "/", "%"],
+    ];
+    let Some(operators) = LEVELS.get(level) else {
+      return self.unary();
+    };
+    let mut value = self.binary(level + 1)?;
+    while let Some(operator) =
+      self.peek().filter(|token| operators.contains(token))
+    {
+      let operator = operator.to_owned();
+      self.at += 1;
+      let right = self.binary(level + 1)?;
+      value = match operator.as_str() {
+        "==" => (value == right) as i64,
+        "!=" => (value != right) as i64,
+        "<" => (value < right) as i64,
+        ">" => (value > right) as i64,
+        "<=" => (value <= right) as i64,
+        ">=" => (value >= right) as i64,
+        "+" => value.wrapping_add(right),
+        "-" => value.wrapping_sub(right),
+        "*" => value.wrapping_mul(right),
+        _ if right == 0 => bail!("division by zero in #if"),
+        "/" => value / right,
+        _ => value % right,
+      };
+    }
+    Ok(value)
+  }
+
+  fn unary(&mut self) -> anyhow::Result<i64> {
+    if self.eat("!") {
+      return Ok((self.unary()? == 0) as i64);
+    }
+    if self.eat("-") {
+      return Ok(self.unary()?.wrapping_neg());
+    }
+    if self.eat("(") {
+      let value = self.or()?;
+      if !self.eat(")") {
+        bail!("missing `)` in #if");
+      }
+      return Ok(value);
+    }
+    let token = self
+      .peek()
+      .ok_or_else(|| anyhow!("#if expression ends early"))?
+      .to_owned();
+    self.at += 1;
+    if token == "defined" {
+      let parens = self.eat("(");
+      let name = self
+        .peek()
+        .ok_or_else(|| anyhow!("expected a name after defined"))?
+        .to_owned();
+      self.at += 1;
+      if parens && !self.eat(")") {
+        bail!("missing `)` after defined({}", name);
+      }
+      return Ok(self.defines.contains_key(&name) as i64);
+    }
+    if let Ok(number) = token.trim_end_matches(['u', 'U']).parse() {
+      return Ok(number);
+    }
+    Ok(
+      self
+        .defines
+        .get(&token)
+        .and_then(|value| value.trim_end_matches(['u', 'U']).parse().ok())
+        .unwrap_or(0),
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // a directory of its own for each test, as they run in parallel
+  fn sources(test: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+      "cs5610-preprocessor-{}-{}",
+      test,
+      std::process::id()
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    for (name, text) in files {
+      std::fs::write(dir.join(name), text).unwrap();
+    }
+    dir
+  }
+
+  // without #line directives, so the text is just the lines
+  fn plain() -> Preprocessor {
+    Preprocessor::new().line_directives(false)
+  }
+
+  fn lines(source: &Source) -> Vec<&str> {
+    source.text.lines().collect()
+  }
+
+  #[test]
+  fn includes_once() {
+    let dir = sources(
+      "once",
+      &[
+        (
+          "main.glsl",
+          "#version 330\n#include \"once.glsl\"\n#include \"guarded.glsl\"\n\
+           #include \"once.glsl\"\n#include \"guarded.glsl\"\nmain\n",
+        ),
+        ("once.glsl", "#pragma once\nonce\n"),
+        (
+          "guarded.glsl",
+          "#ifndef GUARDED\n#define GUARDED\nguarded\n#endif\n",
+        ),
+      ],
+    );
+    let source = plain()
+      .resolve_conditionals(true)
+      .process(dir.join("main.glsl"))
+      .unwrap();
+    assert_eq!(
+      lines(&source),
+      ["#version 330", "once", "#define GUARDED", "guarded", "main"]
+    );
+  }
+
+  #[test]
+  fn include_cycles() {
+    let dir = sources(
+      "cycle",
+      &[
+        ("main.glsl", "#include \"a.glsl\"\n"),
+        ("a.glsl", "#include \"b.glsl\"\n"),
+        ("b.glsl", "#include \"a.glsl\"\n"),
+      ],
+    );
+    let error = plain().process(dir.join("main.glsl")).unwrap_err();
+    let error = format!("{:#}", error);
+    assert!(error.contains("include cycle"), "{}", error);
+    assert!(error.contains("a.glsl -> "), "{}", error);
+    assert!(error.ends_with("a.glsl"), "{}", error);
+  }
+
+  #[test]
+  fn includes_from_the_search_paths() {
+    let shared = sources("search-shared", &[("lib.glsl", "lib\n")]);
+    let dir = sources(
+      "search",
+      &[("main.glsl", "#include <lib.glsl>\n#include \"lib.glsl\"\n")],
+    );
+    let source = plain()
+      .search_path(&shared)
+      .process(dir.join("main.glsl"))
+      .unwrap();
+    assert_eq!(lines(&source), ["lib", "lib"]);
+
+    let error = plain().process(dir.join("main.glsl")).unwrap_err();
+    assert!(format!("{:#}", error).contains("can't find lib.glsl"));
+  }
+
+  const NESTED: &str = "#version 330
+#if MODE == 1
+one
+#elif MODE == 2
+two
+#ifdef EXTRA
+extra
+#elif defined(OTHER) && MODE > 1
+other
+#else
+neither
+#endif
+#else
+default
+#endif
+end
+";
+
+  #[test]
+  fn nested_conditionals() {
+    let dir = sources("nested", &[("main.glsl", NESTED)]);
+    let resolve = |preprocessor: Preprocessor| {
+      let source = preprocessor
+        .resolve_conditionals(true)
+        .process(dir.join("main.glsl"))
+        .unwrap();
+      // without the #version and #defines
+      source
+        .text
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(str::to_owned)
+        .collect::<Vec<_>>()
+    };
+
+    assert_eq!(resolve(plain()), ["default", "end"]);
+    assert_eq!(resolve(plain().define("MODE", 1)), ["one", "end"]);
+    assert_eq!(
+      resolve(plain().define("MODE", 2)),
+      ["two", "neither", "end"]
+    );
+    let extra = plain().define("MODE", 2).define("EXTRA", "");
+    assert_eq!(resolve(extra), ["two", "extra", "end"]);
+    let other = plain().define("MODE", 2).define("OTHER", "");
+    assert_eq!(resolve(other), ["two", "other", "end"]);
+    // nothing in a branch not taken counts
+    let other = plain().define("MODE", 1).define("OTHER", "");
+    assert_eq!(resolve(other), ["one", "end"]);
+  }
+
+  #[test]
+  fn unbalanced_conditionals() {
+    for (test, text, message) in [
+      ("unterminated", "#if 1\n", "unterminated #if"),
+      ("stray-endif", "#endif\n", "#endif without #if"),
+      (
+        "elif-after-else",
+        "#if 0\n#else\n#elif 1\n#endif\n",
+        "#elif after",
+      ),
+    ] {
+      let dir = sources(test, &[("main.glsl", text)]);
+      let error = plain()
+        .resolve_conditionals(true)
+        .process(dir.join("main.glsl"))
+        .unwrap_err();
+      let error = format!("{:#}", error);
+      assert!(error.contains(message), "{}: {}", test, error);
+    }
+  }
+
+  #[test]
+  fn defines_after_the_version() {
+    let dir = sources(
+      "defines",
+      &[
+        ("version.glsl", "#version 330\nvoid main() {}\n"),
+        ("no_version.glsl", "void main() {}\n"),
+      ],
+    );
+    let preprocessor = Preprocessor::new().define("A", 1).define("B", "");
+    let source = preprocessor.process(dir.join("version.glsl")).unwrap();
+    assert_eq!(
+      source.text,
+      "#version 330\n#define A 1\n#define B\n#line 2 0\nvoid main() {}\n"
+    );
+    let source = preprocessor.process(dir.join("no_version.glsl")).unwrap();
+    assert_eq!(
+      source.text,
+      "#define A 1\n#define B\n#line 1 0\nvoid main() {}\n"
+    );
+
+    // defining again replaces the value
+    let source = preprocessor
+      .define("A", 2)
+      .line_directives(false)
+      .process(dir.join("version.glsl"))
+      .unwrap();
+    assert_eq!(lines(&source)[1], "#define A 2");
+  }
+
+  fn with_include() -> PathBuf {
+    sources(
+      "origin",
+      &[
+        ("main.glsl", "#version 330\n#include \"a.glsl\"\nmain\n"),
+        ("a.glsl", "a1\na2\n"),
+      ],
+    )
+  }
+
+  #[test]
+  fn origins() {
+    let dir = with_include();
+    let source = plain().process(dir.join("main.glsl")).unwrap();
+    assert_eq!(lines(&source), ["#version 330", "a1", "a2", "main"]);
+
+    let (main, a) = (dir.join("main.glsl"), dir.join("a.glsl"));
+    assert_eq!(source.origin(1), Some((main.as_path(), 1)));
+    assert_eq!(source.origin(2), Some((a.as_path(), 1)));
+    assert_eq!(source.origin(3), Some((a.as_path(), 2)));
+    assert_eq!(source.origin(4), Some((main.as_path(), 3)));
+    assert_eq!(source.origin(0), None);
+    assert_eq!(source.origin(5), None);
+  }
+
+  #[test]
+  fn maps_logs() {
+    let dir = with_include();
+    let (main, a) = (dir.join("main.glsl"), dir.join("a.glsl"));
+    let (main, a) = (main.display(), a.display());
+
+    // the driver reports the files and lines of the #line directives
+    let source = Preprocessor::new().process(dir.join("main.glsl")).unwrap();
+    let log = "0:3(5): error: mesa\nERROR: 1:2: angle\n\
+               0(1) : error C0000: nvidia\nlinking failed\n";
+    assert_eq!(
+      source.map_log(log),
+      format!(
+        "{main}:3(5): error: mesa\nERROR: {a}:2: angle\n\
+         {main}:1 : error C0000: nvidia\nlinking failed\n"
+      )
+    );
+
+    // or the lines of the whole text without them
+    let source = plain().process(dir.join("main.glsl")).unwrap();
+    let log = "0:4(5): error: mesa\nWARNING: 0:2: angle\n0(3) : nvidia\n";
+    assert_eq!(
+      source.map_log(log),
+      format!(
+        "{main}:3(5): error: mesa\nWARNING: {a}:1: angle\n{a}:2 : nvidia\n"
+      )
+    );
+  }
+}
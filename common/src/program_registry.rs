@@ -0,0 +1,300 @@
+// Programs that follow their shader files. Each program loaded through a
+// ProgramHandle remembers every file it was built from, includes too,
+// and poll(), called once a frame, rebuilds the ones whose files were
+// modified since. A failed rebuild keeps the last good program and the
+// error stays around for showing until a rebuild succeeds.
+//
+// The registry is per thread, as the GL context is, and only holds on
+// to the programs while some handle does.
+
+use std::{
+  cell::{Ref, RefCell},
+  path::{Path, PathBuf},
+  rc::{Rc, Weak},
+  time::SystemTime,
+};
+
+use glium::{backend::Facade, Program};
+
+use crate::{glium_helper::compile_stages, preprocessor::Preprocessor};
+
+thread_local! {
+  static PROGRAMS: RefCell<Vec<Weak<Entry>>> = const { RefCell::new(Vec::new()) };
+}
+
+#[derive(Clone)]
+pub struct ProgramHandle(Rc<Entry>);
+
+struct Entry {
+  stages: Vec<PathBuf>,
+  preprocessor: Preprocessor,
+  program: RefCell<Program>,
+  // every file the last build read, as modified then
+  files: RefCell<Vec<(PathBuf, Option<SystemTime>)>>,
+  error: RefCell<Option<String>>,
+}
+
+impl ProgramHandle {
+  // path.vert and path.frag, like load_program
+  pub fn load(
+    path: impl AsRef<Path>,
+    facade: &impl Facade,
+  ) -> anyhow::Result<Self> {
+    Self::load_with(path, &Preprocessor::new(), facade)
+  }
+
+  pub fn load_with(
+    path: impl AsRef<Path>,
+    preprocessor: &Preprocessor,
+    facade: &impl Facade,
+  ) -> anyhow::Result<Self> {
+    let path = path.as_ref();
+    let stages = [path.with_extension("vert"), path.with_extension("frag")];
+    Self::load_stages(&stages, preprocessor, facade)
+  }
+
+  // like load_stages. The first build has no program to fall back to,
+  // its errors are returned.
+  pub fn load_stages<P: AsRef<Path>>(
+    stages: &[P],
+    preprocessor: &Preprocessor,
+    facade: &impl Facade,
+  ) -> anyhow::Result<Self> {
+    let stages: Vec<PathBuf> =
+      stages.iter().map(|p| p.as_ref().to_owned()).collect();
+    let sources = preprocessor.process_stages(&stages)?;
+    let program = compile_stages(&stages, &sources, facade)?;
+    let files = sources.last().map_or(&[][..], |s| &s.files[..]);
+    let entry = Rc::new(Entry {
+      files: RefCell::new(stamped(files)),
+      stages,
+      preprocessor: preprocessor.clone(),
+      program: RefCell::new(program),
+      error: RefCell::new(None),
+    });
+    PROGRAMS.with_borrow_mut(|programs| {
+      programs.retain(|entry| entry.strong_count() > 0);
+      programs.push(Rc::downgrade(&entry));
+    });
+    Ok(Self(entry))
+  }
+
+  // the last program that built
+  pub fn get(&self) -> Ref<'_, Program> {
+    self.0.program.borrow()
+  }
+
+  // why the files as they are now don't build
+  pub fn error(&self) -> Option<String> {
+    self.0.error.borrow().clone()
+  }
+}
+
+impl Entry {
+  fn name(&self) -> String {
+    let names: Vec<String> = self
+      .stages
+      .iter()
+      .filter_map(|path| Some(path.file_name()?.to_string_lossy().into()))
+      .collect();
+    names.join(" + ")
+  }
+
+  fn changed(&self) -> bool {
+    self
+      .files
+      .borrow()
+      .iter()
+      .any(|(path, modified)| modified_time(path) != *modified)
+  }
+
+  // Whatever happens, the files are stamped again, so a broken file is
+  // only tried again after the next change.
+  fn rebuild(&self, facade: &impl Facade) -> anyhow::Result<()> {
+    let result =
+      self
+        .preprocessor
+        .process_stages(&self.stages)
+        .and_then(|sources| {
+          let files = sources.last().map_or(&[][..], |s| &s.files[..]);
+          *self.files.borrow_mut() = stamped(files);
+          compile_stages(&self.stages, &sources, facade)
+        });
+    match result {
+      Ok(program) => {
+        *self.program.borrow_mut() = program;
+        *self.error.borrow_mut() = None;
+        Ok(())
+      }
+      Err(e) => {
+        let files: Vec<PathBuf> =
+          self.files.borrow().iter().map(|(p, _)| p.clone()).collect();
+        *self.files.borrow_mut() = stamped(&files);
+        *self.error.borrow_mut() = Some(format!("{:#}", e));
+        Err(e)
+      }
+    }
+  }
+}
+
+fn stamped(files: &[PathBuf]) -> Vec<(PathBuf, Option<SystemTime>)> {
+  files
+    .iter()
+    .map(|path| (path.clone(), modified_time(path)))
+    .collect()
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+  std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn live_entries() -> Vec<Rc<Entry>> {
+  PROGRAMS.with_borrow_mut(|programs| {
+    programs.retain(|entry| entry.strong_count() > 0);
+    programs.iter().filter_map(Weak::upgrade).collect()
+  })
+}
+
+// Rebuilds the programs whose files changed, printing what happened.
+// Whether any was rebuilt, so the frame can be drawn again.
+pub fn poll(facade: &impl Facade) -> bool {
+  let mut rebuilt = false;
+  for entry in live_entries() {
+    if !entry.changed() {
+      continue;
+    }
+    match entry.rebuild(facade) {
+      Ok(()) => println!("Rebuilt {}", entry.name()),
+      Err(e) => eprintln!("Failed to rebuild {}: {:#}", entry.name(), e),
+    }
+    rebuilt = true;
+  }
+  rebuilt
+}
+
+// every program, changed or not, failing with all the errors
+pub fn reload_all(facade: &impl Facade) -> anyhow::Result<()> {
+  let errors: Vec<String> = live_entries()
+    .iter()
+    .filter_map(|entry| {
+      let e = entry.rebuild(facade).err()?;
+      Some(format!("{}: {:#}", entry.name(), e))
+    })
+    .collect();
+  if errors.is_empty() {
+    Ok(())
+  } else {
+    anyhow::bail!("{}", errors.join("\n"))
+  }
+}
+
+// the programs that currently don't build, with their errors
+pub fn errors() -> Vec<(String, String)> {
+  live_entries()
+    .iter()
+    .filter_map(|entry| Some((entry.name(), entry.error.borrow().clone()?)))
+    .collect()
+}
+
+// for a title bar, the details went to stderr when the rebuild failed
+pub fn error_summary() -> Option<String> {
+  let failed: Vec<String> =
+    errors().into_iter().map(|(name, _)| name).collect();
+  if failed.is_empty() {
+    return None;
+  }
+  Some(format!("shader error in {}", failed.join(", ")))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{fs::File, time::Duration};
+
+  use super::*;
+  use crate::gl_boilerplate::{init_headless, Headless};
+
+  const VERTEX: &str = "#version 330\nin vec3 pos;\n\
+                        void main() { gl_Position = vec4(pos, 1.0); }\n";
+  const FRAGMENT: &str = "#version 330\nuniform vec4 tint;\nout vec4 color;\n\
+                          void main() { color = tint; }\n";
+
+  struct Shaders {
+    dir: PathBuf,
+    headless: Headless,
+  }
+
+  impl Shaders {
+    fn new(name: &str) -> Self {
+      let dir = std::env::temp_dir().join(format!(
+        "cs5610-{}-{}",
+        name,
+        std::process::id()
+      ));
+      std::fs::create_dir_all(&dir).unwrap();
+      let (headless, _) = init_headless(16, 16).unwrap();
+      let shaders = Self { dir, headless };
+      shaders.write("test.vert", VERTEX, 1);
+      shaders.write("test.frag", FRAGMENT, 1);
+      shaders
+    }
+
+    // modified at `second`, so the changes don't depend on how fine the
+    // file system's times are
+    fn write(&self, name: &str, source: &str, second: u64) {
+      let path = self.dir.join(name);
+      std::fs::write(&path, source).unwrap();
+      let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(second);
+      File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+    }
+
+    fn load(&self) -> ProgramHandle {
+      ProgramHandle::load(self.dir.join("test"), &self.headless).unwrap()
+    }
+  }
+
+  impl Drop for Shaders {
+    fn drop(&mut self) {
+      let _ = std::fs::remove_dir_all(&self.dir);
+    }
+  }
+
+  #[test]
+  fn rebuilds_modified_programs() {
+    let shaders = Shaders::new("rebuild");
+    let handle = shaders.load();
+    assert!(!poll(&shaders.headless));
+    assert!(handle.get().get_uniform("tint").is_some());
+
+    let fragment = FRAGMENT.replace("tint", "shade");
+    shaders.write("test.frag", &fragment, 2);
+    assert!(poll(&shaders.headless));
+    assert!(handle.get().get_uniform("shade").is_some());
+    assert!(handle.error().is_none());
+    assert!(!poll(&shaders.headless));
+  }
+
+  #[test]
+  fn keeps_the_last_good_program() {
+    let shaders = Shaders::new("last-good");
+    let handle = shaders.load();
+
+    shaders.write("test.frag", "#version 330\nvoid main() { x = 1; }\n", 2);
+    assert!(poll(&shaders.headless));
+    assert!(handle.get().get_uniform("tint").is_some());
+    assert!(handle.error().is_some());
+    let summary = error_summary().unwrap();
+    assert!(summary.contains("test.vert + test.frag"), "{}", summary);
+    // tried once, until the next change
+    assert!(!poll(&shaders.headless));
+
+    shaders.write("test.frag", FRAGMENT, 3);
+    assert!(poll(&shaders.headless));
+    assert!(handle.error().is_none());
+    assert!(error_summary().is_none());
+  }
+}
@@ -1,8 +1,70 @@
+use std::cell::Ref;
+
+use glam::{Mat4, Vec2, Vec3};
 use glium::{uniforms::Uniforms, DrawParameters};
 
+use crate::{
+  camera::DepthMode,
+  math::{DepthRange, Ray},
+};
+
+// Pixels are window coordinates with the origin at the top left, as
+// winit reports them. Depth is in normalized device coordinates.
 pub trait CameraLike {
   fn view(&self) -> [[f32; 4]; 4];
   fn projection(&self) -> [[f32; 4]; 4];
+
+  // what the depth tests and clear values of its draws follow
+  fn depth_mode(&self) -> DepthMode {
+    DepthMode::default()
+  }
+
+  // where the projection puts the near and far planes
+  fn depth_range(&self) -> DepthRange {
+    self.depth_mode().range()
+  }
+
+  fn view_projection_matrix(&self) -> Mat4 {
+    Mat4::from_cols_array_2d(&self.projection())
+      * Mat4::from_cols_array_2d(&self.view())
+  }
+
+  // the world space point under a pixel at the given depth
+  fn unproject(&self, pixel: Vec2, depth: f32, viewport: Vec2) -> Vec3 {
+    let ndc = pixel_to_ndc(pixel, viewport).extend(depth);
+    self.view_projection_matrix().inverse().project_point3(ndc)
+  }
+
+  // from the near plane through the pixel, with a unit direction so t
+  // is the distance from the near plane
+  fn pixel_ray(&self, pixel: Vec2, viewport: Vec2) -> Ray {
+    let inverse = self.view_projection_matrix().inverse();
+    let ndc = pixel_to_ndc(pixel, viewport);
+    let depth = self.depth_range();
+    let near = inverse.project_point3(ndc.extend(depth.near));
+    // halfway to the far plane, which is finite even for infinite
+    // projections
+    let halfway = (depth.near + depth.far) / 2.0;
+    let far = inverse.project_point3(ndc.extend(halfway));
+    Ray::new(near, (far - near).normalize())
+  }
+
+  // the pixel and depth a world space point ends up at, None if it is
+  // behind the camera
+  fn project_to_pixel(&self, point: Vec3, viewport: Vec2) -> Option<Vec3> {
+    let clip = self.view_projection_matrix() * point.extend(1.0);
+    if clip.w <= 0.0 {
+      return None;
+    }
+    let ndc = clip.truncate() / clip.w;
+    let pixel = Vec2::new(ndc.x + 1.0, 1.0 - ndc.y) * viewport / 2.0;
+    Some(pixel.extend(ndc.z))
+  }
+}
+
+fn pixel_to_ndc(pixel: Vec2, viewport: Vec2) -> Vec2 {
+  let ndc = pixel / viewport * 2.0 - Vec2::ONE;
+  Vec2::new(ndc.x, -ndc.y)
 }
 
 pub trait ToUniforms<'a> {
@@ -20,12 +82,14 @@ pub trait Draw {
   ) -> anyhow::Result<()>;
 }
 
+// Ref as the programs are ProgramHandles, which can be rebuilt between
+// frames
 pub trait HasProgram {
-  fn program(&self) -> &glium::Program;
+  fn program(&self) -> Ref<'_, glium::Program>;
 }
 
 pub trait HasShadow {
-  fn shadow_program(&self) -> Option<&glium::Program> {
+  fn shadow_program(&self) -> Option<Ref<'_, glium::Program>> {
     None
   }
   fn casts_shadow(&self) -> bool {
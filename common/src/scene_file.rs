@@ -0,0 +1,364 @@
+// Scenes described in RON files, so test scenes can be set up without
+// recompiling. A file lists the objects with their transforms and
+// flags, the lights, an optional environment cubemap and where the
+// camera starts, e.g.
+//
+//   (
+//     camera: Some((eye: (0.5, 1.0, 1.0), target: (0.0, 0.0, 0.0))),
+//     lights: [Directional(direction: (0.5, 1.0, 0.5))],
+//     objects: [
+//       (
+//         model: Teapot,
+//         transform: (rotation: (-90.0, 0.0, 0.0), scale: (0.05, 0.05, 0.05)),
+//         material: Some((diffuse: Some((0.8, 0.2, 0.2)))),
+//       ),
+//       (model: Obj("plane.obj"), cast_shadow: false),
+//     ],
+//   )
+//
+// Every field has a default, so only what differs needs to be written.
+// What a project does with lights or flags it doesn't support is up to
+// the project.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context as _};
+use glam::{EulerRot, Mat4, Quat, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  camera::OrbitController, find_asset, Mtl, NodeId, SceneGraph, Transform,
+};
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SceneFile {
+  pub camera: Option<CameraDesc>,
+  pub cubemap: Option<CubemapDesc>,
+  pub lights: Vec<LightDesc>,
+  pub objects: Vec<ObjectDesc>,
+  // relative paths in the file are looked up here first
+  #[serde(skip)]
+  pub base_dir: PathBuf,
+  // where it was loaded from, for errors
+  #[serde(skip)]
+  pub path: PathBuf,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CameraDesc {
+  pub eye: Vec3,
+  pub target: Vec3,
+  // vertical, in degrees
+  #[serde(default)]
+  pub fov: Option<f32>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CubemapDesc {
+  // +x, -x, +y, -y, +z, -z
+  pub faces: [PathBuf; 6],
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum LightDesc {
+  Directional {
+    // towards the light
+    direction: Vec3,
+    #[serde(default = "white")]
+    color: Vec3,
+  },
+  Point {
+    position: Vec3,
+    #[serde(default = "white")]
+    color: Vec3,
+  },
+  Spot {
+    position: Vec3,
+    // in degrees
+    fov: f32,
+    #[serde(default = "white")]
+    color: Vec3,
+  },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ObjectDesc {
+  pub name: Option<String>,
+  pub model: ModelDesc,
+  // relative to the parent object
+  pub transform: TransformDesc,
+  pub material: Option<MaterialDesc>,
+  pub cast_shadow: bool,
+  pub receive_shadow: bool,
+  pub reflective: bool,
+  pub children: Vec<ObjectDesc>,
+}
+
+impl Default for ObjectDesc {
+  fn default() -> Self {
+    Self {
+      name: None,
+      model: ModelDesc::Empty,
+      transform: TransformDesc::default(),
+      material: None,
+      cast_shadow: true,
+      receive_shadow: true,
+      reflective: false,
+      children: Vec::new(),
+    }
+  }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum ModelDesc {
+  // only groups its children
+  #[default]
+  Empty,
+  Teapot,
+  Sphere,
+  // a flat quad, however the project makes one
+  Plane,
+  Obj(PathBuf),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TransformDesc {
+  pub translation: Vec3,
+  // euler angles in degrees, applied around x, then y, then z
+  pub rotation: Vec3,
+  pub scale: Vec3,
+}
+
+impl Default for TransformDesc {
+  fn default() -> Self {
+    Self {
+      translation: Vec3::ZERO,
+      rotation: Vec3::ZERO,
+      scale: Vec3::ONE,
+    }
+  }
+}
+
+impl TransformDesc {
+  pub fn to_transform(&self) -> Transform {
+    let [x, y, z] = self.rotation.to_array().map(f32::to_radians);
+    Transform {
+      translation: self.translation,
+      rotation: Quat::from_euler(EulerRot::ZYX, z, y, x),
+      scale: self.scale,
+    }
+  }
+}
+
+// Overrides for the materials that come with the model.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MaterialDesc {
+  pub ambient: Option<Vec3>,
+  pub diffuse: Option<Vec3>,
+  pub specular: Option<Vec3>,
+  pub shininess: Option<f32>,
+}
+
+impl MaterialDesc {
+  pub fn apply(&self, mtl: &mut Mtl) {
+    if let Some(ambient) = self.ambient {
+      mtl.Ka = ambient.into();
+    }
+    if let Some(diffuse) = self.diffuse {
+      mtl.Kd = diffuse.into();
+    }
+    if let Some(specular) = self.specular {
+      mtl.Ks = specular.into();
+    }
+    if let Some(shininess) = self.shininess {
+      mtl.Ns = shininess;
+    }
+  }
+}
+
+fn white() -> Vec3 {
+  Vec3::ONE
+}
+
+impl SceneFile {
+  pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path)
+      .with_context(|| format!("reading {}", path.display()))?;
+    let mut file: Self = ron::from_str(&text)
+      .with_context(|| format!("parsing {}", path.display()))?;
+    file.base_dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+    file.path = path.to_path_buf();
+    Ok(file)
+  }
+
+  // next to the file, or in the shared assets. `field` names where the
+  // path came from in the error.
+  pub fn resolve(&self, field: &str, path: &Path) -> anyhow::Result<PathBuf> {
+    let local = self.base_dir.join(path);
+    if path.is_absolute() || local.exists() {
+      return Ok(local);
+    }
+    find_asset(&path.to_string_lossy()).ok_or_else(|| {
+      anyhow!(
+        "{}: {} {} is neither next to the scene file nor in the assets",
+        self.path.display(),
+        field,
+        path.display()
+      )
+    })
+  }
+
+  fn shared_asset(&self, name: &str) -> anyhow::Result<PathBuf> {
+    find_asset(name).ok_or_else(|| {
+      anyhow!("{}: {} is not in the assets", self.path.display(), name)
+    })
+  }
+
+  // the obj file to load, None for models the project provides
+  pub fn model_path(
+    &self,
+    model: &ModelDesc,
+  ) -> anyhow::Result<Option<PathBuf>> {
+    Ok(match model {
+      ModelDesc::Teapot => Some(self.shared_asset("teapot.obj")?),
+      ModelDesc::Sphere => Some(self.shared_asset("sphere.obj")?),
+      ModelDesc::Obj(path) => Some(self.resolve("model", path)?),
+      ModelDesc::Empty | ModelDesc::Plane => None,
+    })
+  }
+
+  pub fn cubemap_faces(&self) -> anyhow::Result<Option<[PathBuf; 6]>> {
+    let Some(cubemap) = &self.cubemap else {
+      return Ok(None);
+    };
+    let mut faces = cubemap.faces.clone();
+    for (i, face) in faces.iter_mut().enumerate() {
+      *face = self.resolve(&format!("cubemap face {}", i), face)?;
+    }
+    Ok(Some(faces))
+  }
+
+  pub fn orbit_controller(&self) -> Option<OrbitController> {
+    let camera = self.camera.as_ref()?;
+    Some(OrbitController::looking_at(camera.eye, camera.target))
+  }
+
+  // Adds the objects to the graph under `parent`, keeping the nesting.
+  // Objects with an empty model become groups; `make` turns every other
+  // object into whatever the project draws. Returns the top level nodes.
+  pub fn instantiate<T>(
+    &self,
+    graph: &mut SceneGraph<T>,
+    parent: Option<NodeId>,
+    mut make: impl FnMut(&ObjectDesc) -> anyhow::Result<T>,
+  ) -> anyhow::Result<Vec<NodeId>> {
+    self.instantiate_all(&self.objects, graph, parent, &mut make)
+  }
+
+  fn instantiate_all<T>(
+    &self,
+    objects: &[ObjectDesc],
+    graph: &mut SceneGraph<T>,
+    parent: Option<NodeId>,
+    make: &mut impl FnMut(&ObjectDesc) -> anyhow::Result<T>,
+  ) -> anyhow::Result<Vec<NodeId>> {
+    let mut ids = Vec::with_capacity(objects.len());
+    for object in objects {
+      let transform = object.transform.to_transform();
+      let id = match object.model {
+        ModelDesc::Empty => graph.add_group(parent, transform),
+        _ => {
+          let value = make(object).with_context(|| {
+            format!("loading {}", object.name.as_deref().unwrap_or("object"))
+          })?;
+          graph.add(parent, transform, value)
+        }
+      };
+      self.instantiate_all(&object.children, graph, Some(id), make)?;
+      ids.push(id);
+    }
+    Ok(ids)
+  }
+
+  // For projects without a scene graph: every object with a model,
+  // with its model to world matrix.
+  pub fn flatten(&self) -> Vec<(Mat4, &ObjectDesc)> {
+    fn walk<'a>(
+      objects: &'a [ObjectDesc],
+      parent: Mat4,
+      out: &mut Vec<(Mat4, &'a ObjectDesc)>,
+    ) {
+      for object in objects {
+        let world = parent * object.transform.to_transform().to_mat4();
+        if object.model != ModelDesc::Empty {
+          out.push((world, object));
+        }
+        walk(&object.children, world, out);
+      }
+    }
+
+    let mut out = Vec::new();
+    walk(&self.objects, Mat4::IDENTITY, &mut out);
+    out
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // nothing is next to it
+  fn file(objects: Vec<ObjectDesc>) -> SceneFile {
+    SceneFile {
+      objects,
+      base_dir: PathBuf::from("no/such/dir"),
+      path: PathBuf::from("no/such/dir/scene.ron"),
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn resolves_shared_assets() {
+    let path = file(vec![])
+      .resolve("model", Path::new("plane.obj"))
+      .unwrap();
+    assert!(path.ends_with("assets/plane.obj"), "{}", path.display());
+
+    let path = file(vec![])
+      .model_path(&ModelDesc::Teapot)
+      .unwrap()
+      .unwrap();
+    assert!(path.ends_with("assets/teapot.obj"), "{}", path.display());
+  }
+
+  #[test]
+  fn missing_paths_name_the_file_and_field() {
+    let error = file(vec![])
+      .resolve("model", Path::new("missing.obj"))
+      .unwrap_err()
+      .to_string();
+    assert!(error.contains("no/such/dir/scene.ron"), "{}", error);
+    assert!(error.contains("model missing.obj"), "{}", error);
+  }
+
+  #[test]
+  fn instantiate_passes_missing_models_on() {
+    let file = file(vec![ObjectDesc {
+      name: Some("floor".into()),
+      model: ModelDesc::Obj("missing.obj".into()),
+      ..Default::default()
+    }]);
+    let mut graph = SceneGraph::new();
+    let error = file
+      .instantiate(&mut graph, None, |desc| file.model_path(&desc.model))
+      .unwrap_err();
+    let error = format!("{:#}", error);
+    assert!(error.starts_with("loading floor"), "{}", error);
+    assert!(error.contains("no/such/dir/scene.ron"), "{}", error);
+  }
+}
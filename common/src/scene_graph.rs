@@ -0,0 +1,428 @@
+// A hierarchy of transforms. Every node has a transform relative to its
+// parent and an optional value, e.g. an object to draw; a node without a
+// value just groups its children, like a pivot for a light rig.
+//
+// World matrices are cached. Changing a transform marks the node and
+// its subtree dirty, and a dirty world matrix is recomputed from the
+// parent's when it's asked for, so a frame only pays for what moved.
+
+use std::cell::Cell;
+
+use anyhow::ensure;
+use glam::Mat4;
+
+use crate::{math::Aabb, Transform};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+struct Node<T> {
+  local: Transform,
+  value: Option<T>,
+  parent: Option<NodeId>,
+  children: Vec<NodeId>,
+  world: Cell<Mat4>,
+  // a dirty node only has dirty descendants
+  dirty: Cell<bool>,
+}
+
+pub struct SceneGraph<T> {
+  // removed nodes leave a hole, so stale ids never alias a new node
+  nodes: Vec<Option<Node<T>>>,
+}
+
+impl<T> Default for SceneGraph<T> {
+  fn default() -> Self {
+    Self { nodes: Vec::new() }
+  }
+}
+
+impl<T> SceneGraph<T> {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn add(
+    &mut self,
+    parent: Option<NodeId>,
+    local: Transform,
+    value: T,
+  ) -> NodeId {
+    self.add_node(parent, local, Some(value))
+  }
+
+  pub fn add_group(
+    &mut self,
+    parent: Option<NodeId>,
+    local: Transform,
+  ) -> NodeId {
+    self.add_node(parent, local, None)
+  }
+
+  fn add_node(
+    &mut self,
+    parent: Option<NodeId>,
+    local: Transform,
+    value: Option<T>,
+  ) -> NodeId {
+    let id = NodeId(self.nodes.len());
+    self.nodes.push(Some(Node {
+      local,
+      value,
+      parent,
+      children: Vec::new(),
+      world: Cell::new(Mat4::IDENTITY),
+      dirty: Cell::new(true),
+    }));
+    if let Some(parent) = parent {
+      self.node_mut(parent).children.push(id);
+    }
+    id
+  }
+
+  // removes the node with its whole subtree, returning the node's value
+  pub fn remove(&mut self, id: NodeId) -> Option<T> {
+    self.unlink(id);
+    let node = self.nodes[id.0].take().expect("stale node id");
+    let mut orphans = node.children;
+    while let Some(orphan) = orphans.pop() {
+      if let Some(orphan) = self.nodes[orphan.0].take() {
+        orphans.extend(orphan.children);
+      }
+    }
+    node.value
+  }
+
+  // Moves a node under `parent`, or makes it a root. The node keeps its
+  // place in the world, so picking up a lid doesn't teleport it.
+  pub fn attach(
+    &mut self,
+    id: NodeId,
+    parent: Option<NodeId>,
+  ) -> anyhow::Result<()> {
+    if let Some(parent) = parent {
+      ensure!(
+        !self.is_ancestor(id, parent),
+        "attaching {:?} to {:?} would create a cycle",
+        id,
+        parent
+      );
+    }
+
+    let world = self.world(id);
+    let parent_world = parent.map_or(Mat4::IDENTITY, |p| self.world(p));
+    self.unlink(id);
+    if let Some(parent) = parent {
+      self.node_mut(parent).children.push(id);
+    }
+    self.node_mut(id).parent = parent;
+    self.set_local(id, Transform::from_mat4(parent_world.inverse() * world));
+    Ok(())
+  }
+
+  pub fn detach(&mut self, id: NodeId) {
+    self
+      .attach(id, None)
+      .expect("a root can't be part of a cycle");
+  }
+
+  // true if `ancestor` is `id` or above it
+  pub fn is_ancestor(&self, ancestor: NodeId, id: NodeId) -> bool {
+    let mut current = Some(id);
+    while let Some(node) = current {
+      if node == ancestor {
+        return true;
+      }
+      current = self.node(node).parent;
+    }
+    false
+  }
+
+  fn unlink(&mut self, id: NodeId) {
+    if let Some(parent) = self.node(id).parent {
+      self.node_mut(parent).children.retain(|&child| child != id);
+    }
+  }
+
+  pub fn contains(&self, id: NodeId) -> bool {
+    matches!(self.nodes.get(id.0), Some(Some(_)))
+  }
+
+  pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+    self.node(id).parent
+  }
+
+  pub fn children(&self, id: NodeId) -> &[NodeId] {
+    &self.node(id).children
+  }
+
+  pub fn roots(&self) -> impl Iterator<Item = NodeId> + '_ {
+    self.ids().filter(|&id| self.node(id).parent.is_none())
+  }
+
+  pub fn get(&self, id: NodeId) -> Option<&T> {
+    self.node(id).value.as_ref()
+  }
+
+  pub fn get_mut(&mut self, id: NodeId) -> Option<&mut T> {
+    self.node_mut(id).value.as_mut()
+  }
+
+  pub fn local(&self, id: NodeId) -> Transform {
+    self.node(id).local
+  }
+
+  pub fn set_local(&mut self, id: NodeId, local: Transform) {
+    self.node_mut(id).local = local;
+    self.mark_dirty(id);
+  }
+
+  fn mark_dirty(&self, id: NodeId) {
+    let node = self.node(id);
+    if node.dirty.replace(true) {
+      return;
+    }
+    for &child in &node.children {
+      self.mark_dirty(child);
+    }
+  }
+
+  // model to world
+  pub fn world(&self, id: NodeId) -> Mat4 {
+    let node = self.node(id);
+    if node.dirty.get() {
+      let parent = node.parent.map_or(Mat4::IDENTITY, |p| self.world(p));
+      node.world.set(parent * node.local.to_mat4());
+      node.dirty.set(false);
+    }
+    node.world.get()
+  }
+
+  // the node and everything below it, parents before children
+  pub fn subtree(&self, id: NodeId) -> Vec<NodeId> {
+    let mut ids = vec![id];
+    let mut i = 0;
+    while let Some(&id) = ids.get(i) {
+      ids.extend_from_slice(self.children(id));
+      i += 1;
+    }
+    ids
+  }
+
+  // Union of the values' bounds in world space, given their bounds in
+  // model space.
+  pub fn subtree_bounds(
+    &self,
+    id: NodeId,
+    bounds: impl Fn(&T) -> Aabb,
+  ) -> Aabb {
+    self
+      .subtree(id)
+      .into_iter()
+      .filter_map(|id| Some(bounds(self.get(id)?).transform(self.world(id))))
+      .fold(Aabb::EMPTY, |a, b| a.union(&b))
+  }
+
+  pub fn bounds(&self, bounds: impl Fn(&T) -> Aabb) -> Aabb {
+    self
+      .iter()
+      .map(|(_, world, value)| bounds(value).transform(world))
+      .fold(Aabb::EMPTY, |a, b| a.union(&b))
+  }
+
+  fn ids(&self) -> impl Iterator<Item = NodeId> + '_ {
+    self
+      .nodes
+      .iter()
+      .enumerate()
+      .filter(|(_, node)| node.is_some())
+      .map(|(i, _)| NodeId(i))
+  }
+
+  // the values with their world matrices
+  pub fn iter(&self) -> impl Iterator<Item = (NodeId, Mat4, &T)> {
+    self
+      .ids()
+      .filter_map(|id| Some((id, self.world(id), self.get(id)?)))
+  }
+
+  pub fn values_mut(&mut self) -> impl Iterator<Item = &mut T> {
+    self
+      .nodes
+      .iter_mut()
+      .flatten()
+      .filter_map(|n| n.value.as_mut())
+  }
+
+  fn node(&self, id: NodeId) -> &Node<T> {
+    self.nodes[id.0].as_ref().expect("stale node id")
+  }
+
+  fn node_mut(&mut self, id: NodeId) -> &mut Node<T> {
+    self.nodes[id.0].as_mut().expect("stale node id")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::{Quat, Vec3};
+
+  use super::*;
+
+  fn moved(x: f32, y: f32, z: f32) -> Transform {
+    Transform::from_translation(Vec3::new(x, y, z))
+  }
+
+  fn assert_mat_eq(a: Mat4, b: Mat4) {
+    assert!(a.abs_diff_eq(b, 1e-5), "{a} != {b}");
+  }
+
+  // root -> child -> grandchild, one unit apart along x
+  fn chain() -> (SceneGraph<&'static str>, [NodeId; 3]) {
+    let mut graph = SceneGraph::new();
+    let root = graph.add(None, moved(1.0, 0.0, 0.0), "root");
+    let child = graph.add(Some(root), moved(1.0, 0.0, 0.0), "child");
+    let grandchild = graph.add(Some(child), moved(1.0, 0.0, 0.0), "grandchild");
+    (graph, [root, child, grandchild])
+  }
+
+  #[test]
+  fn world_composes_parents() {
+    let (graph, [_, child, grandchild]) = chain();
+    assert_mat_eq(
+      graph.world(grandchild),
+      Mat4::from_translation(Vec3::new(3.0, 0.0, 0.0)),
+    );
+    assert_mat_eq(
+      graph.world(child),
+      Mat4::from_translation(Vec3::new(2.0, 0.0, 0.0)),
+    );
+  }
+
+  #[test]
+  fn moving_a_parent_moves_the_subtree() {
+    let (mut graph, [root, child, grandchild]) = chain();
+    graph.world(grandchild);
+
+    graph.set_local(root, moved(0.0, 1.0, 0.0));
+    // only the middle gets cleaned, the grandchild must still be dirty
+    // for the next change to stop early at it
+    graph.world(child);
+    graph.set_local(root, moved(0.0, 2.0, 0.0));
+
+    assert_mat_eq(
+      graph.world(grandchild),
+      Mat4::from_translation(Vec3::new(2.0, 2.0, 0.0)),
+    );
+    assert_mat_eq(
+      graph.world(child),
+      Mat4::from_translation(Vec3::new(1.0, 2.0, 0.0)),
+    );
+  }
+
+  #[test]
+  fn attach_keeps_the_world_pose() {
+    let mut graph = SceneGraph::new();
+    let parent = graph.add(
+      None,
+      Transform {
+        translation: Vec3::new(1.0, 2.0, 3.0),
+        rotation: Quat::from_rotation_y(0.7),
+        scale: Vec3::splat(2.0),
+      },
+      "parent",
+    );
+    let lid = graph.add(
+      None,
+      Transform {
+        translation: Vec3::new(-1.0, 0.5, 0.0),
+        rotation: Quat::from_rotation_x(0.3),
+        scale: Vec3::ONE,
+      },
+      "lid",
+    );
+    let before = graph.world(lid);
+
+    graph.attach(lid, Some(parent)).unwrap();
+    assert_eq!(graph.parent(lid), Some(parent));
+    assert_eq!(graph.children(parent), &[lid]);
+    assert_mat_eq(graph.world(lid), before);
+
+    // and follows the parent from now on
+    graph.set_local(parent, moved(0.0, 0.0, 0.0));
+    assert!(!graph.world(lid).abs_diff_eq(before, 1e-3));
+
+    let attached = graph.world(lid);
+    graph.detach(lid);
+    assert_eq!(graph.parent(lid), None);
+    assert!(graph.children(parent).is_empty());
+    assert_mat_eq(graph.world(lid), attached);
+  }
+
+  #[test]
+  fn attach_rejects_cycles() {
+    let (mut graph, [root, child, grandchild]) = chain();
+    assert!(graph.attach(root, Some(grandchild)).is_err());
+    assert!(graph.attach(child, Some(child)).is_err());
+
+    // nothing changed
+    assert_eq!(graph.parent(root), None);
+    assert_eq!(graph.parent(grandchild), Some(child));
+    assert_eq!(graph.children(child), &[grandchild]);
+
+    // moving within the tree is fine
+    graph.attach(grandchild, Some(root)).unwrap();
+    assert_eq!(graph.children(root), &[child, grandchild]);
+  }
+
+  #[test]
+  fn remove_drops_the_subtree() {
+    let (mut graph, [root, child, grandchild]) = chain();
+    let sibling = graph.add(Some(root), moved(0.0, 1.0, 0.0), "sibling");
+
+    assert_eq!(graph.remove(child), Some("child"));
+    assert!(!graph.contains(child));
+    assert!(!graph.contains(grandchild));
+    assert_eq!(graph.children(root), &[sibling]);
+
+    let values: Vec<_> = graph.iter().map(|(_, _, value)| *value).collect();
+    assert_eq!(values, ["root", "sibling"]);
+    assert_eq!(graph.roots().collect::<Vec<_>>(), [root]);
+
+    // ids aren't reused
+    let new = graph.add(None, Transform::default(), "new");
+    assert_ne!(new, child);
+    assert_ne!(new, grandchild);
+  }
+
+  #[test]
+  fn subtree_bounds_in_world_space() {
+    let unit = Aabb::new(Vec3::ZERO, Vec3::ONE);
+    let mut graph = SceneGraph::new();
+    let rig = graph.add_group(None, moved(10.0, 0.0, 0.0));
+    let a = graph.add(Some(rig), Transform::default(), unit);
+    graph.add(
+      Some(a),
+      Transform {
+        scale: Vec3::splat(2.0),
+        ..moved(0.0, 5.0, 0.0)
+      },
+      unit,
+    );
+    let elsewhere = graph.add(None, moved(-10.0, 0.0, 0.0), unit);
+
+    let bounds = graph.subtree_bounds(rig, |aabb| *aabb);
+    assert_eq!(
+      bounds,
+      Aabb::new(Vec3::new(10.0, 0.0, 0.0), Vec3::new(12.0, 7.0, 2.0))
+    );
+
+    // a group on its own has nothing to bound
+    let empty = graph.add_group(Some(elsewhere), Transform::default());
+    assert!(graph.subtree_bounds(empty, |aabb| *aabb).is_empty());
+
+    let all = graph.bounds(|aabb| *aabb);
+    assert_eq!(all.min, Vec3::new(-10.0, 0.0, 0.0));
+    assert_eq!(all.max, Vec3::new(12.0, 7.0, 2.0));
+  }
+}
@@ -0,0 +1,89 @@
+// Where a project was left off: the camera, the light, the toggles. Each
+// project puts what it wants to keep into one serializable struct, saves
+// it on exit and restores it on the next start.
+
+use std::path::Path;
+
+use anyhow::Context as _;
+use glam::Mat4;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::camera::{Camera, Projection};
+
+// None if nothing was saved yet
+pub fn load<T: DeserializeOwned>(
+  path: impl AsRef<Path>,
+) -> anyhow::Result<Option<T>> {
+  let path = path.as_ref();
+  if !path.exists() {
+    return Ok(None);
+  }
+  let text = std::fs::read_to_string(path)
+    .with_context(|| format!("reading {}", path.display()))?;
+  let state = ron::from_str(&text)
+    .with_context(|| format!("parsing {}", path.display()))?;
+  Ok(Some(state))
+}
+
+pub fn save<T: Serialize>(
+  path: impl AsRef<Path>,
+  state: &T,
+) -> anyhow::Result<()> {
+  let path = path.as_ref();
+  let text = ron::ser::to_string_pretty(state, Default::default())?;
+  std::fs::write(path, text)
+    .with_context(|| format!("writing {}", path.display()))
+}
+
+// for hotkeys and exit, where there is no one to return the error to
+pub fn save_or_warn<T: Serialize>(path: impl AsRef<Path>, state: &T) {
+  let path = path.as_ref();
+  match save(path, state) {
+    Ok(()) => println!("Saved session to {}", path.display()),
+    Err(e) => eprintln!("Failed to save session: {:#}", e),
+  }
+}
+
+// None if nothing was saved yet, or if it can't be read
+pub fn load_or_warn<T: DeserializeOwned>(path: impl AsRef<Path>) -> Option<T> {
+  match load(path) {
+    Ok(state) => state,
+    Err(e) => {
+      eprintln!("Failed to load session: {:#}", e);
+      None
+    }
+  }
+}
+
+// Enough to put the camera back. The controllers are not saved, they
+// are rebuilt from the camera with their from_camera.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CameraState {
+  pub view: Mat4,
+  pub focus_distance: f32,
+  pub orthographic: bool,
+}
+
+impl CameraState {
+  pub fn from_camera(camera: &Camera) -> Self {
+    Self {
+      view: camera.view(),
+      focus_distance: camera.focus_distance(),
+      orthographic: matches!(
+        camera.projection,
+        Projection::Orthographic { .. }
+      ),
+    }
+  }
+
+  // custom projections stay as they are
+  pub fn apply(&self, camera: &mut Camera) {
+    camera.set_view(self.view);
+    camera.set_focus_distance(self.focus_distance);
+    let orthographic =
+      matches!(camera.projection, Projection::Orthographic { .. });
+    if orthographic != self.orthographic {
+      camera.toggle_projection();
+    }
+  }
+}
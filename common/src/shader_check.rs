@@ -0,0 +1,1098 @@
+// Checks the shaders of a project without a GL context, so mistakes show
+// up in `cargo test` instead of at startup or on reload:
+// - syntax errors, through naga for vertex and fragment shaders and the
+//   declarations only for the other stages
+// - outputs of one stage that don't match the inputs of the next,
+//   interface blocks like VertData member by member
+// - uniforms declared with different types in two stages
+// - uniforms set from Rust with `uniform!` or DynUniforms that no shader
+//   declares, or of an incompatible type where the code shows it
+// - uniform blocks laid out differently in GLSL, by naga with std140,
+//   and in the Rust structs filling them
+// The sources go through the preprocessor first, so the includes are
+// checked where they're used, and messages name the file a line came
+// from. The active uniforms of each program are printed, see them with
+// `cargo test -- --nocapture`.
+
+mod glsl;
+mod rust;
+mod syntax;
+
+use std::{
+  collections::{BTreeSet, HashSet},
+  fmt,
+  path::{Path, PathBuf},
+};
+
+use anyhow::bail;
+use glium::{program::BlockLayout, uniforms::UniformType};
+
+use glsl::Statement;
+
+use crate::{
+  preprocessor::{Preprocessor, Source},
+  uniform_blocks::Std140,
+};
+
+pub use rust::{RustUniform, ValueKind};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+  Vertex,
+  TessControl,
+  TessEvaluation,
+  Geometry,
+  Fragment,
+}
+
+impl Stage {
+  // from the file extension
+  pub fn from_path(path: &Path) -> Option<Self> {
+    match path.extension()?.to_str()? {
+      "vert" => Some(Self::Vertex),
+      "tcs" => Some(Self::TessControl),
+      "tes" => Some(Self::TessEvaluation),
+      "geom" => Some(Self::Geometry),
+      "frag" => Some(Self::Fragment),
+      _ => None,
+    }
+  }
+
+  // the inputs are arrays with an element per vertex
+  fn arrayed_inputs(self) -> bool {
+    matches!(
+      self,
+      Self::TessControl | Self::TessEvaluation | Self::Geometry
+    )
+  }
+
+  fn arrayed_outputs(self) -> bool {
+    self == Self::TessControl
+  }
+}
+
+// An input, output or uniform. Block members are named Block.member for
+// inputs and outputs, as blocks match by their name between stages. The
+// array over the vertices of a tessellation or geometry stage is left
+// out of the type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Variable {
+  pub name: String,
+  pub ty: String,
+  // whether main or a function reads or writes it
+  pub used: bool,
+  pub line: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct Uniform {
+  pub name: String,
+  // "block" for a uniform block
+  pub ty: String,
+  // the block it's a member of
+  pub block: Option<String>,
+  // whether main or a function uses it, a bit more than what the
+  // driver counts as active
+  pub active: bool,
+  pub line: usize,
+}
+
+impl fmt::Display for Uniform {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match &self.block {
+      Some(block) => write!(f, "{} {} (in {})", self.ty, self.name, block),
+      None => write!(f, "{} {}", self.ty, self.name),
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct Shader {
+  pub name: String,
+  pub stage: Stage,
+  pub version: Option<String>,
+  pub inputs: Vec<Variable>,
+  pub outputs: Vec<Variable>,
+  pub uniforms: Vec<Uniform>,
+  source: String,
+  // the file and line of each line of the source, when it went through
+  // the preprocessor
+  origins: Vec<(String, usize)>,
+}
+
+impl Shader {
+  // errors as "name:line: message"
+  pub fn parse(name: &str, stage: Stage, source: &str) -> Result<Self, String> {
+    Self::parse_declarations(name, stage, source)
+      .map_err(|e| locate(name, &[], &e))
+  }
+
+  // with the lines in messages those of the included files
+  pub fn preprocessed(
+    name: &str,
+    stage: Stage,
+    source: &Source,
+  ) -> Result<Self, String> {
+    let origins: Vec<(String, usize)> = (1..=source.text.lines().count())
+      .filter_map(|line| {
+        let (path, line) = source.origin(line)?;
+        let file = path.file_name()?.to_string_lossy().into_owned();
+        Some((file, line))
+      })
+      .collect();
+    let mut shader = Self::parse_declarations(name, stage, &source.text)
+      .map_err(|e| locate(name, &origins, &e))?;
+    shader.origins = origins;
+    Ok(shader)
+  }
+
+  // "file:line" for a line of the source
+  pub fn at(&self, line: usize) -> String {
+    match line.checked_sub(1).and_then(|i| self.origins.get(i)) {
+      Some((file, line)) => format!("{}:{}", file, line),
+      None => format!("{}:{}", self.name, line),
+    }
+  }
+
+  fn parse_declarations(
+    name: &str,
+    stage: Stage,
+    source: &str,
+  ) -> Result<Self, String> {
+    let tokens = glsl::tokenize(source)?;
+    let statements = glsl::statements(&tokens)?;
+    let mut shader = Self {
+      name: name.to_owned(),
+      stage,
+      version: None,
+      inputs: Vec::new(),
+      outputs: Vec::new(),
+      uniforms: Vec::new(),
+      source: source.to_owned(),
+      origins: Vec::new(),
+    };
+
+    // names used in functions, members after a dot aside, for telling
+    // the active uniforms and the used inputs
+    let mut used = HashSet::new();
+    for statement in &statements {
+      if let Statement::Function { body, .. } = statement {
+        let body = &tokens[body.clone()];
+        used.extend(body.iter().enumerate().filter_map(|(i, token)| {
+          let member = i > 0 && body[i - 1].is(".");
+          (token.is_ident() && !member).then_some(token.text.as_str())
+        }));
+      }
+    }
+
+    for statement in &statements {
+      match statement {
+        Statement::Directive(i) => {
+          let text = tokens[*i].text.trim_start_matches('#').trim();
+          if let Some(version) = text.strip_prefix("version") {
+            shader.version = Some(version.trim().to_owned());
+          }
+        }
+        Statement::Declaration(range) => {
+          let decl = glsl::declaration(&tokens, range.clone())?;
+          if decl.ty.is_empty() {
+            continue;
+          }
+          for declarator in &decl.declarators {
+            let mut dims =
+              [declarator.dims.clone(), decl.ty_dims.clone()].concat();
+            let variable = |dims: &[String]| Variable {
+              name: declarator.name.clone(),
+              ty: glsl::type_name(&decl.ty, dims),
+              used: used.contains(declarator.name.as_str()),
+              line: tokens[declarator.at].line,
+            };
+            match decl.storage() {
+              Some("uniform") => {
+                shader.uniforms.push(Uniform::from(variable(&dims)))
+              }
+              Some("in") => {
+                if stage.arrayed_inputs() && !decl.has("patch") {
+                  strip_vertex_array(&mut dims, &declarator.name)?;
+                }
+                shader.inputs.push(variable(&dims));
+              }
+              Some("out") => {
+                if stage.arrayed_outputs() && !decl.has("patch") {
+                  strip_vertex_array(&mut dims, &declarator.name)?;
+                }
+                shader.outputs.push(variable(&dims));
+              }
+              _ => (),
+            }
+          }
+        }
+        Statement::Block { head, body, tail } => {
+          let decl = glsl::declaration(&tokens, head.clone())?;
+          let block = decl.ty.clone();
+          let instance = tokens[tail.clone()]
+            .first()
+            .filter(|t| t.is_ident())
+            .map(|t| t.text.clone());
+          let mut members = Vec::new();
+          for member in glsl::members(&tokens, body.clone())? {
+            for declarator in &member.declarators {
+              let dims =
+                [declarator.dims.clone(), member.ty_dims.clone()].concat();
+              members.push(Variable {
+                name: declarator.name.clone(),
+                ty: glsl::type_name(&member.ty, &dims),
+                used: match &instance {
+                  Some(instance) => used.contains(instance.as_str()),
+                  None => used.contains(declarator.name.as_str()),
+                },
+                line: tokens[declarator.at].line,
+              });
+            }
+          }
+
+          match decl.storage() {
+            Some("uniform") => {
+              let uniforms: Vec<Uniform> = members
+                .into_iter()
+                .map(|member| Uniform {
+                  block: Some(block.clone()),
+                  ..Uniform::from(member)
+                })
+                .collect();
+              shader.uniforms.push(Uniform {
+                name: block.clone(),
+                ty: "block".to_owned(),
+                block: None,
+                active: uniforms.iter().any(|u| u.active),
+                line: tokens[head.start].line,
+              });
+              shader.uniforms.extend(uniforms);
+            }
+            Some(storage @ ("in" | "out")) => {
+              let members = members.into_iter().map(|member| Variable {
+                name: format!("{}.{}", block, member.name),
+                ..member
+              });
+              if storage == "in" {
+                shader.inputs.extend(members);
+              } else {
+                shader.outputs.extend(members);
+              }
+            }
+            // structs and buffers
+            _ => (),
+          }
+        }
+        Statement::Function { .. } => (),
+      }
+    }
+    Ok(shader)
+  }
+
+  pub fn uniform(&self, name: &str) -> Option<&Uniform> {
+    self.uniforms.iter().find(|u| u.name == name)
+  }
+}
+
+impl From<Variable> for Uniform {
+  fn from(variable: Variable) -> Self {
+    Self {
+      name: variable.name,
+      ty: variable.ty,
+      block: None,
+      active: variable.used,
+      line: variable.line,
+    }
+  }
+}
+
+// "line: message" as "file:line: message"
+fn locate(name: &str, origins: &[(String, usize)], message: &str) -> String {
+  let line = message
+    .split_once(':')
+    .and_then(|(line, rest)| Some((line.parse::<usize>().ok()?, rest)));
+  match line {
+    Some((line, rest)) => match origins.get(line.wrapping_sub(1)) {
+      Some((file, line)) => format!("{}:{}:{}", file, line, rest),
+      None => format!("{}:{}:{}", name, line, rest),
+    },
+    None => format!("{}:{}", name, message),
+  }
+}
+
+// `in vec3 pos[]` in a tessellation shader is one vec3 per vertex
+fn strip_vertex_array(
+  dims: &mut Vec<String>,
+  name: &str,
+) -> Result<(), String> {
+  if dims.is_empty() {
+    return Err(format!("{} should be an array over the vertices", name));
+  }
+  dims.remove(0);
+  Ok(())
+}
+
+// The stages of one program, in pipeline order.
+#[derive(Clone, Debug)]
+pub struct Program {
+  pub shaders: Vec<Shader>,
+}
+
+impl Program {
+  pub fn new(mut shaders: Vec<Shader>) -> Result<Self, String> {
+    shaders.sort_by_key(|shader| shader.stage);
+    let stages: Vec<Stage> = shaders.iter().map(|s| s.stage).collect();
+    if stages.first() != Some(&Stage::Vertex)
+      || stages.last() != Some(&Stage::Fragment)
+    {
+      return Err(format!(
+        "{}: needs a vertex and a fragment shader",
+        names(&shaders)
+      ));
+    }
+    if stages.windows(2).any(|pair| pair[0] == pair[1]) {
+      return Err(format!("{}: a stage is given twice", names(&shaders)));
+    }
+    if stages.contains(&Stage::TessControl)
+      != stages.contains(&Stage::TessEvaluation)
+    {
+      return Err(format!(
+        "{}: tessellation needs both the control and the evaluation shader",
+        names(&shaders)
+      ));
+    }
+    Ok(Self { shaders })
+  }
+
+  pub fn name(&self) -> String {
+    names(&self.shaders)
+  }
+
+  // errors for a broken program, warnings for what is merely unused
+  pub fn link(&self) -> (Vec<String>, Vec<String>) {
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+
+    for pair in self.shaders.windows(2) {
+      let (from, to) = (&pair[0], &pair[1]);
+      for input in to.inputs.iter().filter(|v| !is_builtin(&v.name)) {
+        match from.outputs.iter().find(|o| o.name == input.name) {
+          // only a link error when the input is read
+          None => {
+            let message = format!(
+              "{}: input `{} {}` isn't an output of {}",
+              to.at(input.line),
+              input.ty,
+              input.name,
+              from.name
+            );
+            if input.used {
+              errors.push(message);
+            } else {
+              warnings.push(format!("{} (unused)", message));
+            }
+          }
+          Some(output) if output.ty != input.ty => errors.push(format!(
+            "{}: input `{}` is {}, but {} writes {}",
+            to.at(input.line),
+            input.name,
+            input.ty,
+            from.name,
+            output.ty
+          )),
+          Some(_) => (),
+        }
+      }
+      for output in from.outputs.iter().filter(|v| !is_builtin(&v.name)) {
+        if to.inputs.iter().any(|i| i.name == output.name) {
+          continue;
+        }
+        // a block has to be declared with the same members on both sides
+        let block = output.name.split_once('.').map(|(block, _)| block);
+        let block_read = block.is_some_and(|block| {
+          to.inputs
+            .iter()
+            .any(|i| i.name.split_once('.').map(|(b, _)| b) == Some(block))
+        });
+        if block_read {
+          errors.push(format!(
+            "{}: block member `{}` is missing in {}",
+            from.at(output.line),
+            output.name,
+            to.name
+          ));
+        } else {
+          warnings.push(format!(
+            "{}: output `{}` isn't read by {}",
+            from.at(output.line),
+            output.name,
+            to.name
+          ));
+        }
+      }
+    }
+
+    for (i, shader) in self.shaders.iter().enumerate() {
+      for uniform in &shader.uniforms {
+        let earlier = self.shaders[..i]
+          .iter()
+          .find_map(|s| Some((s, s.uniform(&uniform.name)?)));
+        match earlier {
+          Some((other, first)) if first.ty != uniform.ty => {
+            errors.push(format!(
+              "{}: uniform `{}` is {}, but {} in {}",
+              shader.at(uniform.line),
+              uniform.name,
+              uniform.ty,
+              first.ty,
+              other.name
+            ))
+          }
+          _ => (),
+        }
+      }
+    }
+
+    (errors, warnings)
+  }
+
+  // each name once, active in any stage or not
+  pub fn uniforms(&self) -> Vec<Uniform> {
+    let mut uniforms: Vec<Uniform> = Vec::new();
+    for uniform in self.shaders.iter().flat_map(|s| &s.uniforms) {
+      match uniforms.iter_mut().find(|u| u.name == uniform.name) {
+        Some(existing) => existing.active |= uniform.active,
+        None => uniforms.push(uniform.clone()),
+      }
+    }
+    uniforms
+  }
+}
+
+fn names(shaders: &[Shader]) -> String {
+  let names: Vec<&str> = shaders.iter().map(|s| s.name.as_str()).collect();
+  names.join(" + ")
+}
+
+fn is_builtin(name: &str) -> bool {
+  name.starts_with("gl_")
+}
+
+// The programs of a project, given as the files of their stages, and the
+// Rust sources setting their uniforms. Everything found is collected and
+// reported at once by finish.
+pub struct ShaderCheck {
+  dir: PathBuf,
+  programs: Vec<Program>,
+  rust_uniforms: Vec<RustUniform>,
+  // set from Rust on purpose without a shader declaring them
+  undeclared: BTreeSet<String>,
+  // every file given to a program
+  covered: BTreeSet<String>,
+  // the blocks naga laid out, with the shader declaring them
+  blocks: Vec<(String, syntax::BlockLayout)>,
+  rust_blocks: Vec<RustBlock>,
+  errors: Vec<String>,
+}
+
+// a Std140 struct, its members as name, offset and GLSL type
+struct RustBlock {
+  name: &'static str,
+  ty: &'static str,
+  size: usize,
+  members: Vec<(String, usize, String)>,
+}
+
+impl ShaderCheck {
+  // the directory with the shader files
+  pub fn new(dir: impl Into<PathBuf>) -> Self {
+    Self {
+      dir: dir.into(),
+      programs: Vec::new(),
+      rust_uniforms: Vec::new(),
+      undeclared: BTreeSet::new(),
+      covered: BTreeSet::new(),
+      blocks: Vec::new(),
+      rust_blocks: Vec::new(),
+      errors: Vec::new(),
+    }
+  }
+
+  // file names in the directory, the stage from the extension
+  pub fn program(self, files: &[&str]) -> Self {
+    self.variant(files, &[])
+  }
+
+  // the program built with the defines set, as ProgramVariants does
+  pub fn variant(mut self, files: &[&str], defines: &[&str]) -> Self {
+    let preprocessor = defines
+      .iter()
+      .fold(Preprocessor::new(), |p, define| p.define(define, 1))
+      .line_directives(false)
+      .resolve_conditionals(true);
+    let mut shaders = Vec::new();
+    for file in files {
+      self.covered.insert(file.to_string());
+      match self.load(file, &preprocessor) {
+        Ok(mut shader) => {
+          if !defines.is_empty() {
+            shader.name = format!("{} ({})", file, defines.join(", "));
+          }
+          shaders.push(shader)
+        }
+        Err(e) => self.errors.push(e),
+      }
+    }
+    if shaders.len() < files.len() {
+      return self;
+    }
+
+    let program = match Program::new(shaders) {
+      Ok(program) => program,
+      Err(e) => {
+        self.errors.push(e);
+        return self;
+      }
+    };
+    for (i, shader) in program.shaders.iter().enumerate() {
+      // naga wants gl_Position written, which only the last stage
+      // before the fragment shader does
+      let next = program.shaders.get(i + 1).map(|s| s.stage);
+      let validate =
+        shader.stage != Stage::Vertex || next == Some(Stage::Fragment);
+      match syntax::check(shader.stage, &shader.source, validate) {
+        Ok(blocks) => self
+          .blocks
+          .extend(blocks.into_iter().map(|block| (shader.name.clone(), block))),
+        Err(errors) => self.errors.extend(
+          errors
+            .iter()
+            .map(|e| locate(&shader.name, &shader.origins, e)),
+        ),
+      }
+    }
+    let (errors, _) = program.link();
+    self.errors.extend(errors);
+    self.programs.push(program);
+    self
+  }
+
+  fn load(
+    &self,
+    file: &str,
+    preprocessor: &Preprocessor,
+  ) -> Result<Shader, String> {
+    let path = self.dir.join(file);
+    let stage = Stage::from_path(&path)
+      .ok_or_else(|| format!("{}: unknown shader stage", file))?;
+    let source = preprocessor
+      .process(&path)
+      .map_err(|e| format!("{}: {:#}", file, e))?;
+    Shader::preprocessed(file, stage, &source)
+  }
+
+  // the uniforms set in the .rs files in dir and below
+  pub fn rust_uniforms(mut self, dir: impl AsRef<Path>) -> Self {
+    match rust::scan_dir(dir.as_ref()) {
+      Ok(uniforms) => self.rust_uniforms.extend(uniforms),
+      Err(e) => {
+        self
+          .errors
+          .push(format!("reading {}: {}", dir.as_ref().display(), e))
+      }
+    }
+    self
+  }
+
+  // the struct for a uniform block, which at least one shader has to
+  // declare the same way
+  pub fn uniform_block<T: Std140>(mut self) -> Self {
+    let members = match T::build_layout(0) {
+      BlockLayout::Struct { members } => members
+        .into_iter()
+        .map(|(name, layout)| match layout {
+          BlockLayout::BasicType {
+            ty,
+            offset_in_buffer,
+          } => (name, offset_in_buffer, glsl_type(ty)),
+          other => (name, 0, format!("{:?}", other)),
+        })
+        .collect(),
+      _ => Vec::new(),
+    };
+    let ty = std::any::type_name::<T>();
+    self.rust_blocks.push(RustBlock {
+      name: T::NAME,
+      ty: ty.rsplit("::").next().unwrap_or(ty),
+      size: std::mem::size_of::<T>(),
+      members,
+    });
+    self
+  }
+
+  // uniforms the code sets for shaders that may or may not use them
+  pub fn allow_undeclared(mut self, names: &[&str]) -> Self {
+    self
+      .undeclared
+      .extend(names.iter().map(|name| name.to_string()));
+    self
+  }
+
+  // Prints what was found and fails with all the errors. Shader files
+  // in the directory that aren't part of any program are errors too, so
+  // new ones can't go unchecked.
+  pub fn finish(mut self) -> anyhow::Result<()> {
+    self.check_coverage();
+    self.check_rust_uniforms();
+    self.check_uniform_blocks();
+
+    for program in &self.programs {
+      let uniforms = program.uniforms();
+      let active: Vec<String> = uniforms
+        .iter()
+        .filter(|u| u.active)
+        .map(|u| u.to_string())
+        .collect();
+      let inactive: Vec<String> = uniforms
+        .iter()
+        .filter(|u| !u.active)
+        .map(|u| u.to_string())
+        .collect();
+      println!("{}", program.name());
+      println!("  active uniforms: {}", list(&active));
+      if !inactive.is_empty() {
+        println!("  unused uniforms: {}", list(&inactive));
+      }
+      for warning in program.link().1 {
+        println!("  warning: {}", warning);
+      }
+      // there's no glslang here, so say what went unchecked
+      let unchecked: Vec<&str> = program
+        .shaders
+        .iter()
+        .filter(|s| !matches!(s.stage, Stage::Vertex | Stage::Fragment))
+        .map(|s| s.name.as_str())
+        .collect();
+      if !unchecked.is_empty() {
+        println!(
+          "  declarations only, naga has no tessellation or geometry \
+           stages: {}",
+          unchecked.join(", ")
+        );
+      }
+    }
+
+    if self.errors.is_empty() {
+      return Ok(());
+    }
+    bail!(
+      "{} shader problem(s) in {}:\n{}",
+      self.errors.len(),
+      self.dir.display(),
+      self.errors.join("\n")
+    )
+  }
+
+  fn check_coverage(&mut self) {
+    let Ok(entries) = std::fs::read_dir(&self.dir) else {
+      self
+        .errors
+        .push(format!("can't read {}", self.dir.display()));
+      return;
+    };
+    let mut unchecked: Vec<String> = entries
+      .filter_map(|entry| entry.ok())
+      .map(|entry| entry.path())
+      .filter(|path| Stage::from_path(path).is_some())
+      .filter_map(|path| Some(path.file_name()?.to_str()?.to_owned()))
+      .filter(|name| !self.covered.contains(name))
+      .collect();
+    unchecked.sort();
+    for name in unchecked {
+      self
+        .errors
+        .push(format!("{}: not part of any checked program", name));
+    }
+  }
+
+  // Which program a uniform goes to can't be told from the source, so
+  // a uniform is fine when any program declares it with a type that
+  // takes the value.
+  fn check_rust_uniforms(&mut self) {
+    let uniforms: Vec<Uniform> =
+      self.programs.iter().flat_map(Program::uniforms).collect();
+    for set in &self.rust_uniforms {
+      let declared: Vec<&Uniform> = uniforms
+        .iter()
+        .filter(|u| u.name == set.name && u.block.is_none())
+        .collect();
+      let at = format!("{}:{}", set.file.display(), set.line);
+      if declared.is_empty()
+        && (set.guarded || self.undeclared.contains(&set.name))
+      {
+        continue;
+      }
+      if declared.is_empty() {
+        self.errors.push(format!(
+          "{}: sets uniform `{}`, which no shader declares",
+          at, set.name
+        ));
+      } else if !declared.iter().any(|u| set.kind.accepts(&u.ty)) {
+        let types: BTreeSet<&str> =
+          declared.iter().map(|u| u.ty.as_str()).collect();
+        self.errors.push(format!(
+          "{}: sets uniform `{}` to a {:?} value, but it's declared {}",
+          at,
+          set.name,
+          set.kind,
+          types.into_iter().collect::<Vec<_>>().join(" or ")
+        ));
+      }
+    }
+  }
+
+  // The includes put the same block in many shaders, a difference is
+  // reported once for the first of them.
+  fn check_uniform_blocks(&mut self) {
+    for rust in &self.rust_blocks {
+      let declared: Vec<&(String, syntax::BlockLayout)> = self
+        .blocks
+        .iter()
+        .filter(|(_, block)| block.name == rust.name)
+        .collect();
+      if declared.is_empty() {
+        self.errors.push(format!(
+          "{}: uniform block `{}` isn't declared by any shader",
+          rust.ty, rust.name
+        ));
+        continue;
+      }
+      let mut reported = HashSet::new();
+      for (shader, block) in declared {
+        for difference in block_differences(rust, block) {
+          if reported.insert(difference.clone()) {
+            self.errors.push(format!(
+              "{}: uniform block `{}`: {}",
+              shader, rust.name, difference
+            ));
+          }
+        }
+      }
+    }
+  }
+}
+
+fn block_differences(
+  rust: &RustBlock,
+  block: &syntax::BlockLayout,
+) -> Vec<String> {
+  let mut differences = Vec::new();
+  for (name, offset, ty) in &block.members {
+    let Some((_, rust_offset, rust_ty)) =
+      rust.members.iter().find(|(n, _, _)| n == name)
+    else {
+      differences.push(format!("`{}` is missing in {}", name, rust.ty));
+      continue;
+    };
+    if *offset as usize != *rust_offset {
+      differences.push(format!(
+        "`{}` is at {} with std140, but at {} in {}",
+        name, offset, rust_offset, rust.ty
+      ));
+    }
+    if ty != rust_ty {
+      differences.push(format!(
+        "`{}` is {}, but {} in {}",
+        name, ty, rust_ty, rust.ty
+      ));
+    }
+  }
+  for (name, _, _) in &rust.members {
+    if !block.members.iter().any(|(n, _, _)| n == name) {
+      differences.push(format!("`{}` of {} isn't in the block", name, rust.ty));
+    }
+  }
+  if block.size as usize != rust.size {
+    differences.push(format!(
+      "{} bytes with std140, but {} is {}",
+      block.size, rust.ty, rust.size
+    ));
+  }
+  differences
+}
+
+// as naga's types are named in syntax.rs
+fn glsl_type(ty: UniformType) -> String {
+  let name = match ty {
+    UniformType::Float => "float",
+    UniformType::FloatVec2 => "vec2",
+    UniformType::FloatVec3 => "vec3",
+    UniformType::FloatVec4 => "vec4",
+    UniformType::Int => "int",
+    UniformType::IntVec2 => "ivec2",
+    UniformType::IntVec3 => "ivec3",
+    UniformType::IntVec4 => "ivec4",
+    UniformType::UnsignedInt => "uint",
+    UniformType::UnsignedIntVec2 => "uvec2",
+    UniformType::UnsignedIntVec3 => "uvec3",
+    UniformType::UnsignedIntVec4 => "uvec4",
+    UniformType::Bool => "bool",
+    UniformType::FloatMat2 => "mat2",
+    UniformType::FloatMat3 => "mat3",
+    UniformType::FloatMat4 => "mat4",
+    other => return format!("{:?}", other),
+  };
+  name.to_owned()
+}
+
+fn list(items: &[String]) -> String {
+  if items.is_empty() {
+    "none".to_owned()
+  } else {
+    items.join(", ")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn shader(name: &str, source: &str) -> Shader {
+    let stage = Stage::from_path(Path::new(name)).unwrap();
+    Shader::parse(name, stage, source).unwrap()
+  }
+
+  fn variables(variables: &[Variable]) -> Vec<(&str, &str, bool)> {
+    variables
+      .iter()
+      .map(|v| (v.name.as_str(), v.ty.as_str(), v.used))
+      .collect()
+  }
+
+  fn uniforms(shader: &Shader) -> Vec<(&str, &str, Option<&str>, bool)> {
+    shader
+      .uniforms
+      .iter()
+      .map(|u| (u.name.as_str(), u.ty.as_str(), u.block.as_deref(), u.active))
+      .collect()
+  }
+
+  fn link(shaders: &[(&str, &str)]) -> (Vec<String>, Vec<String>) {
+    let shaders = shaders
+      .iter()
+      .map(|(name, source)| shader(name, source))
+      .collect();
+    Program::new(shaders).unwrap().link()
+  }
+
+  #[test]
+  fn parses_blocks() {
+    let shader = shader(
+      "phong.frag",
+      "#version 410 core\n\
+       layout(std140) uniform Light { vec3 pos; float power[2]; } light;\n\
+       uniform Material { vec3 Kd; vec3 Ks; };\n\
+       uniform Unused { float x; } unused;\n\
+       in VertData { vec3 n; vec2 uv; } v;\n\
+       out vec4 color;\n\
+       void main() { color = vec4(v.n * light.pos + Kd, 1.0); }\n",
+    );
+    assert_eq!(shader.version.as_deref(), Some("410 core"));
+    assert_eq!(
+      uniforms(&shader),
+      [
+        ("Light", "block", None, true),
+        ("pos", "vec3", Some("Light"), true),
+        ("power", "float[2]", Some("Light"), true),
+        ("Material", "block", None, true),
+        ("Kd", "vec3", Some("Material"), true),
+        ("Ks", "vec3", Some("Material"), false),
+        ("Unused", "block", None, false),
+        ("x", "float", Some("Unused"), false),
+      ]
+    );
+    assert_eq!(
+      variables(&shader.inputs),
+      [("VertData.n", "vec3", true), ("VertData.uv", "vec2", true)]
+    );
+    assert_eq!(variables(&shader.outputs), [("color", "vec4", true)]);
+    assert_eq!(shader.uniform("Kd").unwrap().line, 3);
+  }
+
+  #[test]
+  fn parses_arrays() {
+    let shader = shader(
+      "skin.vert",
+      "uniform vec3 lights[4], ambient;\n\
+       uniform mat4[2] bones;\n\
+       uniform float grid[2][3];\n\
+       in vec3 pos;\n\
+       void main() { gl_Position = bones[0] * vec4(pos + lights[1], 1); }\n",
+    );
+    assert_eq!(
+      uniforms(&shader),
+      [
+        ("lights", "vec3[4]", None, true),
+        ("ambient", "vec3", None, false),
+        ("bones", "mat4[2]", None, true),
+        ("grid", "float[2][3]", None, false),
+      ]
+    );
+  }
+
+  #[test]
+  fn strips_the_vertex_arrays() {
+    let tcs = shader(
+      "patch.tcs",
+      "layout(vertices = 3) out;\n\
+       in vec3 v_pos[];\n\
+       out vec3 tc_pos[];\n\
+       patch out vec4 inner;\n\
+       void main() { tc_pos[gl_InvocationID] = v_pos[gl_InvocationID]; }\n",
+    );
+    assert_eq!(variables(&tcs.inputs), [("v_pos", "vec3", true)]);
+    assert_eq!(
+      variables(&tcs.outputs),
+      [("tc_pos", "vec3", true), ("inner", "vec4", false)]
+    );
+
+    let tes = shader(
+      "patch.tes",
+      "in vec3 tc_pos[];\npatch in vec4 inner;\nin float weights[][2];\n\
+       void main() {}\n",
+    );
+    assert_eq!(
+      variables(&tes.inputs),
+      [
+        ("tc_pos", "vec3", false),
+        ("inner", "vec4", false),
+        ("weights", "float[2]", false)
+      ]
+    );
+
+    let geom = shader(
+      "normals.geom",
+      "in vec3 n[];\nout vec3 g_n[2];\nvoid main() {}\n",
+    );
+    assert_eq!(variables(&geom.inputs), [("n", "vec3", false)]);
+    // only the control stage has arrayed outputs
+    assert_eq!(variables(&geom.outputs), [("g_n", "vec3[2]", false)]);
+
+    let error =
+      Shader::parse("patch.tes", Stage::TessEvaluation, "in vec3 p;\n")
+        .unwrap_err();
+    assert!(error.contains("p should be an array over the vertices"));
+  }
+
+  #[test]
+  fn parse_errors_are_located() {
+    let error =
+      Shader::parse("a.frag", Stage::Fragment, "void main() {\n").unwrap_err();
+    assert_eq!(error, "a.frag:1: `{` is never closed");
+  }
+
+  #[test]
+  fn link_mismatches() {
+    let (errors, warnings) = link(&[
+      (
+        "a.vert",
+        "out vec3 n;\nout vec2 uv;\nout float spare;\nvoid main() {}\n",
+      ),
+      (
+        "a.frag",
+        "in vec3 n;\nin vec3 uv;\nin vec4 extra;\nin float unread;\n\
+         out vec4 color;\n\
+         void main() { color = vec4(n + uv, extra.x); }\n",
+      ),
+    ]);
+    assert_eq!(
+      errors,
+      [
+        "a.frag:2: input `uv` is vec3, but a.vert writes vec2",
+        "a.frag:3: input `vec4 extra` isn't an output of a.vert",
+      ]
+    );
+    assert_eq!(
+      warnings,
+      [
+        "a.frag:4: input `float unread` isn't an output of a.vert (unused)",
+        "a.vert:3: output `spare` isn't read by a.frag",
+      ]
+    );
+  }
+
+  #[test]
+  fn link_block_members() {
+    let (errors, _) = link(&[
+      (
+        "b.vert",
+        "out VertData { vec3 n; vec2 uv; } v;\nvoid main() {}\n",
+      ),
+      (
+        "b.frag",
+        "in VertData { vec3 n; } v;\nvoid main() { v.n; }\n",
+      ),
+    ]);
+    assert_eq!(
+      errors,
+      ["b.vert:1: block member `VertData.uv` is missing in b.frag"]
+    );
+  }
+
+  #[test]
+  fn link_uniform_types() {
+    let (errors, _) = link(&[
+      (
+        "c.vert",
+        "uniform mat4 model;\nuniform float t;\nvoid main() {}\n",
+      ),
+      (
+        "c.frag",
+        "uniform float t;\n\nuniform mat3 model;\nvoid main() {}\n",
+      ),
+    ]);
+    assert_eq!(
+      errors,
+      ["c.frag:3: uniform `model` is mat3, but mat4 in c.vert"]
+    );
+  }
+
+  #[test]
+  fn link_through_tessellation() {
+    let (errors, warnings) = link(&[
+      ("d.frag", "in vec3 g;\nvoid main() { g; }\n"),
+      ("d.tes", "in vec3 tc[];\nout vec3 g;\nvoid main() { tc; }\n"),
+      ("d.vert", "out vec3 v;\nvoid main() {}\n"),
+      (
+        "d.tcs",
+        "in vec3 v[];\nout vec3 tc[];\nvoid main() { v; }\n",
+      ),
+    ]);
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert!(warnings.is_empty(), "{:?}", warnings);
+  }
+
+  #[test]
+  fn program_stages() {
+    let stages = |names: &[&str]| {
+      let shaders = names.iter().map(|name| shader(name, "")).collect();
+      Program::new(shaders).map(|program| program.name())
+    };
+    assert_eq!(stages(&["e.frag", "e.vert"]).unwrap(), "e.vert + e.frag");
+    assert_eq!(
+      stages(&["e.vert"]).unwrap_err(),
+      "e.vert: needs a vertex and a fragment shader"
+    );
+    assert_eq!(
+      stages(&["e.vert", "f.vert", "e.frag"]).unwrap_err(),
+      "e.vert + f.vert + e.frag: a stage is given twice"
+    );
+    assert!(stages(&["e.vert", "e.tcs", "e.frag"])
+      .unwrap_err()
+      .ends_with(
+        "tessellation needs both the control and the evaluation \
+                  shader"
+      ));
+  }
+}
@@ -0,0 +1,657 @@
+// Just enough of GLSL to find the global declarations: a tokenizer and a
+// split of the token stream into top level statements. Function bodies
+// are only checked for balanced brackets.
+
+use std::ops::Range;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Kind {
+  Ident,
+  Number,
+  Punct,
+  // a whole preprocessor line
+  Directive,
+}
+
+#[derive(Clone, Debug)]
+pub(super) struct Token {
+  pub kind: Kind,
+  pub text: String,
+  pub line: usize,
+}
+
+impl Token {
+  pub fn is(&self, text: &str) -> bool {
+    self.kind != Kind::Directive && self.text == text
+  }
+
+  pub fn is_ident(&self) -> bool {
+    self.kind == Kind::Ident
+  }
+}
+
+// longest first, so that "<<=" isn't read as "<<" and "="
+const OPERATORS: [&str; 21] = [
+  "<<=", ">>=", "++", "--", "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=",
+  "==", "!=", "<=", ">=", "&&", "||", "^^", "<<", ">>",
+];
+
+pub(super) fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+  let chars: Vec<char> = source.chars().collect();
+  let mut tokens = Vec::new();
+  let mut line = 1;
+  let mut line_start = true;
+  let mut i = 0;
+
+  while i < chars.len() {
+    let c = chars[i];
+    if c == '\n' {
+      line += 1;
+      line_start = true;
+      i += 1;
+    } else if c.is_whitespace() {
+      i += 1;
+    } else if c == '/' && chars.get(i + 1) == Some(&'/') {
+      while i < chars.len() && chars[i] != '\n' {
+        i += 1;
+      }
+    } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+      let start = line;
+      i += 2;
+      loop {
+        match chars.get(i) {
+          None => return Err(format!("{}: unterminated comment", start)),
+          Some('*') if chars.get(i + 1) == Some(&'/') => break,
+          Some('\n') => line += 1,
+          _ => (),
+        }
+        i += 1;
+      }
+      i += 2;
+    } else if c == '#' && line_start {
+      // up to the end of the line, following backslash continuations
+      let start = i;
+      let first_line = line;
+      while i < chars.len() && chars[i] != '\n' {
+        if chars[i] == '\\' && chars.get(i + 1) == Some(&'\n') {
+          line += 1;
+          i += 1;
+        }
+        i += 1;
+      }
+      let text: String = chars[start..i].iter().collect();
+      tokens.push(Token {
+        kind: Kind::Directive,
+        text: text.replace("\\\n", " "),
+        line: first_line,
+      });
+    } else {
+      line_start = false;
+      let start = i;
+      let kind = if c.is_ascii_alphabetic() || c == '_' {
+        while i < chars.len()
+          && (chars[i].is_ascii_alphanumeric() || chars[i] == '_')
+        {
+          i += 1;
+        }
+        Kind::Ident
+      } else if c.is_ascii_digit()
+        || (c == '.' && chars.get(i + 1).is_some_and(char::is_ascii_digit))
+      {
+        i += 1;
+        while i < chars.len() {
+          let c = chars[i];
+          let exponent_sign = (c == '+' || c == '-')
+            && matches!(chars[i - 1], 'e' | 'E')
+            && !chars[start..i].iter().any(|c| matches!(c, 'x' | 'X'));
+          if c.is_ascii_alphanumeric() || c == '.' || exponent_sign {
+            i += 1;
+          } else {
+            break;
+          }
+        }
+        Kind::Number
+      } else {
+        let rest: String = chars[i..chars.len().min(i + 3)].iter().collect();
+        let len = OPERATORS
+          .iter()
+          .find(|op| rest.starts_with(*op))
+          .map_or(1, |op| op.len());
+        i += len;
+        Kind::Punct
+      };
+      tokens.push(Token {
+        kind,
+        text: chars[start..i].iter().collect(),
+        line,
+      });
+    }
+  }
+
+  check_brackets(&tokens)?;
+  Ok(tokens)
+}
+
+fn check_brackets(tokens: &[Token]) -> Result<(), String> {
+  let mut open: Vec<&Token> = Vec::new();
+  for token in tokens.iter().filter(|t| t.kind == Kind::Punct) {
+    let expected = match token.text.as_str() {
+      "(" | "[" | "{" => {
+        open.push(token);
+        continue;
+      }
+      ")" => "(",
+      "]" => "[",
+      "}" => "{",
+      _ => continue,
+    };
+    match open.pop() {
+      Some(opening) if opening.text == expected => (),
+      Some(opening) => {
+        return Err(format!(
+          "{}: `{}` closes the `{}` from line {}",
+          token.line, token.text, opening.text, opening.line
+        ))
+      }
+      None => {
+        return Err(format!("{}: unmatched `{}`", token.line, token.text))
+      }
+    }
+  }
+  match open.pop() {
+    Some(opening) => Err(format!(
+      "{}: `{}` is never closed",
+      opening.line, opening.text
+    )),
+    None => Ok(()),
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) enum Statement {
+  Directive(usize),
+  // up to and without the semicolon
+  Declaration(Range<usize>),
+  // the tokens before the braces, the members inside and whatever
+  // follows up to the semicolon (an instance name)
+  Block {
+    head: Range<usize>,
+    body: Range<usize>,
+    tail: Range<usize>,
+  },
+  // the signature and the body without the braces
+  Function {
+    signature: Range<usize>,
+    body: Range<usize>,
+  },
+}
+
+// The top level statements. The brackets are known to be balanced.
+pub(super) fn statements(tokens: &[Token]) -> Result<Vec<Statement>, String> {
+  let mut statements = Vec::new();
+  let mut i = 0;
+  while i < tokens.len() {
+    if tokens[i].kind == Kind::Directive {
+      statements.push(Statement::Directive(i));
+      i += 1;
+      continue;
+    }
+    if tokens[i].is(";") {
+      i += 1;
+      continue;
+    }
+
+    let start = i;
+    let mut depth = 0;
+    while i < tokens.len() {
+      let token = &tokens[i];
+      if token.kind == Kind::Directive && depth == 0 {
+        return Err(format!("{}: directive inside a declaration", token.line));
+      }
+      if token.is("(") || token.is("[") {
+        depth += 1;
+      } else if token.is(")") || token.is("]") {
+        depth -= 1;
+      } else if depth == 0 && (token.is(";") || token.is("{")) {
+        break;
+      }
+      i += 1;
+    }
+
+    let Some(end_token) = tokens.get(i) else {
+      return Err(format!(
+        "{}: missing `;` at the end of the file",
+        tokens[start].line
+      ));
+    };
+    if end_token.is(";") {
+      statements.push(Statement::Declaration(start..i));
+      i += 1;
+      continue;
+    }
+
+    let body_start = i + 1;
+    let body_end = matching_brace(tokens, i);
+    let head = start..i;
+    i = body_end + 1;
+    // a function head ends with its parameters, a block head can have
+    // a layout in parentheses before the name
+    if tokens[head.clone()].last().is_some_and(|t| t.is(")")) {
+      statements.push(Statement::Function {
+        signature: head,
+        body: body_start..body_end,
+      });
+      continue;
+    }
+
+    let tail_start = i;
+    while i < tokens.len() && !tokens[i].is(";") {
+      i += 1;
+    }
+    if i == tokens.len() {
+      return Err(format!(
+        "{}: missing `;` after the block",
+        tokens[body_end].line
+      ));
+    }
+    statements.push(Statement::Block {
+      head,
+      body: body_start..body_end,
+      tail: tail_start..i,
+    });
+    i += 1;
+  }
+  Ok(statements)
+}
+
+fn matching_brace(tokens: &[Token], open: usize) -> usize {
+  let mut depth = 0;
+  for (i, token) in tokens.iter().enumerate().skip(open) {
+    if token.is("{") {
+      depth += 1;
+    } else if token.is("}") {
+      depth -= 1;
+      if depth == 0 {
+        return i;
+      }
+    }
+  }
+  unreachable!("brackets are checked while tokenizing")
+}
+
+// Qualifiers that can come before the type of a global.
+const QUALIFIERS: [&str; 22] = [
+  "in",
+  "out",
+  "uniform",
+  "buffer",
+  "const",
+  "attribute",
+  "varying",
+  "flat",
+  "smooth",
+  "noperspective",
+  "centroid",
+  "sample",
+  "patch",
+  "invariant",
+  "precise",
+  "highp",
+  "mediump",
+  "lowp",
+  "readonly",
+  "writeonly",
+  "coherent",
+  "shared",
+];
+
+// A declaration taken apart: `layout(..) qualifiers type names`.
+#[derive(Clone, Debug, Default)]
+pub(super) struct Declaration {
+  // the contents of the layout parentheses, if any
+  pub layout: Option<Range<usize>>,
+  pub qualifiers: Vec<String>,
+  // empty for a lone qualifier like `layout(vertices = 4) out`
+  pub ty: String,
+  // array dimensions right after the type
+  pub ty_dims: Vec<String>,
+  pub declarators: Vec<Declarator>,
+  // where the type starts, after the qualifiers
+  pub ty_start: usize,
+}
+
+#[derive(Clone, Debug)]
+pub(super) struct Declarator {
+  pub name: String,
+  pub dims: Vec<String>,
+  // the name token
+  pub at: usize,
+}
+
+impl Declaration {
+  pub fn has(&self, qualifier: &str) -> bool {
+    self.qualifiers.iter().any(|q| q == qualifier)
+  }
+
+  pub fn storage(&self) -> Option<&str> {
+    ["in", "out", "uniform", "buffer"]
+      .into_iter()
+      .find(|q| self.has(q))
+  }
+}
+
+// Parses the tokens of a declaration. For the head of a block the type
+// is the block name and there are no declarators.
+pub(super) fn declaration(
+  tokens: &[Token],
+  range: Range<usize>,
+) -> Result<Declaration, String> {
+  let mut decl = Declaration::default();
+  let mut i = range.start;
+  let line = |i: usize| tokens[i.min(range.end.max(1) - 1)].line;
+
+  while i < range.end {
+    let token = &tokens[i];
+    if token.is("layout") {
+      if !tokens.get(i + 1).is_some_and(|t| t.is("(")) {
+        return Err(format!("{}: expected `(` after layout", line(i)));
+      }
+      let close = (i + 1..range.end)
+        .find(|&j| tokens[j].is(")"))
+        .ok_or_else(|| format!("{}: unclosed layout", line(i)))?;
+      decl.layout = Some(i + 2..close);
+      i = close + 1;
+    } else if QUALIFIERS.contains(&token.text.as_str()) {
+      decl.qualifiers.push(token.text.clone());
+      i += 1;
+    } else {
+      break;
+    }
+  }
+
+  decl.ty_start = i;
+  if i == range.end {
+    return Ok(decl);
+  }
+  if !tokens[i].is_ident() {
+    return Err(format!(
+      "{}: expected a type, found `{}`",
+      line(i),
+      tokens[i].text
+    ));
+  }
+  decl.ty = tokens[i].text.clone();
+  i += 1;
+  i = dimensions(tokens, i, range.end, &mut decl.ty_dims);
+
+  while i < range.end {
+    let token = &tokens[i];
+    if !token.is_ident() {
+      return Err(format!(
+        "{}: expected a name, found `{}`",
+        token.line, token.text
+      ));
+    }
+    let mut declarator = Declarator {
+      name: token.text.clone(),
+      dims: Vec::new(),
+      at: i,
+    };
+    i = dimensions(tokens, i + 1, range.end, &mut declarator.dims);
+    decl.declarators.push(declarator);
+
+    // skip an initializer
+    let mut depth = 0;
+    while i < range.end {
+      let token = &tokens[i];
+      if token.is("(") || token.is("[") || token.is("{") {
+        depth += 1;
+      } else if token.is(")") || token.is("]") || token.is("}") {
+        depth -= 1;
+      } else if depth == 0 && token.is(",") {
+        break;
+      }
+      i += 1;
+    }
+    i += 1;
+  }
+  Ok(decl)
+}
+
+// `[4][]` as `["4", ""]`
+fn dimensions(
+  tokens: &[Token],
+  mut i: usize,
+  end: usize,
+  dims: &mut Vec<String>,
+) -> usize {
+  while i < end && tokens[i].is("[") {
+    let close = (i..end).find(|&j| tokens[j].is("]")).unwrap_or(end);
+    let size: Vec<&str> = tokens[i + 1..close]
+      .iter()
+      .map(|t| t.text.as_str())
+      .collect();
+    dims.push(size.join(" "));
+    i = close + 1;
+  }
+  i
+}
+
+// the member declarations of a block body
+pub(super) fn members(
+  tokens: &[Token],
+  body: Range<usize>,
+) -> Result<Vec<Declaration>, String> {
+  let mut members = Vec::new();
+  let mut start = body.start;
+  for i in body.clone() {
+    if tokens[i].is(";") {
+      members.push(declaration(tokens, start..i)?);
+      start = i + 1;
+    }
+  }
+  if start < body.end {
+    return Err(format!(
+      "{}: missing `;` after the last member",
+      tokens[body.end - 1].line
+    ));
+  }
+  Ok(members)
+}
+
+pub(super) fn type_name(ty: &str, dims: &[String]) -> String {
+  let dims: String = dims.iter().map(|d| format!("[{}]", d)).collect();
+  format!("{}{}", ty, dims)
+}
+
+// samplers, images and atomic counters can't go into blocks
+pub(super) fn is_opaque(ty: &str) -> bool {
+  ty.contains("sampler") || ty.contains("image") || ty == "atomic_uint"
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn texts(tokens: &[Token]) -> Vec<&str> {
+    tokens.iter().map(|t| t.text.as_str()).collect()
+  }
+
+  fn parse(source: &str) -> (Vec<Token>, Vec<Statement>) {
+    let tokens = tokenize(source).unwrap();
+    let statements = statements(&tokens).unwrap();
+    (tokens, statements)
+  }
+
+  // the single declaration of a source
+  fn declare(source: &str) -> (Vec<Token>, Declaration) {
+    let (tokens, statements) = parse(source);
+    let [Statement::Declaration(range)] = statements.as_slice() else {
+      panic!("{:?}", statements);
+    };
+    let decl = declaration(&tokens, range.clone()).unwrap();
+    (tokens, decl)
+  }
+
+  #[test]
+  fn tokens() {
+    let tokens = tokenize("a <<= b++ >= 1.5e-3 * .5f + 0x1E-c;").unwrap();
+    assert_eq!(
+      texts(&tokens),
+      [
+        "a", "<<=", "b", "++", ">=", "1.5e-3", "*", ".5f", "+", "0x1E", "-",
+        "c", ";"
+      ]
+    );
+    let kinds: Vec<Kind> = tokens[..6].iter().map(|t| t.kind).collect();
+    use Kind::*;
+    assert_eq!(kinds, [Ident, Punct, Ident, Punct, Punct, Number]);
+  }
+
+  #[test]
+  fn comments_and_lines() {
+    let source = "a // b\n/* c\nd */ e\n  #define F \\\n  1\ng";
+    let tokens = tokenize(source).unwrap();
+    assert_eq!(texts(&tokens), ["a", "e", "#define F    1", "g"]);
+    let lines: Vec<usize> = tokens.iter().map(|t| t.line).collect();
+    assert_eq!(lines, [1, 3, 4, 6]);
+    assert_eq!(tokens[2].kind, Kind::Directive);
+    // only at the start of a line
+    assert!(tokenize("a # b").unwrap()[1].is("#"));
+  }
+
+  #[test]
+  fn token_errors() {
+    assert_eq!(tokenize("a\n/* b").unwrap_err(), "2: unterminated comment");
+    assert_eq!(
+      tokenize("f(\n]").unwrap_err(),
+      "2: `]` closes the `(` from line 1"
+    );
+    assert_eq!(tokenize("a)").unwrap_err(), "1: unmatched `)`");
+    assert_eq!(tokenize("{\n").unwrap_err(), "1: `{` is never closed");
+  }
+
+  #[test]
+  fn top_level_statements() {
+    let (tokens, statements) = parse(
+      "#version 330\n\
+       uniform float a[2];;\n\
+       layout(std140) uniform B { vec4 c; } b;\n\
+       struct S { float d; };\n\
+       vec3 f(float x) { if (x > 0) { return vec3(x); } return vec3(0); }",
+    );
+    let text = |range: &Range<usize>| texts(&tokens[range.clone()]).join(" ");
+    let found: Vec<String> = statements
+      .iter()
+      .map(|statement| match statement {
+        Statement::Directive(i) => tokens[*i].text.clone(),
+        Statement::Declaration(range) => text(range),
+        Statement::Block { head, body, tail } => {
+          format!("{} {{ {} }} {}", text(head), text(body), text(tail))
+        }
+        Statement::Function { signature, body } => {
+          format!("{} {{ {} }}", text(signature), text(body))
+        }
+      })
+      .collect();
+    assert_eq!(
+      found,
+      [
+        "#version 330",
+        "uniform float a [ 2 ]",
+        "layout ( std140 ) uniform B { vec4 c ; } b",
+        "struct S { float d ; } ",
+        "vec3 f ( float x ) { if ( x > 0 ) { return vec3 ( x ) ; } \
+         return vec3 ( 0 ) ; }",
+      ]
+    );
+  }
+
+  #[test]
+  fn statement_errors() {
+    let tokens = tokenize("uniform float a").unwrap();
+    assert_eq!(
+      statements(&tokens).unwrap_err(),
+      "1: missing `;` at the end of the file"
+    );
+    let tokens = tokenize("uniform B {\n float a;\n}").unwrap();
+    assert_eq!(
+      statements(&tokens).unwrap_err(),
+      "3: missing `;` after the block"
+    );
+    let tokens = tokenize("uniform float\n#define A\na;").unwrap();
+    assert_eq!(
+      statements(&tokens).unwrap_err(),
+      "2: directive inside a declaration"
+    );
+  }
+
+  #[test]
+  fn declarations() {
+    let (tokens, decl) =
+      declare("layout(location = 2) flat in ivec2 a, b[3] = x[0], c[2][N];");
+    assert_eq!(
+      texts(&tokens[decl.layout.clone().unwrap()]),
+      ["location", "=", "2"]
+    );
+    assert_eq!(decl.qualifiers, ["flat", "in"]);
+    assert_eq!(decl.storage(), Some("in"));
+    assert!(decl.has("flat") && !decl.has("patch"));
+    assert_eq!(decl.ty, "ivec2");
+    assert!(decl.ty_dims.is_empty());
+    let declarators: Vec<(&str, Vec<String>)> = decl
+      .declarators
+      .iter()
+      .map(|d| (d.name.as_str(), d.dims.clone()))
+      .collect();
+    assert_eq!(
+      declarators,
+      [
+        ("a", vec![]),
+        ("b", vec!["3".to_owned()]),
+        ("c", vec!["2".to_owned(), "N".to_owned()]),
+      ]
+    );
+    assert!(tokens[decl.declarators[2].at].is("c"));
+  }
+
+  #[test]
+  fn array_types_and_lone_qualifiers() {
+    let (_, decl) = declare("uniform mat4[4] bones;");
+    assert_eq!(decl.ty_dims, ["4"]);
+    assert_eq!(type_name(&decl.ty, &decl.ty_dims), "mat4[4]");
+
+    let (_, decl) = declare("layout(vertices = 4) out;");
+    assert_eq!(decl.storage(), Some("out"));
+    assert!(decl.ty.is_empty() && decl.declarators.is_empty());
+
+    let (tokens, statements) = parse("in vec3 4;");
+    let Statement::Declaration(range) = &statements[0] else {
+      unreachable!()
+    };
+    assert_eq!(
+      declaration(&tokens, range.clone()).unwrap_err(),
+      "1: expected a name, found `4`"
+    );
+  }
+
+  #[test]
+  fn block_members() {
+    let (tokens, statements) =
+      parse("out VertData {\n  vec3 n;\n  vec2 uv[2], st;\n} v;");
+    let Statement::Block { head, body, .. } = &statements[0] else {
+      unreachable!()
+    };
+    let head = declaration(&tokens, head.clone()).unwrap();
+    assert_eq!(
+      (head.storage(), head.ty.as_str()),
+      (Some("out"), "VertData")
+    );
+    let members = members(&tokens, body.clone()).unwrap();
+    let names: Vec<&str> = members
+      .iter()
+      .flat_map(|m| m.declarators.iter().map(|d| d.name.as_str()))
+      .collect();
+    assert_eq!(names, ["n", "uv", "st"]);
+  }
+}
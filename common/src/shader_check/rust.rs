@@ -0,0 +1,389 @@
+// Finds the uniforms the Rust code sets, by reading the sources: the
+// entries of `uniform!` and the literal names passed to `DynUniforms`;
+// names built at run time aren't seen. The type is guessed from how the
+// value is written, where it shows.
+
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueKind {
+  Float,
+  Int,
+  Uint,
+  Bool,
+  Vector,
+  Matrix,
+  Sampler,
+  // nothing to tell from the expression
+  Unknown,
+}
+
+impl ValueKind {
+  fn of(expr: &str) -> Self {
+    let expr = expr.trim().trim_start_matches('&');
+    if expr.ends_with(".to_cols_array_2d()") {
+      return Self::Matrix;
+    }
+    if expr.ends_with(".to_array()") {
+      return Self::Vector;
+    }
+    if expr.contains(".sampled()") {
+      return Self::Sampler;
+    }
+    if expr == "true" || expr == "false" {
+      return Self::Bool;
+    }
+    if let Some(kind) = [
+      ("as f32", Self::Float),
+      ("as i32", Self::Int),
+      ("as u32", Self::Uint),
+    ]
+    .into_iter()
+    .find_map(|(cast, kind)| expr.ends_with(cast).then_some(kind))
+    {
+      return kind;
+    }
+    if let Some(items) =
+      expr.strip_prefix('[').and_then(|e| e.strip_suffix(']'))
+    {
+      return if items.trim_start().starts_with('[') {
+        Self::Matrix
+      } else {
+        Self::Vector
+      };
+    }
+    literal_kind(expr).unwrap_or(Self::Unknown)
+  }
+
+  // whether a GLSL uniform of type ty can take the value
+  pub fn accepts(self, ty: &str) -> bool {
+    let starts = |prefixes: &[&str]| prefixes.iter().any(|p| ty.starts_with(p));
+    match self {
+      Self::Unknown => true,
+      // arrays are set element by element
+      _ if ty.ends_with(']') => false,
+      Self::Float => ty == "float" || ty == "double",
+      Self::Int => ty == "int",
+      Self::Uint => ty == "uint",
+      Self::Bool => ty == "bool",
+      Self::Vector => starts(&["vec", "ivec", "uvec", "bvec", "dvec"]),
+      Self::Matrix => starts(&["mat", "dmat"]),
+      Self::Sampler => ty.contains("sampler"),
+    }
+  }
+}
+
+// 1.0, 0.5f32, 3u32 and so on
+fn literal_kind(expr: &str) -> Option<ValueKind> {
+  let suffixed = [
+    ("f32", ValueKind::Float),
+    ("i32", ValueKind::Int),
+    ("u32", ValueKind::Uint),
+  ];
+  for (suffix, kind) in suffixed {
+    if let Some(number) = expr.strip_suffix(suffix) {
+      let number = number.trim_end_matches('_');
+      if number.parse::<f64>().is_ok() {
+        return Some(kind);
+      }
+    }
+  }
+  let plain = expr.replace('_', "");
+  if plain.parse::<i64>().is_ok() {
+    return Some(ValueKind::Int);
+  }
+  plain.parse::<f64>().is_ok().then_some(ValueKind::Float)
+}
+
+#[derive(Clone, Debug)]
+pub struct RustUniform {
+  pub name: String,
+  pub kind: ValueKind,
+  // only set when `program.get_uniform` finds it
+  pub guarded: bool,
+  pub file: PathBuf,
+  pub line: usize,
+}
+
+// every .rs file in dir and below, except the tests
+pub(super) fn scan_dir(dir: &Path) -> anyhow::Result<Vec<RustUniform>> {
+  let mut uniforms = Vec::new();
+  let mut entries: Vec<_> =
+    std::fs::read_dir(dir)?.collect::<Result<_, _>>()?;
+  entries.sort_by_key(|entry| entry.path());
+  for entry in entries {
+    let path = entry.path();
+    if path.is_dir() {
+      uniforms.extend(scan_dir(&path)?);
+    } else if path.extension().is_some_and(|ext| ext == "rs")
+      && path.file_stem().is_some_and(|stem| stem != "tests")
+    {
+      let source = std::fs::read_to_string(&path)?;
+      uniforms.extend(scan(&path, &source));
+    }
+  }
+  Ok(uniforms)
+}
+
+fn scan(file: &Path, source: &str) -> Vec<RustUniform> {
+  let code = blank_comments(source);
+  let guarded: Vec<&str> = code
+    .match_indices("get_uniform(")
+    .filter_map(|(at, method)| {
+      string_literal(split_args(&code[at + method.len()..]).first()?.0)
+    })
+    .collect();
+  let mut uniforms = Vec::new();
+  let mut found = |name: &str, expr: &str, at: usize| {
+    uniforms.push(RustUniform {
+      name: name.to_owned(),
+      kind: ValueKind::of(expr),
+      guarded: guarded.contains(&name),
+      file: file.to_owned(),
+      line: code[..at].matches('\n').count() + 1,
+    })
+  };
+
+  for (at, _) in code.match_indices("uniform!") {
+    let rest = &code[at + "uniform!".len()..];
+    let Some(open) = rest.find(|c: char| !c.is_whitespace()) else {
+      continue;
+    };
+    let body_start = at + "uniform!".len() + open + 1;
+    for (entry, offset) in split_args(&code[body_start..]) {
+      if let Some((name, expr)) = entry.split_once(':') {
+        let name = name.trim();
+        if !name.is_empty() && name.chars().all(is_ident_char) {
+          found(name, expr, body_start + offset);
+        }
+      }
+    }
+  }
+
+  for method in [".add(", ".add_raw(", ".add_override(", ".add_raw_override("] {
+    for (at, _) in code.match_indices(method) {
+      let args_start = at + method.len();
+      let args = split_args(&code[args_start..]);
+      let [(name, offset), (expr, _)] = args.as_slice() else {
+        continue;
+      };
+      if let Some(name) = string_literal(name) {
+        found(name, expr, args_start + offset);
+      }
+    }
+  }
+
+  uniforms.sort_by_key(|uniform| uniform.line);
+  uniforms
+}
+
+// the contents of a plain "..." string
+fn string_literal(code: &str) -> Option<&str> {
+  code.trim().strip_prefix('"')?.strip_suffix('"')
+}
+
+fn is_ident_char(c: char) -> bool {
+  c.is_alphanumeric() || c == '_'
+}
+
+// The comma separated items up to the bracket closing the list, with
+// their offsets. Brackets and strings in the items are skipped over.
+fn split_args(code: &str) -> Vec<(&str, usize)> {
+  let mut items = Vec::new();
+  let mut depth = 0;
+  let mut start = 0;
+  let mut in_string = false;
+  let mut escaped = false;
+  for (i, c) in code.char_indices() {
+    if in_string {
+      match c {
+        _ if escaped => escaped = false,
+        '\\' => escaped = true,
+        '"' => in_string = false,
+        _ => (),
+      }
+      continue;
+    }
+    match c {
+      '"' => in_string = true,
+      '(' | '[' | '{' => depth += 1,
+      ')' | ']' | '}' if depth == 0 => {
+        items.push((&code[start..i], start));
+        break;
+      }
+      ')' | ']' | '}' => depth -= 1,
+      ',' if depth == 0 => {
+        items.push((&code[start..i], start));
+        start = i + 1;
+      }
+      _ => (),
+    }
+  }
+  // a trailing comma leaves an empty item
+  items
+    .into_iter()
+    .filter(|(item, _)| !item.trim().is_empty())
+    .map(|(item, start)| {
+      let trimmed = item.trim_start();
+      (trimmed, start + item.len() - trimmed.len())
+    })
+    .collect()
+}
+
+// Comments turned into spaces, keeping the newlines and the offsets.
+// Strings are left alone, so "//" in one doesn't start a comment.
+fn blank_comments(source: &str) -> String {
+  let mut out = String::with_capacity(source.len());
+  let mut chars = source.chars().peekable();
+  let mut in_string = false;
+  while let Some(c) = chars.next() {
+    if in_string {
+      out.push(c);
+      if c == '\\' {
+        out.extend(chars.next());
+      } else if c == '"' {
+        in_string = false;
+      }
+      continue;
+    }
+    match (c, chars.peek()) {
+      ('"', _) => {
+        in_string = true;
+        out.push(c);
+      }
+      ('/', Some('/')) => {
+        while let Some(&c) = chars.peek() {
+          if c == '\n' {
+            break;
+          }
+          out.push(' ');
+          chars.next();
+        }
+        out.push(' ');
+      }
+      ('/', Some('*')) => {
+        out.push(' ');
+        let mut last = ' ';
+        for c in chars.by_ref() {
+          out.push(if c == '\n' { '\n' } else { ' ' });
+          if last == '*' && c == '/' {
+            break;
+          }
+          last = c;
+        }
+      }
+      _ => out.push(c),
+    }
+  }
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn kinds_of_values() {
+    use ValueKind::*;
+    let cases = [
+      ("&self.model.to_cols_array_2d()", Matrix),
+      ("light.pos.to_array()", Vector),
+      ("texture.sampled().wrap_function(w)", Sampler),
+      ("true", Bool),
+      ("self.t as f32", Float),
+      ("i as i32", Int),
+      ("n as u32", Uint),
+      ("[1.0, 0.0, 0.0]", Vector),
+      ("[[1.0, 0.0], [0.0, 1.0]]", Matrix),
+      ("1.0", Float),
+      ("0.5f32", Float),
+      ("1e-3", Float),
+      ("3u32", Uint),
+      ("1_000", Int),
+      ("-2", Int),
+      ("self.shininess", Unknown),
+    ];
+    for (expr, kind) in cases {
+      assert_eq!(ValueKind::of(expr), kind, "{}", expr);
+    }
+  }
+
+  #[test]
+  fn accepted_types() {
+    use ValueKind::*;
+    assert!(Float.accepts("float") && Float.accepts("double"));
+    assert!(!Float.accepts("int") && !Int.accepts("float"));
+    assert!(Uint.accepts("uint") && Bool.accepts("bool"));
+    assert!(Vector.accepts("vec3") && Vector.accepts("uvec2"));
+    assert!(!Vector.accepts("mat4") && Matrix.accepts("mat3x4"));
+    assert!(Sampler.accepts("sampler2DShadow") && !Sampler.accepts("vec4"));
+    // arrays are set element by element
+    assert!(!Matrix.accepts("mat4[4]") && !Float.accepts("float[2]"));
+    assert!(Unknown.accepts("mat4[4]") && Unknown.accepts("sampler2D"));
+  }
+
+  #[test]
+  fn scans_macros_and_dyn_uniforms() {
+    let source = r#"
+fn draw() {
+  let uniforms = uniform! {
+    model: self.model.to_cols_array_2d(),
+    // commented: 1.0,
+    /* also_commented: 1.0, */
+    t: self.t as f32,
+    tex: self.texture.sampled().wrap_function(f(a, b)),
+  };
+  let mut dyn_uniforms = DynUniforms::new();
+  dyn_uniforms.add("Kd", self.kd);
+  dyn_uniforms.add_raw("label, with comma", 1u32);
+  dyn_uniforms.add_override("shadow_bias", 0.005);
+  let name = "dynamic";
+  dyn_uniforms.add(name, 1.0);
+}
+"#;
+    let uniforms = scan(Path::new("mesh.rs"), source);
+    let found: Vec<(&str, ValueKind, usize)> = uniforms
+      .iter()
+      .map(|u| (u.name.as_str(), u.kind, u.line))
+      .collect();
+    use ValueKind::*;
+    assert_eq!(
+      found,
+      [
+        ("model", Matrix, 4),
+        ("t", Float, 7),
+        ("tex", Sampler, 8),
+        ("Kd", Unknown, 11),
+        ("label, with comma", Uint, 12),
+        ("shadow_bias", Float, 13),
+      ]
+    );
+    assert!(uniforms.iter().all(|u| !u.guarded));
+    assert_eq!(uniforms[0].file, Path::new("mesh.rs"));
+  }
+
+  #[test]
+  fn get_uniform_guards() {
+    let source = r#"
+if program.get_uniform("light_count").is_some() {
+  uniforms.add("light_count", count as i32);
+}
+uniforms.add("ambient", 0.1);
+// program.get_uniform("ambient")
+"#;
+    let uniforms = scan(Path::new("light.rs"), source);
+    let guarded: Vec<(&str, bool)> = uniforms
+      .iter()
+      .map(|u| (u.name.as_str(), u.guarded))
+      .collect();
+    assert_eq!(guarded, [("light_count", true), ("ambient", false)]);
+  }
+
+  #[test]
+  fn arguments() {
+    let args = split_args(r#""a(", f(b, [c, d]), "\"", ) tail"#);
+    let items: Vec<&str> = args.iter().map(|(item, _)| *item).collect();
+    assert_eq!(items, [r#""a(""#, "f(b, [c, d])", r#""\"""#]);
+    assert_eq!(args[1].1, 6);
+  }
+}
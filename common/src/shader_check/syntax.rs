@@ -0,0 +1,514 @@
+// Vertex and fragment shaders also go through naga, which parses and
+// type checks the whole source. naga only takes Vulkan flavoured GLSL
+// 4.40 and up, so the source is rewritten first, keeping the lines where
+// they were:
+// - the version becomes 450
+// - loose uniforms move into a block, as Vulkan has no loose uniforms
+// - combined samplers are split into a texture and a sampler, and every
+//   use becomes a `sampler2D(texture, sampler)` constructor
+// - inputs and outputs get locations, blocks get bindings
+// naga has no tessellation or geometry stages, those only get the
+// checks of the declarations. The uniform blocks naga lays out with
+// std140 are handed back, for comparing with the Rust structs.
+
+use std::collections::HashMap;
+
+use naga::{
+  front::glsl::{Frontend, Options},
+  valid::{Capabilities, ValidationFlags, Validator},
+  AddressSpace, Expression, Module, SampleLevel, ScalarKind, ShaderStage,
+  TypeInner,
+};
+
+use super::{
+  glsl::{self, Declaration, Statement, Token},
+  Stage,
+};
+
+// where the locations handed out start, above any set in the sources
+const FIRST_LOCATION: u32 = 16;
+
+// the block the loose uniforms are moved into
+const LOOSE_UNIFORMS: &str = "Uniforms_";
+
+// A uniform block with the offsets of its members, and its size
+// rounded up as std140 does.
+#[derive(Clone, Debug)]
+pub(super) struct BlockLayout {
+  pub name: String,
+  pub size: u32,
+  // name, offset and GLSL type
+  pub members: Vec<(String, u32, String)>,
+}
+
+// Errors as "line: message". Without `validate` only the parser runs,
+// for a vertex shader that doesn't write gl_Position as it's followed by
+// tessellation.
+pub(super) fn check(
+  stage: Stage,
+  source: &str,
+  validate: bool,
+) -> Result<Vec<BlockLayout>, Vec<String>> {
+  let stage = match stage {
+    Stage::Vertex => ShaderStage::Vertex,
+    Stage::Fragment => ShaderStage::Fragment,
+    _ => return Ok(Vec::new()),
+  };
+  let rewritten = rewrite(source).map_err(|e| vec![e])?;
+
+  let mut module = Frontend::default()
+    .parse(&Options::from(stage), &rewritten)
+    .map_err(|e| {
+      e.errors
+        .iter()
+        .map(|error| {
+          let line = error.meta.location(&rewritten).line_number;
+          format!("{}: {}", line, error.kind)
+        })
+        .collect::<Vec<_>>()
+    })?;
+
+  let blocks = block_layouts(&module);
+  if !validate {
+    return Ok(blocks);
+  }
+  if stage == ShaderStage::Vertex {
+    sample_base_level(&mut module);
+  }
+  Validator::new(ValidationFlags::all(), Capabilities::all())
+    .validate(&module)
+    .map(|_| blocks)
+    .map_err(|error| {
+      // the spans go from the function down to the statement
+      let line = error
+        .spans()
+        .last()
+        .map(|(span, _)| span.location(&rewritten).line_number);
+      // the innermost error says the most
+      let mut message = error.as_inner().to_string();
+      let mut source = std::error::Error::source(error.as_inner());
+      while let Some(inner) = source {
+        message = inner.to_string();
+        source = inner.source();
+      }
+      match line {
+        Some(line) => vec![format!("{}: {}", line, message)],
+        None => vec![message],
+      }
+    })
+}
+
+// GL samples the base level with texture() in a vertex shader, Vulkan
+// only allows an explicit level there. Making the level explicit keeps
+// naga from stopping at that and missing the errors after it.
+fn sample_base_level(module: &mut Module) {
+  let functions = module.functions.iter_mut().map(|(_, function)| function);
+  let entry_points = module.entry_points.iter_mut().map(|e| &mut e.function);
+  for function in functions.chain(entry_points) {
+    for (_, expression) in function.expressions.iter_mut() {
+      if let Expression::ImageSample { level, .. } = expression {
+        if matches!(level, SampleLevel::Auto) {
+          *level = SampleLevel::Zero;
+        }
+      }
+    }
+  }
+}
+
+// the blocks declared in the source, not the one rewrite makes of the
+// loose uniforms
+fn block_layouts(module: &Module) -> Vec<BlockLayout> {
+  let mut blocks = Vec::new();
+  for (_, variable) in module.global_variables.iter() {
+    if variable.space != AddressSpace::Uniform {
+      continue;
+    }
+    let ty = &module.types[variable.ty];
+    let (Some(name), TypeInner::Struct { members, span }) =
+      (&ty.name, &ty.inner)
+    else {
+      continue;
+    };
+    if name == LOOSE_UNIFORMS {
+      continue;
+    }
+    let members = members
+      .iter()
+      .map(|member| {
+        let name = member.name.clone().unwrap_or_default();
+        let ty = type_name(&module.types[member.ty].inner);
+        (name, member.offset, ty)
+      })
+      .collect();
+    blocks.push(BlockLayout {
+      name: name.clone(),
+      size: *span,
+      members,
+    });
+  }
+  blocks
+}
+
+// GLSL names for what blocks hold, anything else by naga's name
+fn type_name(inner: &TypeInner) -> String {
+  fn prefix(kind: ScalarKind) -> &'static str {
+    match kind {
+      ScalarKind::Sint => "i",
+      ScalarKind::Uint => "u",
+      ScalarKind::Bool => "b",
+      _ => "",
+    }
+  }
+  match inner {
+    TypeInner::Scalar(scalar) => match scalar.kind {
+      ScalarKind::Sint => "int".to_owned(),
+      ScalarKind::Uint => "uint".to_owned(),
+      ScalarKind::Bool => "bool".to_owned(),
+      _ => "float".to_owned(),
+    },
+    TypeInner::Vector { size, scalar } => {
+      format!("{}vec{}", prefix(scalar.kind), *size as u8)
+    }
+    TypeInner::Matrix { columns, rows, .. } if columns == rows => {
+      format!("mat{}", *columns as u8)
+    }
+    TypeInner::Matrix { columns, rows, .. } => {
+      format!("mat{}x{}", *columns as u8, *rows as u8)
+    }
+    other => format!("{:?}", other),
+  }
+}
+
+fn rewrite(source: &str) -> Result<String, String> {
+  let tokens = glsl::tokenize(source)?;
+  let statements = glsl::statements(&tokens)?;
+
+  // loose uniforms and samplers, up front as the block takes all the
+  // loose uniforms at the place of the first one
+  let mut loose = Vec::new();
+  let mut samplers = HashMap::new();
+  for statement in &statements {
+    let Statement::Declaration(range) = statement else {
+      continue;
+    };
+    let decl = glsl::declaration(&tokens, range.clone())?;
+    if decl.storage() != Some("uniform") {
+      continue;
+    }
+    for declarator in &decl.declarators {
+      if glsl::is_opaque(&decl.ty) {
+        samplers.insert(declarator.name.clone(), decl.ty.clone());
+      } else {
+        let dims = [declarator.dims.clone(), decl.ty_dims.clone()].concat();
+        loose.push(format!(
+          "{} {};",
+          decl.ty,
+          glsl::type_name(&declarator.name, &dims)
+        ));
+      }
+    }
+  }
+
+  let mut out = Output::default();
+  let mut binding = 1;
+  let mut in_location = FIRST_LOCATION;
+  let mut out_location = FIRST_LOCATION;
+  for statement in &statements {
+    match statement {
+      Statement::Directive(i) => {
+        let token = &tokens[*i];
+        if token
+          .text
+          .trim_start_matches('#')
+          .trim()
+          .starts_with("version")
+        {
+          out.push(token.line, "#version 450 core");
+        } else {
+          out.push(token.line, &token.text);
+        }
+      }
+      Statement::Declaration(range) => {
+        let decl = glsl::declaration(&tokens, range.clone())?;
+        let line = tokens[range.start].line;
+        match decl.storage() {
+          Some("uniform") if glsl::is_opaque(&decl.ty) => {
+            for declarator in &decl.declarators {
+              let (texture, sampler) = split_sampler(&decl.ty)?;
+              out.push(
+                line,
+                &format!(
+                  "layout(binding = {}) uniform {} {}; \
+                   layout(binding = {}) uniform {} {}_sampler_;",
+                  binding,
+                  texture,
+                  declarator.name,
+                  binding + 1,
+                  sampler,
+                  declarator.name
+                ),
+              );
+              binding += 2;
+            }
+          }
+          Some("uniform") if !decl.ty.is_empty() => {
+            if !loose.is_empty() {
+              let members = std::mem::take(&mut loose).join(" ");
+              out.push(
+                line,
+                &format!(
+                  "layout(std140, binding = 0) uniform {} {{ {} }};",
+                  LOOSE_UNIFORMS, members
+                ),
+              );
+            }
+          }
+          Some(storage @ ("in" | "out"))
+            if !decl.ty.is_empty() && !has_location(&tokens, &decl) =>
+          {
+            let location = if storage == "in" {
+              &mut in_location
+            } else {
+              &mut out_location
+            };
+            for declarator in &decl.declarators {
+              let dims =
+                [declarator.dims.clone(), decl.ty_dims.clone()].concat();
+              out.push(
+                line,
+                &format!(
+                  "layout(location = {}) {} {} {};",
+                  location,
+                  decl.qualifiers.join(" "),
+                  decl.ty,
+                  glsl::type_name(&declarator.name, &dims)
+                ),
+              );
+              *location += location_count(&decl.ty, &dims);
+            }
+          }
+          _ => {
+            out.tokens(&tokens[range.clone()], &HashMap::new());
+            out.push(line, ";");
+          }
+        }
+      }
+      Statement::Block { head, body, tail } => {
+        let decl = glsl::declaration(&tokens, head.clone())?;
+        let line = tokens[head.start].line;
+        if matches!(decl.storage(), Some("uniform" | "buffer")) {
+          // the qualifiers and the block name, with a binding added
+          let mut layout = vec![format!("binding = {}", binding)];
+          binding += 1;
+          if let Some(range) = &decl.layout {
+            let existing = join(&tokens[range.clone()]);
+            if existing.contains("binding") {
+              layout.clear();
+            }
+            layout.insert(0, existing);
+          } else {
+            layout.insert(0, "std140".to_owned());
+          }
+          out.push(
+            line,
+            &format!(
+              "layout({}) {} {}",
+              layout.join(", "),
+              decl.qualifiers.join(" "),
+              decl.ty
+            ),
+          );
+        } else {
+          out.tokens(&tokens[head.clone()], &HashMap::new());
+        }
+        out.push(line, "{");
+        out.tokens(&tokens[body.clone()], &HashMap::new());
+        out.push(tokens[body.end].line, "}");
+        out.tokens(&tokens[tail.clone()], &HashMap::new());
+        out.push(tokens[body.end].line, ";");
+      }
+      Statement::Function { signature, body } => {
+        out.tokens(&tokens[signature.clone()], &HashMap::new());
+        out.push(tokens[body.start - 1].line, "{");
+        out.tokens(&tokens[body.clone()], &samplers);
+        out.push(tokens[body.end].line, "}");
+      }
+    }
+  }
+  Ok(out.text)
+}
+
+// The source text with the line breaks where they were.
+#[derive(Default)]
+struct Output {
+  text: String,
+  line: usize,
+}
+
+impl Output {
+  fn push(&mut self, line: usize, text: &str) {
+    if self.line == 0 {
+      self.line = 1;
+    }
+    while self.line < line {
+      self.text.push('\n');
+      self.line += 1;
+    }
+    self.text.push_str(text);
+    self.text.push(' ');
+  }
+
+  // with each use of a sampler turned into a constructor
+  fn tokens(&mut self, tokens: &[Token], samplers: &HashMap<String, String>) {
+    for (i, token) in tokens.iter().enumerate() {
+      let member = i > 0 && tokens[i - 1].is(".");
+      match samplers.get(&token.text) {
+        Some(ty) if token.is_ident() && !member => self.push(
+          token.line,
+          &format!("{}({}, {}_sampler_)", ty, token.text, token.text),
+        ),
+        _ => self.push(token.line, &token.text),
+      }
+    }
+  }
+}
+
+fn join(tokens: &[Token]) -> String {
+  let texts: Vec<&str> = tokens.iter().map(|t| t.text.as_str()).collect();
+  texts.join(" ")
+}
+
+fn has_location(tokens: &[Token], decl: &Declaration) -> bool {
+  decl
+    .layout
+    .as_ref()
+    .is_some_and(|range| tokens[range.clone()].iter().any(|t| t.is("location")))
+}
+
+// matrices take a location per column, arrays one per element
+fn location_count(ty: &str, dims: &[String]) -> u32 {
+  let columns = match ty.trim_start_matches('d') {
+    t if t.starts_with("mat") => t[3..]
+      .chars()
+      .next()
+      .and_then(|c| c.to_digit(10))
+      .unwrap_or(4),
+    _ => 1,
+  };
+  let elements: u32 = dims.iter().map(|d| d.parse().unwrap_or(1)).product();
+  columns * elements
+}
+
+// sampler2DShadow into texture2D and samplerShadow, and so on
+fn split_sampler(ty: &str) -> Result<(String, &'static str), String> {
+  let (prefix, rest) = match ty.split_once("sampler") {
+    Some(split) => split,
+    None => return Err(format!("{} can't be rewritten for naga", ty)),
+  };
+  match rest.strip_suffix("Shadow") {
+    Some(dims) => Ok((format!("{}texture{}", prefix, dims), "samplerShadow")),
+    None => Ok((format!("{}texture{}", prefix, rest), "sampler")),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const VERTEX: &str = "\
+#version 330 core
+uniform mat4 mvp;
+uniform sampler2D height;
+layout(std140) uniform Light {
+  vec3 pos;
+  float power;
+  mat4 shadow;
+} light;
+in vec3 pos;
+out vec3 n;
+void main() {
+  float h = texture(height, pos.xz).r;
+  n = light.pos * light.power;
+  gl_Position = mvp * vec4(pos.x, h, pos.z, 1.0);
+}
+";
+
+  #[test]
+  fn rewrites_for_naga_on_the_same_lines() {
+    let rewritten = rewrite(VERTEX).unwrap();
+    let lines: Vec<&str> = rewritten.lines().collect();
+    assert_eq!(lines.len(), VERTEX.lines().count());
+    assert!(lines[0].starts_with("#version 450 core"));
+    assert!(lines[1].contains("uniform Uniforms_ { mat4 mvp; }"));
+    assert!(lines[2].contains("uniform texture2D height"));
+    assert!(lines[2].contains("uniform sampler height_sampler_"));
+    assert!(lines[3].contains("layout(std140, binding = 3)"));
+    assert!(lines[8].contains("layout(location = 16) in vec3 pos"));
+    assert!(lines[11].contains("sampler2D(height, height_sampler_)"));
+  }
+
+  #[test]
+  fn lays_out_blocks() {
+    let blocks = check(Stage::Vertex, VERTEX, true).unwrap();
+    let [light] = blocks.as_slice() else {
+      panic!("{:?}", blocks);
+    };
+    assert_eq!(light.name, "Light");
+    assert_eq!(light.size, 80);
+    let members: Vec<(&str, u32, &str)> = light
+      .members
+      .iter()
+      .map(|(name, offset, ty)| (name.as_str(), *offset, ty.as_str()))
+      .collect();
+    assert_eq!(
+      members,
+      [
+        ("pos", 0, "vec3"),
+        ("power", 12, "float"),
+        ("shadow", 16, "mat4")
+      ]
+    );
+  }
+
+  #[test]
+  fn errors_on_their_line() {
+    let source = "#version 330\nout vec4 color;\nvoid main() {\n  \
+                  color = vec3(1.0);\n}\n";
+    let errors = check(Stage::Fragment, source, true).unwrap_err();
+    assert!(errors.iter().all(|e| e.starts_with("4: ")), "{:?}", errors);
+
+    let source = "#version 330\nvoid main() {\n  float a = b;\n}\n";
+    let errors = check(Stage::Fragment, source, true).unwrap_err();
+    assert!(errors[0].starts_with("3: "), "{:?}", errors);
+  }
+
+  #[test]
+  fn texture_in_vertex_shader() {
+    // sampling without a level is fine, not writing gl_Position isn't
+    let source = "#version 330\nuniform sampler2D height;\nin vec2 uv;\n\
+                  out float h;\nvoid main() {\n  \
+                  h = texture(height, uv).r;\n}\n";
+    let errors = check(Stage::Vertex, source, true).unwrap_err();
+    assert!(errors[0].contains("position"), "{:?}", errors);
+
+    let fixed = source.replace("r;", "r;\n  gl_Position = vec4(uv, h, 1.0);");
+    assert!(check(Stage::Vertex, &fixed, true).is_ok());
+  }
+
+  #[test]
+  fn vertex_before_tessellation() {
+    // gl_Position is left to the tessellation, only parsed
+    let source = "#version 410\nin vec3 pos;\nout vec3 v_pos;\n\
+                  void main() { v_pos = pos; }\n";
+    assert!(check(Stage::Vertex, source, false).is_ok());
+  }
+
+  #[test]
+  fn other_stages_pass_through() {
+    // not even parsed, Shader::parse checks their declarations
+    let source = "#version 410\nlayout(vertices = 3) out;\nnot glsl";
+    for stage in [Stage::TessControl, Stage::TessEvaluation, Stage::Geometry] {
+      assert!(check(stage, source, true).unwrap().is_empty());
+    }
+  }
+}
@@ -0,0 +1,77 @@
+use glam::{Mat3, Mat4, Quat, Vec3};
+use glium::uniforms::{AsUniformValue, UniformValue};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+  pub translation: Vec3,
+  pub rotation: Quat,
+  // applied after the rotation, along the parent's axes
+  pub scale: Vec3,
+}
+
+impl Transform {
+  pub fn from_translation(translation: Vec3) -> Self {
+    Self {
+      translation,
+      ..Self::default()
+    }
+  }
+
+  // The closest transform to an affine matrix. Exact for anything
+  // built from transforms with uniform scale; a non-uniform scale above
+  // a rotation shears, which can't be represented and gets dropped.
+  pub fn from_mat4(m: Mat4) -> Self {
+    let m3 = Mat3::from_mat4(m).transpose();
+    let mut scale =
+      Vec3::new(m3.x_axis.length(), m3.y_axis.length(), m3.z_axis.length());
+    if m3.determinant() < 0.0 {
+      scale.x = -scale.x;
+    }
+
+    let rows = Mat3::from_cols(
+      m3.x_axis / scale.x,
+      m3.y_axis / scale.y,
+      m3.z_axis / scale.z,
+    );
+    Self {
+      translation: m.w_axis.truncate(),
+      rotation: Quat::from_mat3(&rows.transpose()).normalize(),
+      scale,
+    }
+  }
+
+  pub fn map_point(&self, p: Vec3) -> Vec3 {
+    self.scale * (self.rotation * p) + self.translation
+  }
+
+  pub fn map_vec(&self, v: Vec3) -> Vec3 {
+    self.scale * (self.rotation * v)
+  }
+
+  pub fn to_mat3(&self) -> Mat3 {
+    let scale = Mat3::from_diagonal(self.scale);
+    scale * Mat3::from_quat(self.rotation)
+  }
+
+  pub fn to_mat4(&self) -> Mat4 {
+    let mut mat: Mat4 = Mat4::from_mat3(self.to_mat3());
+    mat.w_axis = self.translation.extend(1.0);
+    mat
+  }
+}
+
+impl Default for Transform {
+  fn default() -> Self {
+    Self {
+      translation: Vec3::ZERO,
+      rotation: Quat::IDENTITY,
+      scale: Vec3::ONE,
+    }
+  }
+}
+
+impl AsUniformValue for Transform {
+  fn as_uniform_value(&self) -> UniformValue<'_> {
+    UniformValue::Mat4(self.to_mat4().to_cols_array_2d())
+  }
+}
@@ -0,0 +1,288 @@
+// Uniforms that stay the same over many draws, kept in buffers and set
+// as std140 blocks, see assets/shaders/camera.glsl, light.glsl and
+// material.glsl. A block is written once per frame or per material,
+// instead of being set uniform by uniform for every object and group.
+//
+// The structs are #[repr(C)] and ordered so that their fields already
+// sit at the std140 offsets without padding: a vec3 is followed by a
+// scalar that fills its last 4 bytes, and every block is a multiple of
+// 16 bytes. The members are named as in GLSL, where the blocks have no
+// instance name, and glium checks them against the program when
+// drawing. The shaders tests compare them with the GLSL declarations,
+// see ShaderCheck::uniform_block.
+#![allow(non_snake_case)]
+
+use std::mem::offset_of;
+
+use glium::{
+  backend::Facade,
+  program::{self, BlockLayout},
+  uniforms::{
+    LayoutMismatchError, UniformBlock, UniformBuffer, UniformValue, Uniforms,
+  },
+};
+
+use crate::CameraLike;
+
+// a struct for a GLSL uniform block, NAME as the block is declared
+pub trait Std140: UniformBlock + Copy {
+  const NAME: &'static str;
+}
+
+// What glium's implement_uniform_block! does, but with offset_of!: the
+// macro takes the offsets from a null pointer, which debug builds of
+// recent Rust abort on.
+macro_rules! implement_uniform_block {
+  ($block:ident, $($field:ident),+ $(,)?) => {
+    impl UniformBlock for $block {
+      fn matches(
+        layout: &BlockLayout,
+        base_offset: usize,
+      ) -> Result<(), LayoutMismatchError> {
+        let BlockLayout::Struct { members } = layout else {
+          return Err(LayoutMismatchError::LayoutMismatch {
+            expected: layout.clone(),
+            obtained: Self::build_layout(base_offset),
+          });
+        };
+        for (name, _) in members {
+          if $(name != stringify!($field) &&)+ true {
+            return Err(LayoutMismatchError::MissingField {
+              name: name.clone(),
+            });
+          }
+        }
+        $(
+          let member = members
+            .iter()
+            .find(|(name, _)| name == stringify!($field))
+            .ok_or_else(|| LayoutMismatchError::MissingField {
+              name: stringify!($field).to_owned(),
+            })?;
+          let field = None::<&$block>.map(|block| &block.$field);
+          let offset = base_offset + offset_of!($block, $field);
+          field_matches(field, &member.1, offset).map_err(|e| {
+            LayoutMismatchError::MemberMismatch {
+              member: stringify!($field).to_owned(),
+              err: Box::new(e),
+            }
+          })?;
+        )+
+        Ok(())
+      }
+
+      fn build_layout(base_offset: usize) -> BlockLayout {
+        BlockLayout::Struct {
+          members: vec![$((
+            stringify!($field).to_owned(),
+            field_layout(
+              None::<&$block>.map(|block| &block.$field),
+              base_offset + offset_of!($block, $field),
+            ),
+          )),+],
+        }
+      }
+    }
+  };
+}
+
+// the field's type from an Option that is always None
+fn field_matches<T: UniformBlock>(
+  _: Option<&T>,
+  layout: &BlockLayout,
+  offset: usize,
+) -> Result<(), LayoutMismatchError> {
+  T::matches(layout, offset)
+}
+
+fn field_layout<T: UniformBlock>(_: Option<&T>, offset: usize) -> BlockLayout {
+  T::build_layout(offset)
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CameraBlock {
+  pub view: [[f32; 4]; 4],
+  pub projection: [[f32; 4]; 4],
+  pub view_projection: [[f32; 4]; 4],
+}
+
+implement_uniform_block!(CameraBlock, view, projection, view_projection);
+
+impl Std140 for CameraBlock {
+  const NAME: &'static str = "Camera";
+}
+
+impl CameraBlock {
+  pub fn new(camera: &impl CameraLike) -> Self {
+    Self {
+      view: camera.view(),
+      projection: camera.projection(),
+      view_projection: camera.view_projection_matrix().to_cols_array_2d(),
+    }
+  }
+}
+
+// light_type is 0 for a directional light, where light_dir_or_loc is
+// the direction towards it, and 1 for a spot light at light_dir_or_loc
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LightBlock {
+  pub light_dir_or_loc: [f32; 3],
+  pub light_type: i32,
+  pub light_color: [f32; 3],
+  pub light_cone_angle: f32,
+}
+
+implement_uniform_block!(
+  LightBlock,
+  light_dir_or_loc,
+  light_type,
+  light_color,
+  light_cone_angle
+);
+
+impl Std140 for LightBlock {
+  const NAME: &'static str = "Light";
+}
+
+// from world space to the texture space of the shadow map
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ShadowBlock {
+  pub shadow_transform: [[f32; 4]; 4],
+}
+
+implement_uniform_block!(ShadowBlock, shadow_transform);
+
+impl Std140 for ShadowBlock {
+  const NAME: &'static str = "Shadow";
+}
+
+// the .mtl values, the textures stay samplers of their own
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MaterialBlock {
+  pub Kd: [f32; 3],
+  pub Ns: f32,
+  pub Ks: [f32; 3],
+  pub use_map_Kd: u32,
+  pub Ka: [f32; 3],
+  pub use_map_Ks: u32,
+  pub use_map_Ka: u32,
+  pub use_map_bump: u32,
+  pub d: f32,
+  pub Ni: f32,
+}
+
+implement_uniform_block!(
+  MaterialBlock,
+  Kd,
+  Ns,
+  Ks,
+  use_map_Kd,
+  Ka,
+  use_map_Ks,
+  use_map_Ka,
+  use_map_bump,
+  d,
+  Ni
+);
+
+impl Std140 for MaterialBlock {
+  const NAME: &'static str = "Material";
+}
+
+// A block in a buffer of its own. It is a Uniforms with the one block,
+// to be merged with the rest.
+pub struct BlockBuffer<T: Std140> {
+  buffer: UniformBuffer<T>,
+}
+
+impl<T: Std140> BlockBuffer<T> {
+  pub fn new(facade: &impl Facade, data: T) -> anyhow::Result<Self> {
+    let buffer = UniformBuffer::dynamic(facade, data)?;
+    Ok(Self { buffer })
+  }
+
+  pub fn write(&self, data: &T) {
+    self.buffer.write(data);
+  }
+}
+
+impl<T: Std140> Uniforms for BlockBuffer<T> {
+  fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
+    f(
+      T::NAME,
+      UniformValue::Block(self.buffer.as_slice_any(), matches::<T>),
+    );
+  }
+}
+
+// to share one buffer between draws that take their uniforms by value
+impl<T: Std140> Uniforms for &BlockBuffer<T> {
+  fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, f: F) {
+    (**self).visit_values(f)
+  }
+}
+
+fn matches<T: Std140>(
+  block: &program::UniformBlock,
+) -> Result<(), LayoutMismatchError> {
+  T::matches(&block.layout, 0)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::mem::{offset_of, size_of};
+
+  use super::*;
+
+  // the offsets std140 gives the GLSL declarations
+  #[test]
+  fn camera_layout() {
+    assert_eq!(size_of::<CameraBlock>(), 192);
+    assert_eq!(offset_of!(CameraBlock, view), 0);
+    assert_eq!(offset_of!(CameraBlock, projection), 64);
+    assert_eq!(offset_of!(CameraBlock, view_projection), 128);
+  }
+
+  #[test]
+  fn light_layout() {
+    assert_eq!(size_of::<LightBlock>(), 32);
+    assert_eq!(offset_of!(LightBlock, light_dir_or_loc), 0);
+    assert_eq!(offset_of!(LightBlock, light_type), 12);
+    assert_eq!(offset_of!(LightBlock, light_color), 16);
+    assert_eq!(offset_of!(LightBlock, light_cone_angle), 28);
+  }
+
+  #[test]
+  fn shadow_layout() {
+    assert_eq!(size_of::<ShadowBlock>(), 64);
+    assert_eq!(offset_of!(ShadowBlock, shadow_transform), 0);
+  }
+
+  #[test]
+  fn material_layout() {
+    assert_eq!(size_of::<MaterialBlock>(), 64);
+    assert_eq!(offset_of!(MaterialBlock, Kd), 0);
+    assert_eq!(offset_of!(MaterialBlock, Ns), 12);
+    assert_eq!(offset_of!(MaterialBlock, Ks), 16);
+    assert_eq!(offset_of!(MaterialBlock, use_map_Kd), 28);
+    assert_eq!(offset_of!(MaterialBlock, Ka), 32);
+    assert_eq!(offset_of!(MaterialBlock, use_map_Ks), 44);
+    assert_eq!(offset_of!(MaterialBlock, use_map_Ka), 48);
+    assert_eq!(offset_of!(MaterialBlock, use_map_bump), 52);
+    assert_eq!(offset_of!(MaterialBlock, d), 56);
+    assert_eq!(offset_of!(MaterialBlock, Ni), 60);
+  }
+
+  // the layout glium builds from the struct is the one it checks against
+  #[test]
+  fn layouts_match_themselves() {
+    CameraBlock::matches(&CameraBlock::build_layout(0), 0).unwrap();
+    LightBlock::matches(&LightBlock::build_layout(0), 0).unwrap();
+    ShadowBlock::matches(&ShadowBlock::build_layout(0), 0).unwrap();
+    MaterialBlock::matches(&MaterialBlock::build_layout(0), 0).unwrap();
+  }
+}
@@ -0,0 +1,19 @@
+use std::time::Duration;
+
+use common::{app::App, golden::Golden};
+
+use crate::World;
+
+const GOLDEN_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/golden");
+
+#[test]
+fn background() -> anyhow::Result<()> {
+  let golden = Golden::new(GOLDEN_DIR)?;
+  let mut world = World::new();
+  world.update(Duration::ZERO);
+  golden.check("background_0", |frame| world.render(frame))?;
+
+  // the colour runs at half speed, two seconds in it's at 1
+  world.update(Duration::from_secs(2));
+  golden.check("background_1", |frame| world.render(frame))
+}
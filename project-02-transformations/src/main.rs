@@ -1,42 +1,33 @@
+#[cfg(test)]
+mod tests;
+
 use std::{mem::size_of, path::Path, time::Duration};
 
-use glam::{EulerRot, Mat3, Mat4, Vec3};
+use glam::{EulerRot, Mat4, Vec2, Vec3};
 use glium::{
   backend::Facade, glutin::surface::WindowSurface, program::SourceCode,
-  uniform, Display, DrawParameters, Frame, Program, Surface, VertexBuffer,
+  uniform, Display, DrawParameters, Program, Surface, VertexBuffer,
 };
 
-use common::{gl_boilerplate::init_display, Axis, SimpleObj};
-use winit::{
-  application::ApplicationHandler,
-  dpi::{LogicalSize, PhysicalPosition, PhysicalSize},
-  event::{self, KeyEvent, WindowEvent},
-  event_loop::{ActiveEventLoop, ControlFlow, EventLoop},
-  keyboard::NamedKey,
-  window::{Window, WindowAttributes, WindowId},
+use common::{
+  app::{App, Input},
+  camera::{Camera, CameraController, OrbitController},
+  math::{Aabb, Ray},
+  mesh::Bvh,
+  Axis, CameraLike, SimpleObj,
 };
+use winit::event::{KeyEvent, MouseButton};
 
-type Error = Box<dyn std::error::Error>;
-type Result<T> = std::result::Result<T, Error>;
+type Result<T> = anyhow::Result<T>;
 
 const SHADER_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/shader");
 
-const TARGET_UPS: u32 = 60;
-const TARGET_FRAME_TIME: Duration =
-  Duration::from_micros(1_000_000 / TARGET_UPS as u64);
-
 struct World {
   t: f32,
   clear_color: [f32; 4],
-  // camera
-  aspect_ratio: f32,
-  camera_distance: f32,
-  camera_rotation: [f32; 2],
-  perspective: bool,
-  // world space to camera space
-  m_view: Mat4,
-  // view space to clip space
-  m_proj: Mat4,
+  camera: Camera,
+  orbit: OrbitController,
+  viewport: Vec2,
   axis: Option<Axis>,
   show_axis: bool,
   teapot: Option<Teapot>,
@@ -48,6 +39,9 @@ struct Teapot {
   model_vbo: VertexBuffer<[f32; 3]>,
   program: Program,
   center: Vec3,
+  // model space, for picking
+  bvh: Bvh,
+  model: Mat4,
   mvp: Mat4,
 }
 
@@ -55,19 +49,32 @@ impl World {
   fn new() -> Self {
     Self {
       t: 0.0,
-      aspect_ratio: 1.0,
-      camera_distance: 2.0,
-      camera_rotation: [0.0, 0.0],
-      perspective: true,
       clear_color: [0.0, 0.0, 0.0, 1.0],
-      m_view: Mat4::IDENTITY,
-      m_proj: Mat4::IDENTITY,
+      camera: Camera::default(),
+      // eye at (0, 0, 2), looking at the origin
+      orbit: OrbitController::new(Vec3::ZERO, 2.0)
+        .with_distance_range(0.1, 10.0),
+      viewport: Vec2::ONE,
       axis: None,
       show_axis: true,
       teapot: None,
     }
   }
 
+  // any facade does, the tests render without a window
+  fn load(&mut self, facade: &impl Facade) -> Result<()> {
+    let teapot = Teapot::load_file(
+      facade,
+      &common::teapot_path(),
+      Path::new(SHADER_PATH),
+    )?;
+    let axis = Axis::new(facade)?;
+    self.set_teapot(teapot);
+    self.set_axis(axis);
+    self.update(std::time::Duration::from_secs(0));
+    Ok(())
+  }
+
   fn set_teapot(&mut self, teapot: Teapot) {
     self.teapot = Some(teapot);
   }
@@ -77,66 +84,37 @@ impl World {
   }
 
   fn update_view(&mut self) {
-    // default view matrix: eye at (0, 0, 2), looking at (0, 0, -1), up (0, 1, 0)
-    let dir = Mat3::from_euler(
-      EulerRot::YXZ,
-      self.camera_rotation[0].to_radians(),
-      self.camera_rotation[1].to_radians(),
-      0.0,
-    ) * Vec3::new(0.0, 0.0, -1.0);
-    let eye = Vec3::new(0.0, 0.0, 0.0) + -dir * self.camera_distance;
-    let up = Vec3::new(0.0, 1.0, 0.0);
-    let origin = Vec3::new(0.0, 0.0, 0.0);
-    self.m_view = Mat4::look_at_rh(eye, origin, up);
+    self.orbit.apply(&mut self.camera);
   }
 
-  fn update(&mut self, dt: Duration) {
-    // self.update_bg_color(dt);
-    self.rotate_teapot(dt);
-  }
+  fn handle_key(&mut self, event: KeyEvent) {
+    if !event.state.is_pressed() {
+      return;
+    }
 
-  fn update_projection(&mut self) {
-    // fov, aspect ratio, near, far
-    self.m_proj = if self.perspective {
-      Mat4::perspective_rh_gl(
-        60.0_f32.to_radians(),
-        self.aspect_ratio,
-        0.1,
-        100.0,
-      )
-    } else {
-      Mat4::orthographic_rh_gl(
-        -1.0,
-        1.0,
-        -1.0 / self.aspect_ratio,
-        1.0 / self.aspect_ratio,
-        0.1,
-        100.0,
-      ) * (1.0 / self.camera_distance)
-    };
+    if event.logical_key == "p" {
+      self.camera.toggle_projection();
+    } else if event.logical_key == "a" {
+      self.show_axis = !self.show_axis;
+    } else if event.logical_key == "f" {
+      self.frame_teapot();
+    }
   }
 
-  fn render(&self, context: &Display<WindowSurface>) -> Result<()> {
-    let mut frame = context.draw();
-    frame.clear_color_and_depth(self.clear_color.into(), 1.0);
-
-    if let Some(teapot) = self.teapot.as_ref() {
-      if let Err(e) = teapot.draw(&mut frame) {
-        eprintln!("Failed to draw teapot: {}", e);
-      }
+  // the teapot is all there is to select
+  fn pick(&mut self, pixel: Vec2) {
+    let ray = self.camera.pixel_ray(pixel, self.viewport);
+    match self.teapot.as_ref().and_then(|teapot| teapot.hit(&ray)) {
+      Some(_) => println!("Selected the teapot"),
+      None => println!("Selection cleared"),
     }
+  }
 
-    if let Some(axis) = self.axis.as_ref() {
-      if self.show_axis {
-        let m_view_proj = self.m_proj * self.m_view;
-        if let Err(e) = axis.draw(&mut frame, &m_view_proj) {
-          eprintln!("Failed to draw axis: {}", e);
-        }
-      }
+  fn frame_teapot(&mut self) {
+    if let Some(teapot) = &self.teapot {
+      self.orbit.frame(&self.camera, &teapot.bounding_box());
+      self.update_view();
     }
-
-    frame.finish()?;
-    Ok(())
   }
 
   #[allow(unused)]
@@ -165,7 +143,98 @@ impl World {
         0.0,
       )
       * Mat4::from_translation(-teapot.center);
-    teapot.mvp = self.m_proj * self.m_view * m_model;
+    teapot.model = m_model;
+    // near and far planes as tight as the turning teapot allows
+    self.camera.fit_near_far(&teapot.bounding_box());
+    teapot.mvp = self.camera.view_projection() * m_model;
+  }
+}
+
+impl App for World {
+  fn init(&mut self, display: &Display<WindowSurface>) -> Result<()> {
+    self.load(display)
+  }
+
+  fn update(&mut self, dt: Duration) {
+    // self.update_bg_color(dt);
+    self.rotate_teapot(dt);
+  }
+
+  fn render(&self, frame: &mut impl Surface) -> Result<()> {
+    let clear_depth = self.camera.depth_mode().clear_depth();
+    frame.clear_color_and_depth(self.clear_color.into(), clear_depth);
+
+    if let Some(teapot) = self.teapot.as_ref() {
+      if let Err(e) = teapot.draw(frame) {
+        eprintln!("Failed to draw teapot: {}", e);
+      }
+    }
+
+    if let Some(axis) = self.axis.as_ref() {
+      if self.show_axis {
+        if let Err(e) = axis.draw(frame, &self.camera.view_projection()) {
+          eprintln!("Failed to draw axis: {}", e);
+        }
+      }
+    }
+
+    Ok(())
+  }
+
+  fn on_input(&mut self, input: Input) {
+    match input {
+      Input::Key(event) => self.handle_key(event),
+      Input::Click {
+        button: MouseButton::Left,
+        pos,
+        ..
+      } => self.pick(pos),
+      // left drag: rotate camera
+      Input::Drag {
+        button: MouseButton::Left,
+        from,
+        to,
+        ..
+      } => {
+        self.orbit.drag(from, to, self.viewport);
+        self.update_view();
+      }
+      // right drag: change camera distance, dragging down moves away
+      Input::Drag {
+        button: MouseButton::Right,
+        from,
+        to,
+        ..
+      } => {
+        self.orbit.zoom(-(to.y - from.y) / 20.0);
+        self.update_view();
+      }
+      Input::Scroll { dy, .. } => {
+        // 30 per notch
+        self.orbit.zoom(dy / 30.0);
+        self.update_view();
+      }
+      _ => {}
+    }
+  }
+
+  fn resize(&mut self, width: u32, height: u32) {
+    println!("Resized to {}x{}", width, height);
+    self.viewport = Vec2::new(width as f32, height as f32);
+    self.camera.handle_resize(width as f32, height as f32);
+    self.update_view();
+  }
+
+  fn reload_shaders(&mut self, display: &Display<WindowSurface>) -> Result<()> {
+    match self.teapot.as_mut() {
+      Some(teapot) => teapot.recompile_shader(display),
+      None => Ok(()),
+    }
+  }
+
+  fn help(&self) -> &str {
+    "Press 'p' to toggle perspective, 'a' to toggle axis, click to select \
+      the teapot, 'f' to frame it, Esc to quit"
   }
 }
 
@@ -220,6 +289,7 @@ impl Teapot {
     };
     let mvp = Mat4::IDENTITY;
     let center = Vec3::from(model.center());
+    let bvh = Bvh::from_simple_obj(model);
 
     Ok(Self {
       rotation: 0.0,
@@ -228,10 +298,22 @@ impl Teapot {
       mvp,
       program,
       center,
+      bvh,
+      model: Mat4::IDENTITY,
     })
   }
 
-  fn draw(&self, frame: &mut Frame) -> Result<()> {
+  // world space distance along the ray
+  fn hit(&self, ray: &Ray) -> Option<f32> {
+    let hit = self.bvh.closest_hit_placed(ray, self.model, f32::MAX)?;
+    Some(hit.t)
+  }
+
+  fn bounding_box(&self) -> Aabb {
+    self.bvh.aabb().transform(self.model)
+  }
+
+  fn draw(&self, frame: &mut impl Surface) -> Result<()> {
     let mvp: [[f32; 4]; 4] = self.mvp.to_cols_array_2d();
     let uniforms = uniform! {
       mvp: mvp,
@@ -255,19 +337,6 @@ impl Teapot {
   }
 }
 
-struct App {
-  window: Option<Window>,
-  display: Option<Display<WindowSurface>>,
-  last_update: std::time::Instant,
-
-  // left, right
-  mouse_down: (bool, bool),
-  last_pos: [f32; 2],
-  mouse_pos: [f32; 2],
-
-  world: World,
-}
-
 #[allow(non_upper_case_globals)]
 const VF_F32x3: glium::vertex::VertexFormat = &[(
   // attribute name
@@ -282,239 +351,6 @@ const VF_F32x3: glium::vertex::VertexFormat = &[(
   false,
 )];
 
-impl App {
-  fn new() -> Self {
-    let last_update = std::time::Instant::now();
-
-    let world = World::new();
-
-    Self {
-      window: None,
-      display: None,
-      last_update,
-      last_pos: [0.0, 0.0],
-      mouse_pos: [0.0, 0.0],
-      mouse_down: (false, false),
-      world,
-    }
-  }
-
-  fn handle_resize(&mut self, size: PhysicalSize<u32>) {
-    println!("Resized to {:?}", size);
-    self.world.aspect_ratio = size.width as f32 / size.height as f32;
-    self.world.update_projection();
-    self.world.update_view();
-
-    self.request_redraw();
-  }
-
-  fn request_redraw(&self) {
-    if let Some(window) = self.window.as_ref() {
-      window.request_redraw();
-    }
-  }
-
-  fn handle_keyboard(&mut self, event: KeyEvent, event_loop: &ActiveEventLoop) {
-    if !event.state.is_pressed() {
-      return;
-    }
-    if event.logical_key == NamedKey::Escape {
-      event_loop.exit();
-    } else if event.logical_key.to_text() == Some("p") {
-      self.world.perspective = !self.world.perspective;
-      self.world.update_projection();
-      self.request_redraw();
-    } else if event.logical_key.to_text() == Some("a") {
-      self.world.show_axis = !self.world.show_axis;
-      self.request_redraw();
-    } else if event.logical_key == NamedKey::F6 {
-      self.recompile_shader();
-    }
-  }
-
-  fn handle_redraw(&mut self) {
-    if let Some(display) = &self.display {
-      self.world.render(display).expect("Failed to render");
-    }
-  }
-
-  fn recompile_shader(&mut self) {
-    if let Some(display) = &self.display {
-      if let Some(teapot) = self.world.teapot.as_mut() {
-        if let Err(e) = teapot.recompile_shader(display) {
-          eprintln!("Failed to recompile shader: {}", e);
-        } else {
-          println!("Recompiled shader");
-          self.request_redraw();
-        }
-      }
-    }
-  }
-
-  fn update(&mut self) {
-    self.world.update(self.last_update.elapsed());
-
-    let help =
-      "Press 'p' to toggle perspective, 'a' to toggle axis, Esc to quit";
-
-    let title = format!(
-      "Teapot - {:.0} UPS",
-      1_000_000_000 / self.last_update.elapsed().as_nanos()
-    );
-
-    if let Some(window) = self.window.as_ref() {
-      window.set_title(&format!("{} - {}", title, help));
-      window.request_redraw();
-    }
-
-    self.last_update = std::time::Instant::now();
-  }
-
-  fn handle_mouse_input(
-    &mut self,
-    state: event::ElementState,
-    button: event::MouseButton,
-  ) {
-    let pressed = state.is_pressed();
-
-    match button {
-      event::MouseButton::Left => {
-        self.mouse_down.0 = pressed;
-      }
-      event::MouseButton::Right => {
-        self.mouse_down.1 = pressed;
-      }
-      _ => {}
-    }
-  }
-
-  fn handle_cursor_moved(&mut self, position: PhysicalPosition<f64>) {
-    self.mouse_pos = [position.x as f32, position.y as f32];
-
-    // left drag: rotate camera
-    if self.mouse_down.0 {
-      let dx = self.mouse_pos[0] - self.last_pos[0];
-      let dy = self.mouse_pos[1] - self.last_pos[1];
-      self.world.camera_rotation[0] += dx * 0.1;
-      self.world.camera_rotation[1] += dy * 0.1;
-      self.world.update_view();
-    }
-
-    // right drag: change camera distance
-    if self.mouse_down.1 {
-      let dy = self.mouse_pos[1] - self.last_pos[1];
-
-      self.world.camera_distance += dy * 0.01;
-      self.world.camera_distance = self.world.camera_distance.clamp(0.1, 10.0);
-      self.world.update_projection();
-      self.world.update_view();
-    }
-
-    self.last_pos = self.mouse_pos;
-    self.request_redraw();
-  }
-
-  fn handle_mouse_wheel(&mut self, delta: event::MouseScrollDelta) {
-    let d = match delta {
-      event::MouseScrollDelta::LineDelta(_x, y) => y * 20.0,
-      event::MouseScrollDelta::PixelDelta(pos) => pos.y as f32,
-    };
-
-    self.world.camera_distance -= d * 0.01;
-    self.world.camera_distance = self.world.camera_distance.clamp(0.1, 10.0);
-    self.world.update_projection();
-    self.world.update_view();
-  }
-
-  fn schedule_next_frame(&self, event_loop: &ActiveEventLoop) {
-    let wake_up_at = self.last_update + TARGET_FRAME_TIME;
-    event_loop.set_control_flow(ControlFlow::WaitUntil(wake_up_at));
-  }
-
-  fn handle_init(&mut self, display: &Display<WindowSurface>) -> Result<()> {
-    let teapot = Teapot::load_file(
-      display,
-      &common::teapot_path(),
-      Path::new(SHADER_PATH),
-    )?;
-    let axis = Axis::new(display)?;
-    self.world.set_teapot(teapot);
-    self.world.set_axis(axis);
-    self.world.update(std::time::Duration::from_secs(0));
-    Ok(())
-  }
-}
-
-impl ApplicationHandler for App {
-  fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-    if self.window.is_none() {
-      let window_attrs = WindowAttributes::default()
-        .with_title("Teapot")
-        .with_inner_size(LogicalSize::new(800, 600));
-
-      match event_loop.create_window(window_attrs) {
-        Ok(window) => {
-          let display = init_display(&window);
-          self.handle_init(&display).expect("Failed to init world");
-
-          self.window = Some(window);
-          self.display = Some(display);
-        }
-        Err(e) => {
-          eprintln!("Failed to create window: {}", e);
-          event_loop.exit();
-        }
-      }
-    }
-  }
-
-  fn window_event(
-    &mut self,
-    event_loop: &ActiveEventLoop,
-    _window_id: WindowId,
-    event: WindowEvent,
-  ) {
-    match event {
-      WindowEvent::Resized(size) => self.handle_resize(size),
-      WindowEvent::KeyboardInput { event, .. } => {
-        self.handle_keyboard(event, event_loop)
-      }
-      WindowEvent::RedrawRequested => {
-        self.handle_redraw();
-      }
-      WindowEvent::CloseRequested => {
-        event_loop.exit();
-      }
-      WindowEvent::MouseInput { state, button, .. } => {
-        self.handle_mouse_input(state, button);
-      }
-      WindowEvent::MouseWheel { delta, .. } => {
-        self.handle_mouse_wheel(delta);
-      }
-      WindowEvent::CursorMoved { position, .. } => {
-        self.handle_cursor_moved(position);
-      }
-      _ => {}
-    }
-  }
-
-  fn new_events(
-    &mut self,
-    event_loop: &ActiveEventLoop,
-    _cause: event::StartCause,
-  ) {
-    self.schedule_next_frame(event_loop);
-
-    if self.last_update.elapsed() > TARGET_FRAME_TIME {
-      self.update();
-    }
-  }
-}
-
 fn main() -> Result<()> {
-  let event_loop = EventLoop::new().expect("Failed to create event loop");
-  let mut app = App::new();
-
-  event_loop.run_app(&mut app)?;
-  Ok(())
+  common::app::run(World::new())
 }
@@ -0,0 +1,46 @@
+use std::time::Duration;
+
+use common::{
+  app::App,
+  golden::{Golden, HEIGHT, WIDTH},
+  shader_check::ShaderCheck,
+};
+
+use crate::World;
+
+const GOLDEN_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/golden");
+
+fn world(golden: &Golden) -> anyhow::Result<World> {
+  let mut world = World::new();
+  world.camera.handle_resize(WIDTH as f32, HEIGHT as f32);
+  world.update_view();
+  world.load(golden.facade())?;
+  Ok(world)
+}
+
+fn check(golden: &Golden, name: &str, world: &World) -> anyhow::Result<()> {
+  golden.check(name, |frame| world.render(frame))
+}
+
+#[test]
+fn teapot_points() -> anyhow::Result<()> {
+  let golden = Golden::new(GOLDEN_DIR)?;
+  let mut world = world(&golden)?;
+  check(&golden, "teapot", &world)?;
+
+  world.update(Duration::from_millis(500));
+  check(&golden, "teapot_rotated", &world)?;
+
+  world.camera.toggle_projection();
+  world.update(Duration::ZERO);
+  check(&golden, "teapot_orthographic", &world)
+}
+
+// every shader in assets, grouped into the programs the code builds
+#[test]
+fn shaders() -> anyhow::Result<()> {
+  ShaderCheck::new(concat!(env!("CARGO_MANIFEST_DIR"), "/assets"))
+    .program(&["shader.vert", "shader.frag"])
+    .rust_uniforms(concat!(env!("CARGO_MANIFEST_DIR"), "/src"))
+    .finish()
+}
@@ -1,6 +1,6 @@
-use common::SimpleObj;
+use common::{math::Aabb, SimpleObj};
 use glam::{EulerRot, Mat3, Mat4, Vec3};
-use glium::{backend::Facade, uniform, DrawParameters};
+use glium::{backend::Facade, uniform};
 
 use crate::{
   mesh::{GPUMeshFormat as _, MeshFormat, TriangleIndex, TriangleIndexGPU},
@@ -45,16 +45,33 @@ impl Light {
     self.rotation += delta;
   }
 
+  pub fn rotation(&self) -> f32 {
+    self.rotation
+  }
+
+  pub fn set_rotation(&mut self, rotation: f32) {
+    self.rotation = rotation;
+  }
+
   fn model(&self) -> Mat4 {
     Mat4::from_translation(self.position_world())
       * Mat4::from_scale(Vec3::splat(0.05))
   }
 
+  // of the sphere drawn for it, which spans -0.5..0.5 in model space
+  pub fn bounding_box(&self) -> Aabb {
+    Aabb::new(Vec3::splat(-0.5), Vec3::splat(0.5)).transform(self.model())
+  }
+
   pub fn color(&self) -> [f32; 3] {
     self.color
   }
 
-  pub fn draw(&self, frame: &mut glium::Frame, camera: &Camera) -> Result<()> {
+  pub fn draw(
+    &self,
+    frame: &mut impl glium::Surface,
+    camera: &Camera,
+  ) -> Result<()> {
     let Some(gpu) = self.gpu.as_ref() else {
       return Ok(());
     };
@@ -80,14 +97,7 @@ impl Light {
       shininess: 10.0f32,
     };
 
-    let params = DrawParameters {
-      depth: glium::Depth {
-        test: glium::draw_parameters::DepthTest::IfLess,
-        write: true,
-        ..Default::default()
-      },
-      ..Default::default()
-    };
+    let params = camera.depth_mode().draw_parameters();
 
     gpu.mesh.draw(frame, &uniforms, &params);
 
@@ -1,130 +1,139 @@
 mod light;
 mod mesh;
 mod teapot;
+#[cfg(test)]
+mod tests;
 
 use std::time::Duration;
 
-use glam::{EulerRot, Mat3, Mat4, Vec3};
-use glium::{glutin::surface::WindowSurface, Display, Surface};
+use glam::{Vec2, Vec3};
+use glium::{
+  backend::Facade, glutin::surface::WindowSurface, Display, Surface,
+};
+use serde::{Deserialize, Serialize};
 
-use teapot::{Teapot, TeapotKind};
+use teapot::{RenderMode, Teapot, TeapotKind};
 use winit::{
-  application::ApplicationHandler,
-  dpi::{LogicalSize, PhysicalPosition, PhysicalSize},
-  event::{self, KeyEvent, WindowEvent},
-  event_loop::{ActiveEventLoop, ControlFlow, EventLoop},
-  keyboard::{Key, ModifiersState, NamedKey},
-  window::{Window, WindowAttributes, WindowId},
+  event::{KeyEvent, MouseButton},
+  keyboard::{Key, NamedKey},
 };
 
-use common::Axis;
+use common::{
+  app::{App, Input},
+  camera::{Camera, CameraController, OrbitController},
+  math::Aabb,
+  session::{self, CameraState},
+  Axis, CameraLike,
+};
 use light::Light;
 
-type Error = Box<dyn std::error::Error>;
-type Result<T> = std::result::Result<T, Error>;
+type Result<T> = anyhow::Result<T>;
+
+// how close the orbit gets and how far it goes
+const MIN_DISTANCE: f32 = 0.1;
+const MAX_DISTANCE: f32 = 10.0;
+
+// saved on exit and with F5, restored on start and with F9
+const SESSION_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/session.ron");
 
-const TARGET_UPS: u32 = 60;
-const TARGET_FRAME_TIME: Duration =
-  Duration::from_micros(1_000_000 / TARGET_UPS as u64);
+#[derive(Serialize, Deserialize)]
+struct Session {
+  camera: CameraState,
+  light_rotation: f32,
+  show_axis: bool,
+  teapot: usize,
+  // one for each teapot
+  render_modes: Vec<RenderMode>,
+}
 
 struct World {
+  clear_color: [f32; 4],
   camera: Camera,
+  orbit: OrbitController,
+  viewport: Vec2,
   show_axis: bool,
   axis: Option<Axis>,
   teapots: Vec<TeapotKind>,
   teapot_idx: usize,
   light: Light,
+  // strips without the debugging colours, read by init
+  plain_strips: bool,
 }
 
-struct Camera {
-  clear_color: [f32; 4],
-  aspect_ratio: f32,
-  distance: f32,
-  rotation: [f32; 2],
-  perspective: bool,
-  m_view: Mat4,
-  m_proj: Mat4,
-  m_view_proj: Mat4,
-}
-
-impl Camera {
+impl World {
   fn new() -> Self {
     Self {
       clear_color: [0.0, 0.0, 0.0, 1.0],
-      aspect_ratio: 1.0,
-      distance: 2.0,
-      rotation: [0.0, 0.0],
-      perspective: true,
-      m_view: Mat4::IDENTITY,
-      m_proj: Mat4::IDENTITY,
-      m_view_proj: Mat4::IDENTITY,
+      camera: Camera::default(),
+      // looking at the front of the teapot
+      orbit: OrbitController::new(Vec3::ZERO, 2.0)
+        .with_angles(std::f32::consts::PI, 0.0)
+        .with_distance_range(MIN_DISTANCE, MAX_DISTANCE),
+      viewport: Vec2::ONE,
+      axis: None,
+      show_axis: true,
+      teapots: vec![],
+      teapot_idx: 0,
+      light: Light::new(),
+      plain_strips: std::env::var("NO_DEBUG_TRIANGLE_STRIP").as_deref()
+        == Ok("1"),
     }
   }
 
-  fn handle_window_resize(&mut self, new_size: (u32, u32)) {
-    self.aspect_ratio = new_size.0 as f32 / new_size.1 as f32;
-  }
-
-  fn view(&self) -> Mat4 {
-    self.m_view
-  }
+  // any facade does, the tests render without a window
+  fn load(&mut self, facade: &impl Facade) -> Result<()> {
+    // setup world objects
+    let axis = Axis::new(facade)?;
+    self.set_axis(axis);
+    let teapot1 = Teapot::new_triangle_list()?.upload(facade);
+    let teapot2 = Teapot::new_triangle_index()?;
+    let teapot3 = teapot2.to_strips(self.plain_strips);
+    let teapot3 = teapot3?.upload(facade);
+    self.add_teapot_alternative(teapot3);
+    let teapot2 = teapot2.upload(facade);
+    self.add_teapot_alternative(teapot2);
+    self.add_teapot_alternative(teapot1);
 
-  fn view_projection(&self) -> Mat4 {
-    self.m_view_proj
-  }
+    self.light.upload(facade)?;
 
-  fn projection(&self) -> Mat4 {
-    self.m_proj
+    // initial update
+    self.update(Duration::from_secs(0));
+    Ok(())
   }
 
-  fn calc_view(&self) -> Mat4 {
-    let dir = Mat3::from_euler(
-      EulerRot::YXZ,
-      self.rotation[0].to_radians(),
-      -self.rotation[1].to_radians(),
-      0.0,
-    ) * Vec3::new(0.0, 0.0, 1.0);
-    let eye = Vec3::ZERO - dir * self.distance;
-    let up = Vec3::Y;
-    Mat4::look_at_rh(eye, Vec3::ZERO, up)
+  fn session(&self) -> Session {
+    Session {
+      camera: CameraState::from_camera(&self.camera),
+      light_rotation: self.light.rotation(),
+      show_axis: self.show_axis,
+      teapot: self.teapot_idx,
+      render_modes: self.teapots.iter().map(TeapotKind::render_mode).collect(),
+    }
   }
 
-  fn calc_proj(&self) -> Mat4 {
-    if self.perspective {
-      Mat4::perspective_rh_gl(
-        60.0_f32.to_radians(),
-        self.aspect_ratio,
-        0.1,
-        100.0,
-      )
-    } else {
-      Mat4::orthographic_rh_gl(
-        -1.0,
-        1.0,
-        -1.0 / self.aspect_ratio,
-        1.0 / self.aspect_ratio,
-        0.1,
-        100.0,
-      ) * (1.0 / self.distance)
+  fn restore_session(&mut self, session: &Session) {
+    session.camera.apply(&mut self.camera);
+    self.orbit = OrbitController::from_camera(&self.camera)
+      .with_distance_range(MIN_DISTANCE, MAX_DISTANCE);
+    self.light.set_rotation(session.light_rotation);
+    self.show_axis = session.show_axis;
+    if session.teapot < self.teapots.len() {
+      self.teapot_idx = session.teapot;
+    }
+    for (teapot, &mode) in self.teapots.iter_mut().zip(&session.render_modes) {
+      teapot.set_render_mode(mode);
     }
+    self.update_view();
   }
 
-  fn update_view(&mut self) {
-    self.m_view = self.calc_view();
-    self.m_proj = self.calc_proj();
-    self.m_view_proj = self.m_proj * self.m_view;
+  fn save_session(&self) {
+    session::save_or_warn(SESSION_PATH, &self.session());
   }
-}
 
-impl World {
-  fn new() -> Self {
-    Self {
-      camera: Camera::new(),
-      axis: None,
-      show_axis: true,
-      teapots: vec![],
-      teapot_idx: 0,
-      light: Light::new(),
+  fn load_session(&mut self) {
+    if let Some(session) = session::load_or_warn(SESSION_PATH) {
+      self.restore_session(&session);
+      println!("Restored session from {}", SESSION_PATH);
     }
   }
 
@@ -137,293 +146,174 @@ impl World {
   }
 
   fn update_view(&mut self) {
-    self.camera.update_view();
+    self.orbit.apply(&mut self.camera);
+    self.fit_near_far();
   }
 
-  fn update(&mut self, dt: Duration) {
-    for teapot in &mut self.teapots {
-      teapot.update(dt);
-    }
+  // near and far planes as tight as the teapot and the light allow
+  fn fit_near_far(&mut self) {
+    let bounds = self.bounds();
+    self.camera.fit_near_far(&bounds);
   }
 
-  fn render(&self, context: &Display<WindowSurface>) -> Result<()> {
-    let mut frame = context.draw();
-    frame.clear_color_and_depth(self.camera.clear_color.into(), 1.0);
-
-    let teapot = &self.teapots[self.teapot_idx];
-    if let Err(e) = teapot.draw(&mut frame, &self.camera, &self.light) {
-      eprintln!("Failed to draw teapot: {}", e);
-    }
-
-    if let Some(axis) = self.axis.as_ref() {
-      if self.show_axis {
-        if let Err(e) = axis.draw(&mut frame, &self.camera.view_projection()) {
-          eprintln!("Failed to draw axis: {}", e);
-        }
-      }
+  // what is drawn, the teapot on screen and the light
+  fn bounds(&self) -> Aabb {
+    let light = self.light.bounding_box();
+    match self.teapots.get(self.teapot_idx) {
+      Some(teapot) => teapot.bounding_box().union(&light),
+      None => light,
     }
-
-    self.light.draw(&mut frame, &self.camera)?;
-
-    frame.finish()?;
-    Ok(())
   }
-}
-
-struct App {
-  window: Option<Window>,
-  display: Option<Display<WindowSurface>>,
-  last_update: std::time::Instant,
-
-  mouse_down: (bool, bool),
-  last_pos: [f32; 2],
-  mouse_pos: [f32; 2],
-  modifiers: ModifiersState,
-
-  world: World,
-}
-
-impl App {
-  fn new() -> Self {
-    let last_update = std::time::Instant::now();
-
-    let world = World::new();
 
-    Self {
-      window: None,
-      display: None,
-      last_update,
-      last_pos: [0.0, 0.0],
-      mouse_pos: [0.0, 0.0],
-      mouse_down: (false, false),
-      modifiers: ModifiersState::empty(),
-      world,
-    }
-  }
-
-  fn handle_resize(&mut self, size: PhysicalSize<u32>) {
-    println!("Resized to {:?}", size);
-    self
-      .world
-      .camera
-      .handle_window_resize((size.width, size.height));
-    self.world.update_view();
-    self.request_redraw();
-  }
-
-  fn handle_keyboard(&mut self, event: KeyEvent, event_loop: &ActiveEventLoop) {
+  fn handle_key(&mut self, event: KeyEvent) {
     if !event.state.is_pressed() {
       return;
     }
-    if event.logical_key == NamedKey::Escape {
-      event_loop.exit();
-    } else if event.logical_key.to_text() == Some("p") {
-      self.world.camera.perspective ^= true;
-      self.world.update_view();
-      self.request_redraw();
-    } else if event.logical_key.to_text() == Some("a") {
-      self.world.show_axis = !self.world.show_axis;
-      self.request_redraw();
-    } else if event.logical_key.to_text() == Some("t") {
-      self.world.teapot_idx =
-        (self.world.teapot_idx + 1) % self.world.teapots.len();
-      self.request_redraw();
+
+    if event.logical_key == "p" {
+      self.camera.toggle_projection();
+      self.update_view();
+    } else if event.logical_key == "a" {
+      self.show_axis = !self.show_axis;
+    } else if event.logical_key == NamedKey::F5 {
+      self.save_session();
+    } else if event.logical_key == NamedKey::F9 {
+      self.load_session();
+    } else if event.logical_key == "f" {
+      self.frame_teapot();
+    } else if event.logical_key == "t" {
+      self.teapot_idx = (self.teapot_idx + 1) % self.teapots.len();
     } else if let Key::Character(ch) = event.logical_key {
-      if let Some(mode) = teapot::RenderMode::from_key(ch) {
-        self.world.teapots[self.world.teapot_idx].set_render_mode(mode);
-        self.request_redraw();
+      if let Some(mode) = RenderMode::from_key(ch) {
+        self.teapots[self.teapot_idx].set_render_mode(mode);
       }
     }
   }
 
-  fn handle_redraw(&mut self) {
-    if let Some(display) = &self.display {
-      self.world.render(display).expect("Failed to render");
+  // the teapot on screen is all there is to select
+  fn pick(&mut self, pixel: Vec2) {
+    let ray = self.camera.pixel_ray(pixel, self.viewport);
+    match self.teapots.get(self.teapot_idx).and_then(|t| t.hit(&ray)) {
+      Some(_) => println!("Selected the teapot"),
+      None => println!("Selection cleared"),
     }
   }
 
-  fn update(&mut self) {
-    self.world.update(self.last_update.elapsed());
-
-    let help =
-      "Press 'p' to toggle perspective, 'a' to toggle axis, Esc to quit";
-
-    let title = format!(
-      "Teapot - {:.0} UPS",
-      1_000_000_000 / self.last_update.elapsed().as_nanos()
-    );
-
-    if let Some(window) = self.window.as_ref() {
-      window.set_title(&format!("{} - {}", title, help));
-      window.request_redraw();
+  fn frame_teapot(&mut self) {
+    if let Some(teapot) = self.teapots.get(self.teapot_idx) {
+      self.orbit.frame(&self.camera, &teapot.bounding_box());
+      self.update_view();
     }
-
-    self.last_update = std::time::Instant::now();
   }
+}
 
-  fn handle_mouse_input(
-    &mut self,
-    state: event::ElementState,
-    button: event::MouseButton,
-  ) {
-    let pressed = state.is_pressed();
-
-    match button {
-      event::MouseButton::Left => {
-        self.mouse_down.0 = pressed;
-      }
-      event::MouseButton::Right => {
-        self.mouse_down.1 = pressed;
-      }
-      _ => {}
-    }
+impl App for World {
+  fn init(&mut self, display: &Display<WindowSurface>) -> Result<()> {
+    self.load(display)?;
+    // the tests start from the defaults, only the window picks up where
+    // the last run left off
+    self.load_session();
+    Ok(())
   }
 
-  fn handle_cursor_moved(&mut self, position: PhysicalPosition<f64>) {
-    self.mouse_pos = [position.x as f32, position.y as f32];
-
-    // left drag: rotate camera
-    if self.mouse_down.0 && !self.modifiers.control_key() {
-      let dx = self.mouse_pos[0] - self.last_pos[0];
-      let dy = self.mouse_pos[1] - self.last_pos[1];
-      self.world.camera.rotation[0] += dx * 0.1;
-      self.world.camera.rotation[1] += dy * 0.1;
-      self.world.update_view();
-      self.request_redraw();
-    }
-
-    // ctrl + left drag: rotate light
-    if self.mouse_down.0 && self.modifiers.control_key() {
-      let dx = self.mouse_pos[0] - self.last_pos[0];
-      self.world.light.add_rotation(dx * 0.01);
-      self.request_redraw();
-    }
-
-    // right drag: change camera distance
-    if self.mouse_down.1 {
-      let dy = self.mouse_pos[1] - self.last_pos[1];
-
-      self.world.camera.distance += dy * 0.01;
-      self.world.camera.distance = self.world.camera.distance.clamp(0.1, 10.0);
-      self.world.update_view();
-      self.request_redraw();
+  fn update(&mut self, dt: Duration) {
+    for teapot in &mut self.teapots {
+      teapot.update(dt);
     }
-
-    self.last_pos = self.mouse_pos;
+    // the teapot turns, its bounds with it
+    self.fit_near_far();
   }
 
-  fn handle_mouse_wheel(&mut self, delta: event::MouseScrollDelta) {
-    let d = match delta {
-      event::MouseScrollDelta::LineDelta(_x, y) => y * 20.0,
-      event::MouseScrollDelta::PixelDelta(pos) => pos.y as f32,
-    };
+  fn render(&self, frame: &mut impl Surface) -> Result<()> {
+    let clear_depth = self.camera.depth_mode().clear_depth();
+    frame.clear_color_and_depth(self.clear_color.into(), clear_depth);
 
-    self.world.camera.distance -= d * 0.01;
-    self.world.camera.distance = self.world.camera.distance.clamp(0.1, 10.0);
-    self.world.update_view();
-  }
-
-  fn request_redraw(&self) {
-    if let Some(window) = self.window.as_ref() {
-      window.request_redraw();
+    let teapot = &self.teapots[self.teapot_idx];
+    if let Err(e) = teapot.draw(frame, &self.camera, &self.light) {
+      eprintln!("Failed to draw teapot: {}", e);
     }
-  }
-
-  fn schedule_next_frame(&self, event_loop: &ActiveEventLoop) {
-    let wake_up_at = self.last_update + TARGET_FRAME_TIME;
-    event_loop.set_control_flow(ControlFlow::WaitUntil(wake_up_at));
-  }
-
-  fn handle_init(&mut self, display: &Display<WindowSurface>) -> Result<()> {
-    // setup world objects
-    let axis = Axis::new(display)?;
-    self.world.set_axis(axis);
-    let teapot1 = Teapot::new_triangle_list()?.upload(display);
-    let teapot2 = Teapot::new_triangle_index()?;
-    let teapot3 = teapot2.to_strips();
-    let teapot3 = teapot3?.upload(display);
-    self.world.add_teapot_alternative(teapot3);
-    let teapot2 = teapot2.upload(display);
-    self.world.add_teapot_alternative(teapot2);
-    self.world.add_teapot_alternative(teapot1);
-
-    self.world.light.upload(display)?;
-
-    // initial update
-    self.world.update(Duration::from_secs(0));
-    Ok(())
-  }
-}
-
-impl ApplicationHandler for App {
-  fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-    if self.window.is_none() {
-      let window_attrs = WindowAttributes::default()
-        .with_title("Project 03")
-        .with_inner_size(LogicalSize::new(800, 600));
-
-      match event_loop.create_window(window_attrs) {
-        Ok(window) => {
-          let display = common::gl_boilerplate::init_display(&window);
-          self.handle_init(&display).expect("Failed to init world");
 
-          self.window = Some(window);
-          self.display = Some(display);
-        }
-        Err(e) => {
-          eprintln!("Failed to create window: {}", e);
-          event_loop.exit();
+    if let Some(axis) = self.axis.as_ref() {
+      if self.show_axis {
+        if let Err(e) = axis.draw(frame, &self.camera.view_projection()) {
+          eprintln!("Failed to draw axis: {}", e);
         }
       }
     }
+
+    self.light.draw(frame, &self.camera)?;
+
+    Ok(())
   }
 
-  fn window_event(
-    &mut self,
-    event_loop: &ActiveEventLoop,
-    _window_id: WindowId,
-    event: WindowEvent,
-  ) {
-    match event {
-      WindowEvent::Resized(size) => self.handle_resize(size),
-      WindowEvent::KeyboardInput { event, .. } => {
-        self.handle_keyboard(event, event_loop)
-      }
-      WindowEvent::RedrawRequested => {
-        self.handle_redraw();
+  fn on_input(&mut self, input: Input) {
+    match input {
+      Input::Key(event) => self.handle_key(event),
+      Input::Click {
+        button: MouseButton::Left,
+        pos,
+        ..
+      } => self.pick(pos),
+      // ctrl + left drag: rotate light
+      Input::Drag {
+        button: MouseButton::Left,
+        from,
+        to,
+        modifiers,
+      } if modifiers.control_key() => {
+        self.light.add_rotation((to.x - from.x) * 0.01);
       }
-      WindowEvent::CloseRequested => {
-        event_loop.exit();
+      // left drag: rotate camera
+      Input::Drag {
+        button: MouseButton::Left,
+        from,
+        to,
+        ..
+      } => {
+        self.orbit.drag(from, to, self.viewport);
+        self.update_view();
       }
-      WindowEvent::MouseInput { state, button, .. } => {
-        self.handle_mouse_input(state, button);
+      // right drag: change camera distance, dragging down moves away
+      Input::Drag {
+        button: MouseButton::Right,
+        from,
+        to,
+        ..
+      } => {
+        self.orbit.zoom(-(to.y - from.y) / 20.0);
+        self.update_view();
       }
-      WindowEvent::MouseWheel { delta, .. } => {
-        self.handle_mouse_wheel(delta);
-      }
-      WindowEvent::CursorMoved { position, .. } => {
-        self.handle_cursor_moved(position);
-      }
-      WindowEvent::ModifiersChanged(new_modifiers) => {
-        self.modifiers = new_modifiers.state();
+      Input::Scroll { dy, .. } => {
+        // 30 per notch
+        self.orbit.zoom(dy / 30.0);
+        self.update_view();
       }
       _ => {}
     }
   }
 
-  fn new_events(
-    &mut self,
-    event_loop: &ActiveEventLoop,
-    _cause: winit::event::StartCause,
-  ) {
-    self.update();
-    self.schedule_next_frame(event_loop);
+  fn resize(&mut self, width: u32, height: u32) {
+    println!("Resized to {}x{}", width, height);
+    self.viewport = Vec2::new(width as f32, height as f32);
+    self.camera.handle_resize(width as f32, height as f32);
+    self.update_view();
+  }
+
+  fn help(&self) -> &str {
+    "Press 'p' to toggle perspective, 'a' to toggle axis, 't' to switch the \
+      mesh format, 0-9 for the render modes, click to select the teapot, 'f' \
+      to frame it, F5 to save the session, F9 to restore it, F12 for a \
+      screenshot, Esc to quit"
+  }
+
+  // only once there was something to look at
+  fn exit(&mut self) {
+    if !self.teapots.is_empty() {
+      self.save_session();
+    }
   }
 }
 
 fn main() -> Result<()> {
-  let event_loop = EventLoop::new()?;
-  let mut app = App::new();
-  event_loop.run_app(&mut app)?;
-  Ok(())
+  common::app::run(World::new())
 }
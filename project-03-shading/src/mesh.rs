@@ -4,10 +4,10 @@ use std::{
   ops::Range,
 };
 
-use common::{mesh::tear_into_strips, SimpleObj};
+use common::{mesh::tear_into_strips, ProgramHandle, SimpleObj};
 use glium::{
   implement_vertex, index::PrimitiveType, uniforms::Uniforms, DrawParameters,
-  IndexBuffer, Program, VertexBuffer,
+  IndexBuffer, VertexBuffer,
 };
 use rand::Rng as _;
 
@@ -17,8 +17,8 @@ pub trait MeshFormat {
   fn upload(&self, surface: &impl glium::backend::Facade) -> Self::GPURepr;
 }
 
-const VERT_SHADER: &str = include_str!("../assets/mesh.vert");
-const FRAG_SHADER: &str = include_str!("../assets/mesh.frag");
+// mesh.vert and mesh.frag
+const SHADER_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/mesh");
 
 pub trait GPUMeshFormat {
   fn draw(
@@ -83,7 +83,7 @@ impl MeshFormat for TriangleList {
   fn upload(&self, surface: &impl glium::backend::Facade) -> Self::GPURepr {
     let vbo =
       VertexBuffer::new(surface, &self.trigs).expect("Failed to create VBO");
-    let program = Program::from_source(surface, VERT_SHADER, FRAG_SHADER, None)
+    let program = ProgramHandle::load(SHADER_PATH, surface)
       .expect("Failed to create program");
 
     eprintln!("TriangleList, buffer size: {} (v)", vbo.get_size());
@@ -93,7 +93,7 @@ impl MeshFormat for TriangleList {
 }
 
 pub struct TriangleListGPU {
-  program: Program,
+  program: ProgramHandle,
   vbo: VertexBuffer<Vertex>,
 }
 
@@ -108,7 +108,7 @@ impl GPUMeshFormat for TriangleListGPU {
       .draw(
         &self.vbo,
         glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList),
-        &self.program,
+        &self.program.get(),
         uniforms,
         params,
       )
@@ -167,7 +167,7 @@ impl MeshFormat for TriangleIndex {
     let ibo =
       IndexBuffer::new(surface, PrimitiveType::TrianglesList, &self.indices)
         .expect("Failed to create IBO");
-    let program = Program::from_source(surface, VERT_SHADER, FRAG_SHADER, None)
+    let program = ProgramHandle::load(SHADER_PATH, surface)
       .expect("Failed to create program");
 
     eprintln!(
@@ -182,7 +182,7 @@ impl MeshFormat for TriangleIndex {
 }
 
 pub struct TriangleIndexGPU {
-  program: Program,
+  program: ProgramHandle,
   vbo: VertexBuffer<Vertex>,
   ibo: IndexBuffer<u32>,
 }
@@ -195,7 +195,7 @@ impl GPUMeshFormat for TriangleIndexGPU {
     params: &DrawParameters<'_>,
   ) {
     frame
-      .draw(&self.vbo, &self.ibo, &self.program, uniforms, params)
+      .draw(&self.vbo, &self.ibo, &self.program.get(), uniforms, params)
       .expect("Failed to draw");
   }
 }
@@ -205,6 +205,8 @@ pub struct TriangleStrip {
   indices: Vec<u32>,
   // used for debugging only
   ranges: Vec<(Range<usize>, DebuggingColor)>,
+  // each strip in a random colour of its own
+  debug_colors: bool,
 }
 
 impl From<&TriangleIndex> for TriangleStrip {
@@ -244,6 +246,17 @@ impl From<&TriangleIndex> for TriangleStrip {
       vertices: vertices.clone(),
       ranges,
       indices,
+      debug_colors: true,
+    }
+  }
+}
+
+impl TriangleStrip {
+  // in the colour of the material, as the other formats
+  pub fn plain(self) -> Self {
+    Self {
+      debug_colors: false,
+      ..self
     }
   }
 }
@@ -257,7 +270,7 @@ impl MeshFormat for TriangleStrip {
     let ibo =
       IndexBuffer::new(surface, PrimitiveType::TriangleStrip, &self.indices)
         .expect("Failed to create IBO");
-    let program = Program::from_source(surface, VERT_SHADER, FRAG_SHADER, None)
+    let program = ProgramHandle::load(SHADER_PATH, surface)
       .expect("Failed to create program");
 
     eprintln!(
@@ -272,15 +285,17 @@ impl MeshFormat for TriangleStrip {
       vbo,
       ibo,
       ranges: self.ranges.clone(),
+      debug_colors: self.debug_colors,
     }
   }
 }
 
 pub struct TriangleStripGPU {
-  program: Program,
+  program: ProgramHandle,
   vbo: VertexBuffer<Vertex>,
   ibo: IndexBuffer<u32>,
   ranges: Vec<(Range<usize>, DebuggingColor)>,
+  debug_colors: bool,
 }
 
 impl GPUMeshFormat for TriangleStripGPU {
@@ -290,9 +305,10 @@ impl GPUMeshFormat for TriangleStripGPU {
     uniforms: &impl Uniforms,
     params: &DrawParameters<'_>,
   ) {
-    if std::env::var("NO_DEBUG_TRIANGLE_STRIP").as_deref() == Ok("1") {
+    let program = self.program.get();
+    if !self.debug_colors {
       frame
-        .draw(&self.vbo, &self.ibo, &self.program, uniforms, params)
+        .draw(&self.vbo, &self.ibo, &program, uniforms, params)
         .expect("Failed to draw");
       return;
     }
@@ -303,7 +319,7 @@ impl GPUMeshFormat for TriangleStripGPU {
       new_uniforms.set("k_d", color);
       let ibo = self.ibo.slice(range.clone()).unwrap();
       frame
-        .draw(&self.vbo, &ibo, &self.program, &new_uniforms, params)
+        .draw(&self.vbo, &ibo, &program, &new_uniforms, params)
         .expect("Failed to draw");
     }
   }
@@ -2,12 +2,17 @@ use std::time::Duration;
 
 use derive_more::From;
 
-use common::SimpleObj;
+use common::{
+  math::{Aabb, Ray},
+  mesh::Bvh,
+  SimpleObj,
+};
 use glam::EulerRot;
 use glam::Mat3;
 use glam::Mat4;
 use glam::Vec3;
-use glium::{backend::Facade, uniform, DrawParameters, Frame};
+use glium::{backend::Facade, uniform, DrawParameters, Surface};
+use serde::{Deserialize, Serialize};
 
 use crate::mesh::TriangleIndex;
 use crate::mesh::TriangleIndexGPU;
@@ -20,7 +25,7 @@ use crate::Result;
 
 use crate::mesh::{self, TriangleList};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 #[repr(u32)]
 pub enum RenderMode {
   Default = 0,
@@ -71,7 +76,7 @@ impl TeapotKind {
 
   pub fn draw(
     &self,
-    frame: &mut Frame,
+    frame: &mut impl Surface,
     camera: &Camera,
     light: &Light,
   ) -> Result<()> {
@@ -82,6 +87,31 @@ impl TeapotKind {
     }
   }
 
+  // world space distance along the ray
+  pub fn hit(&self, ray: &Ray) -> Option<f32> {
+    match self {
+      Self::TrigList(teapot) => teapot.hit(ray),
+      Self::TrigIndex(teapot) => teapot.hit(ray),
+      Self::TriangleStrip(teapot) => teapot.hit(ray),
+    }
+  }
+
+  pub fn bounding_box(&self) -> Aabb {
+    match self {
+      Self::TrigList(teapot) => teapot.bounding_box(),
+      Self::TrigIndex(teapot) => teapot.bounding_box(),
+      Self::TriangleStrip(teapot) => teapot.bounding_box(),
+    }
+  }
+
+  pub fn render_mode(&self) -> RenderMode {
+    match self {
+      Self::TrigList(teapot) => teapot.render_mode,
+      Self::TrigIndex(teapot) => teapot.render_mode,
+      Self::TriangleStrip(teapot) => teapot.render_mode,
+    }
+  }
+
   pub fn set_render_mode(&mut self, render_mode: RenderMode) {
     match self {
       Self::TrigList(teapot) => teapot.set_render_mode(render_mode),
@@ -95,42 +125,52 @@ pub struct Teapot<Mesh> {
   rotation: f32,
   rotation_speed: f32,
   mesh: Mesh,
+  // model space, for picking
+  bvh: Bvh,
   render_mode: RenderMode,
 }
 
 impl Teapot<TriangleList> {
   pub fn new_triangle_list() -> Result<Self> {
     let simple_obj = SimpleObj::load_from(&common::teapot_path())?;
+    let bvh = Bvh::from_simple_obj(&simple_obj);
     let mesh = TriangleList::from_simple_obj(simple_obj);
-    Self::new(mesh)
+    Self::new(mesh, bvh)
   }
 }
 
 impl Teapot<TriangleIndex> {
   pub fn new_triangle_index() -> Result<Self> {
     let simple_obj = SimpleObj::load_from(&common::teapot_path())?;
+    let bvh = Bvh::from_simple_obj(&simple_obj);
     let mesh = TriangleIndex::from_simple_obj(simple_obj);
-    Self::new(mesh)
+    Self::new(mesh, bvh)
   }
 
-  pub fn to_strips(&self) -> Result<Teapot<TriangleStrip>> {
-    let mesh = TriangleStrip::from(&self.mesh);
+  // strips in random colours, or plain ones that look like the others
+  pub fn to_strips(&self, plain: bool) -> Result<Teapot<TriangleStrip>> {
+    let mut mesh = TriangleStrip::from(&self.mesh);
+    if plain {
+      mesh = mesh.plain();
+    }
     Ok(Teapot {
       rotation: self.rotation,
       rotation_speed: self.rotation_speed,
       render_mode: self.render_mode,
       mesh,
+      bvh: self.bvh.clone(),
     })
   }
 }
 
 impl<Mesh> Teapot<Mesh> {
-  pub fn new(mesh: Mesh) -> Result<Self> {
+  pub fn new(mesh: Mesh, bvh: Bvh) -> Result<Self> {
     Ok(Self {
       rotation: 0.0,
       rotation_speed: 1.0,
       render_mode: RenderMode::Default,
       mesh,
+      bvh,
     })
   }
 
@@ -149,6 +189,15 @@ impl<Mesh> Teapot<Mesh> {
       * Mat4::from_euler(EulerRot::YXZ, self.rotation, -90f32.to_radians(), 0.0)
   }
 
+  pub fn hit(&self, ray: &Ray) -> Option<f32> {
+    let model = self.model_transform();
+    Some(self.bvh.closest_hit_placed(ray, model, f32::MAX)?.t)
+  }
+
+  pub fn bounding_box(&self) -> Aabb {
+    self.bvh.aabb().transform(self.model_transform())
+  }
+
   pub fn upload<GPUMesh>(&self, surface: &impl Facade) -> Teapot<GPUMesh>
   where
     Mesh: mesh::MeshFormat<GPURepr = GPUMesh>,
@@ -158,12 +207,13 @@ impl<Mesh> Teapot<Mesh> {
       rotation_speed: self.rotation_speed,
       render_mode: self.render_mode,
       mesh: self.mesh.upload(surface),
+      bvh: self.bvh.clone(),
     }
   }
 
   pub fn draw(
     &self,
-    frame: &mut Frame,
+    frame: &mut impl Surface,
     camera: &Camera,
     light: &Light,
   ) -> Result<()>
@@ -194,14 +244,9 @@ impl<Mesh> Teapot<Mesh> {
 
     // by default the depth buffer is not used.
     let draw_params = DrawParameters {
-      depth: glium::Depth {
-        test: glium::draw_parameters::DepthTest::IfLess,
-        write: true,
-        ..Default::default()
-      },
       backface_culling:
         glium::draw_parameters::BackfaceCullingMode::CullClockwise,
-      ..Default::default()
+      ..camera.depth_mode().draw_parameters()
     };
 
     self.mesh.draw(frame, &uniforms, &draw_params);
@@ -0,0 +1,65 @@
+use common::{
+  app::App,
+  golden::{Golden, HEIGHT, WIDTH},
+  shader_check::ShaderCheck,
+};
+
+use crate::{teapot::RenderMode, World};
+
+const GOLDEN_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/golden");
+
+fn world(golden: &Golden) -> anyhow::Result<World> {
+  let mut world = World::new();
+  // the strips are drawn in random colours otherwise
+  world.plain_strips = true;
+  world.camera.handle_resize(WIDTH as f32, HEIGHT as f32);
+  world.update_view();
+  world.load(golden.facade())?;
+  Ok(world)
+}
+
+fn check(golden: &Golden, name: &str, world: &World) -> anyhow::Result<()> {
+  golden.check(name, |frame| world.render(frame))
+}
+
+// every mode behind the number keys
+#[test]
+fn render_modes() -> anyhow::Result<()> {
+  let golden = Golden::new(GOLDEN_DIR)?;
+  let mut world = world(&golden)?;
+  for key in 0..=9 {
+    let mode = RenderMode::from_key(key.to_string()).unwrap();
+    world.teapots[world.teapot_idx].set_render_mode(mode);
+    check(&golden, &format!("render_mode_{}", key), &world)?;
+  }
+  Ok(())
+}
+
+// strips, indexed and plain triangles should look the same
+#[test]
+fn mesh_formats() -> anyhow::Result<()> {
+  let golden = Golden::new(GOLDEN_DIR)?;
+  let mut world = world(&golden)?;
+  for idx in 0..world.teapots.len() {
+    world.teapot_idx = idx;
+    check(&golden, "mesh_format", &world)?;
+  }
+  Ok(())
+}
+
+#[test]
+fn moved_light() -> anyhow::Result<()> {
+  let golden = Golden::new(GOLDEN_DIR)?;
+  let mut world = world(&golden)?;
+  world.light.add_rotation(120.0);
+  check(&golden, "moved_light", &world)
+}
+
+// every shader in assets, grouped into the programs the code builds
+#[test]
+fn shaders() -> anyhow::Result<()> {
+  ShaderCheck::new(concat!(env!("CARGO_MANIFEST_DIR"), "/assets"))
+    .program(&["mesh.vert", "mesh.frag"])
+    .rust_uniforms(concat!(env!("CARGO_MANIFEST_DIR"), "/src"))
+    .finish()
+}
@@ -30,6 +30,14 @@ impl Light {
     self.rotation += delta;
   }
 
+  pub fn rotation(&self) -> f32 {
+    self.rotation
+  }
+
+  pub fn set_rotation(&mut self, rotation: f32) {
+    self.rotation = rotation;
+  }
+
   #[allow(dead_code)]
   fn model(&self) -> Mat4 {
     Mat4::from_translation(self.position_world())
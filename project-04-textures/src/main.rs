@@ -1,130 +1,128 @@
 mod light;
 mod mesh;
 mod object;
+#[cfg(test)]
+mod tests;
 
 use std::time::Duration;
 
-use glam::{Mat4, Vec3};
-use glium::{glutin::surface::WindowSurface, Display, Surface};
+use glam::{Vec2, Vec3};
+use glium::{
+  backend::Facade, glutin::surface::WindowSurface, Display, Surface,
+};
+use serde::{Deserialize, Serialize};
 
 use object::{GPUObject, Teapot, Yoda};
 
-use common::Axis;
+use common::{
+  app::{App, Input},
+  camera::{Camera, CameraController, OrbitController},
+  math::Aabb,
+  session::{self, CameraState},
+  Axis, CameraLike,
+};
 use light::Light;
 use winit::{
-  application::ApplicationHandler,
-  dpi::{LogicalSize, PhysicalSize},
-  event::{self, WindowEvent},
-  event_loop::{ActiveEventLoop, ControlFlow, EventLoop},
-  keyboard::{ModifiersState, NamedKey},
-  window::{Window, WindowAttributes},
+  event::{KeyEvent, MouseButton},
+  keyboard::NamedKey,
 };
 
 type Result<T> = anyhow::Result<T>;
 
-const TARGET_UPS: u32 = 60;
-const TARGET_FRAME_TIME: Duration =
-  Duration::from_micros(1_000_000 / TARGET_UPS as u64);
+// how close the orbit gets and how far it goes
+const MIN_DISTANCE: f32 = 0.1;
+const MAX_DISTANCE: f32 = 10.0;
+
+// saved on exit and with F5, restored on start and with F9
+const SESSION_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/session.ron");
+
+#[derive(Serialize, Deserialize)]
+struct Session {
+  camera: CameraState,
+  light_rotation: f32,
+  show_axis: bool,
+}
 
 struct World {
+  model_name: &'static str,
   t: f32,
+  clear_color: [f32; 4],
   camera: Camera,
+  orbit: OrbitController,
+  viewport: Vec2,
   show_axis: bool,
   axis: Option<Axis>,
   light: Light,
   objects: Vec<object::GPUObject>,
+  // index into `objects`
+  selected: Option<usize>,
 }
 
-struct Camera {
-  clear_color: [f32; 4],
-  // camera
-  aspect_ratio: f32,
-  distance: f32,
-  rotation: [f32; 2],
-  perspective: bool,
-  // cached matrices
-  m_view: Mat4,
-  m_proj: Mat4,
-  m_view_proj: Mat4,
-}
-
-impl Camera {
-  fn new() -> Self {
+impl World {
+  fn new(model_name: &'static str) -> Self {
     Self {
+      model_name,
+      t: 0.0,
       clear_color: [0.0, 0.0, 0.0, 1.0],
-      aspect_ratio: 1.0,
-      distance: 2.0,
-      rotation: [0.0, 0.0],
-      perspective: true,
-
-      m_view: Mat4::IDENTITY,
-      m_proj: Mat4::IDENTITY,
-      m_view_proj: Mat4::IDENTITY,
+      camera: Camera::default(),
+      // looking at the front of the models
+      orbit: OrbitController::new(Vec3::ZERO, 2.0)
+        .with_angles(std::f32::consts::PI, 0.0)
+        .with_distance_range(MIN_DISTANCE, MAX_DISTANCE),
+      viewport: Vec2::ONE,
+      axis: None,
+      show_axis: true,
+      light: Light::new(),
+      objects: Vec::new(),
+      selected: None,
     }
   }
 
-  fn handle_window_resize(&mut self, new_size: (u32, u32)) {
-    self.aspect_ratio = new_size.0 as f32 / new_size.1 as f32;
-  }
-
-  fn view(&self) -> Mat4 {
-    self.m_view
-  }
+  // any facade does, the tests render without a window
+  fn load(&mut self, facade: &impl Facade) -> Result<()> {
+    let model = match self.model_name {
+      "teapot" => Teapot::load(facade)?,
+      "yoda" => Yoda::load(facade)?,
+      _ => unreachable!(),
+    };
+    self.add_object(model);
+    // fit the near and far planes to it
+    self.update_view();
 
-  fn view_projection(&self) -> Mat4 {
-    self.m_view_proj
-  }
+    // setup axis object
+    let axis = Axis::new(facade)?;
+    self.set_axis(axis);
 
-  fn projection(&self) -> Mat4 {
-    self.m_proj
+    // initial update
+    self.update(Duration::ZERO);
+    Ok(())
   }
 
-  fn calc_view(&self) -> Mat4 {
-    // default view matrix: eye at (0, 0, 2), looking at (0, 0, -1), up (0, 1, 0)
-    let dir = Mat4::from_euler(
-      glam::EulerRot::YXZ,
-      self.rotation[0],
-      self.rotation[1],
-      0.0,
-    )
-    .transform_vector3(Vec3::new(0.0, 0.0, 1.0));
-    let eye = Vec3::new(0.0, 0.0, 0.0) + -dir * self.distance;
-    let up = Vec3::new(0.0, 1.0, 0.0);
-    let origin = Vec3::new(0.0, 0.0, 0.0);
-    Mat4::look_at_rh(eye, origin, up)
+  fn session(&self) -> Session {
+    Session {
+      camera: CameraState::from_camera(&self.camera),
+      light_rotation: self.light.rotation(),
+      show_axis: self.show_axis,
+    }
   }
 
-  fn calc_proj(&self) -> Mat4 {
-    if self.perspective {
-      Mat4::perspective_rh_gl(60f32.to_radians(), self.aspect_ratio, 0.1, 100.0)
-    } else {
-      Mat4::orthographic_rh_gl(
-        -1.0,
-        1.0,
-        -1.0 / self.aspect_ratio,
-        1.0 / self.aspect_ratio,
-        0.1,
-        100.0,
-      ) * (1.0 / self.distance)
-    }
+  fn restore_session(&mut self, session: &Session) {
+    session.camera.apply(&mut self.camera);
+    self.orbit = OrbitController::from_camera(&self.camera)
+      .with_distance_range(MIN_DISTANCE, MAX_DISTANCE);
+    self.light.set_rotation(session.light_rotation);
+    self.show_axis = session.show_axis;
+    self.update_view();
   }
 
-  fn update_view(&mut self) {
-    self.m_view = self.calc_view();
-    self.m_proj = self.calc_proj();
-    self.m_view_proj = self.m_proj * self.m_view;
+  fn save_session(&self) {
+    session::save_or_warn(SESSION_PATH, &self.session());
   }
-}
 
-impl World {
-  fn new() -> Self {
-    Self {
-      t: 0.0,
-      camera: Camera::new(),
-      axis: None,
-      show_axis: true,
-      light: Light::new(),
-      objects: Vec::new(),
+  fn load_session(&mut self) {
+    if let Some(session) = session::load_or_warn(SESSION_PATH) {
+      self.restore_session(&session);
+      println!("Restored session from {}", SESSION_PATH);
     }
   }
 
@@ -133,33 +131,17 @@ impl World {
   }
 
   fn update_view(&mut self) {
-    self.camera.update_view();
+    self.orbit.apply(&mut self.camera);
+    // near and far planes as tight as the objects allow
+    let bounds = self.bounds();
+    self.camera.fit_near_far(&bounds);
   }
 
-  fn update(&mut self, dt: Duration) {
-    for obj in &mut self.objects {
-      obj.update(&dt);
-    }
-  }
-
-  fn render(&self, context: &Display<WindowSurface>) -> Result<()> {
-    let mut frame = context.draw();
-    frame.clear_color_and_depth(self.camera.clear_color.into(), 1.0);
-
-    if let Some(axis) = self.axis.as_ref() {
-      if self.show_axis {
-        if let Err(e) = axis.draw(&mut frame, &self.camera.view_projection()) {
-          eprintln!("Failed to draw axis: {}", e);
-        }
-      }
-    }
-
-    for obj in &self.objects {
-      obj.draw(&mut frame, &self.camera, &self.light);
-    }
-
-    frame.finish()?;
-    Ok(())
+  fn bounds(&self) -> Aabb {
+    self
+      .objects
+      .iter()
+      .fold(Aabb::EMPTY, |aabb, o| aabb.union(&o.bounding_box()))
   }
 
   #[allow(unused)]
@@ -169,287 +151,162 @@ impl World {
     let r = t.sin().abs();
     let g = (t * 2.0).sin().abs();
     let b = (t * 3.0).sin().abs();
-    self.camera.clear_color = [r, g, b, 1.0];
+    self.clear_color = [r, g, b, 1.0];
   }
 
   fn add_object(&mut self, object: GPUObject) {
     self.objects.push(object);
   }
-}
-
-struct App {
-  window: Option<Window>,
-  display: Option<Display<WindowSurface>>,
-  last_update: std::time::Instant,
-
-  // left, right
-  mouse_down: (bool, bool),
-  last_pos: [f32; 2],
-  mouse_pos: [f32; 2],
-  modifiers: ModifiersState,
-
-  model_name: &'static str,
-  world: World,
-}
-
-impl App {
-  fn new(model_name: &'static str) -> Self {
-    let last_update = std::time::Instant::now();
-
-    let world = World::new();
 
-    Self {
-      window: None,
-      display: None,
-      last_update,
-      last_pos: [0.0, 0.0],
-      mouse_pos: [0.0, 0.0],
-      mouse_down: (false, false),
-      modifiers: ModifiersState::empty(),
-      world,
-      model_name,
+  // the closest object under the cursor
+  fn pick(&mut self, pixel: Vec2) {
+    let ray = self.camera.pixel_ray(pixel, self.viewport);
+    self.selected = self
+      .objects
+      .iter()
+      .enumerate()
+      .filter_map(|(i, object)| Some((i, object.hit(&ray)?)))
+      .min_by(|a, b| a.1.total_cmp(&b.1))
+      .map(|(i, _)| i);
+
+    match self.selected {
+      Some(i) => println!("Selected object {}", i),
+      None => println!("Selection cleared"),
     }
   }
 
-  fn handle_resize(&mut self, size: PhysicalSize<u32>) {
-    println!("Resized to {:?}", size);
-    self
-      .world
-      .camera
-      .handle_window_resize((size.width, size.height));
-    self.world.update_view();
-    self.request_redraw();
+  // the selected object, or all of them if nothing is selected
+  fn frame_selected(&mut self) {
+    let bounds = match self.selected {
+      Some(i) => self.objects[i].bounding_box(),
+      None => self.bounds(),
+    };
+    self.orbit.frame(&self.camera, &bounds);
+    self.update_view();
   }
 
-  fn handle_keyboard(
-    &mut self,
-    event: event::KeyEvent,
-    event_loop: &ActiveEventLoop,
-  ) {
+  fn handle_key(&mut self, event: KeyEvent) {
     if !event.state.is_pressed() {
       return;
     }
-    if event.logical_key == NamedKey::Escape {
-      event_loop.exit();
-    } else if event.logical_key.to_text() == Some("p") {
-      self.world.camera.perspective ^= true;
-      self.world.update_view();
-      self.request_redraw();
-    } else if event.logical_key.to_text() == Some("a") {
-      self.world.show_axis = !self.world.show_axis;
-      self.request_redraw();
-    } else if event.logical_key == NamedKey::F6 {
-      self.reload_shaders();
-    }
-  }
 
-  fn handle_redraw(&mut self) {
-    if let Some(display) = &self.display {
-      self.world.render(display).expect("Failed to render");
+    if event.logical_key == "p" {
+      self.camera.toggle_projection();
+      self.update_view();
+    } else if event.logical_key == "a" {
+      self.show_axis = !self.show_axis;
+    } else if event.logical_key == "f" {
+      self.frame_selected();
+    } else if event.logical_key == NamedKey::F5 {
+      self.save_session();
+    } else if event.logical_key == NamedKey::F9 {
+      self.load_session();
     }
   }
+}
 
-  fn handle_idle(&mut self) {
-    self.world.update(self.last_update.elapsed());
-
-    let help =
-      "Press 'p' to toggle perspective, 'a' to toggle axis, Esc to quit";
-
-    let title = format!(
-      "Teapot - {:.0} UPS",
-      1_000_000_000 / self.last_update.elapsed().as_nanos()
-    );
-
-    if let Some(window) = self.window.as_ref() {
-      window.set_title(&format!("{} - {}", title, help));
-      window.request_redraw();
-    }
-
-    self.last_update = std::time::Instant::now();
-  }
-
-  fn handle_mouse_input(
-    &mut self,
-    state: event::ElementState,
-    button: event::MouseButton,
-  ) {
-    let pressed = state.is_pressed();
-
-    match button {
-      event::MouseButton::Left => {
-        self.mouse_down.0 = pressed;
-      }
-      event::MouseButton::Right => {
-        self.mouse_down.1 = pressed;
-      }
-      _ => {}
-    }
+impl App for World {
+  fn init(&mut self, display: &Display<WindowSurface>) -> Result<()> {
+    self.load(display)?;
+    // the tests start from the defaults, only the window picks up where
+    // the last run left off
+    self.load_session();
+    Ok(())
   }
 
-  fn handle_cursor_moved(
-    &mut self,
-    position: winit::dpi::PhysicalPosition<f64>,
-  ) {
-    self.mouse_pos = [position.x as f32, position.y as f32];
-
-    // left drag: rotate camera
-    if self.mouse_down.0 && !self.modifiers.control_key() {
-      let dx = self.mouse_pos[0] - self.last_pos[0];
-      let dy = self.mouse_pos[1] - self.last_pos[1];
-      self.world.camera.rotation[0] += dx * 0.01;
-      self.world.camera.rotation[1] += dy * 0.01;
-      self.world.update_view();
-      self.request_redraw();
-    }
-
-    // ctrl + left drag: rotate light
-    if self.mouse_down.0 && self.modifiers.control_key() {
-      let dx = self.mouse_pos[0] - self.last_pos[0];
-      let _dy = self.mouse_pos[1] - self.last_pos[1];
-      self.world.light.add_rotation(dx * 0.01);
-      self.request_redraw();
-    }
-
-    // right drag: change camera distance
-    if self.mouse_down.1 {
-      let dy = self.mouse_pos[1] - self.last_pos[1];
-
-      self.world.camera.distance += dy * 0.01;
-      self.world.camera.distance = self.world.camera.distance.clamp(0.1, 10.0);
-      self.world.update_view();
-      self.request_redraw();
+  fn update(&mut self, dt: Duration) {
+    for obj in &mut self.objects {
+      obj.update(&dt);
     }
-
-    self.last_pos = self.mouse_pos;
-  }
-
-  fn handle_mouse_wheel(&mut self, delta: event::MouseScrollDelta) {
-    let d = match delta {
-      event::MouseScrollDelta::LineDelta(_x, y) => y * 20.0,
-      event::MouseScrollDelta::PixelDelta(pos) => pos.y as f32,
-    };
-
-    self.world.camera.distance -= d * 0.01;
-    self.world.camera.distance = self.world.camera.distance.clamp(0.1, 10.0);
-    self.world.update_view();
   }
 
-  fn request_redraw(&self) {
-    if let Some(window) = &self.window {
-      window.request_redraw();
-    }
-  }
+  fn render(&self, frame: &mut impl Surface) -> Result<()> {
+    let clear_depth = self.camera.depth_mode().clear_depth();
+    frame.clear_color_and_depth(self.clear_color.into(), clear_depth);
 
-  fn reload_shaders(&mut self) {
-    if let Some(display) = &self.display {
-      for obj in self.world.objects.iter_mut() {
-        if let Err(e) = obj.reload_shader(display) {
-          eprintln!("Failed to reload shader: {}", e);
-          return;
+    if let Some(axis) = self.axis.as_ref() {
+      if self.show_axis {
+        if let Err(e) = axis.draw(frame, &self.camera.view_projection()) {
+          eprintln!("Failed to draw axis: {}", e);
         }
       }
-      println!("Reloaded shaders");
-      self.request_redraw();
     }
-  }
 
-  fn handle_init(&mut self, display: &Display<WindowSurface>) -> Result<()> {
-    let model = match self.model_name {
-      "teapot" => Teapot::load(display)?,
-      "yoda" => Yoda::load(display)?,
-      _ => unreachable!(),
-    };
-    self.world.add_object(model);
-
-    // setup axis object
-    let axis = Axis::new(display)?;
-    self.world.set_axis(axis);
+    for obj in &self.objects {
+      obj.draw(frame, &self.camera, &self.light);
+    }
 
-    // initial update
-    self.world.update(std::time::Duration::from_secs(0));
     Ok(())
   }
-}
 
-impl ApplicationHandler for App {
-  fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-    if self.window.is_none() {
-      let window_attrs = WindowAttributes::default()
-        .with_title("Project 04")
-        .with_inner_size(LogicalSize::new(800, 600));
-
-      let window = match event_loop.create_window(window_attrs) {
-        Ok(window) => window,
-        Err(e) => {
-          eprintln!("Failed to create window: {}", e);
-          event_loop.exit();
-          return;
-        }
-      };
-
-      let display = common::gl_boilerplate::init_display(&window);
-
-      match self.handle_init(&display) {
-        Ok(_) => {
-          self.window = Some(window);
-          self.display = Some(display);
-        }
-        Err(e) => {
-          eprintln!("Failed to initialize: {}", e);
-          event_loop.exit();
-        }
-      }
-    }
-  }
-
-  fn window_event(
-    &mut self,
-    event_loop: &ActiveEventLoop,
-    _window_id: winit::window::WindowId,
-    event: event::WindowEvent,
-  ) {
-    match event {
-      WindowEvent::Resized(size) => self.handle_resize(size),
-      WindowEvent::KeyboardInput { event, .. } => {
-        self.handle_keyboard(event, event_loop)
-      }
-      WindowEvent::RedrawRequested => self.handle_redraw(),
-      WindowEvent::CloseRequested => {
-        event_loop.exit();
+  fn on_input(&mut self, input: Input) {
+    match input {
+      Input::Key(event) => self.handle_key(event),
+      Input::Click {
+        button: MouseButton::Left,
+        pos,
+        ..
+      } => self.pick(pos),
+      // ctrl + left drag: rotate light
+      Input::Drag {
+        button: MouseButton::Left,
+        from,
+        to,
+        modifiers,
+      } if modifiers.control_key() => {
+        self.light.add_rotation((to.x - from.x) * 0.01);
       }
-      WindowEvent::MouseInput { state, button, .. } => {
-        self.handle_mouse_input(state, button);
+      // left drag: rotate camera
+      Input::Drag {
+        button: MouseButton::Left,
+        from,
+        to,
+        ..
+      } => {
+        self.orbit.drag(from, to, self.viewport);
+        self.update_view();
       }
-      WindowEvent::MouseWheel { delta, .. } => {
-        self.handle_mouse_wheel(delta);
+      // right drag: change camera distance, dragging down moves away
+      Input::Drag {
+        button: MouseButton::Right,
+        from,
+        to,
+        ..
+      } => {
+        self.orbit.zoom(-(to.y - from.y) / 20.0);
+        self.update_view();
       }
-      WindowEvent::CursorMoved { position, .. } => {
-        self.handle_cursor_moved(position);
-      }
-      WindowEvent::ModifiersChanged(new_modifiers) => {
-        self.modifiers = new_modifiers.state();
+      Input::Scroll { dy, .. } => {
+        // 30 per notch
+        self.orbit.zoom(dy / 30.0);
+        self.update_view();
       }
       _ => {}
     }
   }
 
-  fn new_events(
-    &mut self,
-    event_loop: &ActiveEventLoop,
-    _cause: event::StartCause,
-  ) {
-    let wake_up_at = self.last_update + TARGET_FRAME_TIME;
-    event_loop.set_control_flow(ControlFlow::WaitUntil(wake_up_at));
+  fn resize(&mut self, width: u32, height: u32) {
+    println!("Resized to {}x{}", width, height);
+    self.viewport = Vec2::new(width as f32, height as f32);
+    self.camera.handle_resize(width as f32, height as f32);
+    self.update_view();
+  }
+
+  fn help(&self) -> &str {
+    "Press 'p' to toggle perspective, 'a' to toggle axis, click to select, \
+      'f' to frame the selection, F5 to save the session, F9 to restore it, \
+      F12 for a screenshot, Esc to quit"
+  }
 
-    if self.last_update.elapsed() >= TARGET_FRAME_TIME {
-      self.handle_idle();
+  // only once there was something to look at
+  fn exit(&mut self) {
+    if !self.objects.is_empty() {
+      self.save_session();
     }
   }
 }
 
 fn main() -> Result<()> {
-  let event_loop = EventLoop::new()?;
-
   let mut args = std::env::args();
   let bin_name = args.next().unwrap(); // skip $0
   let model_name = match args.next().as_deref() {
@@ -462,8 +319,5 @@ fn main() -> Result<()> {
     }
   };
 
-  let mut app = App::new(model_name);
-  event_loop.run_app(&mut app)?;
-
-  Ok(())
+  common::app::run(World::new(model_name))
 }
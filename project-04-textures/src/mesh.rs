@@ -1,11 +1,11 @@
 #![allow(non_snake_case)]
 
 use common::{
-  mesh::{concat_strips, tear_into_strips},
+  mesh::{lod_cache_path, Bvh, LodStrips},
   DynUniforms, MergedUniform, Mtl,
 };
 use image::RgbImage;
-use std::{borrow::Cow, collections::HashMap, ops::Range};
+use std::{borrow::Cow, collections::HashMap, ops::Range, path::Path};
 
 use crate::Result;
 use common::obj_loader::{MtlLib, Obj, VAIdx};
@@ -46,7 +46,6 @@ implement_vertex!(Vertex, pos, uv, n);
 struct Group {
   #[allow(dead_code)]
   name: String,
-  index_range: Range<u32>,
   mtl: Option<String>,
 }
 
@@ -55,20 +54,36 @@ pub struct Mesh {
   indices: Vec<u32>,
   mtl_lib: MtlLib,
   groups: Vec<Group>,
+  lods: LodStrips,
+  // model space, for picking
+  bvh: Bvh,
 }
 
 impl Mesh {
-  pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+  // also generate simplified levels of detail at the given ratios of
+  // the triangle count, see common::mesh::lod_chain
+  pub fn load_with_lods<P: AsRef<Path>>(
+    path: P,
+    lod_ratios: &[f32],
+  ) -> Result<Self> {
     let obj = Obj::load_from(&path)?;
-    Ok(Self::from_obj(obj))
+    // building the lods of a big model takes a while, so they are kept
+    // next to it
+    let lod_cache = lod_cache_path(path.as_ref());
+    Ok(Self::from_obj(obj, lod_ratios, Some(&lod_cache)))
   }
 
-  pub fn from_obj(obj: Obj) -> Self {
+  pub fn from_obj(
+    obj: Obj,
+    lod_ratios: &[f32],
+    lod_cache: Option<&Path>,
+  ) -> Self {
     // hash(vertex) -> index
     let mut vert_index: HashMap<u64, usize> = HashMap::new();
     let mut vertices = Vec::new();
-    let mut indices = Vec::new();
     let mut groups = Vec::new();
+    // triangle list of each group
+    let mut group_trigs = Vec::new();
 
     let to_vert_attr = |[v, vt, vn]: VAIdx| Vertex {
       pos: obj.v[v - 1],
@@ -90,25 +105,33 @@ impl Mesh {
           group_indices.push(*i as u32);
         }
       }
-      let strips = tear_into_strips(&group_indices);
-      let group_indices = concat_strips(&strips);
-      let begin = indices.len() as u32;
-      indices.extend_from_slice(&group_indices);
-      let range = begin..(indices.len() as u32);
-      let group = Group {
+      group_trigs.push(group_indices);
+      groups.push(Group {
         name: group.name,
-        index_range: range,
         mtl: group.usemtl,
-      };
-      groups.push(group);
+      });
     }
 
+    // the groups of the full mesh and of each lod as strips
+    let positions: Vec<[f32; 3]> = vertices.iter().map(|v| v.pos).collect();
+    let bvh = Bvh::new(&positions, &group_trigs.concat(), &[]);
+    let mut indices = Vec::new();
+    let lods = LodStrips::build(
+      &mut indices,
+      &positions,
+      &group_trigs,
+      lod_ratios,
+      lod_cache,
+    );
+
     let mtl_lib = obj.mtl_lib;
     Self {
       vertices,
       indices,
       mtl_lib,
       groups,
+      lods,
+      bvh,
     }
   }
 
@@ -129,11 +152,15 @@ impl Mesh {
       })
       .collect::<Result<HashMap<_, _>>>()?;
     let groups = self.groups.clone();
+    let lods = self.lods.clone();
+    let bvh = self.bvh.clone();
 
     Ok(GPUMesh {
       vbo,
       ibo,
       groups,
+      lods,
+      bvh,
       mtls,
     })
   }
@@ -209,20 +236,20 @@ impl GPUMtl {
   fn to_uniforms(&self) -> impl glium::uniforms::Uniforms + '_ {
     let mut uniforms = DynUniforms::new();
 
-    uniforms.add("Ns", &self.Ns);
-    uniforms.add("Ni", &self.Ni);
-    uniforms.add("d", &self.d);
-    uniforms.add("Tr", &self.Tr);
-    uniforms.add("Tf", &self.Tf);
-    uniforms.add("illum", &self.illum);
-    uniforms.add("Ka", &self.Ka);
-    uniforms.add("Kd", &self.Kd);
-    uniforms.add("Ks", &self.Ks);
-    uniforms.add("Ke", &self.Ke);
-    uniforms.add("use_map_Ka", &self.use_map_Ka);
-    uniforms.add("use_map_Kd", &self.use_map_Kd);
-    uniforms.add("use_map_Ks", &self.use_map_Ks);
-    uniforms.add("use_map_bump", &self.use_map_bump);
+    uniforms.add("Ns", self.Ns);
+    uniforms.add("Ni", self.Ni);
+    uniforms.add("d", self.d);
+    uniforms.add("Tr", self.Tr);
+    uniforms.add("Tf", self.Tf);
+    uniforms.add("illum", self.illum);
+    uniforms.add("Ka", self.Ka);
+    uniforms.add("Kd", self.Kd);
+    uniforms.add("Ks", self.Ks);
+    uniforms.add("Ke", self.Ke);
+    uniforms.add("use_map_Ka", self.use_map_Ka);
+    uniforms.add("use_map_Kd", self.use_map_Kd);
+    uniforms.add("use_map_Ks", self.use_map_Ks);
+    uniforms.add("use_map_bump", self.use_map_bump);
 
     if let Some(map_Ka) = &self.map_Ka {
       uniforms.add_raw(
@@ -260,21 +287,29 @@ pub struct GPUMesh {
   vbo: glium::VertexBuffer<Vertex>,
   ibo: glium::IndexBuffer<u32>,
   groups: Vec<Group>,
+  lods: LodStrips,
+  // model space, for picking
+  bvh: Bvh,
   mtls: HashMap<String, GPUMtl>,
 }
 
 impl GPUMesh {
+  // lod 0 is the full mesh
   pub fn draw(
     &self,
     frame: &mut impl glium::Surface,
     program: &glium::Program,
     uniforms: &impl glium::uniforms::Uniforms,
     params: &glium::DrawParameters<'_>,
+    lod: usize,
   ) {
-    for group in &self.groups {
+    for (group, range) in self.groups.iter().zip(self.lods.groups(lod)) {
+      if range.is_empty() {
+        continue;
+      }
+
       let mtl = group.mtl.as_deref().and_then(|name| self.mtls.get(name));
-      let range: Range<usize> =
-        (group.index_range.start as usize)..(group.index_range.end as usize);
+      let range: Range<usize> = (range.start as usize)..(range.end as usize);
       let ibo_slice = self.ibo.slice(range).unwrap();
 
       if let Some(mtl) = mtl {
@@ -290,6 +325,15 @@ impl GPUMesh {
       }
     }
   }
+
+  // the levels draw takes, with the model space bounding sphere
+  pub fn lods(&self) -> &LodStrips {
+    &self.lods
+  }
+
+  pub fn bvh(&self) -> &Bvh {
+    &self.bvh
+  }
 }
 
 const fn sampler_behavior_Kd() -> glium::uniforms::SamplerBehavior {
@@ -1,8 +1,13 @@
-use std::path::{Path, PathBuf};
-
-use common::{asset_path, teapot_path};
+use std::path::Path;
+
+use common::mesh::DEFAULT_LOD_RATIOS;
+use common::{
+  asset_path,
+  math::{Aabb, Ray},
+  teapot_path, ProgramHandle,
+};
 use glam::{EulerRot, Mat3, Mat4, Vec3};
-use glium::{uniform, DrawParameters, Program};
+use glium::{uniform, Surface};
 
 use crate::light::Light;
 use crate::mesh::{GPUMesh, Mesh};
@@ -30,7 +35,12 @@ impl Yoda {
     const SHADER_PATH: &str =
       concat!(env!("CARGO_MANIFEST_DIR"), "/assets/shader");
     let yoda_model = asset_path("yoda/yoda.obj");
-    let mut object = GPUObject::load(&yoda_model, &SHADER_PATH, facade)?;
+    let mut object = GPUObject::load_with_lods(
+      &yoda_model,
+      &SHADER_PATH,
+      &DEFAULT_LOD_RATIOS,
+      facade,
+    )?;
 
     object.model = Mat4::from_translation([0.0, -0.3, 0.0].into())
       * Mat4::from_scale(Vec3::splat(0.0003))
@@ -46,41 +56,32 @@ impl Yoda {
 }
 
 pub struct GPUObject {
-  shader_path: PathBuf,
-  program: Program,
+  program: ProgramHandle,
   mesh: GPUMesh,
   model: Mat4,
 }
 
 impl GPUObject {
-  pub fn reload_shader(
-    &mut self,
+  pub fn load(
+    obj_path: &impl AsRef<Path>,
+    shader_path: &impl AsRef<Path>,
     facade: &impl glium::backend::Facade,
-  ) -> Result<()> {
-    let shader_path = &self.shader_path;
-
-    match load_program(shader_path, facade) {
-      Ok(program) => self.program = program,
-      Err(e) => {
-        eprintln!("Failed to reload shader: {}", e);
-      }
-    }
-
-    Ok(())
+  ) -> Result<Self> {
+    Self::load_with_lods(obj_path, shader_path, &[], facade)
   }
 
-  pub fn load(
+  pub fn load_with_lods(
     obj_path: &impl AsRef<Path>,
     shader_path: &impl AsRef<Path>,
+    lod_ratios: &[f32],
     facade: &impl glium::backend::Facade,
   ) -> Result<Self> {
-    let mesh = Mesh::load(obj_path)?;
+    let mesh = Mesh::load_with_lods(obj_path, lod_ratios)?;
     let mesh = mesh.upload(facade)?;
-    let program = load_program(shader_path.as_ref(), facade)?;
+    let program = ProgramHandle::load(shader_path, facade)?;
     let model = Mat4::IDENTITY;
 
     Ok(Self {
-      shader_path: shader_path.as_ref().to_path_buf(),
       program,
       mesh,
       model,
@@ -89,11 +90,30 @@ impl GPUObject {
 
   pub fn update(&mut self, _dt: &std::time::Duration) {}
 
+  // level of detail based on the projected size of the bounding sphere
+  fn lod(&self, mv: Mat4, projection: Mat4, viewport_height: f32) -> usize {
+    self
+      .mesh
+      .lods()
+      .select_projected(mv, projection, viewport_height)
+  }
+
   pub fn model(&self) -> Mat4 {
     self.model
   }
 
-  pub fn draw(&self, frame: &mut glium::Frame, camera: &Camera, light: &Light) {
+  // world space distance along the ray
+  pub fn hit(&self, ray: &Ray) -> Option<f32> {
+    let bvh = self.mesh.bvh();
+    Some(bvh.closest_hit_placed(ray, self.model, f32::MAX)?.t)
+  }
+
+  // world space
+  pub fn bounding_box(&self) -> Aabb {
+    self.mesh.bvh().aabb().transform(self.model)
+  }
+
+  pub fn draw(&self, frame: &mut impl Surface, camera: &Camera, light: &Light) {
     let mv: Mat4 = camera.view() * self.model();
     let mvp: Mat4 = camera.projection() * mv;
 
@@ -112,29 +132,13 @@ impl GPUObject {
       light_color: light.color(),
     };
 
-    let draw_params = DrawParameters {
-      depth: glium::Depth {
-        test: glium::draw_parameters::DepthTest::IfLess,
-        write: true,
-        ..Default::default()
-      },
-      ..Default::default()
-    };
-    let program = &self.program;
+    let draw_params = camera.depth_mode().draw_parameters();
+    let program = self.program.get();
 
-    self.mesh.draw(frame, program, &uniforms, &draw_params);
+    let viewport_height = frame.get_dimensions().1 as f32;
+    let lod = self.lod(mv, camera.projection(), viewport_height);
+    self
+      .mesh
+      .draw(frame, &program, &uniforms, &draw_params, lod);
   }
 }
-
-fn load_program(
-  path: &Path,
-  facade: &impl glium::backend::Facade,
-) -> Result<Program> {
-  let vert_path = path.with_extension("vert");
-  let frag_path = path.with_extension("frag");
-
-  let vert_src = std::fs::read_to_string(vert_path)?;
-  let frag_src = std::fs::read_to_string(frag_path)?;
-
-  Ok(Program::from_source(facade, &vert_src, &frag_src, None)?)
-}
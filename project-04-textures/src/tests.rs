@@ -0,0 +1,32 @@
+use common::{
+  app::App,
+  golden::{Golden, HEIGHT, WIDTH},
+  shader_check::ShaderCheck,
+};
+
+use crate::World;
+
+const GOLDEN_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/golden");
+
+// the yoda model isn't checked in, see assets/README
+#[test]
+fn textured_teapot() -> anyhow::Result<()> {
+  let golden = Golden::new(GOLDEN_DIR)?;
+  let mut world = World::new("teapot");
+  world.camera.handle_resize(WIDTH as f32, HEIGHT as f32);
+  world.update_view();
+  world.load(golden.facade())?;
+  golden.check("teapot", |frame| world.render(frame))?;
+
+  world.light.add_rotation(1.5);
+  golden.check("teapot_moved_light", |frame| world.render(frame))
+}
+
+// every shader in assets, grouped into the programs the code builds
+#[test]
+fn shaders() -> anyhow::Result<()> {
+  ShaderCheck::new(concat!(env!("CARGO_MANIFEST_DIR"), "/assets"))
+    .program(&["shader.vert", "shader.frag"])
+    .rust_uniforms(concat!(env!("CARGO_MANIFEST_DIR"), "/src"))
+    .finish()
+}
@@ -1,37 +1,92 @@
 mod light;
 mod mesh;
 mod object;
+#[cfg(test)]
+mod tests;
 
 use std::time::Duration;
 
 use glium::{
-  dynamic_uniform, glutin::surface::WindowSurface, Display, Surface,
+  backend::Facade, dynamic_uniform, glutin::surface::WindowSurface, Display,
+  Surface,
 };
+use serde::{Deserialize, Serialize};
 
 use object::{GPUObject, IndirectScene, Teapot, Yoda};
 use winit::{
-  application::ApplicationHandler,
-  dpi::{LogicalSize, PhysicalPosition, PhysicalSize},
-  event::{self, KeyEvent, WindowEvent},
-  event_loop::{ActiveEventLoop, ControlFlow, EventLoop},
+  event::{KeyEvent, MouseButton},
   keyboard::{ModifiersState, NamedKey},
-  window::{Window, WindowAttributes, WindowId},
 };
 
-use glam::{EulerRot, Mat3, Mat4, Vec3};
+use glam::{Vec2, Vec3};
 
-use common::Axis;
+use common::{
+  app::{App, Input},
+  camera::{Camera, CameraController, OrbitController},
+  session::{self, CameraState},
+  Axis, CameraLike,
+};
 use light::Light;
 
 type Result<T> = anyhow::Result<T>;
 
-const TARGET_UPS: u32 = 60;
-const TARGET_FRAME_TIME: Duration =
-  Duration::from_micros(1_000_000 / TARGET_UPS as u64);
+// how close the orbit gets and how far it goes
+const MIN_DISTANCE: f32 = 0.1;
+const MAX_DISTANCE: f32 = 10.0;
+
+// looking at the front of the models
+fn orbit_controller() -> OrbitController {
+  OrbitController::new(Vec3::ZERO, 2.0)
+    .with_angles(std::f32::consts::PI, 0.0)
+    .with_distance_range(MIN_DISTANCE, MAX_DISTANCE)
+}
+
+// saved on exit and with F5, restored on start and with F9
+const SESSION_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/session.ron");
+
+#[derive(Serialize, Deserialize)]
+struct Session {
+  view: ViewState,
+  // of the scene rendered into the texture
+  indirect_view: Option<ViewState>,
+  show_axis: bool,
+}
+
+// the camera and the light, both the window and the indirect scene have
+// their own
+#[derive(Serialize, Deserialize)]
+struct ViewState {
+  camera: CameraState,
+  light_rotation: f32,
+}
+
+impl ViewState {
+  fn new(camera: &Camera, light: &Light) -> Self {
+    Self {
+      camera: CameraState::from_camera(camera),
+      light_rotation: light.rotation(),
+    }
+  }
+
+  fn apply(
+    &self,
+    camera: &mut Camera,
+    orbit: &mut OrbitController,
+    light: &mut Light,
+  ) {
+    self.camera.apply(camera);
+    *orbit = OrbitController::from_camera(camera)
+      .with_distance_range(MIN_DISTANCE, MAX_DISTANCE);
+    light.set_rotation(self.light_rotation);
+  }
+}
 
 struct World {
   t: f32,
+  clear_color: [f32; 4],
   camera: Camera,
+  orbit: OrbitController,
+  viewport: Vec2,
   show_axis: bool,
   axis: Option<Axis>,
   light: Light,
@@ -39,106 +94,159 @@ struct World {
   object: Option<GPUObject>,
 }
 
-struct Camera {
-  clear_color: [f32; 4],
-  aspect_ratio: f32,
-  distance: f32,
-  rotation: [f32; 2],
-  perspective: bool,
-  m_view: Mat4,
-  m_proj: Mat4,
-  m_view_proj: Mat4,
-}
-
-impl Camera {
+impl World {
   fn new() -> Self {
     Self {
+      t: 0.0,
       clear_color: [0.0, 0.0, 0.0, 1.0],
-      aspect_ratio: 1.0,
-      distance: 2.0,
-      rotation: [0.0, 0.0],
-      perspective: true,
-      m_view: Mat4::IDENTITY,
-      m_proj: Mat4::IDENTITY,
-      m_view_proj: Mat4::IDENTITY,
+      camera: Camera::default(),
+      orbit: orbit_controller(),
+      viewport: Vec2::ONE,
+      axis: None,
+      show_axis: true,
+      light: Light::new(),
+      object: None,
+      indirect_scene: None,
     }
   }
 
-  fn handle_window_resize(&mut self, new_size: (u32, u32)) {
-    self.aspect_ratio = new_size.0 as f32 / new_size.1 as f32;
-  }
+  // any facade does, the tests render without a window
+  fn load(
+    &mut self,
+    facade: &impl Facade,
+    indirect_scene: IndirectScene,
+  ) -> Result<()> {
+    let axis = Axis::new(facade)?;
+    self.set_axis(axis);
+
+    self.set_object(Teapot::load(facade)?);
+    self.set_indirect_scene(indirect_scene);
 
-  fn view(&self) -> Mat4 {
-    self.m_view
+    self.update(Duration::ZERO);
+    Ok(())
   }
 
-  fn view_projection(&self) -> Mat4 {
-    self.m_view_proj
+  fn session(&self) -> Session {
+    Session {
+      view: ViewState::new(&self.camera, &self.light),
+      indirect_view: self
+        .indirect_scene
+        .as_ref()
+        .map(|scene| ViewState::new(&scene.camera, &scene.light)),
+      show_axis: self.show_axis,
+    }
   }
 
-  fn projection(&self) -> Mat4 {
-    self.m_proj
+  fn restore_session(&mut self, session: &Session) {
+    let view = &session.view;
+    view.apply(&mut self.camera, &mut self.orbit, &mut self.light);
+    if let (Some(scene), Some(view)) =
+      (&mut self.indirect_scene, &session.indirect_view)
+    {
+      view.apply(&mut scene.camera, &mut scene.orbit, &mut scene.light);
+      scene.update_view();
+    }
+    self.show_axis = session.show_axis;
+    self.update_view();
   }
 
-  fn calc_view(&self) -> Mat4 {
-    let dir = Mat3::from_euler(
-      EulerRot::YXZ,
-      self.rotation[0].to_radians(),
-      -self.rotation[1].to_radians(),
-      0.0,
-    ) * Vec3::new(0.0, 0.0, 1.0);
-    let eye = Vec3::new(0.0, 0.0, 0.0) + -dir * self.distance;
-    let up = Vec3::new(0.0, 1.0, 0.0);
-    let origin = Vec3::new(0.0, 0.0, 0.0);
-    Mat4::look_at_rh(eye, origin, up)
+  fn save_session(&self) {
+    session::save_or_warn(SESSION_PATH, &self.session());
   }
 
-  fn calc_proj(&self) -> Mat4 {
-    if self.perspective {
-      Mat4::perspective_rh_gl(
-        60.0_f32.to_radians(),
-        self.aspect_ratio,
-        0.1,
-        100.0,
-      )
-    } else {
-      Mat4::orthographic_rh_gl(
-        -1.0,
-        1.0,
-        -1.0 / self.aspect_ratio,
-        1.0 / self.aspect_ratio,
-        0.1,
-        100.0,
-      ) * (1.0 / self.distance)
+  fn load_session(&mut self) {
+    if let Some(session) = session::load_or_warn(SESSION_PATH) {
+      self.restore_session(&session);
+      println!("Restored session from {}", SESSION_PATH);
     }
   }
 
+  fn set_axis(&mut self, axis: Axis) {
+    self.axis = Some(axis);
+  }
+
   fn update_view(&mut self) {
-    self.m_view = self.calc_view();
-    self.m_proj = self.calc_proj();
-    self.m_view_proj = self.m_proj * self.m_view;
+    self.orbit.apply(&mut self.camera);
+    // near and far planes as tight as the object allows
+    if let Some(object) = &self.object {
+      self.camera.fit_near_far(&object.bounding_box());
+    }
   }
-}
 
-impl World {
-  fn new() -> Self {
-    Self {
-      t: 0.0,
-      camera: Camera::new(),
-      axis: None,
-      show_axis: true,
-      light: Light::new(),
-      object: None,
-      indirect_scene: None,
+  fn set_indirect_scene(&mut self, scene: IndirectScene) {
+    let _ = self.indirect_scene.insert(scene);
+  }
+
+  fn set_object(&mut self, object: GPUObject) {
+    let _ = self.object.insert(object);
+    self.update_view();
+  }
+
+  fn handle_key(&mut self, event: KeyEvent) {
+    if !event.state.is_pressed() {
+      return;
+    }
+
+    if event.logical_key == "p" {
+      self.camera.toggle_projection();
+      self.update_view();
+    } else if event.logical_key == "a" {
+      self.show_axis = !self.show_axis;
+    } else if event.logical_key == "f" {
+      self.frame_object();
+    } else if event.logical_key == NamedKey::F5 {
+      self.save_session();
+    } else if event.logical_key == NamedKey::F9 {
+      self.load_session();
     }
   }
 
-  fn set_axis(&mut self, axis: Axis) {
-    self.axis = Some(axis);
+  // the textured object is all there is to select in the window, the
+  // scene inside the texture is only looked at
+  fn pick(&mut self, pixel: Vec2) {
+    let ray = self.camera.pixel_ray(pixel, self.viewport);
+    match self.object.as_ref().and_then(|object| object.hit(&ray)) {
+      Some(_) => println!("Selected the object"),
+      None => println!("Selection cleared"),
+    }
   }
 
-  fn update_view(&mut self) {
-    self.camera.update_view();
+  fn frame_object(&mut self) {
+    if let Some(object) = &self.object {
+      self.orbit.frame(&self.camera, &object.bounding_box());
+      self.update_view();
+    }
+  }
+
+  // with shift held the mouse moves the scene rendered into the texture
+  fn view_mut(
+    &mut self,
+    modifiers: ModifiersState,
+  ) -> (&mut OrbitController, &mut Light) {
+    match &mut self.indirect_scene {
+      Some(scene) if modifiers.shift_key() => {
+        (&mut scene.orbit, &mut scene.light)
+      }
+      _ => (&mut self.orbit, &mut self.light),
+    }
+  }
+
+  fn update_views(&mut self) {
+    if let Some(scene) = &mut self.indirect_scene {
+      scene.update_view();
+    }
+    self.update_view();
+  }
+}
+
+impl App for World {
+  fn init(&mut self, display: &Display<WindowSurface>) -> Result<()> {
+    let indirect_scene = Yoda::load_indirect_scene(display)?;
+    self.load(display, indirect_scene)?;
+    // the tests start from the defaults, only the window picks up where
+    // the last run left off
+    self.load_session();
+    Ok(())
   }
 
   fn update(&mut self, dt: Duration) {
@@ -155,11 +263,7 @@ impl World {
     }
   }
 
-  fn render(
-    &self,
-    context: &Display<WindowSurface>,
-    _dt: Duration,
-  ) -> Result<()> {
+  fn render(&self, frame: &mut impl Surface) -> Result<()> {
     let sampler = self
       .indirect_scene
       .as_ref()
@@ -171,12 +275,12 @@ impl World {
           .minify_filter(glium::uniforms::MinifySamplerFilter::Linear)
       });
 
-    let mut frame = context.draw();
-    frame.clear_color_and_depth(self.camera.clear_color.into(), 1.0);
+    let clear_depth = self.camera.depth_mode().clear_depth();
+    frame.clear_color_and_depth(self.clear_color.into(), clear_depth);
 
     if let Some(axis) = self.axis.as_ref() {
       if self.show_axis {
-        if let Err(e) = axis.draw(&mut frame, &self.camera.view_projection()) {
+        if let Err(e) = axis.draw(frame, &self.camera.view_projection()) {
           eprintln!("Failed to draw axis: {}", e);
         }
       }
@@ -196,299 +300,84 @@ impl World {
         dynamic_uniform! {}
       };
 
-      obj.draw_with_extra_uniforms(
-        &mut frame,
-        &self.camera,
-        &self.light,
-        uniforms,
-      );
+      obj.draw_with_extra_uniforms(frame, &self.camera, &self.light, uniforms);
     }
 
-    frame.finish()?;
     Ok(())
   }
 
-  fn set_indirect_scene(&mut self, scene: IndirectScene) {
-    let _ = self.indirect_scene.insert(scene);
-  }
-
-  fn set_object(&mut self, object: GPUObject) {
-    let _ = self.object.insert(object);
-  }
-}
-
-struct App {
-  window: Option<Window>,
-  display: Option<Display<WindowSurface>>,
-  last_update: std::time::Instant,
-
-  mouse_down: (bool, bool),
-  last_pos: [f32; 2],
-  mouse_pos: [f32; 2],
-  modifiers: ModifiersState,
-
-  world: World,
-}
-
-impl App {
-  fn new() -> Self {
-    let last_update = std::time::Instant::now();
-
-    Self {
-      window: None,
-      display: None,
-      last_update,
-      last_pos: [0.0, 0.0],
-      mouse_pos: [0.0, 0.0],
-      mouse_down: (false, false),
-      modifiers: ModifiersState::empty(),
-      world: World::new(),
-    }
-  }
-
-  fn handle_resize(&mut self, size: PhysicalSize<u32>) {
-    println!("Resized to {:?}", size);
-    self
-      .world
-      .camera
-      .handle_window_resize((size.width, size.height));
-    self.world.update_view();
-    self.request_redraw();
-  }
-
-  fn handle_keyboard(&mut self, event: KeyEvent, event_loop: &ActiveEventLoop) {
-    if !event.state.is_pressed() {
-      return;
-    }
-    if event.logical_key == NamedKey::Escape {
-      event_loop.exit();
-    } else if event.logical_key.to_text() == Some("p") {
-      self.world.camera.perspective ^= true;
-      self.world.update_view();
-      self.request_redraw();
-    } else if event.logical_key.to_text() == Some("a") {
-      self.world.show_axis = !self.world.show_axis;
-      self.request_redraw();
-    } else if event.logical_key == NamedKey::F6 {
-      if let Some(indirect_scene) = &mut self.world.indirect_scene {
-        for obj in &mut indirect_scene.objects {
-          obj.reload_shader(self.display.as_ref().unwrap()).unwrap();
-        }
+  fn on_input(&mut self, input: Input) {
+    match input {
+      Input::Key(event) => self.handle_key(event),
+      Input::Click {
+        button: MouseButton::Left,
+        pos,
+        ..
+      } => self.pick(pos),
+      // ctrl + left drag: rotate light
+      Input::Drag {
+        button: MouseButton::Left,
+        from,
+        to,
+        modifiers,
+      } if modifiers.control_key() => {
+        let (_, light) = self.view_mut(modifiers);
+        light.add_rotation((to.x - from.x) * 0.01);
       }
-
-      if let Some(object) = &mut self.world.object {
-        object
-          .reload_shader(self.display.as_ref().unwrap())
-          .unwrap();
+      // left drag: rotate camera
+      Input::Drag {
+        button: MouseButton::Left,
+        from,
+        to,
+        modifiers,
+      } => {
+        let viewport = self.viewport;
+        let (orbit, _) = self.view_mut(modifiers);
+        orbit.drag(from, to, viewport);
+        self.update_views();
       }
-
-      println!("Reloaded shaders");
-      self.request_redraw();
-    }
-  }
-
-  fn handle_redraw(&mut self) {
-    if let Some(display) = &self.display {
-      self
-        .world
-        .render(display, self.last_update.elapsed())
-        .expect("Failed to render");
-    }
-  }
-
-  fn update(&mut self) {
-    self.world.update(self.last_update.elapsed());
-
-    let help =
-      "Press 'p' to toggle perspective, 'a' to toggle axis, Esc to quit";
-    let title = format!(
-      "3D Object Viewer - {:.0} UPS",
-      1_000_000_000 / self.last_update.elapsed().as_nanos()
-    );
-
-    if let Some(window) = self.window.as_ref() {
-      window.set_title(&format!("{} - {}", title, help));
-      window.request_redraw();
-    }
-
-    self.last_update = std::time::Instant::now();
-  }
-
-  fn handle_mouse_input(
-    &mut self,
-    state: event::ElementState,
-    button: event::MouseButton,
-  ) {
-    let pressed = state.is_pressed();
-
-    match button {
-      event::MouseButton::Left => {
-        self.mouse_down.0 = pressed;
+      // right drag: change camera distance, dragging down moves away
+      Input::Drag {
+        button: MouseButton::Right,
+        from,
+        to,
+        modifiers,
+      } => {
+        let (orbit, _) = self.view_mut(modifiers);
+        orbit.zoom(-(to.y - from.y) / 20.0);
+        self.update_views();
       }
-      event::MouseButton::Right => {
-        self.mouse_down.1 = pressed;
+      Input::Scroll { dy, modifiers, .. } => {
+        let (orbit, _) = self.view_mut(modifiers);
+        // 30 per notch
+        orbit.zoom(dy / 30.0);
+        self.update_views();
       }
       _ => {}
     }
   }
 
-  fn handle_cursor_moved(&mut self, position: PhysicalPosition<f64>) {
-    self.mouse_pos = [position.x as f32, position.y as f32];
-    let indirect_scene = self.world.indirect_scene.as_mut().unwrap();
-
-    let camera_target: &mut Camera = if self.modifiers.shift_key() {
-      &mut indirect_scene.camera
-    } else {
-      &mut self.world.camera
-    };
-    let light_target: &mut Light = if self.modifiers.shift_key() {
-      &mut indirect_scene.light
-    } else {
-      &mut self.world.light
-    };
-
-    if self.mouse_down.0 && !self.modifiers.control_key() {
-      let dx = self.mouse_pos[0] - self.last_pos[0];
-      let dy = self.mouse_pos[1] - self.last_pos[1];
-      camera_target.rotation[0] += dx * 0.1;
-      camera_target.rotation[1] += dy * 0.1;
-    }
-
-    if self.mouse_down.0 && self.modifiers.control_key() {
-      let dx = self.mouse_pos[0] - self.last_pos[0];
-      let _dy = self.mouse_pos[1] - self.last_pos[1];
-      light_target.add_rotation(dx * 0.01);
-    }
-
-    if self.mouse_down.1 {
-      let dy = self.mouse_pos[1] - self.last_pos[1];
-
-      camera_target.distance += dy * 0.01;
-      camera_target.distance = camera_target.distance.clamp(0.1, 10.0);
-    }
-
-    indirect_scene.camera.update_view();
-    self.world.update_view();
-    self.last_pos = self.mouse_pos;
-    self.request_redraw();
-  }
-
-  fn handle_mouse_wheel(&mut self, delta: event::MouseScrollDelta) {
-    let d = match delta {
-      event::MouseScrollDelta::LineDelta(_x, y) => y * 20.0,
-      event::MouseScrollDelta::PixelDelta(pos) => pos.y as f32,
-    };
-
-    let indirect_scene = self.world.indirect_scene.as_mut().unwrap();
-    let camera_target: &mut Camera = if self.modifiers.shift_key() {
-      &mut indirect_scene.camera
-    } else {
-      &mut self.world.camera
-    };
-    camera_target.distance -= d * 0.01;
-    camera_target.distance = camera_target.distance.clamp(0.1, 10.0);
-    indirect_scene.camera.update_view();
-    self.world.update_view();
-  }
-
-  fn request_redraw(&self) {
-    if let Some(window) = self.window.as_ref() {
-      window.request_redraw();
-    }
-  }
-
-  fn schedule_next_frame(&self, event_loop: &ActiveEventLoop) {
-    let wake_up_at = self.last_update + TARGET_FRAME_TIME;
-    event_loop.set_control_flow(ControlFlow::WaitUntil(wake_up_at));
-  }
-
-  fn handle_init(&mut self, display: &Display<WindowSurface>) -> Result<()> {
-    let axis = Axis::new(display)?;
-    self.world.set_axis(axis);
-
-    self.world.set_object(Teapot::load(display)?);
-
-    let indirect_scene = Yoda::load_indirect_scene(display)?;
-    self.world.set_indirect_scene(indirect_scene);
-
-    self.world.update(Duration::from_secs(0));
-    Ok(())
-  }
-}
-
-impl ApplicationHandler for App {
-  fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-    if self.window.is_none() {
-      let window_attrs = WindowAttributes::default()
-        .with_title("3D Object Viewer")
-        .with_inner_size(LogicalSize::new(800, 600));
-
-      match event_loop.create_window(window_attrs) {
-        Ok(window) => {
-          let display = common::gl_boilerplate::init_display(&window);
-          self.handle_init(&display).expect("Failed to init world");
-
-          self.window = Some(window);
-          self.display = Some(display);
-        }
-        Err(e) => {
-          eprintln!("Failed to create window: {}", e);
-          event_loop.exit();
-        }
-      }
-    }
+  fn resize(&mut self, width: u32, height: u32) {
+    println!("Resized to {}x{}", width, height);
+    self.viewport = Vec2::new(width as f32, height as f32);
+    self.camera.handle_resize(width as f32, height as f32);
+    self.update_view();
   }
 
-  fn window_event(
-    &mut self,
-    event_loop: &ActiveEventLoop,
-    _window_id: WindowId,
-    event: WindowEvent,
-  ) {
-    match event {
-      WindowEvent::Resized(size) => self.handle_resize(size),
-      WindowEvent::KeyboardInput { event, .. } => {
-        self.handle_keyboard(event, event_loop)
-      }
-      WindowEvent::RedrawRequested => {
-        self.handle_redraw();
-      }
-      WindowEvent::CloseRequested => {
-        event_loop.exit();
-      }
-      WindowEvent::MouseInput { state, button, .. } => {
-        self.handle_mouse_input(state, button);
-      }
-      WindowEvent::MouseWheel { delta, .. } => {
-        self.handle_mouse_wheel(delta);
-      }
-      WindowEvent::CursorMoved { position, .. } => {
-        self.handle_cursor_moved(position);
-      }
-      WindowEvent::ModifiersChanged(new_modifiers) => {
-        self.modifiers = new_modifiers.state();
-      }
-      _ => {}
-    }
+  fn help(&self) -> &str {
+    "Press 'p' to toggle perspective, 'a' to toggle axis, shift to move the \
+      inner scene, click to select the object, 'f' to frame it, F5 to save \
+      the session, F9 to restore it, F12 for a screenshot, Esc to quit"
   }
 
-  fn new_events(
-    &mut self,
-    event_loop: &ActiveEventLoop,
-    _cause: event::StartCause,
-  ) {
-    self.schedule_next_frame(event_loop);
-
-    if self.last_update.elapsed() > TARGET_FRAME_TIME {
-      self.update();
+  // only once there was something to look at
+  fn exit(&mut self) {
+    if self.object.is_some() {
+      self.save_session();
     }
   }
 }
 
 fn main() -> Result<()> {
-  let event_loop = EventLoop::new()?;
-  let mut app = App::new();
-
-  event_loop.run_app(&mut app)?;
-  Ok(())
+  common::app::run(World::new())
 }
@@ -1,16 +1,22 @@
-use std::path::{Path, PathBuf};
+use std::path::Path;
 use std::rc::Rc;
 
-use common::{asset_path, teapot_path, DynUniforms, MergedUniform};
+use common::camera::CameraController;
+use common::mesh::DEFAULT_LOD_RATIOS;
+use common::{
+  asset_path,
+  math::{Aabb, Ray},
+  teapot_path, DynUniforms, MergedUniform, ProgramHandle,
+};
 use glam::{Mat3, Mat4, Vec3};
 use glium::backend::{Context, Facade};
 use glium::framebuffer::{DepthRenderBuffer, SimpleFrameBuffer};
 use glium::uniforms::Uniforms;
-use glium::{uniform, DrawParameters, Program, Surface, Texture2d};
+use glium::{uniform, Surface, Texture2d};
 
 use crate::light::Light;
 use crate::mesh::{GPUMesh, Mesh};
-use crate::{Camera, Result};
+use crate::{orbit_controller, Camera, OrbitController, Result};
 
 pub struct RenderBuffer {
   texture: Texture2d,
@@ -39,6 +45,7 @@ impl RenderBuffer {
 
 pub struct IndirectScene {
   pub camera: Camera,
+  pub orbit: OrbitController,
   pub light: Light,
   pub objects: Vec<GPUObject>,
   pub buffer: RenderBuffer,
@@ -52,23 +59,38 @@ impl IndirectScene {
     light: Light,
     buffer: RenderBuffer,
   ) -> Self {
-    Self {
+    let mut scene = Self {
       camera,
+      orbit: orbit_controller(),
       light,
       buffer,
       objects: Vec::new(),
       context: facade.get_context().clone(),
-    }
+    };
+    scene.update_view();
+    scene
+  }
+
+  pub fn update_view(&mut self) {
+    self.orbit.apply(&mut self.camera);
+    // near and far planes as tight as the objects allow
+    let bounds = self
+      .objects
+      .iter()
+      .fold(Aabb::EMPTY, |aabb, o| aabb.union(&o.bounding_box()));
+    self.camera.fit_near_far(&bounds);
   }
 
   pub fn add_object(&mut self, object: GPUObject) {
     self.objects.push(object);
+    self.update_view();
   }
 
   pub fn render(&self) -> Result<&Texture2d> {
     let mut fb = self.buffer.framebuffer(&self.context)?;
 
-    fb.clear_color_and_depth((0.0, 0.0, 0.0, 1.0), 1.0);
+    let clear_depth = self.camera.depth_mode().clear_depth();
+    fb.clear_color_and_depth((0.0, 0.0, 0.0, 1.0), clear_depth);
     for obj in &self.objects {
       obj.draw(&mut fb, &self.camera, &self.light);
     }
@@ -94,7 +116,7 @@ impl Teapot {
 
   #[allow(dead_code)]
   pub fn load_indirect_scene(facade: &impl Facade) -> Result<IndirectScene> {
-    let camera = Camera::new();
+    let camera = Camera::default();
     let light = Light::new();
     let object = Self::load(facade)?;
     let mut scene = IndirectScene::new(
@@ -116,7 +138,12 @@ impl Yoda {
     const SHADER_PATH: &str =
       concat!(env!("CARGO_MANIFEST_DIR"), "/assets/shader");
     let yoda_model = asset_path("yoda/yoda.obj");
-    let mut object = GPUObject::load(&yoda_model, &SHADER_PATH, facade)?;
+    let mut object = GPUObject::load_with_lods(
+      &yoda_model,
+      &SHADER_PATH,
+      &DEFAULT_LOD_RATIOS,
+      facade,
+    )?;
 
     object.model = Mat4::from_translation(Vec3::new(0.0, -0.3, 0.0))
       * Mat4::from_scale(Vec3::splat(0.0003))
@@ -127,7 +154,7 @@ impl Yoda {
   }
 
   pub fn load_indirect_scene(facade: &impl Facade) -> Result<IndirectScene> {
-    let camera = Camera::new();
+    let camera = Camera::default();
     let light = Light::new();
     let object = Self::load(facade)?;
     let mut scene = IndirectScene::new(
@@ -143,38 +170,32 @@ impl Yoda {
 }
 
 pub struct GPUObject {
-  shader_path: PathBuf,
-  program: Program,
+  program: ProgramHandle,
   mesh: GPUMesh,
   model: Mat4,
 }
 
 impl GPUObject {
-  pub fn reload_shader(&mut self, facade: &impl Facade) -> Result<()> {
-    let shader_path = &self.shader_path;
-
-    match load_program(shader_path, facade) {
-      Ok(program) => self.program = program,
-      Err(e) => {
-        eprintln!("Failed to reload shader: {}", e);
-      }
-    }
-
-    Ok(())
+  pub fn load(
+    obj_path: &impl AsRef<Path>,
+    shader_path: &impl AsRef<Path>,
+    facade: &impl Facade,
+  ) -> Result<Self> {
+    Self::load_with_lods(obj_path, shader_path, &[], facade)
   }
 
-  pub fn load(
+  pub fn load_with_lods(
     obj_path: &impl AsRef<Path>,
     shader_path: &impl AsRef<Path>,
+    lod_ratios: &[f32],
     facade: &impl Facade,
   ) -> Result<Self> {
-    let mesh = Mesh::load(obj_path)?;
+    let mesh = Mesh::load_with_lods(obj_path, lod_ratios)?;
     let mesh = mesh.upload(facade)?;
-    let program = load_program(shader_path.as_ref(), facade)?;
+    let program = ProgramHandle::load(shader_path, facade)?;
     let model = Mat4::IDENTITY;
 
     Ok(Self {
-      shader_path: shader_path.as_ref().to_path_buf(),
       program,
       mesh,
       model,
@@ -183,10 +204,29 @@ impl GPUObject {
 
   pub fn update(&mut self, _dt: &std::time::Duration) {}
 
+  // level of detail based on the projected size of the bounding sphere
+  fn lod(&self, mv: Mat4, projection: Mat4, viewport_height: f32) -> usize {
+    self
+      .mesh
+      .lods()
+      .select_projected(mv, projection, viewport_height)
+  }
+
   pub fn model(&self) -> Mat4 {
     self.model
   }
 
+  // world space distance along the ray
+  pub fn hit(&self, ray: &Ray) -> Option<f32> {
+    let bvh = self.mesh.bvh();
+    Some(bvh.closest_hit_placed(ray, self.model, f32::MAX)?.t)
+  }
+
+  // world space
+  pub fn bounding_box(&self) -> Aabb {
+    self.mesh.bvh().aabb().transform(self.model)
+  }
+
   pub fn draw(&self, frame: &mut impl Surface, camera: &Camera, light: &Light) {
     self.draw_with_extra_uniforms(frame, camera, light, DynUniforms::new());
   }
@@ -217,27 +257,14 @@ impl GPUObject {
         light_color: light.color(),
     };
 
-    let draw_params = DrawParameters {
-      depth: glium::Depth {
-        test: glium::draw_parameters::DepthTest::IfLess,
-        write: true,
-        ..Default::default()
-      },
-      ..Default::default()
-    };
-    let program = &self.program;
+    let draw_params = camera.depth_mode().draw_parameters();
+    let program = self.program.get();
     let uniforms = MergedUniform::new(&extra_uniforms, &uniforms);
 
-    self.mesh.draw(frame, program, &uniforms, &draw_params);
+    let viewport_height = frame.get_dimensions().1 as f32;
+    let lod = self.lod(mv, camera.projection(), viewport_height);
+    self
+      .mesh
+      .draw(frame, &program, &uniforms, &draw_params, lod);
   }
 }
-
-fn load_program(path: &Path, facade: &impl Facade) -> Result<Program> {
-  let vert_path = path.with_extension("vert");
-  let frag_path = path.with_extension("frag");
-
-  let vert_src = std::fs::read_to_string(vert_path)?;
-  let frag_src = std::fs::read_to_string(frag_path)?;
-
-  Ok(Program::from_source(facade, &vert_src, &frag_src, None)?)
-}
@@ -0,0 +1,40 @@
+use common::{
+  app::App,
+  golden::{Golden, HEIGHT, WIDTH},
+  shader_check::ShaderCheck,
+};
+
+use crate::{object::Teapot, orbit_controller, World};
+
+const GOLDEN_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/golden");
+
+// the teapot on the teapot, as the yoda model isn't checked in
+#[test]
+fn render_to_texture() -> anyhow::Result<()> {
+  let golden = Golden::new(GOLDEN_DIR)?;
+  let mut world = World::new();
+  world.camera.handle_resize(WIDTH as f32, HEIGHT as f32);
+  world.update_view();
+  world.load(
+    golden.facade(),
+    Teapot::load_indirect_scene(golden.facade())?,
+  )?;
+  golden.check("teapot_texture", |frame| world.render(frame))?;
+
+  // only the inner scene moves
+  if let Some(scene) = &mut world.indirect_scene {
+    scene.orbit =
+      orbit_controller().with_angles(std::f32::consts::PI + 1.0, 0.3);
+    scene.update_view();
+  }
+  golden.check("teapot_texture_rotated", |frame| world.render(frame))
+}
+
+// every shader in assets, grouped into the programs the code builds
+#[test]
+fn shaders() -> anyhow::Result<()> {
+  ShaderCheck::new(concat!(env!("CARGO_MANIFEST_DIR"), "/assets"))
+    .program(&["shader.vert", "shader.frag"])
+    .rust_uniforms(concat!(env!("CARGO_MANIFEST_DIR"), "/src"))
+    .finish()
+}
@@ -3,15 +3,19 @@
 layout(location = 0) in vec2 clip_pos;
 
 uniform mat4 view_proj_inv;
+// ndc depth of the far plane, 1.0 unless depth is reversed
+uniform float far_depth;
 uniform samplerCube env_map;
 
 out vec3 world_pos;
 
 void main()
 {
-  vec4 world_pos4 = view_proj_inv * vec4(clip_pos.xy, 1.0, 1.0);
-  world_pos = world_pos4.xyz / world_pos4.w;
+  vec4 world_pos4 = view_proj_inv * vec4(clip_pos.xy, far_depth, 1.0);
+  // w is positive, or zero for an infinite far plane. either way xyz
+  // points the right way, and the direction is all the cubemap needs.
+  world_pos = world_pos4.xyz;
 
-  // set depth to be 1.0 (far plane)
-  gl_Position = vec4(clip_pos.xy, 1.0, 1.0);
+  // set depth to the far plane
+  gl_Position = vec4(clip_pos.xy, far_depth, 1.0);
 }
@@ -8,23 +8,8 @@ in vec3 n_v; // in view space
 in vec3 pos_w; // in world space
 in vec3 n_w; // in world space
 
-// diffuse, specular, ambient
-uniform vec3 Kd, Ks, Ka;
-uniform float Ns; // shininess
-
-// unused properties:
-
-// dissolve (transparency), index of refraction,
-// transmission filter.
-uniform float d, Ni, Tr;
-uniform vec3 Tf, Ke; // Tf, emission
-uniform uint illum;
-
-// end unused properties
-
-// textures
-uniform sampler2D map_Kd, map_Ks, map_Ka;
-uniform uint use_map_Kd, use_map_Ks, use_map_Ka;
+#include <material.glsl>
+#include <lighting.glsl>
 
 // in view space
 uniform vec3 light_pos;
@@ -41,12 +26,8 @@ void main() {
   vec3 n_v = normalize(n_v);
   vec3 light_dir = normalize(light_pos - pos_v);
   vec3 view_dir = normalize(-pos_v);
-  // geometry term
-  float geom = max(dot(n_v, light_dir), 0.0);
-
-  // half vector
-  vec3 h = normalize(light_dir + view_dir);
-  float spec = pow(max(dot(n_v, h), 0.0), Ns * 100);
+  float geom = lambert(n_v, light_dir);
+  float spec = blinn_phong(n_v, light_dir, view_dir, Ns * 100);
 
   // reflected view direction
   vec3 refl_dir = reflect(-view_dir, n_v);
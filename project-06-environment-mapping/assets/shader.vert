@@ -13,9 +13,6 @@ out vec3 orig_pos; // in model space
 uniform mat4 mv, mvp;
 uniform mat3 mv3, mv_n; // for transforming vertex normals
 
-uniform sampler2D map_bump;
-uniform uint use_map_bump;
-
 void main()
 {
   orig_pos = pos;
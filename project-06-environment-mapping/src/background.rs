@@ -1,12 +1,12 @@
-use std::{fs::read_to_string, path::Path};
+use std::path::Path;
 
-use common::{project_asset_path, to_raw_image};
+use common::{project_asset_path, to_raw_image, ProgramHandle};
 use glium::{
   backend::Facade,
   framebuffer::SimpleFrameBuffer,
   implement_vertex,
   texture::{CubeLayer, Cubemap},
-  uniform, BlitTarget, Program, Surface, Texture2d, VertexBuffer,
+  uniform, BlitTarget, Surface, Texture2d, VertexBuffer,
 };
 
 use crate::{camera::Camera, Result};
@@ -21,7 +21,7 @@ implement_vertex!(CameraPlaneVertex, clip_pos);
 pub struct Background {
   vertices: VertexBuffer<CameraPlaneVertex>,
   cubemap: Cubemap,
-  shader: Program,
+  shader: ProgramHandle,
 }
 
 impl Background {
@@ -35,9 +35,8 @@ impl Background {
     ];
     let vertices = VertexBuffer::new(facade, &verts)?;
 
-    let vert_src = read_to_string(project_asset_path!("camera_plane.vert"))?;
-    let frag_src = read_to_string(project_asset_path!("camera_plane.frag"))?;
-    let shader = Program::from_source(facade, &vert_src, &frag_src, None)?;
+    let shader =
+      ProgramHandle::load(project_asset_path!("camera_plane"), facade)?;
 
     let cubemap = load_cubemap_from_file(facade, cubemap_path)?;
 
@@ -48,16 +47,15 @@ impl Background {
     })
   }
 
-  pub fn reload_shader(&mut self, facade: &impl Facade) -> Result<()> {
-    let vert_src = read_to_string(project_asset_path!("camera_plane.vert"))?;
-    let frag_src = read_to_string(project_asset_path!("camera_plane.frag"))?;
-    self.shader = Program::from_source(facade, &vert_src, &frag_src, None)?;
-    Ok(())
+  pub fn cubemap(&self) -> &Cubemap {
+    &self.cubemap
   }
 
   pub fn draw(&self, target: &mut impl Surface, camera: &Camera) {
-    let view_proj_inv: [[f32; 4]; 4] =
-      camera.view_projection().inverse().to_cols_array_2d();
+    let view_proj_inv: [[f32; 4]; 4] = camera
+      .unclipped_view_projection()
+      .inverse()
+      .to_cols_array_2d();
     let env_map = self
       .cubemap
       .sampled()
@@ -66,6 +64,7 @@ impl Background {
 
     let uniforms = uniform! {
       view_proj_inv: view_proj_inv,
+      far_depth: camera.depth_mode().range().far,
       env_map: env_map,
     };
 
@@ -74,13 +73,14 @@ impl Background {
 
     let draw_params = glium::DrawParameters {
       depth: glium::Depth {
-        // In vertex shader we will write gl_Position.z=1.0 for all
-        // vertices. This way only the background will be drawn
-        // because clear_depth=1.0.
+        // In vertex shader we will write gl_Position.z=far_depth for
+        // all vertices. This way only the background will be drawn
+        // because the depth buffer is cleared to the far plane.
         test: glium::draw_parameters::DepthTest::IfEqual,
         write: false,
         ..Default::default()
       },
+      clip_control_depth: camera.depth_mode().clip_control_depth(),
       ..Default::default()
     };
 
@@ -88,7 +88,7 @@ impl Background {
       .draw(
         &self.vertices,
         indices,
-        &self.shader,
+        &self.shader.get(),
         &uniforms,
         &draw_params,
       )
@@ -1,87 +1,92 @@
-use glam::{Mat3, Mat4, Vec3};
-use glium::Rect;
+use common::{
+  camera::{self, CameraController, DepthMode, OrbitController, Projection},
+  math::{oblique_projection, Aabb, Frustum, Plane},
+  session::CameraState,
+};
+use glam::{Mat4, Vec3};
+use glium::{
+  backend::Facade,
+  texture::{DepthTexture2d, MipmapsOption},
+  Rect,
+};
+
+use crate::Result;
+
+// unless turned off or the context has no clip control. the depth
+// tests, clear values and depth buffers all follow the camera's mode,
+// and the runner draws the window's frames with float depth for it.
+pub const DEFAULT_DEPTH_MODE: DepthMode = DepthMode::ReversedZ;
+
+// as far as the orbit goes, standard depth has no precision left for
+// anything further
+const STANDARD_FAR: f32 = 100.0;
+
+// nothing is beyond the skybox, so with reversed-Z the far plane can go
+fn projection(depth_mode: DepthMode) -> Projection {
+  let far = match depth_mode {
+    DepthMode::Standard => STANDARD_FAR,
+    DepthMode::ReversedZ => f32::INFINITY,
+  };
+  Projection::Perspective {
+    fov: 90.0,
+    near: 0.1,
+    far,
+  }
+}
 
+// the shared camera plus what a render pass needs to know about it
 pub struct Camera {
+  camera: camera::Camera,
   clear_color: [f32; 4],
-  // camera
-  aspect_ratio: f32,
-  distance: f32,
-  rotation: [f32; 2],
-  perspective: bool,
   // only draw within this rectangle (window space)
   scissor: Option<Rect>,
-  // cached matrices
-  m_view: Mat4,
-  m_proj: Mat4,
-  m_view_proj: Mat4,
+  // only draw in front of this plane (world space)
+  clip_plane: Option<Plane>,
   mirror: bool,
 }
 
 impl Camera {
-  pub fn new() -> Self {
-    Self {
-      clear_color: [0.0, 0.0, 0.0, 1.0],
-      aspect_ratio: 1.0,
-      distance: 2.0,
-      rotation: [0.0, 0.0],
-      scissor: None,
-      perspective: true,
-
-      m_view: Mat4::IDENTITY,
-      m_proj: Mat4::IDENTITY,
-      m_view_proj: Mat4::IDENTITY,
-      mirror: false,
-    }
+  pub fn new(depth_mode: DepthMode) -> Self {
+    let camera = camera::Camera::new(Mat4::IDENTITY, projection(depth_mode));
+    Self::with_camera(camera, depth_mode, false)
   }
 
-  pub fn from_view_projection(view: Mat4, proj: Mat4, mirror: bool) -> Self {
-    let m_view_proj = proj * view;
-
+  fn with_camera(
+    camera: camera::Camera,
+    depth_mode: DepthMode,
+    mirror: bool,
+  ) -> Self {
     Self {
-      // all unused
+      camera: camera.with_depth_mode(depth_mode),
       clear_color: [0.0, 0.0, 0.0, 1.0],
-      aspect_ratio: 1.0,
-      distance: 0.0,
-      rotation: [0.0, 0.0],
-      perspective: true,
       scissor: None,
-
-      // actually used
-      m_view: view,
-      m_proj: proj,
-      m_view_proj,
+      clip_plane: None,
       mirror,
     }
   }
 
+  pub fn from_view_projection(
+    view: Mat4,
+    proj: Mat4,
+    depth_mode: DepthMode,
+    mirror: bool,
+  ) -> Self {
+    let camera = camera::Camera::new(view, Projection::Custom(proj));
+    Self::with_camera(camera, depth_mode, mirror)
+  }
+
   // sitting at a point, looking to a direction.
   // point and direction are given in world space.
   pub fn for_cubemap_face(
     point: Vec3,
     dir: Vec3,
     up: Vec3,
+    depth_mode: DepthMode,
     mirror: bool,
   ) -> Self {
-    let m_view = Mat4::look_to_rh(point, dir, up);
-
-    let m_proj = Mat4::perspective_rh(90.0f32.to_radians(), 1.0, 0.1, 100.0);
-    let m_view_proj = m_proj * m_view;
-
-    Self {
-      clear_color: [0.0, 0.0, 0.0, 1.0],
-      // these fields are all dummy. they are not used in this context.
-      aspect_ratio: 1.0,
-      distance: 0.0,
-      rotation: [0.0, 0.0],
-      perspective: true,
-      scissor: None,
-
-      // only these fields can be safely used.
-      m_view,
-      m_proj,
-      m_view_proj,
-      mirror,
-    }
+    let view = Mat4::look_to_rh(point, dir, up);
+    let camera = camera::Camera::new(view, projection(depth_mode));
+    Self::with_camera(camera, depth_mode, mirror)
   }
 
   pub fn mirror(&self) -> bool {
@@ -100,66 +105,92 @@ impl Camera {
     self.clear_color
   }
 
+  pub fn depth_mode(&self) -> DepthMode {
+    self.camera.depth_mode()
+  }
+
   pub fn handle_window_resize(&mut self, new_size: (u32, u32)) {
-    self.aspect_ratio = new_size.0 as f32 / new_size.1 as f32;
+    self
+      .camera
+      .handle_resize(new_size.0 as f32, new_size.1 as f32);
   }
 
   pub fn view(&self) -> Mat4 {
-    self.m_view
+    self.camera.view()
+  }
+
+  pub fn set_clip_plane(&mut self, plane: Plane) {
+    self.clip_plane = Some(plane);
   }
 
   pub fn view_projection(&self) -> Mat4 {
-    self.m_view_proj
+    self.projection() * self.view()
+  }
+
+  // ignores the clip plane, whose projection distorts the far plane.
+  // for reconstructing view directions from clip space.
+  pub fn unclipped_view_projection(&self) -> Mat4 {
+    self.camera.view_projection()
+  }
+
+  // also unclipped, for culling against what the camera sees
+  pub fn frustum(&self) -> Frustum {
+    self.camera.frustum()
   }
 
   pub fn projection(&self) -> Mat4 {
-    self.m_proj
-  }
-
-  pub fn calc_view(&self) -> Mat4 {
-    // default view matrix: eye at (0, 0, 2), looking at (0, 0, -1), up (0, 1, 0)
-    let dir = Mat3::from_rotation_y(self.rotation[0].to_radians())
-      * Mat3::from_rotation_x(-self.rotation[1].to_radians())
-      * Vec3::Z;
-    let eye = -dir * self.distance;
-    let up = Vec3::Y;
-    let origin = Vec3::ZERO;
-    Mat4::look_at_rh(eye, origin, up)
-  }
-
-  pub fn calc_proj(&self) -> Mat4 {
-    if self.perspective {
-      Mat4::perspective_rh(90.0f32.to_radians(), self.aspect_ratio, 0.1, 100.0)
-    } else {
-      Mat4::from_scale(Vec3::splat(1.0 / self.distance))
-        * Mat4::orthographic_rh(
-          -1.0,
-          1.0,
-          -1.0 / self.aspect_ratio,
-          1.0 / self.aspect_ratio,
-          0.1,
-          100.0,
-        )
+    match self.clip_plane {
+      Some(plane) => oblique_projection(
+        self.camera.projection(),
+        plane.transform(self.view()),
+        self.depth_mode().range(),
+      ),
+      None => self.camera.projection(),
     }
   }
 
-  pub fn update_view(&mut self) {
-    self.m_view = self.calc_view();
-    self.m_proj = self.calc_proj();
-    self.m_view_proj = self.m_proj * self.m_view;
+  // e.g. for recording it or picking with it
+  pub fn shared(&self) -> &camera::Camera {
+    &self.camera
+  }
+
+  pub fn update_view(&mut self, controller: &impl CameraController) {
+    controller.apply(&mut self.camera);
+  }
+
+  // to the bounds of what is drawn
+  pub fn fit_near_far(&mut self, bounds: &Aabb) {
+    self.camera.fit_near_far(bounds);
   }
 
   pub fn toggle_perspective(&mut self) {
-    self.perspective ^= true;
+    self.camera.toggle_projection();
   }
 
-  pub fn add_rotation(&mut self, delta: [f32; 2]) {
-    self.rotation[0] += delta[0];
-    self.rotation[1] += delta[1];
+  pub fn state(&self) -> CameraState {
+    CameraState::from_camera(&self.camera)
   }
 
-  pub fn add_distance(&mut self, delta: f32) {
-    self.distance += delta;
-    self.distance = self.distance.clamp(0.01, 100.0);
+  pub fn restore(&mut self, state: &CameraState) {
+    state.apply(&mut self.camera);
   }
+
+  // carrying on from where the camera is
+  pub fn orbit_controller(&self) -> OrbitController {
+    OrbitController::from_camera(&self.camera)
+  }
+}
+
+// for offscreen passes, in the format the depth mode wants
+pub fn depth_texture(
+  facade: &impl Facade,
+  depth_mode: DepthMode,
+  width: u32,
+  height: u32,
+) -> Result<DepthTexture2d> {
+  let format = depth_mode.depth_format();
+  let mipmaps = MipmapsOption::NoMipmap;
+  Ok(DepthTexture2d::empty_with_format(
+    facade, format, mipmaps, width, height,
+  )?)
 }
@@ -1,11 +1,14 @@
+use common::scene_file::LightDesc;
 use glam::{EulerRot, Mat4, Vec3};
 
+use crate::Result;
+
 pub struct Light {
   // note the light's color can exceed 1.0
   color: [f32; 3],
 
-  // in world space
-  distance: f32,
+  // in world space, before the rotation
+  position: Vec3,
 
   // rotation around the y axis
   rotation: f32,
@@ -15,21 +18,38 @@ impl Light {
   pub fn new() -> Self {
     Self {
       color: [10.0, 10.0, 10.0],
-      distance: 1.0,
+      position: Vec3::new(1.0, 1.0, 0.0),
       rotation: 0.0,
     }
   }
   pub fn position_world(&self) -> Vec3 {
-    let location = Vec3::new(self.distance, self.distance, 0.0);
     let transform = Mat4::from_euler(EulerRot::XYZ, 0.0, self.rotation, 0.0);
 
-    transform.transform_point3(location)
+    transform.transform_point3(self.position)
+  }
+
+  pub fn set_from_desc(&mut self, desc: &LightDesc) -> Result<()> {
+    let LightDesc::Point { position, color } = *desc else {
+      anyhow::bail!("only point lights are supported, not {:?}", desc);
+    };
+    self.position = position;
+    self.color = color.into();
+    self.rotation = 0.0;
+    Ok(())
   }
 
   pub fn add_rotation(&mut self, delta: f32) {
     self.rotation += delta;
   }
 
+  pub fn rotation(&self) -> f32 {
+    self.rotation
+  }
+
+  pub fn set_rotation(&mut self, rotation: f32) {
+    self.rotation = rotation;
+  }
+
   #[allow(dead_code)]
   fn model(&self) -> Mat4 {
     Mat4::from_translation(self.position_world())
@@ -6,384 +6,295 @@ mod object;
 mod reflective_object;
 mod reflective_plane;
 mod scene;
+#[cfg(test)]
+mod tests;
 
-use std::time::Duration;
+use std::{
+  path::{Path, PathBuf},
+  time::Duration,
+};
 
-use glium::{glutin::surface::WindowSurface, Display};
-use object::{Plane, Teapot};
+use glam::Vec2;
+use glium::{
+  backend::Facade, glutin::surface::WindowSurface, Display, Surface,
+};
 use scene::Scene;
+use serde::{Deserialize, Serialize};
 use winit::{
-  application::ApplicationHandler,
-  dpi::{LogicalSize, PhysicalPosition, PhysicalSize},
-  event::{self, KeyEvent, WindowEvent},
-  event_loop::{ActiveEventLoop, ControlFlow, EventLoop},
-  keyboard::{ModifiersState, NamedKey},
-  window::{Window, WindowAttributes, WindowId},
+  event::{KeyEvent, MouseButton},
+  keyboard::NamedKey,
 };
 
-use common::{asset_path, gl_boilerplate::init_display, Axis};
-
-use crate::{camera::Camera, light::Light};
+use anyhow::Context as _;
+use common::{
+  app::{App, Input},
+  camera::{CameraController, DepthMode},
+  capture,
+  gl_boilerplate::{supported_depth_mode, ContextOptions},
+  project_asset_path,
+  session::{self, CameraState},
+  Axis, SceneFile,
+};
 
 type Result<T> = anyhow::Result<T>;
 
-const TARGET_UPS: u32 = 60;
-const TARGET_FRAME_TIME: Duration =
-  Duration::from_micros(1_000_000 / TARGET_UPS as u64);
+// saved on exit and with F5, restored on start and with F9
+const SESSION_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/session.ron");
+
+#[derive(Serialize, Deserialize)]
+struct Session {
+  camera: CameraState,
+  light_rotation: f32,
+  show_axis: bool,
+}
 
 struct World {
   t: f32,
   show_axis: bool,
   axis: Option<Axis>,
   scene: Option<Scene>,
+  scene_path: PathBuf,
+  // what was asked for, the scene gets what the context supports of it
+  depth_mode: DepthMode,
+  viewport: Vec2,
 }
 
 impl World {
-  fn new() -> Self {
+  fn new(scene_path: PathBuf, depth_mode: DepthMode) -> Self {
     Self {
       t: 0.0,
       axis: None,
       show_axis: true,
       scene: None,
+      scene_path,
+      depth_mode,
+      viewport: Vec2::ONE,
     }
   }
 
-  fn set_axis(&mut self, axis: Axis) {
-    self.axis = Some(axis);
-  }
+  // any facade does, the tests render without a window
+  fn load(&mut self, facade: &impl Facade, file: &SceneFile) -> Result<()> {
+    // setup axis object
+    let axis = Axis::new(facade)?;
+    self.set_axis(axis);
 
-  fn set_scene(&mut self, scene: Scene) {
-    self.scene = Some(scene);
+    // setup the scene
+    let cubemap = file.cubemap_faces()?.context("the scene has no cubemap")?;
+    let cubemap = cubemap.each_ref().map(PathBuf::as_path);
+    let depth_mode = supported_depth_mode(facade, self.depth_mode);
+    let mut scene = Scene::new(facade, &cubemap, depth_mode)?;
+    scene.load_file(file, facade)?;
+
+    self.set_scene(scene);
+
+    // initial update
+    self.update(Duration::ZERO);
+
+    Ok(())
   }
 
-  fn handle_resize(&mut self, display: &Display<WindowSurface>) {
-    if let Some(scene) = &mut self.scene {
-      scene.handle_resize(display);
-      scene.camera.update_view();
-    }
+  fn session(&self) -> Option<Session> {
+    let scene = self.scene.as_ref()?;
+    Some(Session {
+      camera: scene.camera.state(),
+      light_rotation: scene.light.rotation(),
+      show_axis: self.show_axis,
+    })
   }
 
-  fn update_view(&mut self) {
+  fn restore_session(&mut self, session: &Session) {
+    self.show_axis = session.show_axis;
     if let Some(scene) = &mut self.scene {
-      scene.camera.update_view();
+      scene.restore_camera(&session.camera);
+      scene.light.set_rotation(session.light_rotation);
     }
   }
 
-  fn update(&mut self, dt: Duration) {
-    self.t += dt.as_secs_f32();
-
-    if let Some(scene) = &mut self.scene {
-      scene.update(&dt);
+  fn save_session(&self) {
+    if let Some(session) = self.session() {
+      session::save_or_warn(SESSION_PATH, &session);
     }
   }
 
-  fn render(&self, context: &Display<WindowSurface>) -> Result<()> {
-    let mut frame = context.draw();
-
-    if let Some(scene) = &self.scene {
-      scene.draw(&mut frame);
-
-      if self.show_axis {
-        if let Some(axis) = &self.axis {
-          axis.draw(&mut frame, &scene.camera.view_projection())?;
-        }
-      }
+  fn load_session(&mut self) {
+    if let Some(session) = session::load_or_warn(SESSION_PATH) {
+      self.restore_session(&session);
+      println!("Restored session from {}", SESSION_PATH);
     }
-
-    frame.finish()?;
-    Ok(())
   }
-}
-
-struct App {
-  window: Option<Window>,
-  display: Option<Display<WindowSurface>>,
-  last_update: std::time::Instant,
 
-  // left, right
-  mouse_down: (bool, bool),
-  last_pos: [f32; 2],
-  mouse_pos: [f32; 2],
-  modifiers: ModifiersState,
-
-  world: World,
-}
-
-impl App {
-  fn new() -> Self {
-    let last_update = std::time::Instant::now();
-    let world = World::new();
+  fn set_axis(&mut self, axis: Axis) {
+    self.axis = Some(axis);
+  }
 
-    Self {
-      window: None,
-      display: None,
-      last_update,
-      last_pos: [0.0, 0.0],
-      mouse_pos: [0.0, 0.0],
-      mouse_down: (false, false),
-      modifiers: ModifiersState::empty(),
-      world,
-    }
+  fn set_scene(&mut self, scene: Scene) {
+    self.scene = Some(scene);
   }
 
-  fn handle_resize(&mut self, size: PhysicalSize<u32>) {
-    println!("Resized to {:?}", size);
-    if let Some(scene) = &mut self.world.scene {
-      scene.camera.handle_window_resize((size.width, size.height));
-    }
-    if let Some(display) = &self.display {
-      self.world.handle_resize(display);
+  fn update_view(&mut self) {
+    if let Some(scene) = &mut self.scene {
+      scene.update_view();
     }
-    self.request_redraw();
   }
 
-  fn request_redraw(&self) {
-    if let Some(window) = self.window.as_ref() {
-      window.request_redraw();
+  fn export_cubemaps(&self) {
+    let Some(scene) = &self.scene else {
+      return;
+    };
+    let dir = Path::new(capture::CAPTURE_DIR).join("cubemaps");
+    match scene.export_cubemaps(&dir) {
+      Ok(()) => println!("Saved cubemap faces to {}", dir.display()),
+      Err(e) => eprintln!("Failed to save cubemaps: {:#}", e),
     }
   }
 
-  fn handle_keyboard(&mut self, event: KeyEvent, event_loop: &ActiveEventLoop) {
+  fn handle_key(&mut self, event: KeyEvent) {
     if !event.state.is_pressed() {
       return;
     }
-    if event.logical_key == NamedKey::Escape {
-      event_loop.exit();
-    } else if event.logical_key.to_text() == Some("p") {
-      if let Some(scene) = &mut self.world.scene {
+    if let Some(scene) = &mut self.scene {
+      if scene.handle_key(&event) {
+        return;
+      }
+    }
+
+    if event.logical_key == "p" {
+      if let Some(scene) = &mut self.scene {
         scene.camera.toggle_perspective();
-        scene.camera.update_view();
       }
-      self.request_redraw();
-    } else if event.logical_key.to_text() == Some("a") {
-      self.world.show_axis = !self.world.show_axis;
-      self.request_redraw();
-    } else if event.logical_key == NamedKey::F6
-      || event.logical_key.to_text() == Some("r")
-    {
-      if let Some(scene) = &mut self.world.scene {
-        if let Some(display) = &self.display {
-          match scene.reload_shader(display) {
-            Ok(_) => println!("Reloaded shaders"),
-            Err(e) => eprintln!("Failed to reload shader: {}", e),
-          }
-        }
+    } else if event.logical_key == "a" {
+      self.show_axis = !self.show_axis;
+    } else if event.logical_key == "f" {
+      if let Some(scene) = &mut self.scene {
+        scene.frame_selected();
       }
-      self.request_redraw();
+    } else if event.logical_key == NamedKey::F5 {
+      self.save_session();
+    } else if event.logical_key == NamedKey::F9 {
+      self.load_session();
+    } else if event.logical_key == NamedKey::F7 {
+      self.export_cubemaps();
     }
   }
+}
 
-  fn handle_redraw(&mut self) {
-    if let Some(display) = &self.display {
-      self.world.render(display).expect("Failed to render");
-    }
+impl App for World {
+  fn init(&mut self, display: &Display<WindowSurface>) -> Result<()> {
+    let file = SceneFile::load(&self.scene_path)?;
+    self.load(display, &file)?;
+    // the tests start from the defaults, only the window picks up where
+    // the last run left off
+    self.load_session();
+    Ok(())
   }
 
-  fn update(&mut self) {
-    let dt = self.last_update.elapsed();
-    self.world.update(dt);
-
-    let help =
-      "Press 'p' to toggle perspective, 'a' to toggle axis, Esc to quit";
-    let title = format!("ups: {:.2} ({})", 1.0 / dt.as_secs_f32(), help);
+  fn update(&mut self, dt: Duration) {
+    self.t += dt.as_secs_f32();
 
-    if let Some(window) = self.window.as_ref() {
-      window.set_title(&title);
-      window.request_redraw();
+    if let Some(scene) = &mut self.scene {
+      scene.update(&dt);
     }
-
-    self.last_update = std::time::Instant::now();
   }
 
-  fn handle_mouse_input(
-    &mut self,
-    state: event::ElementState,
-    button: event::MouseButton,
-  ) {
-    let pressed = state.is_pressed();
+  fn render(&self, frame: &mut impl Surface) -> Result<()> {
+    if let Some(scene) = &self.scene {
+      scene.draw(frame);
 
-    match button {
-      event::MouseButton::Left => {
-        self.mouse_down.0 = pressed;
-      }
-      event::MouseButton::Right => {
-        self.mouse_down.1 = pressed;
+      if self.show_axis {
+        if let Some(axis) = &self.axis {
+          axis.draw(frame, &scene.camera.view_projection())?;
+        }
       }
-      _ => {}
     }
+
+    Ok(())
   }
 
-  fn handle_cursor_moved(&mut self, position: PhysicalPosition<f64>) {
-    self.mouse_pos = [position.x as f32, position.y as f32];
-    let Some(scene) = self.world.scene.as_mut() else {
+  fn on_input(&mut self, input: Input) {
+    if let Input::Key(event) = input {
+      self.handle_key(event);
       return;
-    };
-
-    let camera_target: &mut Camera = &mut scene.camera;
-    let light_target: &mut Light = &mut scene.light;
-
-    // left drag: rotate camera
-    if self.mouse_down.0 && !self.modifiers.control_key() {
-      let dx = self.mouse_pos[0] - self.last_pos[0];
-      let dy = self.mouse_pos[1] - self.last_pos[1];
-      camera_target.add_rotation([dx * 0.1, dy * 0.1]);
-    }
-
-    // ctrl + left drag: rotate light
-    if self.mouse_down.0 && self.modifiers.control_key() {
-      let dx = self.mouse_pos[0] - self.last_pos[0];
-      light_target.add_rotation(dx * 0.01);
-    }
-
-    // right drag: change camera distance
-    if self.mouse_down.1 {
-      let dy = self.mouse_pos[1] - self.last_pos[1];
-      camera_target.add_distance(dy * 0.01);
     }
 
-    self.world.update_view();
-    self.last_pos = self.mouse_pos;
-    self.request_redraw();
-  }
-
-  fn handle_mouse_wheel(&mut self, delta: event::MouseScrollDelta) {
-    let d = match delta {
-      event::MouseScrollDelta::LineDelta(_x, y) => y * 20.0,
-      event::MouseScrollDelta::PixelDelta(pos) => pos.y as f32,
-    };
-
-    let Some(scene) = self.world.scene.as_mut() else {
+    let Some(scene) = &mut self.scene else {
       return;
     };
 
-    let camera_target: &mut Camera = &mut scene.camera;
-    camera_target.add_distance(d * 0.01);
-    self.world.update_view();
-  }
-
-  fn schedule_next_frame(&self, event_loop: &ActiveEventLoop) {
-    let wake_up_at = self.last_update + TARGET_FRAME_TIME;
-    event_loop.set_control_flow(ControlFlow::WaitUntil(wake_up_at));
-  }
-
-  fn handle_init(&mut self, display: &Display<WindowSurface>) -> Result<()> {
-    // setup axis object
-    let axis = Axis::new(display)?;
-    self.world.set_axis(axis);
-
-    // setup the scene
-    let mut scene = Scene::new(
-      display,
-      &[
-        &asset_path("cubemap/cubemap_posx.png"),
-        &asset_path("cubemap/cubemap_negx.png"),
-        &asset_path("cubemap/cubemap_posy.png"),
-        &asset_path("cubemap/cubemap_negy.png"),
-        &asset_path("cubemap/cubemap_posz.png"),
-        &asset_path("cubemap/cubemap_negz.png"),
-      ],
-    )?;
-
-    let teapot1 = Teapot::load(display)?
-      .translated([-1.0, 0.0, 0.0])
-      .rotated_y(-45.0);
-    scene.add_object(teapot1, true)?;
-    let teapot2 = Teapot::load(display)?
-      .rotated_y(45.0)
-      .translated([0.5, 0.0, 0.0]);
-    scene.add_object(teapot2, false)?;
-    let teapot3 = Teapot::load(display)?
-      .translated([-0.3, 0.8, 0.0])
-      .rotated_y(90.0);
-    scene.add_object(teapot3, true)?;
-
-    let plane = Plane::create(display)?
-      .translated([0.0, -0.2, 0.0])
-      .scaled(1.2, 1.0, 1.0);
-    scene.add_plane(plane, true)?;
-
-    self.world.set_scene(scene);
-
-    // initial update
-    self.world.update(Duration::from_secs(0));
-
-    Ok(())
-  }
-}
-
-impl ApplicationHandler for App {
-  fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-    if self.window.is_none() {
-      let window_attrs = WindowAttributes::default()
-        .with_title("3D Scene")
-        .with_inner_size(LogicalSize::new(800, 600));
-
-      match event_loop.create_window(window_attrs) {
-        Ok(window) => {
-          let display = init_display(&window);
-          self.handle_init(&display).expect("Failed to init world");
-
-          self.window = Some(window);
-          self.display = Some(display);
-        }
-        Err(e) => {
-          eprintln!("Failed to create window: {}", e);
-          event_loop.exit();
-        }
+    match input {
+      Input::Click {
+        button: MouseButton::Left,
+        pos,
+        ..
+      } => scene.pick(pos, self.viewport),
+      // ctrl + left drag: rotate light
+      Input::Drag {
+        button: MouseButton::Left,
+        from,
+        to,
+        modifiers,
+      } if modifiers.control_key() => {
+        scene.light.add_rotation((to.x - from.x) * 0.01);
       }
+      // left drag: rotate camera
+      Input::Drag {
+        button: MouseButton::Left,
+        from,
+        to,
+        ..
+      } => scene.orbit.drag(from, to, self.viewport),
+      // right drag: change camera distance, dragging down moves away
+      Input::Drag {
+        button: MouseButton::Right,
+        from,
+        to,
+        ..
+      } => scene.orbit.zoom(-(to.y - from.y) / 20.0),
+      // 30 per notch
+      Input::Scroll { dy, .. } => scene.orbit.zoom(dy / 30.0),
+      _ => {}
     }
+
+    self.update_view();
   }
 
-  fn window_event(
-    &mut self,
-    event_loop: &ActiveEventLoop,
-    _window_id: WindowId,
-    event: WindowEvent,
-  ) {
-    match event {
-      WindowEvent::Resized(size) => self.handle_resize(size),
-      WindowEvent::KeyboardInput { event, .. } => {
-        self.handle_keyboard(event, event_loop)
-      }
-      WindowEvent::RedrawRequested => {
-        self.handle_redraw();
-      }
-      WindowEvent::CloseRequested => {
-        event_loop.exit();
-      }
-      WindowEvent::MouseInput { state, button, .. } => {
-        self.handle_mouse_input(state, button);
-      }
-      WindowEvent::MouseWheel { delta, .. } => {
-        self.handle_mouse_wheel(delta);
-      }
-      WindowEvent::CursorMoved { position, .. } => {
-        self.handle_cursor_moved(position);
-      }
-      WindowEvent::ModifiersChanged(new_modifiers) => {
-        self.modifiers = new_modifiers.state();
-      }
-      _ => {}
+  fn resize(&mut self, width: u32, height: u32) {
+    println!("Resized to {}x{}", width, height);
+    self.viewport = Vec2::new(width as f32, height as f32);
+    if let Some(scene) = &mut self.scene {
+      scene.handle_resize((width, height));
     }
+    self.update_view();
   }
 
-  fn new_events(
-    &mut self,
-    event_loop: &ActiveEventLoop,
-    _cause: event::StartCause,
-  ) {
-    self.schedule_next_frame(event_loop);
+  fn help(&self) -> &str {
+    "Press 'p' to toggle perspective, click to select, 'f' to frame the \
+      selection, 'k' to record a camera path, 'l' to play it back, 'a' to \
+      toggle axis, F5 to save the session, F9 to restore it, F7 to save the \
+      cubemaps, F12 for a screenshot, Esc to quit"
+  }
 
-    if self.last_update.elapsed() > TARGET_FRAME_TIME {
-      self.update();
-    }
+  // only once there was something to look at
+  fn exit(&mut self) {
+    self.save_session();
   }
 }
 
 fn main() -> Result<()> {
-  let event_loop = EventLoop::new().expect("Failed to create event loop");
-  let mut app = App::new();
-
-  event_loop.run_app(&mut app)?;
-  Ok(())
+  // --standard-depth turns reversed-Z off, anything else is a scene file
+  // to load instead of the default one
+  let (flags, paths): (Vec<_>, Vec<_>) = std::env::args()
+    .skip(1)
+    .partition(|arg| arg.starts_with("--"));
+  let depth_mode = match flags.as_slice() {
+    [] => camera::DEFAULT_DEPTH_MODE,
+    [flag] if flag == "--standard-depth" => DepthMode::Standard,
+    _ => anyhow::bail!("unknown flags {:?}", flags),
+  };
+  let scene_path = paths.into_iter().next().map_or_else(
+    || PathBuf::from(project_asset_path!("scene.ron")),
+    PathBuf::from,
+  );
+
+  let options = ContextOptions::new().depth_mode(depth_mode);
+  common::app::run_with(World::new(scene_path, depth_mode), options)
 }
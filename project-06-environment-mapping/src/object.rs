@@ -1,6 +1,11 @@
-use std::path::{Path, PathBuf};
-
-use common::{asset_path, teapot_path, DynUniforms, MergedUniform};
+use std::path::Path;
+
+use common::math::{Aabb, Ray};
+use common::mesh::DEFAULT_LOD_RATIOS;
+use common::scene_file::{ModelDesc, ObjectDesc};
+use common::{
+  asset_path, DynUniforms, MergedUniform, ProgramHandle, SceneFile,
+};
 use glam::{Mat3, Mat4, Vec3};
 use glium::backend::Facade;
 use glium::uniforms::Uniforms;
@@ -8,25 +13,9 @@ use glium::{uniform, DrawParameters, Program, Surface};
 
 use crate::light::Light;
 use crate::mesh::{GPUMesh, Mesh};
-use crate::{Camera, Result};
-
-#[allow(unused)]
-pub struct Teapot;
-
-impl Teapot {
-  pub fn load(facade: &impl Facade) -> Result<GPUObject> {
-    const SHADER_PATH: &str =
-      concat!(env!("CARGO_MANIFEST_DIR"), "/assets/shader");
-    let mut object = GPUObject::load(&teapot_path(), &SHADER_PATH, facade)?;
-
-    object.model = Mat4::from_scale(Vec3::splat(0.05))
-            * Mat4::from_rotation_y(0.0)
-            // the object itself is rotated 90 to the front, let's rotate it back a little.
-            * Mat4::from_rotation_x(-90.0f32.to_radians());
+use crate::{camera::Camera, Result};
 
-    Ok(object)
-  }
-}
+const SHADER_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/shader");
 
 #[allow(unused)]
 pub struct Yoda;
@@ -34,10 +23,13 @@ pub struct Yoda;
 impl Yoda {
   #[allow(unused)]
   pub fn load(facade: &impl Facade) -> Result<GPUObject> {
-    const SHADER_PATH: &str =
-      concat!(env!("CARGO_MANIFEST_DIR"), "/assets/shader");
     let yoda_model = asset_path("yoda/yoda.obj");
-    let mut object = GPUObject::load(&yoda_model, &SHADER_PATH, facade)?;
+    let mut object = GPUObject::load_with_lods(
+      &yoda_model,
+      &SHADER_PATH,
+      &DEFAULT_LOD_RATIOS,
+      facade,
+    )?;
 
     object.model = Mat4::from_translation(Vec3::new(0.0, -0.3, 0.0))
       * Mat4::from_scale(Vec3::splat(0.0003))
@@ -56,7 +48,6 @@ impl Plane {
     let mesh = Mesh::from_genmesh(plane).upload(facade)?;
     let model = Mat4::from_rotation_x(-90.0f32.to_radians());
     let object = GPUObject {
-      shader_path: None,
       program: None,
       mesh,
       model,
@@ -66,85 +57,65 @@ impl Plane {
 }
 
 pub struct GPUObject {
-  shader_path: Option<PathBuf>,
-  program: Option<Program>,
+  program: Option<ProgramHandle>,
   mesh: GPUMesh,
   model: Mat4,
 }
 
 impl GPUObject {
-  pub fn reload_shader(&mut self, facade: &impl Facade) -> Result<()> {
-    let Some(shader_path) = &self.shader_path else {
-      return Ok(());
-    };
-
-    match load_program(shader_path, facade) {
-      Ok(program) => self.program = Some(program),
-      Err(e) => {
-        eprintln!("Failed to reload shader: {}", e);
-      }
-    }
-
-    Ok(())
-  }
-
   // world space
   #[allow(unused)]
   pub fn dimensions(&self) -> [f32; 3] {
-    let [[xmin, xmax], [ymin, ymax], [zmin, zmax]] = self.bounding_box();
-    let dx = xmax - xmin;
-    let dy = ymax - ymin;
-    let dz = zmax - zmin;
-    [dx, dy, dz]
+    self.bounding_box().size().to_array()
   }
 
   // world space
-  pub fn bounding_box(&self) -> [[f32; 2]; 3] {
-    let [[x1, x2], [y1, y2], [z1, z2]] = self.mesh.bounding_box();
-    let mut vertices = [
-      Vec3::new(x1, y1, z1),
-      Vec3::new(x1, y1, z2),
-      Vec3::new(x1, y2, z1),
-      Vec3::new(x1, y2, z2),
-      Vec3::new(x2, y1, z1),
-      Vec3::new(x2, y1, z2),
-      Vec3::new(x2, y2, z1),
-      Vec3::new(x2, y2, z2),
-    ];
-
-    for vert in &mut vertices {
-      *vert = self.model.transform_point3(*vert);
-    }
-
-    let (mut xmin, mut ymin, mut zmin) = (f32::MAX, f32::MAX, f32::MAX);
-    let (mut xmax, mut ymax, mut zmax) = (f32::MIN, f32::MIN, f32::MIN);
-
-    for v in vertices {
-      xmin = xmin.min(v.x);
-      ymin = ymin.min(v.y);
-      zmin = zmin.min(v.z);
-      xmax = xmax.max(v.x);
-      ymax = ymax.max(v.y);
-      zmax = zmax.max(v.z);
-    }
+  pub fn bounding_box(&self) -> Aabb {
+    self.mesh.bounding_box().transform(self.model)
+  }
 
-    [[xmin, xmax], [ymin, ymax], [zmin, zmax]]
+  // `reflective` is up to the scene, shadows don't exist here
+  pub fn from_desc(
+    file: &SceneFile,
+    desc: &ObjectDesc,
+    model: Mat4,
+    facade: &impl Facade,
+  ) -> Result<Self> {
+    let mut object = match file.model_path(&desc.model)? {
+      Some(path) => {
+        let mut mesh = Mesh::load_with_lods(path, &DEFAULT_LOD_RATIOS)?;
+        if let Some(material) = &desc.material {
+          mesh.override_material(material);
+        }
+        Self::from_mesh(&mesh, &SHADER_PATH, facade)?
+      }
+      None if desc.model == ModelDesc::Plane => Plane::create(facade)?,
+      None => anyhow::bail!("no model for {:?}", desc.model),
+    };
+    object.model = model;
+    Ok(object)
   }
 
-  pub fn load(
+  pub fn load_with_lods(
     obj_path: &impl AsRef<Path>,
     shader_path: &impl AsRef<Path>,
+    lod_ratios: &[f32],
+    facade: &impl Facade,
+  ) -> Result<Self> {
+    let mesh = Mesh::load_with_lods(obj_path, lod_ratios)?;
+    Self::from_mesh(&mesh, shader_path, facade)
+  }
+
+  fn from_mesh(
+    mesh: &Mesh,
+    shader_path: &impl AsRef<Path>,
     facade: &impl Facade,
   ) -> Result<Self> {
-    let mesh = Mesh::load(obj_path)?;
     let mesh = mesh.upload(facade)?;
-    let shader_path = shader_path.as_ref().to_path_buf();
-    let program = Some(load_program(&shader_path, facade)?);
-    let shader_path = Some(shader_path);
+    let program = Some(ProgramHandle::load(shader_path, facade)?);
     let model = Mat4::IDENTITY;
 
     Ok(Self {
-      shader_path,
       program,
       mesh,
       model,
@@ -153,13 +124,34 @@ impl GPUObject {
 
   pub fn update(&mut self, _dt: &std::time::Duration) {}
 
+  // level of detail based on the projected size of the bounding sphere
+  fn lod(&self, mv: Mat4, projection: Mat4, viewport_height: f32) -> usize {
+    self
+      .mesh
+      .lods()
+      .select_projected(mv, projection, viewport_height)
+  }
+
   pub fn model(&self) -> Mat4 {
     self.model
   }
 
+  // world space distance along the ray
+  pub fn hit(&self, ray: &Ray) -> Option<f32> {
+    let bvh = self.mesh.bvh();
+    Some(bvh.closest_hit_placed(ray, self.model, f32::MAX)?.t)
+  }
+
   pub fn draw(&self, frame: &mut impl Surface, camera: &Camera, light: &Light) {
     if let Some(program) = &self.program {
-      self.draw_with_program(frame, camera, light, program, DynUniforms::new());
+      let program = program.get();
+      self.draw_with_program(
+        frame,
+        camera,
+        light,
+        &program,
+        DynUniforms::new(),
+      );
     } else {
       eprintln!("GPUObject::draw: program is not loaded");
     }
@@ -200,45 +192,17 @@ impl GPUObject {
       glium::draw_parameters::BackfaceCullingMode::CullClockwise
     };
     let draw_params = DrawParameters {
-      depth: glium::Depth {
-        test: glium::draw_parameters::DepthTest::IfLess,
-        write: true,
-        ..Default::default()
-      },
       backface_culling: culling,
       scissor,
-      ..Default::default()
+      ..camera.depth_mode().draw_parameters()
     };
 
-    self.mesh.draw(frame, program, &uniforms, &draw_params);
+    let viewport_height = frame.get_dimensions().1 as f32;
+    let lod = self.lod(mv, camera.projection(), viewport_height);
+    self.mesh.draw(frame, program, &uniforms, &draw_params, lod);
   }
 
   pub fn world_pos(&self) -> Vec3 {
     self.model.w_axis.truncate()
   }
-
-  pub fn translated(self, d: [f32; 3]) -> Self {
-    let model = Mat4::from_translation(Vec3::from(d)) * self.model;
-    Self { model, ..self }
-  }
-
-  pub fn rotated_y(self, degree: f32) -> Self {
-    let model = Mat4::from_rotation_y(degree.to_radians()) * self.model;
-    Self { model, ..self }
-  }
-
-  pub fn scaled(self, sx: f32, sy: f32, sz: f32) -> Self {
-    let model = Mat4::from_scale(Vec3::new(sx, sy, sz)) * self.model;
-    Self { model, ..self }
-  }
-}
-
-fn load_program(path: &Path, facade: &impl Facade) -> Result<Program> {
-  let vert_path = path.with_extension("vert");
-  let frag_path = path.with_extension("frag");
-
-  let vert_src = std::fs::read_to_string(vert_path)?;
-  let frag_src = std::fs::read_to_string(frag_path)?;
-
-  Ok(Program::from_source(facade, &vert_src, &frag_src, None)?)
 }
@@ -1,21 +1,27 @@
-use std::{cell::Cell, fs::read_to_string, time::Duration};
+use std::{cell::Cell, time::Duration};
 
-use common::project_asset_path;
+use common::{
+  camera::DepthMode, project_asset_path, Preprocessor, ProgramHandle,
+};
 use glam::{Mat3, Vec3};
 use glium::{
   backend::Facade,
   framebuffer::SimpleFrameBuffer,
   texture::{CubeLayer, Cubemap, DepthTexture2d},
-  uniform, Program, Surface,
+  uniform, Surface,
 };
 
 use crate::{
-  camera::Camera, light::Light, object::GPUObject, scene::Scene, Result,
+  camera::{depth_texture, Camera},
+  light::Light,
+  object::GPUObject,
+  scene::Scene,
+  Result,
 };
 
 pub struct ReflectiveObject {
   object: GPUObject,
-  program: Program,
+  program: ProgramHandle,
   cubemap: Cubemap,
   cubemap_depth: DepthTexture2d,
   cubemap_initialized: Cell<bool>,
@@ -25,10 +31,15 @@ pub struct ReflectiveObject {
 const CUBEMAP_RESOLUTION: u32 = 128;
 
 impl ReflectiveObject {
-  pub fn new(facade: &impl Facade, object: GPUObject) -> Result<Self> {
+  pub fn new(
+    facade: &impl Facade,
+    object: GPUObject,
+    depth_mode: DepthMode,
+  ) -> Result<Self> {
     let cubemap = Cubemap::empty(facade, CUBEMAP_RESOLUTION)?;
+    let resolution = CUBEMAP_RESOLUTION;
     let cubemap_depth =
-      DepthTexture2d::empty(facade, CUBEMAP_RESOLUTION, CUBEMAP_RESOLUTION)?;
+      depth_texture(facade, depth_mode, resolution, resolution)?;
     let program = Self::shader(facade)?;
     Ok(Self {
       object,
@@ -40,18 +51,22 @@ impl ReflectiveObject {
     })
   }
 
-  pub fn shader(facade: &impl Facade) -> Result<Program> {
+  pub fn shader(facade: &impl Facade) -> Result<ProgramHandle> {
     // reuse the same vertex shader for normal objects
-    let vert_src = read_to_string(project_asset_path!("shader.vert"))?;
-    let frag_src =
-      read_to_string(project_asset_path!("reflective_object.frag"))?;
-    let program = Program::from_source(facade, &vert_src, &frag_src, None)?;
-    Ok(program)
+    let stages = [
+      project_asset_path!("shader.vert"),
+      project_asset_path!("reflective_object.frag"),
+    ];
+    ProgramHandle::load_stages(&stages, &Preprocessor::new(), facade)
   }
 
-  pub fn reload_shader(&mut self, facade: &impl Facade) -> Result<()> {
-    self.program = Self::shader(facade)?;
-    Ok(())
+  pub fn object(&self) -> &GPUObject {
+    &self.object
+  }
+
+  // what the object sees around it, as of the last update
+  pub fn cubemap(&self) -> &Cubemap {
+    &self.cubemap
   }
 
   pub fn update_cubemap(
@@ -105,7 +120,9 @@ impl ReflectiveObject {
 
     let world_pos = self.object.world_pos();
     // object size
-    let camera = Camera::for_cubemap_face(world_pos, direction, up, false);
+    let depth_mode = scene.camera.depth_mode();
+    let camera =
+      Camera::for_cubemap_face(world_pos, direction, up, depth_mode, false);
 
     scene.draw_with_camera(
       &mut framebuffer,
@@ -139,7 +156,7 @@ impl ReflectiveObject {
       target,
       camera,
       light,
-      &self.program,
+      &self.program.get(),
       uniforms,
     );
   }
@@ -1,32 +1,44 @@
 use core::f32;
-use std::{ffi::c_void, fs::read_to_string, time::Duration};
+use std::{ffi::c_void, time::Duration};
 
-use common::{math::reflect4x4, project_asset_path};
+use common::{
+  camera::DepthMode,
+  math::{reflect4x4, Plane},
+  project_asset_path, Preprocessor, ProgramHandle,
+};
 use glam::{Mat4, Vec3};
 use glium::{
   backend::Facade, framebuffer::SimpleFrameBuffer, texture::DepthTexture2d,
-  uniform, Program, Rect, Surface, Texture2d,
+  uniform, Rect, Surface, Texture2d,
 };
 
 use crate::{
-  camera::Camera, light::Light, object::GPUObject, scene::Scene, Result,
+  camera::{depth_texture, Camera},
+  light::Light,
+  object::GPUObject,
+  scene::Scene,
+  Result,
 };
 
 pub struct ReflectivePlane {
   object: GPUObject,
   normal: Vec3,
-  program: Program,
+  program: ProgramHandle,
   texture: Texture2d,
   depth: DepthTexture2d,
   t: f32,
 }
 
 impl ReflectivePlane {
-  pub fn new(facade: &impl Facade, object: GPUObject) -> Result<Self> {
+  pub fn new(
+    facade: &impl Facade,
+    object: GPUObject,
+    depth_mode: DepthMode,
+  ) -> Result<Self> {
     let normal = object.model().transform_vector3(Vec3::Z).normalize();
     let (w, h) = facade.get_context().get_framebuffer_dimensions();
     let texture = Texture2d::empty(facade, w, h)?;
-    let depth = DepthTexture2d::empty(facade, w, h)?;
+    let depth = depth_texture(facade, depth_mode, w, h)?;
     let program = Self::shader(facade)?;
 
     Ok(Self {
@@ -39,25 +51,29 @@ impl ReflectivePlane {
     })
   }
 
-  pub fn handle_resize(&mut self, facade: &impl Facade, (w, h): (u32, u32)) {
+  pub fn object(&self) -> &GPUObject {
+    &self.object
+  }
+
+  pub fn handle_resize(
+    &mut self,
+    facade: &impl Facade,
+    depth_mode: DepthMode,
+    (w, h): (u32, u32),
+  ) {
     let new_texture = Texture2d::empty(facade, w, h).unwrap();
     // TODO: copy the old texture to the new texture to avoid flickering
     self.texture = new_texture;
-    self.depth = DepthTexture2d::empty(facade, w, h).unwrap();
+    self.depth = depth_texture(facade, depth_mode, w, h).unwrap();
   }
 
-  pub fn shader(facade: &impl Facade) -> Result<Program> {
+  pub fn shader(facade: &impl Facade) -> Result<ProgramHandle> {
     // reuse the same vertex shader for normal objects
-    let vert_src = read_to_string(project_asset_path!("shader.vert"))?;
-    let frag_src =
-      read_to_string(project_asset_path!("reflective_plane.frag"))?;
-    let program = Program::from_source(facade, &vert_src, &frag_src, None)?;
-    Ok(program)
-  }
-
-  pub fn reload_shader(&mut self, facade: &impl Facade) -> Result<()> {
-    self.program = Self::shader(facade)?;
-    Ok(())
+    let stages = [
+      project_asset_path!("shader.vert"),
+      project_asset_path!("reflective_plane.frag"),
+    ];
+    ProgramHandle::load_stages(&stages, &Preprocessor::new(), facade)
   }
 
   pub fn reflected_view(&self, view: &Mat4) -> Mat4 {
@@ -75,25 +91,35 @@ impl ReflectivePlane {
     let mut surface =
       SimpleFrameBuffer::with_depth_buffer(facade, &self.texture, &self.depth)?;
 
-    let bbox_clip =
-            // needs to use the original camera to get the final screen
-            // locations of the plane.
-            map_bounding_box(self.object.bounding_box(), camera.view_projection());
+    // needs to use the original camera to get the final screen
+    // locations of the plane.
+    let bbox = self.object.bounding_box();
+    let camera_vp = camera.view_projection();
+    if !camera.frustum().intersects_aabb(&bbox) {
+      // the mirror is off screen, nothing to reflect
+      return Ok(());
+    }
+    let bbox_clip = bbox.project(camera_vp);
 
     let (view, proj, mirror) =
       (camera.view(), camera.projection(), camera.mirror());
     let view = self.reflected_view(&view);
     // reflect the "mirror" flag to make back-face culling working correctly
-    let mut camera = Camera::from_view_projection(view, proj, !mirror);
+    let mut camera =
+      Camera::from_view_projection(view, proj, camera.depth_mode(), !mirror);
+    // geometry behind the mirror, e.g. poking through the floor, must
+    // not show up in the reflection
+    let plane = Plane::from_point_normal(self.object.world_pos(), self.normal);
+    camera.set_clip_plane(plane);
 
     // only draw the relevant view port of the plane.
     // calculated by projecting the four coordinates to clip space.
     // and then taking the min and max of the x and y values.
     let (screen_w, screen_h) = surface.get_dimensions();
-    let left = screen_w as f32 * (bbox_clip[0][0] + 1.0) / 2.0;
-    let width = screen_w as f32 * (bbox_clip[0][1] - bbox_clip[0][0]) / 2.0;
-    let bottom = screen_h as f32 * (bbox_clip[1][0] + 1.0) / 2.0;
-    let height = screen_h as f32 * (bbox_clip[1][1] - bbox_clip[1][0]) / 2.0;
+    let left = screen_w as f32 * (bbox_clip.min.x + 1.0) / 2.0;
+    let width = screen_w as f32 * bbox_clip.size().x / 2.0;
+    let bottom = screen_h as f32 * (bbox_clip.min.y + 1.0) / 2.0;
+    let height = screen_h as f32 * bbox_clip.size().y / 2.0;
     let scissor = Rect {
       left: left.floor().max(0.0) as u32,
       width: width.ceil().max(0.0) as u32,
@@ -141,40 +167,8 @@ impl ReflectivePlane {
       target,
       camera,
       light,
-      &self.program,
+      &self.program.get(),
       uniforms,
     );
   }
 }
-
-fn map_bounding_box(bbox: [[f32; 2]; 3], map: Mat4) -> [[f32; 2]; 3] {
-  let [[x1, x2], [y1, y2], [z1, z2]] = bbox;
-  let mut vertices = [
-    Vec3::new(x1, y1, z1),
-    Vec3::new(x1, y1, z2),
-    Vec3::new(x1, y2, z1),
-    Vec3::new(x1, y2, z2),
-    Vec3::new(x2, y1, z1),
-    Vec3::new(x2, y1, z2),
-    Vec3::new(x2, y2, z1),
-    Vec3::new(x2, y2, z2),
-  ];
-
-  for vert in &mut vertices {
-    *vert = map.transform_point3(*vert);
-  }
-
-  let (mut xmin, mut ymin, mut zmin) = (f32::MAX, f32::MAX, f32::MAX);
-  let (mut xmax, mut ymax, mut zmax) = (f32::MIN, f32::MIN, f32::MIN);
-
-  for vec in vertices {
-    xmin = xmin.min(vec.x);
-    ymin = ymin.min(vec.y);
-    zmin = zmin.min(vec.z);
-    xmax = xmax.max(vec.x);
-    ymax = ymax.max(vec.y);
-    zmax = zmax.max(vec.z);
-  }
-
-  [[xmin, xmax], [ymin, ymax], [zmin, zmax]]
-}
@@ -1,9 +1,22 @@
 use std::{ffi::c_void, path::Path, rc::Rc, time::Duration};
 
+use common::{
+  camera::{
+    CameraController, CameraPaths, DepthMode, OrbitController, Playback,
+  },
+  capture,
+  math::Aabb,
+  scene_file::ModelDesc,
+  session::CameraState,
+  CameraLike, SceneFile,
+};
+use glam::{Vec2, Vec3};
+
 use glium::{
   backend::{Context, Facade},
   Surface,
 };
+use winit::event::KeyEvent;
 
 use crate::{
   background::Background, camera::Camera, light::Light, object::GPUObject,
@@ -11,12 +24,31 @@ use crate::{
   Result,
 };
 
+// how close the orbit gets and how far it goes
+const MIN_DISTANCE: f32 = 0.01;
+const MAX_DISTANCE: f32 = 100.0;
+
+const CAMERA_PATH: &str = common::project_asset_path!("camera_path.ron");
+
+// what was clicked last, an index into the list it is in
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Selected {
+  Object(usize),
+  ReflectiveObject(usize),
+  ReflectivePlane(usize),
+}
+
 pub struct Scene {
   pub camera: Camera,
+  pub orbit: OrbitController,
+  // recorded into CAMERA_PATH and played back from there. a path being
+  // played overrides the orbit until it ends.
+  paths: CameraPaths,
   pub light: Light,
   objects: Vec<GPUObject>,
   reflective_objects: Vec<ReflectiveObject>,
   reflective_planes: Vec<ReflectivePlane>,
+  selected: Option<Selected>,
   background: Background,
   // context is used to create framebuffers for updating the cubemap
   // without access to the Display.
@@ -24,32 +56,169 @@ pub struct Scene {
 }
 
 impl Scene {
-  pub fn new(facade: &impl Facade, cubemap: &[&Path; 6]) -> Result<Self> {
-    let camera = Camera::new();
+  pub fn new(
+    facade: &impl Facade,
+    cubemap: &[&Path; 6],
+    depth_mode: DepthMode,
+  ) -> Result<Self> {
+    let camera = Camera::new(depth_mode);
+    // looking at the front of the scene
+    let orbit = OrbitController::new(Vec3::ZERO, 2.0)
+      .with_angles(std::f32::consts::PI, 0.0)
+      .with_distance_range(MIN_DISTANCE, MAX_DISTANCE);
     let light = Light::new();
     let background = Background::new(facade, cubemap)?;
     let context = facade.get_context().clone();
 
-    Ok(Self {
+    let mut scene = Self {
       camera,
+      orbit,
+      paths: CameraPaths::new(CAMERA_PATH),
       light,
       objects: vec![],
       reflective_objects: vec![],
       reflective_planes: vec![],
+      selected: None,
       background,
       context,
+    };
+    scene.update_view();
+    Ok(scene)
+  }
+
+  pub fn update_view(&mut self) {
+    match self.paths.player() {
+      Some(player) => self.camera.update_view(player),
+      None => self.camera.update_view(&self.orbit),
+    }
+    // near and far planes as tight as the scene allows
+    let bounds = self.bounds();
+    self.camera.fit_near_far(&bounds);
+  }
+
+  // the camera where it was saved, with the orbit carrying on from there
+  pub fn restore_camera(&mut self, state: &CameraState) {
+    self.camera.restore(state);
+    self.follow_camera();
+  }
+
+  // hands the camera back to the orbit, wherever it is now
+  fn follow_camera(&mut self) {
+    self.orbit = self
+      .camera
+      .orbit_controller()
+      .with_distance_range(MIN_DISTANCE, MAX_DISTANCE);
+    self.paths.stop();
+    self.update_view();
+  }
+
+  // returns whether the key was used
+  pub fn handle_key(&mut self, event: &KeyEvent) -> bool {
+    let used = self.paths.handle_key(event);
+    if used {
+      self.update_view();
+    }
+    used
+  }
+
+  // everything drawn with a mesh, with what selects it
+  fn placed(&self) -> impl Iterator<Item = (Selected, &GPUObject)> {
+    let objects = self
+      .objects
+      .iter()
+      .enumerate()
+      .map(|(i, object)| (Selected::Object(i), object));
+    let reflective_objects = self
+      .reflective_objects
+      .iter()
+      .enumerate()
+      .map(|(i, object)| (Selected::ReflectiveObject(i), object.object()));
+    let reflective_planes = self
+      .reflective_planes
+      .iter()
+      .enumerate()
+      .map(|(i, plane)| (Selected::ReflectivePlane(i), plane.object()));
+    objects.chain(reflective_objects).chain(reflective_planes)
+  }
+
+  fn bounds(&self) -> Aabb {
+    self.placed().fold(Aabb::EMPTY, |aabb, (_, object)| {
+      aabb.union(&object.bounding_box())
     })
   }
 
+  // the closest object under the cursor
+  pub fn pick(&mut self, pixel: Vec2, viewport: Vec2) {
+    let ray = self.camera.shared().pixel_ray(pixel, viewport);
+    self.selected = self
+      .placed()
+      .filter_map(|(selected, object)| Some((selected, object.hit(&ray)?)))
+      .min_by(|a, b| a.1.total_cmp(&b.1))
+      .map(|(selected, _)| selected);
+
+    match self.selected {
+      Some(selected) => println!("Selected {:?}", selected),
+      None => println!("Selection cleared"),
+    }
+  }
+
+  // the selected object, or the whole scene if nothing is selected
+  pub fn frame_selected(&mut self) {
+    let bounds = self
+      .placed()
+      .filter(|(selected, _)| {
+        self.selected.is_none() || self.selected == Some(*selected)
+      })
+      .fold(Aabb::EMPTY, |aabb, (_, object)| {
+        aabb.union(&object.bounding_box())
+      });
+    self.orbit.frame(self.camera.shared(), &bounds);
+    self.paths.stop();
+    self.update_view();
+  }
+
+  // adds the objects, and takes the light and camera if the file has them
+  pub fn load_file(
+    &mut self,
+    file: &SceneFile,
+    facade: &impl Facade,
+  ) -> Result<()> {
+    for (model, desc) in file.flatten() {
+      let object = GPUObject::from_desc(file, desc, model, facade)?;
+      if desc.model == ModelDesc::Plane {
+        self.add_plane(object, desc.reflective)?;
+      } else {
+        self.add_object(object, desc.reflective)?;
+      }
+    }
+
+    if let Some(light) = file.lights.first() {
+      self.light.set_from_desc(light)?;
+    }
+    if file.lights.len() > 1 {
+      eprintln!("Only the first of {} lights is used", file.lights.len());
+    }
+
+    if let Some(orbit) = file.orbit_controller() {
+      self.orbit = orbit.with_distance_range(MIN_DISTANCE, MAX_DISTANCE);
+      self.paths.stop();
+    }
+    self.update_view();
+    Ok(())
+  }
+
   pub fn add_object(
     &mut self,
     object: GPUObject,
     reflective: bool,
   ) -> Result<()> {
     if reflective {
-      self
-        .reflective_objects
-        .push(ReflectiveObject::new(&self.context, object)?);
+      let depth_mode = self.camera.depth_mode();
+      self.reflective_objects.push(ReflectiveObject::new(
+        &self.context,
+        object,
+        depth_mode,
+      )?);
     } else {
       self.objects.push(object);
     }
@@ -62,31 +231,18 @@ impl Scene {
     reflective: bool,
   ) -> Result<()> {
     if reflective {
-      self
-        .reflective_planes
-        .push(ReflectivePlane::new(&self.context, object)?);
+      let depth_mode = self.camera.depth_mode();
+      self.reflective_planes.push(ReflectivePlane::new(
+        &self.context,
+        object,
+        depth_mode,
+      )?);
     } else {
       self.objects.push(object);
     }
     Ok(())
   }
 
-  pub fn reload_shader(&mut self, facade: &impl Facade) -> Result<()> {
-    // TODO: reload shader for background as well
-    self.background.reload_shader(facade)?;
-
-    for obj in &mut self.objects {
-      obj.reload_shader(facade)?;
-    }
-    for obj in &mut self.reflective_objects {
-      obj.reload_shader(facade)?;
-    }
-    for plane in &mut self.reflective_planes {
-      plane.reload_shader(facade)?;
-    }
-    Ok(())
-  }
-
   pub fn draw(&self, target: &mut impl Surface) {
     self.draw_with_camera(target, &self.camera, std::ptr::null());
   }
@@ -97,7 +253,8 @@ impl Scene {
     camera: &Camera,
     skip_obj: *const c_void,
   ) {
-    target.clear_color_and_depth(camera.clear_color().into(), 1.0);
+    let clear_depth = camera.depth_mode().clear_depth();
+    target.clear_color_and_depth(camera.clear_color().into(), clear_depth);
 
     for obj in &self.objects {
       // avoid rendering the object on its own cubemap
@@ -127,7 +284,15 @@ impl Scene {
   }
 
   pub fn update(&mut self, dt: &Duration) {
-    // do nothing for now
+    match self.paths.update(*dt, self.camera.shared()) {
+      Playback::Moved => self.update_view(),
+      Playback::Ended => {
+        self.update_view();
+        self.follow_camera();
+      }
+      Playback::Idle => {}
+    }
+
     for obj in &mut self.objects {
       obj.update(dt);
     }
@@ -151,12 +316,12 @@ impl Scene {
     }
   }
 
-  pub fn handle_resize(&mut self, facade: &impl Facade) {
-    let new_size = facade.get_context().get_framebuffer_dimensions();
+  pub fn handle_resize(&mut self, new_size: (u32, u32)) {
     self.camera.handle_window_resize(new_size);
 
+    let depth_mode = self.camera.depth_mode();
     for plane in &mut self.reflective_planes {
-      plane.handle_resize(facade, new_size);
+      plane.handle_resize(&self.context, depth_mode, new_size);
     }
   }
 
@@ -167,6 +332,18 @@ impl Scene {
     Ok(())
   }
 
+  // the environment and what each reflective object sees, the faces of
+  // each in a directory of their own
+  pub fn export_cubemaps(&self, dir: &Path) -> Result<()> {
+    let environment = self.background.cubemap();
+    capture::save_cubemap(dir.join("environment"), &self.context, environment)?;
+    for (i, obj) in self.reflective_objects.iter().enumerate() {
+      let dir = dir.join(format!("reflection_{}", i));
+      capture::save_cubemap(dir, &self.context, obj.cubemap())?;
+    }
+    Ok(())
+  }
+
   pub fn update_world_textures(&self) -> Result<()> {
     for plane in &self.reflective_planes {
       plane.update_world_texture(&self.context, self, &self.camera)?;
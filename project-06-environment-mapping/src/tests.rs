@@ -0,0 +1,84 @@
+use std::{path::PathBuf, time::Duration};
+
+use common::{
+  app::App,
+  camera::CameraController,
+  golden::{Golden, HEIGHT, WIDTH},
+  project_asset_path,
+  shader_check::ShaderCheck,
+  MaterialBlock, SceneFile,
+};
+use glam::Vec2;
+use image::{Rgb, RgbImage};
+
+use crate::{camera, World};
+
+const GOLDEN_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/golden");
+
+// The cubemap images aren't checked in, see assets/README. A gradient
+// in a color of its own on each face shows the reflections just as well.
+fn generated_cubemap() -> anyhow::Result<[PathBuf; 6]> {
+  let dir = std::env::temp_dir()
+    .join(format!("cs5610-golden-cubemap-{}", std::process::id()));
+  std::fs::create_dir_all(&dir)?;
+  let colors = [
+    [255, 96, 96],
+    [96, 255, 255],
+    [96, 255, 96],
+    [255, 96, 255],
+    [96, 96, 255],
+    [255, 255, 96],
+  ];
+
+  let mut faces = Vec::new();
+  for (i, color) in colors.into_iter().enumerate() {
+    let face = RgbImage::from_fn(64, 64, |x, y| {
+      let shade = (x + y) as f32 / 126.0;
+      Rgb(color.map(|c| (c as f32 * (0.25 + 0.75 * shade)) as u8))
+    });
+    let path = dir.join(format!("face_{}.png", i));
+    face.save(&path)?;
+    faces.push(path);
+  }
+  Ok(faces.try_into().unwrap())
+}
+
+#[test]
+fn reflections() -> anyhow::Result<()> {
+  let depth_mode = camera::DEFAULT_DEPTH_MODE;
+  let golden = Golden::with_depth_mode(GOLDEN_DIR, depth_mode)?;
+  let mut file = SceneFile::load(project_asset_path!("scene.ron"))?;
+  file.cubemap.as_mut().unwrap().faces = generated_cubemap()?;
+
+  let mut world =
+    World::new(project_asset_path!("scene.ron").into(), depth_mode);
+  world.load(golden.facade(), &file)?;
+  world.resize(WIDTH, HEIGHT);
+  world.update(Duration::ZERO);
+  golden.check("scene", |frame| world.render(frame))?;
+
+  // the floor is rendered for the camera, so it has to follow it
+  if let Some(scene) = &mut world.scene {
+    let viewport = Vec2::new(WIDTH as f32, HEIGHT as f32);
+    scene
+      .orbit
+      .drag(Vec2::new(100.0, 100.0), Vec2::new(200.0, 80.0), viewport);
+  }
+  world.update_view();
+  world.update(Duration::ZERO);
+  golden.check("scene_side", |frame| world.render(frame))
+}
+
+// every shader in assets, grouped into the programs the code builds
+#[test]
+fn shaders() -> anyhow::Result<()> {
+  ShaderCheck::new(concat!(env!("CARGO_MANIFEST_DIR"), "/assets"))
+    .program(&["shader.vert", "shader.frag"])
+    .program(&["camera_plane.vert", "camera_plane.frag"])
+    .program(&["shader.vert", "reflective_object.frag"])
+    .program(&["shader.vert", "reflective_plane.frag"])
+    // std140 in GLSL, #[repr(C)] in Rust
+    .uniform_block::<MaterialBlock>()
+    .rust_uniforms(concat!(env!("CARGO_MANIFEST_DIR"), "/src"))
+    .finish()
+}
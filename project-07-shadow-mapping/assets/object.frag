@@ -6,65 +6,39 @@ in vec2 uv_t; // in texture space
 in vec3 pos_v; // in view space
 in vec3 n_v; // in view space
 
-// diffuse, specular, ambient
-uniform vec3 Kd, Ks, Ka;
-uniform float Ns; // shininess
-
-// textures
-uniform sampler2D map_Kd, map_Ks, map_Ka, bump_map;
-uniform uint use_map_Kd, use_map_Ks, use_map_Ka;
+#include <material.glsl>
+#include <lighting.glsl>
+#include <light.glsl>
 
 // in view space
 in vec3 light_dir_raw;
-uniform float light_cone_angle;
-uniform vec3 light_color;
 
 // in light space
 in vec4 shadow_pos;
-uniform sampler2DShadow shadow_map;
+
+// objects that don't receive shadows are drawn with NO_SHADOW defined
+#ifndef NO_SHADOW
+#include <shadow.glsl>
+#endif
 
 void main() {
   vec3 n_v = normalize(n_v);
   vec3 view_dir = normalize(-pos_v);
   vec3 light_dir = normalize(light_dir_raw);
-  // geometry term
-  float geom = max(dot(n_v, light_dir), 0.0);
-
-  float shadow;
-  if (geom <= 0.0) {
-    // fix shadow acne on the back surfaces
-    shadow = 1;
-  } else {
-    vec3 shadow_uv = shadow_pos.xyz;
-    float bias = mix(0.001, 0.0001, geom);
-    shadow_uv.z -= bias;
-    shadow_uv = shadow_uv.xyz / shadow_pos.w;
-    shadow = texture(shadow_map, shadow_uv);
-  }
+  float geom = lambert(n_v, light_dir);
 
-  vec3 oKd;
-  if (use_map_Kd == 1u) {
-    oKd = texture(map_Kd, uv_t).rgb * Kd;
-  } else {
-    oKd = Kd;
+  float shadow = 1.0;
+#ifndef NO_SHADOW
+  // the back surfaces are skipped, which fixes their shadow acne
+  if (geom > 0.0) {
+    shadow = shadow_lookup(shadow_pos, mix(0.001, 0.0001, geom));
   }
+#endif
 
-  // half vector
-  vec3 h = normalize(light_dir + view_dir);
-  float spec = pow(max(dot(n_v, h), 0.0), Ns);
-  vec3 oKs;
-  if (use_map_Ks == 1u) {
-    oKs = texture(map_Ks, uv_t).rgb * Ks;
-  } else {
-    oKs = Ks;
-  }
-
-  vec3 oKa;
-  if (use_map_Ka == 1u) {
-    oKa = texture(map_Ka, uv_t).rgb * Ka;
-  } else {
-    oKa = Ka;
-  }
+  float spec = blinn_phong(n_v, light_dir, view_dir, Ns);
+  vec3 oKd = material_Kd(uv_t);
+  vec3 oKs = material_Ks(uv_t);
+  vec3 oKa = material_Ka(uv_t);
 
   vec3 rgb = light_color * 2 * (geom * oKd + spec * oKs) * shadow + oKa * 0.3;
 
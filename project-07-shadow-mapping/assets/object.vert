@@ -8,15 +8,13 @@ out vec2 uv_t; // in texture space
 out vec3 pos_v; // in view space
 out vec3 n_v; // in view space
 
-uniform mat4 mv, mvp, v, m;
+uniform mat4 mv, mvp, m;
 uniform mat3 mv3, mv_n; // for transforming vertex normals
 
-uniform vec3 light_dir_or_loc;
-uniform int light_type;
+#include <camera.glsl>
+#include <light.glsl>
 
 out vec3 light_dir_raw;
-
-uniform mat4 shadow_transform; // map from world space to shadow texture space
 out vec4 shadow_pos; // in shadow texture space
 
 void main()
@@ -33,11 +31,11 @@ void main()
 
   switch (light_type) {
   case 0: // directional light
-    light_dir_raw = normalize((v * vec4(light_dir_or_loc, 0.0)).xyz);
+    light_dir_raw = normalize((view * vec4(light_dir_or_loc, 0.0)).xyz);
     break;
   case 1: // spot light
     vec3 dir = normalize(light_dir_or_loc - pos_w);
-    light_dir_raw = normalize((v * vec4(dir, 0.0)).xyz);
+    light_dir_raw = normalize((view * vec4(dir, 0.0)).xyz);
     break;
   }
 }
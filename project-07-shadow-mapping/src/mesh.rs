@@ -1,14 +1,18 @@
 #![allow(non_snake_case)]
 
+use common::math::Aabb;
 use common::{
-  mesh::{concat_strips, tear_into_strips},
-  to_raw_image, DynUniforms, MergedUniform, Mtl,
+  mesh::{lod_cache_path, Bvh, LodStrips},
+  to_raw_image, BlockBuffer, DynUniforms, MaterialBlock, MergedUniform, Mtl,
+  OwnedMergedUniform,
 };
+use glam::Vec3;
 use image::RgbImage;
-use std::{collections::HashMap, ops::Range};
+use std::{collections::HashMap, ops::Range, path::Path};
 
 use crate::Result;
 use common::obj_loader::{MtlLib, Obj, VAIdx};
+use common::scene_file::MaterialDesc;
 use glium::{
   backend::Facade, implement_vertex, index::PrimitiveType,
   uniforms::UniformValue, Texture2d,
@@ -46,7 +50,6 @@ implement_vertex!(Vertex, pos, uv, n);
 struct Group {
   #[allow(dead_code)]
   name: String,
-  index_range: Range<u32>,
   mtl: Option<String>,
 }
 
@@ -55,21 +58,37 @@ pub struct Mesh {
   indices: Vec<u32>,
   mtl_lib: MtlLib,
   groups: Vec<Group>,
+  lods: LodStrips,
+  // model space, for picking
+  bvh: Bvh,
   primitive_type: PrimitiveType,
 }
 
 impl Mesh {
-  pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+  // also generate simplified levels of detail at the given ratios of
+  // the triangle count, see common::mesh::lod_chain
+  pub fn load_with_lods<P: AsRef<Path>>(
+    path: P,
+    lod_ratios: &[f32],
+  ) -> Result<Self> {
     let obj = Obj::load_from(&path)?;
-    Ok(Self::from_obj(obj))
+    // building the lods of a big model takes a while, so they are kept
+    // next to it
+    let lod_cache = lod_cache_path(path.as_ref());
+    Ok(Self::from_obj(obj, lod_ratios, Some(&lod_cache)))
   }
 
-  pub fn from_obj(obj: Obj) -> Self {
+  pub fn from_obj(
+    obj: Obj,
+    lod_ratios: &[f32],
+    lod_cache: Option<&Path>,
+  ) -> Self {
     // hash(vertex) -> index
     let mut vert_index: HashMap<u64, usize> = HashMap::new();
     let mut vertices = Vec::new();
-    let mut indices = Vec::new();
     let mut groups = Vec::new();
+    // triangle list of each group
+    let mut group_trigs = Vec::new();
 
     let to_vert_attr = |[v, vt, vn]: VAIdx| Vertex {
       pos: obj.v[v - 1],
@@ -91,29 +110,43 @@ impl Mesh {
           group_indices.push(*i as u32);
         }
       }
-      let strips = tear_into_strips(&group_indices);
-      let group_indices = concat_strips(&strips);
-      let begin = indices.len() as u32;
-      indices.extend_from_slice(&group_indices);
-      let range = begin..(indices.len() as u32);
-      let group = Group {
+      group_trigs.push(group_indices);
+      groups.push(Group {
         name: group.name,
-        index_range: range,
         mtl: group.usemtl,
-      };
-      groups.push(group);
+      });
     }
 
+    // the groups of the full mesh and of each lod as strips
+    let positions: Vec<[f32; 3]> = vertices.iter().map(|v| v.pos).collect();
+    let bvh = Bvh::new(&positions, &group_trigs.concat(), &[]);
+    let mut indices = Vec::new();
+    let lods = LodStrips::build(
+      &mut indices,
+      &positions,
+      &group_trigs,
+      lod_ratios,
+      lod_cache,
+    );
+
     let mtl_lib = obj.mtl_lib;
     Self {
       vertices,
       indices,
       mtl_lib,
       groups,
+      lods,
+      bvh,
       primitive_type: PrimitiveType::TriangleStrip,
     }
   }
 
+  pub fn override_material(&mut self, material: &MaterialDesc) {
+    for mtl in &mut self.mtl_lib.mtls {
+      material.apply(mtl);
+    }
+  }
+
   pub fn upload(&self, facade: &impl Facade) -> Result<GPUMesh> {
     let vbo = glium::VertexBuffer::new(facade, &self.vertices)?;
     let ibo =
@@ -128,12 +161,18 @@ impl Mesh {
       })
       .collect::<Result<HashMap<_, _>>>()?;
     let groups = self.groups.clone();
-    let bounding_box = calc_bounding_box(&self.vertices);
+    let lods = self.lods.clone();
+    let bvh = self.bvh.clone();
+    let points: Vec<Vec3> =
+      self.vertices.iter().map(|v| Vec3::from(v.pos)).collect();
+    let bounding_box = Aabb::from_points(points);
 
     Ok(GPUMesh {
       vbo,
       ibo,
       groups,
+      lods,
+      bvh,
       mtls,
       bounding_box,
     })
@@ -141,90 +180,48 @@ impl Mesh {
 }
 
 pub struct GPUMtl {
-  Ns: f32,
-  Ni: f32,
-  d: f32,
-  Tr: f32,
-  Tf: [f32; 3],
-  illum: u32,
-  Ka: [f32; 3],
-  Kd: [f32; 3],
-  Ks: [f32; 3],
-  Ke: [f32; 3],
+  // what the shaders don't sample, see assets/shaders/material.glsl
+  block: BlockBuffer<MaterialBlock>,
   map_Ka: Option<glium::texture::Texture2d>,
   map_Kd: Option<glium::texture::Texture2d>,
   map_Ks: Option<glium::texture::Texture2d>,
   map_bump: Option<glium::texture::Texture2d>,
-  use_map_Ka: u32,
-  use_map_Kd: u32,
-  use_map_Ks: u32,
-  use_map_bump: u32,
 }
 
 impl GPUMtl {
   fn upload_from(mtl: &Mtl, facade: &impl Facade) -> Result<Self> {
-    let mut gpu_mtl = Self {
-      Ns: mtl.Ns,
-      Ni: mtl.Ni,
-      d: mtl.d,
-      Tr: mtl.Tr,
-      Tf: mtl.Tf,
-      illum: mtl.illum,
-      Ka: mtl.Ka,
+    let mut block = MaterialBlock {
       Kd: mtl.Kd,
+      Ns: mtl.Ns,
       Ks: mtl.Ks,
-      Ke: mtl.Ke,
-      map_Ka: None,
-      map_Kd: None,
-      map_Ks: None,
-      map_bump: None,
-      use_map_Ka: 0,
-      use_map_Kd: 0,
-      use_map_Ks: 0,
-      use_map_bump: 0,
+      Ka: mtl.Ka,
+      d: mtl.d,
+      Ni: mtl.Ni,
+      ..MaterialBlock::default()
+    };
+    let upload = |img: &Option<RgbImage>, used: &mut u32| {
+      let texture = img.as_ref().map(|img| upload_texture(facade, img));
+      *used = texture.is_some() as u32;
+      texture
     };
 
-    if let Some(img) = &mtl.map_Ka {
-      gpu_mtl.map_Ka = Some(upload_texture(facade, img));
-      gpu_mtl.use_map_Ka = 1;
-    }
-
-    if let Some(img) = &mtl.map_Kd {
-      gpu_mtl.map_Kd = Some(upload_texture(facade, img));
-      gpu_mtl.use_map_Kd = 1;
-    }
-
-    if let Some(img) = &mtl.map_Ks {
-      gpu_mtl.map_Ks = Some(upload_texture(facade, img));
-      gpu_mtl.use_map_Ks = 1;
-    }
-
-    if let Some(img) = &mtl.map_bump {
-      gpu_mtl.map_bump = Some(upload_texture(facade, img));
-      gpu_mtl.use_map_bump = 1;
-    }
-
-    Ok(gpu_mtl)
+    let map_Ka = upload(&mtl.map_Ka, &mut block.use_map_Ka);
+    let map_Kd = upload(&mtl.map_Kd, &mut block.use_map_Kd);
+    let map_Ks = upload(&mtl.map_Ks, &mut block.use_map_Ks);
+    let map_bump = upload(&mtl.map_bump, &mut block.use_map_bump);
+
+    Ok(Self {
+      block: BlockBuffer::new(facade, block)?,
+      map_Ka,
+      map_Kd,
+      map_Ks,
+      map_bump,
+    })
   }
 
   fn to_uniforms(&self) -> impl glium::uniforms::Uniforms + '_ {
     let mut uniforms = DynUniforms::new();
 
-    uniforms.add("Ns", &self.Ns);
-    uniforms.add("Ni", &self.Ni);
-    uniforms.add("d", &self.d);
-    uniforms.add("Tr", &self.Tr);
-    uniforms.add("Tf", &self.Tf);
-    uniforms.add("illum", &self.illum);
-    uniforms.add("Ka", &self.Ka);
-    uniforms.add("Kd", &self.Kd);
-    uniforms.add("Ks", &self.Ks);
-    uniforms.add("Ke", &self.Ke);
-    uniforms.add("use_map_Ka", &self.use_map_Ka);
-    uniforms.add("use_map_Kd", &self.use_map_Kd);
-    uniforms.add("use_map_Ks", &self.use_map_Ks);
-    uniforms.add("use_map_bump", &self.use_map_bump);
-
     if let Some(map_Ka) = &self.map_Ka {
       uniforms.add_raw(
         "map_Ka",
@@ -253,7 +250,7 @@ impl GPUMtl {
       );
     }
 
-    uniforms
+    OwnedMergedUniform::new(&self.block, uniforms)
   }
 }
 
@@ -261,22 +258,30 @@ pub struct GPUMesh {
   vbo: glium::VertexBuffer<Vertex>,
   ibo: glium::IndexBuffer<u32>,
   groups: Vec<Group>,
+  lods: LodStrips,
+  // model space, for picking
+  bvh: Bvh,
   mtls: HashMap<String, GPUMtl>,
-  bounding_box: [[f32; 2]; 3],
+  bounding_box: Aabb,
 }
 
 impl GPUMesh {
+  // lod 0 is the full mesh
   pub fn draw(
     &self,
     frame: &mut impl glium::Surface,
     program: &glium::Program,
     uniforms: &impl glium::uniforms::Uniforms,
     params: &glium::DrawParameters<'_>,
+    lod: usize,
   ) {
-    for group in &self.groups {
+    for (group, range) in self.groups.iter().zip(self.lods.groups(lod)) {
+      if range.is_empty() {
+        continue;
+      }
+
       let mtl = group.mtl.as_deref().and_then(|name| self.mtls.get(name));
-      let range: Range<usize> =
-        (group.index_range.start as usize)..(group.index_range.end as usize);
+      let range: Range<usize> = (range.start as usize)..(range.end as usize);
       let ibo_slice = self.ibo.slice(range).unwrap();
 
       if let Some(mtl) = mtl {
@@ -293,18 +298,22 @@ impl GPUMesh {
     }
   }
 
-  pub fn bounding_box(&self) -> [[f32; 2]; 3] {
+  // the levels draw takes, with the model space bounding sphere
+  pub fn lods(&self) -> &LodStrips {
+    &self.lods
+  }
+
+  pub fn bvh(&self) -> &Bvh {
+    &self.bvh
+  }
+
+  pub fn bounding_box(&self) -> Aabb {
     self.bounding_box
   }
 
   #[allow(dead_code)]
   pub fn center(&self) -> [f32; 3] {
-    let [[xmin, xmax], [ymin, ymax], [zmin, zmax]] = self.bounding_box;
-    [
-      (xmin + xmax) / 2.0,
-      (ymin + ymax) / 2.0,
-      (zmin + zmax) / 2.0,
-    ]
+    self.bounding_box.center().to_array()
   }
 }
 
@@ -345,20 +354,3 @@ fn upload_texture(facade: &impl Facade, image: &RgbImage) -> Texture2d {
   unsafe { texture.generate_mipmaps() };
   texture
 }
-
-fn calc_bounding_box(vertices: &[Vertex]) -> [[f32; 2]; 3] {
-  let (mut xmin, mut ymin, mut zmin) = (f32::MAX, f32::MAX, f32::MAX);
-  let (mut xmax, mut ymax, mut zmax) = (f32::MIN, f32::MIN, f32::MIN);
-
-  for v in vertices {
-    let [x, y, z] = v.pos;
-    xmin = xmin.min(x);
-    ymin = ymin.min(y);
-    zmin = zmin.min(z);
-    xmax = xmax.max(x);
-    ymax = ymax.max(y);
-    zmax = zmax.max(z);
-  }
-
-  [[xmin, xmax], [ymin, ymax], [zmin, zmax]]
-}
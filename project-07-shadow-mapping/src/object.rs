@@ -1,48 +1,26 @@
-use std::path::{Path, PathBuf};
-
-use common::{asset_path, load_program, teapot_path, MergedUniform};
-use glam::{Mat3, Mat4, Quat, Vec3};
+use std::cell::Ref;
+use std::path::Path;
+
+use common::math::Aabb;
+use common::mesh::Bvh;
+use common::mesh::DEFAULT_LOD_RATIOS;
+use common::scene_file::{ModelDesc, ObjectDesc};
+use common::{
+  asset_path, MergedUniform, Preprocessor, ProgramVariants, SceneFile,
+  Transform,
+};
+use glam::{Mat3, Mat4, Vec3};
 use glium::backend::Facade;
 use glium::uniforms::Uniforms;
 use glium::{uniform, DrawParameters, Program, Surface};
 
-use crate::light::Light;
 use crate::mesh::{GPUMesh, Mesh};
-use crate::transform::Transform;
 use crate::{Camera, Result};
 
 const SHADER_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/object");
-
-#[allow(unused)]
-pub struct Teapot;
-
-impl Teapot {
-  pub fn load(facade: &impl Facade) -> Result<Object> {
-    let mut object = Object::load(&teapot_path(), &SHADER_PATH, facade)?;
-
-    object.transform = Transform {
-      scale: Vec3::splat(0.05),
-      // the object itself is rotated 90 degrees to the front, let's
-      // rotate it back a little.
-      rotation: Quat::from_rotation_x(-90f32.to_radians()),
-      ..Transform::default()
-    };
-
-    Ok(object)
-  }
-}
-
-#[allow(unused)]
-pub struct Plane;
-
-impl Plane {
-  pub fn load(facade: &impl Facade) -> Result<Object> {
-    let model_path = asset_path("plane.obj");
-    let object = Object::load(&model_path, &SHADER_PATH, facade)?;
-
-    Ok(object)
-  }
-}
+// object.frag leaves the shadow map out with NO_SHADOW
+const VARIANTS: &[(&str, &[&str])] =
+  &[("shadowed", &[]), ("unshadowed", &["NO_SHADOW"])];
 
 #[allow(unused)]
 pub struct LightObject;
@@ -50,81 +28,64 @@ pub struct LightObject;
 impl LightObject {
   pub fn load(facade: &impl Facade) -> Result<Object> {
     let model_path = asset_path("light.obj");
-    let mut object = Object::load(&model_path, &SHADER_PATH, facade)?;
+    let object = Object::load(&model_path, &SHADER_PATH, facade)?;
 
-    object.transform = Transform {
+    Ok(object.cast_no_shadow())
+  }
+
+  pub fn transform() -> Transform {
+    Transform {
       scale: Vec3::splat(0.1),
       ..Transform::default()
-    };
-
-    Ok(object)
+    }
   }
 }
 
 pub struct Object {
-  shader_path: Option<PathBuf>,
-  program: Option<Program>,
+  programs: ProgramVariants,
   mesh: GPUMesh,
-  transform: Transform,
   cast_shadow: bool,
   receive_shadow: bool,
 }
 
 impl Object {
-  pub fn reload_shader(&mut self, facade: &impl Facade) -> Result<()> {
-    let Some(shader_path) = &self.shader_path else {
-      return Ok(());
-    };
-
-    match load_program(shader_path, facade) {
-      Ok(program) => {
-        self.program = Some(program);
-        println!("Shader reloaded: {:?}", shader_path);
-      }
-      Err(e) => {
-        eprintln!("Failed to reload shader: {}", e);
-      }
-    }
-
-    Ok(())
+  // model space
+  pub fn bounding_box(&self) -> Aabb {
+    self.mesh.bounding_box()
   }
 
-  pub fn set_transform(&mut self, transform: Transform) {
-    self.transform = transform;
+  // model space
+  pub fn bvh(&self) -> &Bvh {
+    self.mesh.bvh()
   }
 
-  // world space
-  pub fn bounding_box(&self) -> [[f32; 2]; 3] {
-    let [[x1, x2], [y1, y2], [z1, z2]] = self.mesh.bounding_box();
-    let mut vertices = [
-      [x1, y1, z1],
-      [x1, y1, z2],
-      [x1, y2, z1],
-      [x1, y2, z2],
-      [x2, y1, z1],
-      [x2, y1, z2],
-      [x2, y2, z1],
-      [x2, y2, z2],
-    ];
-
-    let mat = self.transform.to_mat4();
-    for vert in &mut vertices {
-      *vert = mat.transform_point3((*vert).into()).into();
-    }
+  pub fn casts_shadow(&self) -> bool {
+    self.cast_shadow
+  }
 
-    let (mut xmin, mut ymin, mut zmin) = (f32::MAX, f32::MAX, f32::MAX);
-    let (mut xmax, mut ymax, mut zmax) = (f32::MIN, f32::MIN, f32::MIN);
+  pub fn from_desc(
+    file: &SceneFile,
+    desc: &ObjectDesc,
+    facade: &impl Facade,
+  ) -> Result<Self> {
+    let (path, lod_ratios) = match file.model_path(&desc.model)? {
+      Some(path) => (path, &DEFAULT_LOD_RATIOS[..]),
+      None if desc.model == ModelDesc::Plane => {
+        (asset_path("plane.obj"), &[][..])
+      }
+      None => anyhow::bail!("no model for {:?}", desc.model),
+    };
 
-    for [x, y, z] in vertices {
-      xmin = xmin.min(x);
-      ymin = ymin.min(y);
-      zmin = zmin.min(z);
-      xmax = xmax.max(x);
-      ymax = ymax.max(y);
-      zmax = zmax.max(z);
+    let mut mesh = Mesh::load_with_lods(path, lod_ratios)?;
+    if let Some(material) = &desc.material {
+      mesh.override_material(material);
     }
 
-    [[xmin, xmax], [ymin, ymax], [zmin, zmax]]
+    let mut object = Self::from_mesh(&mesh, &SHADER_PATH, facade)?;
+    // there are no reflections here, `reflective` is ignored
+    object.cast_shadow = desc.cast_shadow;
+    object.receive_shadow = desc.receive_shadow;
+    Ok(object)
   }
 
   pub fn load(
@@ -132,17 +93,35 @@ impl Object {
     shader_path: &impl AsRef<Path>,
     facade: &impl Facade,
   ) -> Result<Self> {
-    let mesh = Mesh::load(obj_path)?;
+    Self::load_with_lods(obj_path, shader_path, &[], facade)
+  }
+
+  pub fn load_with_lods(
+    obj_path: &impl AsRef<Path>,
+    shader_path: &impl AsRef<Path>,
+    lod_ratios: &[f32],
+    facade: &impl Facade,
+  ) -> Result<Self> {
+    let mesh = Mesh::load_with_lods(obj_path, lod_ratios)?;
+    Self::from_mesh(&mesh, shader_path, facade)
+  }
+
+  fn from_mesh(
+    mesh: &Mesh,
+    shader_path: &impl AsRef<Path>,
+    facade: &impl Facade,
+  ) -> Result<Self> {
     let mesh = mesh.upload(facade)?;
-    let shader_path = shader_path.as_ref().to_path_buf();
-    let program = Some(load_program(&shader_path, facade)?);
-    let shader_path = Some(shader_path);
+    let programs = ProgramVariants::load(
+      shader_path,
+      &Preprocessor::new(),
+      VARIANTS,
+      facade,
+    )?;
 
     Ok(Self {
-      shader_path,
-      program,
+      programs,
       mesh,
-      transform: Transform::default(),
       cast_shadow: true,
       receive_shadow: true,
     })
@@ -150,14 +129,31 @@ impl Object {
 
   pub fn update(&mut self, _dt: &std::time::Duration) {}
 
-  pub fn model(&self) -> Mat4 {
-    self.transform.to_mat4()
+  // level of detail based on the projected size of the bounding sphere
+  fn lod(&self, mv: Mat4, projection: Mat4, viewport_height: f32) -> usize {
+    self
+      .mesh
+      .lods()
+      .select_projected(mv, projection, viewport_height)
   }
 
-  pub fn draw(&self, frame: &mut impl Surface, camera: &Camera, light: &Light) {
-    if let Some(program) = &self.program {
-      let light_uniforms = light.uniforms(camera);
-      self.draw_with_program(frame, camera, program, light_uniforms, None);
+  // the uniforms of the frame, the camera and light blocks
+  pub fn draw(
+    &self,
+    frame: &mut impl Surface,
+    camera: &Camera,
+    model: Mat4,
+    frame_uniforms: impl Uniforms,
+  ) {
+    if let Some(program) = self.current_program() {
+      self.draw_with_program(
+        frame,
+        camera,
+        model,
+        &program,
+        frame_uniforms,
+        None,
+      );
     } else {
       eprintln!("GPUObject::draw: program is not loaded");
     }
@@ -167,11 +163,11 @@ impl Object {
     &self,
     frame: &mut impl Surface,
     camera: &Camera,
+    m: Mat4,
     program: &Program,
     uniforms: impl Uniforms,
     draw_params: Option<DrawParameters>,
   ) {
-    let m: Mat4 = self.model();
     let v: Mat4 = camera.view();
     let mv: Mat4 = v * m;
     let mv3: Mat3 = Mat3::from_mat4(mv);
@@ -179,7 +175,6 @@ impl Object {
     let mvp: Mat4 = camera.projection() * mv;
 
     let model_uniforms = uniform! {
-      v: v.to_cols_array_2d(),
       m: m.to_cols_array_2d(),
       mvp: mvp.to_cols_array_2d(),
       mv: mv.to_cols_array_2d(),
@@ -189,36 +184,26 @@ impl Object {
     let uniforms = MergedUniform::new(&uniforms, &model_uniforms);
 
     let draw_params = draw_params.unwrap_or_else(|| DrawParameters {
-      depth: glium::Depth {
-        test: glium::draw_parameters::DepthTest::IfLess,
-        write: true,
-        ..Default::default()
-      },
+      // matching the clear value in main.rs
+      depth: camera.depth_mode().depth(),
       backface_culling:
         glium::draw_parameters::BackfaceCullingMode::CullClockwise,
       ..Default::default()
     });
 
-    self.mesh.draw(frame, program, &uniforms, &draw_params);
-  }
-
-  pub fn world_pos(&self) -> Vec3 {
-    self.transform.translation
+    let viewport_height = frame.get_dimensions().1 as f32;
+    let lod = self.lod(mv, camera.projection(), viewport_height);
+    self.mesh.draw(frame, program, &uniforms, &draw_params, lod);
   }
 
-  pub fn translated(mut self, d: [f32; 3]) -> Self {
-    self.transform.translation = Vec3::from(d);
-    self
-  }
-
-  pub fn rotated_y(mut self, angle: f32) -> Self {
-    self.transform.rotation = Quat::from_rotation_y(angle);
-    self
-  }
-
-  pub fn scaled(mut self, sx: f32, sy: f32, sz: f32) -> Self {
-    self.transform.scale = Vec3::new(sx, sy, sz);
-    self
+  // the variant for whether the object receives shadows
+  fn current_program(&self) -> Option<Ref<'_, Program>> {
+    let variant = if self.receive_shadow {
+      "shadowed"
+    } else {
+      "unshadowed"
+    };
+    self.programs.get(variant)
   }
 
   pub fn cast_no_shadow(mut self) -> Self {
@@ -1,19 +1,61 @@
-use std::{rc::Rc, time::Duration};
+use std::{path::Path, rc::Rc, time::Duration};
 
-use glam::Mat4;
+use common::{
+  camera::{
+    ArcballController, CameraController, CameraPaths, FirstPersonController,
+    FlyController, OrbitController, Playback,
+  },
+  capture,
+  math::Aabb,
+  scene_file::LightDesc,
+  session::CameraState,
+  BlockBuffer, CameraBlock, CameraLike, MergedUniform, NodeId, SceneFile,
+  SceneGraph,
+};
+use glam::{Mat4, Vec2, Vec3};
 use glium::backend::Context;
-use winit::keyboard::ModifiersState;
+use serde::{Deserialize, Serialize};
+use winit::{event::KeyEvent, keyboard::ModifiersState};
 
 use crate::{
-  light::ShadowMapVisual, object::LightObject, Camera, Light, Object, Result,
+  light::ShadowMapVisual, object::LightObject, Camera, Light, Object,
+  Projection, Result, Transform,
+};
+
+const PROJECTION: Projection = Projection::Perspective {
+  fov: 90.0,
+  near: 0.1,
+  far: 100.0,
 };
 
+const CAMERA_PATH: &str = common::project_asset_path!("camera_path.ron");
+
+// what is kept between runs
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SceneState {
+  camera: CameraState,
+  controller: usize,
+  light: LightDesc,
+  shadow_map_visual: bool,
+}
+
 pub struct Scene {
   pub light: Light,
   pub camera: Camera,
-  pub objects: Vec<Object>,
-  // tracking the position and orientation of the light
-  pub light_obj: Object,
+  // written at the start of each frame
+  camera_block: BlockBuffer<CameraBlock>,
+  controller: Box<dyn CameraController>,
+  controller_index: usize,
+  // recorded into CAMERA_PATH and played back from there. a path being
+  // played overrides the controller until it ends.
+  paths: CameraPaths,
+  viewport: Vec2,
+  pub graph: SceneGraph<Object>,
+  // parent of everything but the light
+  objects: NodeId,
+  selected: Option<NodeId>,
+  // follows the light, with the light object attached
+  light_rig: NodeId,
   // the boolean is used to toggle the shadow map visual
   shadow_map_visual: (bool, ShadowMapVisual),
   // used internally
@@ -23,43 +65,89 @@ pub struct Scene {
 // Event handling
 impl Scene {
   pub fn new(facade: &impl glium::backend::Facade) -> Result<Self> {
-    Ok(Self {
-      light: Light::new(facade)?,
-      camera: Camera::default(),
-      objects: Vec::new(),
-      light_obj: LightObject::load(facade)?,
+    let light = Light::new(facade)?;
+    let mut graph = SceneGraph::new();
+    let objects = graph.add_group(None, Transform::default());
+    let light_rig = graph.add_group(None, light.pose());
+    graph.add(
+      Some(light_rig),
+      LightObject::transform(),
+      LightObject::load(facade)?,
+    );
+
+    let mut scene = Self {
+      light,
+      camera: Camera::new(Mat4::IDENTITY, PROJECTION),
+      camera_block: BlockBuffer::new(facade, CameraBlock::default())?,
+      controller: Box::new(OrbitController::looking_at(
+        Vec3::new(0.5, 1.0, 1.0),
+        Vec3::ZERO,
+      )),
+      controller_index: 0,
+      paths: CameraPaths::new(CAMERA_PATH),
+      viewport: Vec2::ONE,
+      graph,
+      objects,
+      selected: None,
+      light_rig,
       shadow_map_visual: (false, ShadowMapVisual::new(facade)?),
       context: facade.get_context().clone(),
-    })
+    };
+    scene.update_view();
+    Ok(scene)
   }
 
-  pub fn handle_drag(&mut self, dx: f32, dy: f32, modifiers: ModifiersState) {
+  pub fn handle_drag(
+    &mut self,
+    from: Vec2,
+    to: Vec2,
+    modifiers: ModifiersState,
+  ) {
     if modifiers.shift_key() {
-      self.light.rotate(dx, dy);
-      self.sync_light_obj();
+      let delta = to - from;
+      self.light.rotate(delta.x, delta.y);
+      self.update_light_rig();
     } else {
-      self.camera.rotate(dx, dy);
+      self.controller.drag(from, to, self.viewport);
     }
-    self.camera.update_view();
+    self.update_view();
   }
 
-  fn sync_light_obj(&mut self) {
-    self
-      .light_obj
-      .set_transform(self.light.light_object_transform());
+  // directional or spot
+  pub fn toggle_light_variant(&mut self) {
+    self.light.toggle_light_variant();
+    self.update_light_rig();
+  }
+
+  fn update_light_rig(&mut self) {
+    self.graph.set_local(self.light_rig, self.light.pose());
   }
 
   pub fn handle_resize(&mut self, width: f32, height: f32) {
     self.camera.handle_resize(width, height);
-    self.camera.update_view();
+    self.viewport = Vec2::new(width, height);
+    self.update_view();
   }
 
   pub fn handle_scroll(&mut self, _dx: f32, dy: f32) {
-    self.camera.change_distance(dy);
-    self.camera.update_view();
+    // 30 per notch
+    self.controller.zoom(dy / 30.0);
+    self.update_view();
+  }
+
+  // true if the camera controller used the key
+  pub fn handle_movement_key(&mut self, key: &KeyEvent) -> bool {
+    self
+      .controller
+      .key(&key.logical_key, key.state.is_pressed())
   }
 
   pub fn handle_key(&mut self, key: winit::event::KeyEvent) {
+    if self.paths.handle_key(&key) {
+      self.update_view();
+      return;
+    }
+
     let key = key.logical_key.to_text();
 
     match key {
@@ -67,32 +155,167 @@ impl Scene {
         self.shadow_map_visual.0 = !self.shadow_map_visual.0;
       }
       Some("x") => {
-        self.light.toggle_light_variant();
+        self.toggle_light_variant();
+      }
+      Some("c") => {
+        self.next_controller();
+      }
+      Some("f") => {
+        self.frame_selected();
       }
       _ => {}
     }
   }
 
+  fn next_controller(&mut self) {
+    self.set_controller((self.controller_index + 1) % 4);
+    self.update_view();
+  }
+
+  // orbit, arcball, fly or first person, starting where the previous
+  // one left the camera
+  fn set_controller(&mut self, index: usize) {
+    let camera = &self.camera;
+    let (name, controller): (_, Box<dyn CameraController>) = match index {
+      1 => ("arcball", Box::new(ArcballController::from_camera(camera))),
+      2 => ("fly", Box::new(FlyController::from_camera(camera))),
+      3 => (
+        "first person",
+        Box::new(FirstPersonController::from_camera(camera)),
+      ),
+      _ => ("orbit", Box::new(OrbitController::from_camera(camera))),
+    };
+    println!("Camera controls: {}", name);
+
+    self.controller_index = index;
+    self.controller = controller;
+    self.paths.stop();
+  }
+
+  // the closest object under the cursor
+  pub fn pick(&mut self, pixel: Vec2) {
+    let ray = self.camera.pixel_ray(pixel, self.viewport);
+    self.selected = self
+      .graph
+      .iter()
+      .filter_map(|(id, model, object)| {
+        let hit = object.bvh().closest_hit_placed(&ray, model, f32::MAX)?;
+        Some((id, hit.t))
+      })
+      .min_by(|a, b| a.1.total_cmp(&b.1))
+      .map(|(id, _)| id);
+
+    match self.selected {
+      Some(id) => println!("Selected {:?}", id),
+      None => println!("Selection cleared"),
+    }
+  }
+
+  // the selected object with whatever is attached to it, or the whole
+  // scene if nothing is selected
+  fn frame_selected(&mut self) {
+    let bounds = self
+      .graph
+      .subtree_bounds(self.selected.unwrap_or(self.objects), |object| {
+        object.bounding_box()
+      });
+    self.controller.frame(&self.camera, &bounds);
+    self.update_view();
+  }
+
+  // world space, with the light
+  fn bounds(&self) -> Aabb {
+    self.graph.bounds(Object::bounding_box)
+  }
+
   pub fn update_view(&mut self) {
-    self.camera.update_view()
+    match self.paths.player() {
+      Some(player) => player.apply(&mut self.camera),
+      None => self.controller.apply(&mut self.camera),
+    }
+    // tight near and far planes keep the shadow map small as well
+    self.camera.fit_near_far(&self.bounds());
   }
 
   pub fn update(&mut self, dt: &Duration) {
-    for object in &mut self.objects {
+    match self.paths.update(*dt, &self.camera) {
+      Playback::Moved => self.update_view(),
+      Playback::Ended => {
+        self.update_view();
+        // the controls pick up where the path left the camera
+        self.set_controller(self.controller_index);
+      }
+      Playback::Idle => {
+        if self.controller.update(*dt) {
+          self.update_view();
+        }
+      }
+    }
+
+    for object in self.graph.values_mut() {
       object.update(dt);
     }
+  }
 
-    self.sync_light_obj();
-    self.light_obj.update(dt);
+  pub fn state(&self) -> SceneState {
+    SceneState {
+      camera: CameraState::from_camera(&self.camera),
+      controller: self.controller_index,
+      light: self.light.to_desc(),
+      shadow_map_visual: self.shadow_map_visual.0,
+    }
   }
 
-  pub fn add_object(&mut self, teapot: Object) {
-    self.objects.push(teapot);
+  pub fn restore(&mut self, state: &SceneState) -> Result<()> {
+    self.light.set_from_desc(&state.light)?;
+    self.update_light_rig();
+    self.shadow_map_visual.0 = state.shadow_map_visual;
+
+    state.camera.apply(&mut self.camera);
+    self.set_controller(state.controller);
+    self.update_view();
+    Ok(())
+  }
+
+  // adds the objects, and takes the light and camera if the file has them
+  pub fn load_file(
+    &mut self,
+    file: &SceneFile,
+    facade: &impl glium::backend::Facade,
+  ) -> Result<()> {
+    file.instantiate(&mut self.graph, Some(self.objects), |desc| {
+      Object::from_desc(file, desc, facade)
+    })?;
+
+    if let Some(light) = file.lights.first() {
+      self.light.set_from_desc(light)?;
+      self.update_light_rig();
+    }
+    if file.lights.len() > 1 {
+      eprintln!("Only the first of {} lights is used", file.lights.len());
+    }
+
+    if let Some(orbit) = file.orbit_controller() {
+      self.controller = Box::new(orbit);
+      self.controller_index = 0;
+      self.paths.stop();
+    }
+    let fov = file.camera.as_ref().and_then(|camera| camera.fov);
+    if let (Some(fov), Projection::Perspective { fov: f, .. }) =
+      (fov, &mut self.camera.projection)
+    {
+      *f = fov;
+    }
+
+    self.update_view();
+    Ok(())
   }
 }
 
 impl Scene {
-  pub fn draw(&self, frame: &mut glium::Frame) -> Result<()> {
+  pub fn draw(&self, frame: &mut impl glium::Surface) -> Result<()> {
+    self.camera_block.write(&CameraBlock::new(&self.camera));
+    self.light.update_blocks(&self.camera);
     self.shadow_pass()?;
 
     if self.shadow_map_visual.0 {
@@ -101,14 +324,16 @@ impl Scene {
     }
 
     self.draw_objects(frame)?;
-    self.light_obj.draw(frame, &self.camera, &self.light);
 
     Ok(())
   }
 
-  fn draw_objects(&self, frame: &mut glium::Frame) -> Result<()> {
-    for object in &self.objects {
-      object.draw(frame, &self.camera, &self.light);
+  fn draw_objects(&self, frame: &mut impl glium::Surface) -> Result<()> {
+    let light_uniforms = self.light.uniforms();
+    let frame_uniforms =
+      MergedUniform::new(&self.camera_block, &light_uniforms);
+    for (_, model, object) in self.graph.iter() {
+      object.draw(frame, &self.camera, model, frame_uniforms);
     }
     Ok(())
   }
@@ -118,8 +343,10 @@ impl Scene {
       self.light.shadow_map_target(&self.context, &self.camera)?;
     target.clear();
 
-    for object in &self.objects {
-      target.draw_object(object);
+    for (_, model, object) in self.graph.iter() {
+      if object.casts_shadow() {
+        target.draw_object(object, model);
+      }
     }
 
     Ok(())
@@ -129,14 +356,10 @@ impl Scene {
     self.camera.view_projection()
   }
 
-  pub fn reload_shader(
-    &mut self,
-    facade: &impl glium::backend::Facade,
-  ) -> Result<()> {
-    for object in &mut self.objects {
-      object.reload_shader(facade)?;
-    }
-    self.shadow_map_visual.1.reload_shader(facade)?;
-    Ok(())
+  // the pass is run again first, so the map matches the current view
+  pub fn export_shadow_map(&self, path: impl AsRef<Path>) -> Result<()> {
+    self.shadow_pass()?;
+    let depth = capture::read_depth(&self.context, self.light.shadow_map())?;
+    capture::save_depth(path, &depth)
   }
 }
@@ -0,0 +1,64 @@
+use std::{path::PathBuf, time::Duration};
+
+use common::{
+  app::App,
+  golden::{Golden, HEIGHT, WIDTH},
+  project_asset_path,
+  shader_check::ShaderCheck,
+  CameraBlock, LightBlock, MaterialBlock, ShadowBlock,
+};
+use glam::Vec2;
+use winit::keyboard::ModifiersState;
+
+use crate::World;
+
+const GOLDEN_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/golden");
+
+// the default scene from its own camera and light
+fn world(golden: &Golden) -> anyhow::Result<World> {
+  let mut world = World::new(PathBuf::from(project_asset_path!("scene.ron")));
+  world.load_scene(golden.facade())?;
+  world.resize(WIDTH, HEIGHT);
+  world.update(Duration::ZERO);
+  Ok(world)
+}
+
+// shadow acne and peter panning show up at grazing angles first
+#[test]
+fn directional_light() -> anyhow::Result<()> {
+  let golden = Golden::new(GOLDEN_DIR)?;
+  let mut world = world(&golden)?;
+  golden.check("directional", |frame| world.render(frame))?;
+
+  if let Some(scene) = &mut world.scene {
+    scene.handle_drag(Vec2::ZERO, Vec2::new(8.0, 0.0), ModifiersState::SHIFT);
+  }
+  golden.check("directional_rotated", |frame| world.render(frame))
+}
+
+#[test]
+fn spot_light() -> anyhow::Result<()> {
+  let golden = Golden::new(GOLDEN_DIR)?;
+  let mut world = world(&golden)?;
+  if let Some(scene) = &mut world.scene {
+    scene.toggle_light_variant();
+  }
+  golden.check("spot", |frame| world.render(frame))
+}
+
+// every shader in assets, grouped into the programs the code builds
+#[test]
+fn shaders() -> anyhow::Result<()> {
+  ShaderCheck::new(concat!(env!("CARGO_MANIFEST_DIR"), "/assets"))
+    .program(&["object.vert", "object.frag"])
+    .variant(&["object.vert", "object.frag"], &["NO_SHADOW"])
+    .program(&["shadow.vert", "shadow.frag"])
+    .program(&["preview.vert", "preview.frag"])
+    // std140 in GLSL, #[repr(C)] in Rust
+    .uniform_block::<CameraBlock>()
+    .uniform_block::<LightBlock>()
+    .uniform_block::<ShadowBlock>()
+    .uniform_block::<MaterialBlock>()
+    .rust_uniforms(concat!(env!("CARGO_MANIFEST_DIR"), "/src"))
+    .finish()
+}
@@ -2,8 +2,6 @@
 
 layout(location = 0) out vec4 color;
 
-in vec2 frag_uv;
-
 in VS_OUT {
   vec2 frag_uv;
   vec3 light_dir_m;
@@ -11,13 +11,11 @@ out VS_OUT {
 } vs_out;
 
 uniform mat4 model;
-uniform mat4 view;
-uniform mat4 projection;
 // from model to tangent space
 uniform mat3 tbn_matrix;
 
-uniform vec3 light_dir_or_loc;
-uniform int light_type;
+#include <camera.glsl>
+#include <light.glsl>
 
 void main() {
   vec3 pos_w = (model * vec4(pos, 1)).xyz;
@@ -11,8 +11,9 @@ in vec3 shadow_pos;
 
 uniform sampler2D normal_map;
 
-// shadow map (depth comparison enabled)
-uniform sampler2DShadow shadow_map;
+#include <lighting.glsl>
+#include <shadow.glsl>
+
 // from world space to shadow map space
 // uniform mat4 shadow_transform;
 
@@ -23,21 +24,19 @@ void main() {
   vec3 normal_v = normalize(model_view_normal * norm_m);
   vec3 light_dir_vn = normalize(light_dir_v);
 
-  float geom = max(dot(normal_v, light_dir_vn), 0.0);
+  float geom = lambert(normal_v, light_dir_vn);
   vec3 k_diff = vec3(0.5,0.5,0.5) * geom;
 
   vec3 view_dir_v = normalize(-pos_v);
-  vec3 half_dir_v = normalize(light_dir_vn + view_dir_v);
-  float specular = pow(max(dot(normal_v, half_dir_v), 0.0), 32);
+  float specular = blinn_phong(normal_v, light_dir_vn, view_dir_v, 32.0);
   vec3 k_spec = vec3(1,1,1) * specular;
 
   vec3 ambient = vec3(0.1, 0.1, 0.1);
 
   color = vec4(shadow_pos, 1.0);
-  vec3 shadow_pos_biased = shadow_pos;
-  shadow_pos_biased.z -= mix(0.0005, 0.1, 1.0 - geom);
-  float shadow = texture(shadow_map, shadow_pos_biased);
-  if (shadow_pos_biased.z >= 1.0) {
+  float bias = mix(0.0005, 0.1, 1.0 - geom);
+  float shadow = shadow_lookup(vec4(shadow_pos, 1.0), bias);
+  if (shadow_pos.z - bias >= 1.0) {
     shadow = 1;
   }
 
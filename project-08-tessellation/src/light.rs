@@ -1,18 +1,21 @@
-use std::rc::Rc;
+use std::cell::Ref;
 
-use common::{load_program, Draw, DynUniforms, HasShadow, OwnedMergedUniform};
-use glam::{Mat3, Mat4, Quat, Vec3, Vec4Swizzles as _};
+use common::{
+  scene_file::LightDesc, BlockBuffer, CameraBlock, Draw, HasShadow, LightBlock,
+  MergedUniform, OwnedMergedUniform, ProgramHandle, ShadowBlock,
+};
+use glam::{Mat3, Mat4, Quat, Vec3};
 use glium::{
   backend::Facade,
   framebuffer::SimpleFrameBuffer,
   implement_vertex,
   texture::{DepthTexture2d, MipmapsOption},
   uniform,
-  uniforms::DepthTextureComparison,
-  DrawParameters, Program, Surface as _,
+  uniforms::{DepthTextureComparison, Sampler},
+  DrawParameters, Surface as _,
 };
 
-use crate::{transform::Transform, Camera, Result};
+use crate::{Camera, Projection, Result, Transform};
 
 const SHADOW_MAP_RESOLUTION: u32 = 1024;
 
@@ -46,10 +49,7 @@ impl LightVariant {
     };
   }
 
-  fn light_uniforms(
-    &self,
-    light_color: Vec3,
-  ) -> impl glium::uniforms::Uniforms + '_ {
+  fn light_block(&self, light_color: Vec3) -> LightBlock {
     let typ: i32 = match *self {
       LightVariant::Directional { .. } => 0,
       LightVariant::Spot { .. } => 1,
@@ -63,34 +63,21 @@ impl LightVariant {
       LightVariant::Spot { fov, .. } => *fov,
     };
 
-    uniform! {
-      light_type: typ,
+    LightBlock {
       light_dir_or_loc: dir_or_loc.to_array(),
-      light_cone_angle: cone_angle,
+      light_type: typ,
       light_color: light_color.to_array(),
+      light_cone_angle: cone_angle,
     }
   }
 
-  fn shadow_uniforms<'a>(
-    &'a self,
-    camera: &Camera,
-    map: &'a DepthTexture2d,
-  ) -> impl glium::uniforms::Uniforms + 'a {
+  fn shadow_block(&self, camera: &Camera) -> ShadowBlock {
     let camera = self.shadow_space_camera(camera);
     let transform = Mat4::from_translation(Vec3::new(0.5, 0.5, 0.5))
       * Mat4::from_scale(Vec3::new(0.5, 0.5, 0.5));
     let vp = transform * camera.view_projection();
 
-    let sampled_shadow_map = map
-      .sampled()
-      .depth_texture_comparison(Some(DepthTextureComparison::LessOrEqual))
-      .magnify_filter(glium::uniforms::MagnifySamplerFilter::Linear)
-      .minify_filter(glium::uniforms::MinifySamplerFilter::Nearest)
-      .wrap_function(glium::uniforms::SamplerWrapFunction::BorderClamp)
-      .border_color(Some([1.0, 1.0, 1.0, 1.0]));
-
-    uniform! {
-      shadow_map: sampled_shadow_map,
+    ShadowBlock {
       shadow_transform: vp.to_cols_array_2d(),
     }
   }
@@ -101,58 +88,19 @@ impl LightVariant {
         let view = Mat4::look_at_rh(*dir, Vec3::ZERO, Vec3::Y);
         // calculate orthographic projection based on camera frustum
         let proj = find_bounding_box_projection(camera, view);
-        let proj = crate::Projection::Custom(proj);
-        Camera::new(Vec3::ZERO, -*dir, proj, 1.0)
+        Camera::look_at(Vec3::ZERO, -*dir, Projection::Custom(proj))
       }
       LightVariant::Spot { pos, fov } => {
         let proj = Mat4::perspective_rh_gl(*fov, 1.0, 0.1, 100.0);
-        let proj = crate::Projection::Custom(proj);
-        Camera::new(*pos, Vec3::ZERO, proj, 1.0)
+        Camera::look_at(*pos, Vec3::ZERO, Projection::Custom(proj))
       }
     }
   }
 }
 
 fn find_bounding_box_projection(camera: &Camera, view: Mat4) -> Mat4 {
-  let camera_frustum_world_bbox: (Vec3, Vec3) = camera.world_bounding_box();
-  let get_corner = |x: bool, y: bool, z: bool| -> Vec3 {
-    Vec3::new(
-      if x {
-        camera_frustum_world_bbox.1.x
-      } else {
-        camera_frustum_world_bbox.0.x
-      },
-      if y {
-        camera_frustum_world_bbox.1.y
-      } else {
-        camera_frustum_world_bbox.0.y
-      },
-      if z {
-        camera_frustum_world_bbox.1.z
-      } else {
-        camera_frustum_world_bbox.0.z
-      },
-    )
-  };
-  let corners = [
-    get_corner(false, false, false),
-    get_corner(true, false, false),
-    get_corner(false, true, false),
-    get_corner(true, true, false),
-    get_corner(false, false, true),
-    get_corner(true, false, true),
-    get_corner(false, true, true),
-    get_corner(true, true, true),
-  ];
-
-  let mut min = Vec3::splat(f32::INFINITY);
-  let mut max = Vec3::splat(f32::NEG_INFINITY);
-
-  for corner in &corners {
-    let corner = (view * corner.extend(1.0)).xyz();
-    min = min.min(corner);
-    max = max.max(corner);
-  }
+  // not fitted to the box yet, the fixed volume covers the scene
+  let _light_space_bbox = camera.frustum().aabb().transform(view);
 
   Mat4::orthographic_rh_gl(-1.0, 1.0, -1.0, 1.0, -1.0, 1.0)
 }
@@ -162,22 +110,73 @@ pub struct Light {
   color: Vec3,
   variant: LightVariant,
   map: DepthTexture2d,
-  program: Rc<glium::Program>,
+  program: ProgramHandle,
+  // written by update_blocks
+  light_block: BlockBuffer<LightBlock>,
+  shadow_block: BlockBuffer<ShadowBlock>,
+  // for the shaders of the shadow pass that read the Camera block,
+  // written by shadow_map_target
+  shadow_camera_block: BlockBuffer<CameraBlock>,
 }
 
 impl Light {
   pub fn new(facade: &impl Facade) -> Result<Self> {
     const SHADER_PATH: &str =
       concat!(env!("CARGO_MANIFEST_DIR"), "/assets/shadow");
-    let program = load_program(SHADER_PATH, facade)?;
+    let program = ProgramHandle::load(SHADER_PATH, facade)?;
     Ok(Self {
       color: Vec3::ONE,
       variant: LightVariant::new(),
       map: create_shadow_map(facade),
-      program: Rc::new(program),
+      program,
+      light_block: BlockBuffer::new(facade, LightBlock::default())?,
+      shadow_block: BlockBuffer::new(facade, ShadowBlock::default())?,
+      shadow_camera_block: BlockBuffer::new(facade, CameraBlock::default())?,
     })
   }
 
+  pub fn set_from_desc(&mut self, desc: &LightDesc) -> Result<()> {
+    match *desc {
+      LightDesc::Directional { direction, color } => {
+        self.variant = LightVariant::Directional {
+          dir: direction.normalize(),
+        };
+        self.color = color;
+      }
+      LightDesc::Spot {
+        position,
+        fov,
+        color,
+      } => {
+        self.variant = LightVariant::Spot {
+          pos: position,
+          fov: fov.to_radians(),
+        };
+        self.color = color;
+      }
+      LightDesc::Point { .. } => {
+        anyhow::bail!("point lights have no shadow map, use a spot light")
+      }
+    }
+    Ok(())
+  }
+
+  // the inverse of set_from_desc
+  pub fn to_desc(&self) -> LightDesc {
+    let color = self.color;
+    match self.variant {
+      LightVariant::Directional { dir } => LightDesc::Directional {
+        direction: dir,
+        color,
+      },
+      LightVariant::Spot { pos, fov } => LightDesc::Spot {
+        position: pos,
+        fov: fov.to_degrees(),
+        color,
+      },
+    }
+  }
+
   pub fn toggle_light_variant(&mut self) {
     self.variant.toggle();
   }
@@ -186,17 +185,42 @@ impl Light {
     self.color
   }
 
-  pub fn uniforms(
-    &self,
-    camera: &Camera,
-  ) -> impl glium::uniforms::Uniforms + '_ {
+  // as of the last shadow pass
+  pub fn shadow_map(&self) -> &DepthTexture2d {
+    &self.map
+  }
+
+  // once per frame, before drawing with the uniforms
+  pub fn update_blocks(&self, camera: &Camera) {
+    self
+      .light_block
+      .write(&self.variant.light_block(self.color));
+    self.shadow_block.write(&self.variant.shadow_block(camera));
+  }
+
+  // as of the last update_blocks
+  pub fn uniforms(&self) -> impl glium::uniforms::Uniforms + '_ {
     OwnedMergedUniform::new(
-      self.variant.light_uniforms(self.color),
-      self.variant.shadow_uniforms(camera, &self.map),
+      MergedUniform::new(&self.light_block, &self.shadow_block),
+      uniform! {
+        shadow_map: self.sampled_shadow_map(),
+      },
     )
   }
 
-  pub fn light_object_transform(&self) -> Transform {
+  fn sampled_shadow_map(&self) -> Sampler<'_, DepthTexture2d> {
+    self
+      .map
+      .sampled()
+      .depth_texture_comparison(Some(DepthTextureComparison::LessOrEqual))
+      .magnify_filter(glium::uniforms::MagnifySamplerFilter::Linear)
+      .minify_filter(glium::uniforms::MinifySamplerFilter::Nearest)
+      .wrap_function(glium::uniforms::SamplerWrapFunction::BorderClamp)
+      .border_color(Some([1.0, 1.0, 1.0, 1.0]))
+  }
+
+  // where the light is and which way it points, along +z
+  pub fn pose(&self) -> Transform {
     let position = match self.variant {
       LightVariant::Directional { dir, .. } => dir * 2.0,
       LightVariant::Spot { pos, .. } => pos,
@@ -209,8 +233,8 @@ impl Light {
 
     Transform {
       translation: position,
-      scale: Vec3::splat(0.1),
       rotation: Quat::from_rotation_arc_colinear(Vec3::Z, -dir),
+      ..Transform::default()
     }
   }
 
@@ -233,24 +257,28 @@ impl Light {
   ) -> Result<ShadowMapFramebuffer<'_>> {
     let camera = self.variant.shadow_space_camera(camera);
     let framebuffer = SimpleFrameBuffer::depth_only(facade, &self.map)?;
+    self.shadow_camera_block.write(&CameraBlock::new(&camera));
 
     Ok(ShadowMapFramebuffer {
       camera: Box::new(camera),
+      camera_block: &self.shadow_camera_block,
       framebuffer: Box::new(framebuffer),
-      program: &self.program,
+      program: self.program.get(),
     })
   }
 }
 
 pub struct ShadowMapFramebuffer<'a> {
   camera: Box<Camera>,
+  camera_block: &'a BlockBuffer<CameraBlock>,
   framebuffer: Box<SimpleFrameBuffer<'a>>,
-  program: &'a glium::Program,
+  program: Ref<'a, glium::Program>,
 }
 
 impl<'a> ShadowMapFramebuffer<'a> {
   pub fn clear(&mut self) {
-    self.framebuffer.clear_depth(1.0);
+    let clear_depth = self.camera.depth_mode().clear_depth();
+    self.framebuffer.clear_depth(clear_depth);
   }
 
   pub fn draw_object<O>(&mut self, object: &O) -> Result<()>
@@ -260,22 +288,16 @@ impl<'a> ShadowMapFramebuffer<'a> {
     if !object.casts_shadow() {
       return Ok(());
     }
-    let params = DrawParameters {
-      depth: glium::Depth {
-        test: glium::draw_parameters::DepthTest::IfLess,
-        write: true,
-        ..Default::default()
-      },
-      ..Default::default()
-    };
+    let params = self.camera.depth_mode().draw_parameters();
 
-    let program = object.shadow_program().unwrap_or(self.program);
+    let shadow_program = object.shadow_program();
+    let program = shadow_program.as_deref().unwrap_or(&self.program);
 
     object.draw_raw(
       self.framebuffer.as_mut(),
       self.camera.as_ref(),
       program,
-      DynUniforms::new(),
+      self.camera_block,
       Some(params),
     )?;
 
@@ -303,7 +325,7 @@ struct Vertex {
 implement_vertex!(Vertex, pos, uv);
 
 pub struct ShadowMapVisual {
-  program: Program,
+  program: ProgramHandle,
   vbo: glium::VertexBuffer<Vertex>,
 }
 
@@ -312,7 +334,7 @@ impl ShadowMapVisual {
     concat!(env!("CARGO_MANIFEST_DIR"), "/assets/preview");
 
   pub fn new(facade: &impl Facade) -> Result<Self> {
-    let program = load_program(Self::SHADER_PATH, facade)?;
+    let program = ProgramHandle::load(Self::SHADER_PATH, facade)?;
     let verts = [
       Vertex {
         pos: [-1.0, -1.0],
@@ -335,12 +357,6 @@ impl ShadowMapVisual {
     Ok(Self { program, vbo })
   }
 
-  pub fn reload_shader(&mut self, facade: &impl Facade) -> Result<()> {
-    let program = load_program(Self::SHADER_PATH, facade)?;
-    self.program = program;
-    Ok(())
-  }
-
   pub fn draw(
     &self,
     target: &mut impl glium::Surface,
@@ -370,7 +386,7 @@ impl ShadowMapVisual {
     target.draw(
       &self.vbo,
       glium::index::NoIndices(glium::index::PrimitiveType::TriangleStrip),
-      &self.program,
+      &self.program.get(),
       &uniforms,
       &draw_parameters,
     )?;
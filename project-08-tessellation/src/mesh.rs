@@ -1,9 +1,12 @@
 #![allow(non_snake_case)]
 
+use common::math::Aabb;
 use common::{
-  mesh::{concat_strips, tear_into_strips},
-  to_raw_image, DynUniforms, MergedUniform, Mtl,
+  mesh::{concat_strips, tear_into_strips, Bvh},
+  to_raw_image, BlockBuffer, DynUniforms, MaterialBlock, MergedUniform, Mtl,
+  OwnedMergedUniform,
 };
+use glam::Vec3;
 use image::RgbImage;
 use std::{collections::HashMap, ops::Range};
 
@@ -55,6 +58,8 @@ pub struct Mesh {
   indices: Vec<u32>,
   mtl_lib: MtlLib,
   groups: Vec<Group>,
+  // model space, for picking
+  bvh: Bvh,
   primitive_type: PrimitiveType,
 }
 
@@ -70,6 +75,8 @@ impl Mesh {
     let mut vertices = Vec::new();
     let mut indices = Vec::new();
     let mut groups = Vec::new();
+    // the triangles before they become strips
+    let mut trigs = Vec::new();
 
     let to_vert_attr = |[v, vt, vn]: VAIdx| Vertex {
       pos: obj.v[v - 1],
@@ -91,6 +98,7 @@ impl Mesh {
           group_indices.push(*i as u32);
         }
       }
+      trigs.extend_from_slice(&group_indices);
       let strips = tear_into_strips(&group_indices);
       let group_indices = concat_strips(&strips);
       let begin = indices.len() as u32;
@@ -104,12 +112,16 @@ impl Mesh {
       groups.push(group);
     }
 
+    let positions: Vec<[f32; 3]> = vertices.iter().map(|v| v.pos).collect();
+    let bvh = Bvh::new(&positions, &trigs, &[]);
+
     let mtl_lib = obj.mtl_lib;
     Self {
       vertices,
       indices,
       mtl_lib,
       groups,
+      bvh,
       primitive_type: PrimitiveType::TriangleStrip,
     }
   }
@@ -128,103 +140,64 @@ impl Mesh {
       })
       .collect::<Result<HashMap<_, _>>>()?;
     let groups = self.groups.clone();
-    let bounding_box = calc_bounding_box(&self.vertices);
+    let bvh = self.bvh.clone();
+    let bounding_box =
+      Aabb::from_points(self.vertices.iter().map(|v| Vec3::from(v.pos)));
 
     Ok(GPUMesh {
       vbo,
       ibo,
       groups,
       mtls,
+      bvh,
       bounding_box,
     })
   }
 }
 
 pub struct GPUMtl {
-  Ns: f32,
-  Ni: f32,
-  d: f32,
-  Tr: f32,
-  Tf: [f32; 3],
-  illum: u32,
-  Ka: [f32; 3],
-  Kd: [f32; 3],
-  Ks: [f32; 3],
-  Ke: [f32; 3],
+  // what the shaders don't sample, see assets/shaders/material.glsl
+  block: BlockBuffer<MaterialBlock>,
   map_Ka: Option<glium::texture::Texture2d>,
   map_Kd: Option<glium::texture::Texture2d>,
   map_Ks: Option<glium::texture::Texture2d>,
   map_bump: Option<glium::texture::Texture2d>,
-  use_map_Ka: u32,
-  use_map_Kd: u32,
-  use_map_Ks: u32,
-  use_map_bump: u32,
 }
 
 impl GPUMtl {
   fn upload_from(mtl: &Mtl, facade: &impl Facade) -> Result<Self> {
-    let mut gpu_mtl = Self {
-      Ns: mtl.Ns,
-      Ni: mtl.Ni,
-      d: mtl.d,
-      Tr: mtl.Tr,
-      Tf: mtl.Tf,
-      illum: mtl.illum,
-      Ka: mtl.Ka,
+    let mut block = MaterialBlock {
       Kd: mtl.Kd,
+      Ns: mtl.Ns,
       Ks: mtl.Ks,
-      Ke: mtl.Ke,
-      map_Ka: None,
-      map_Kd: None,
-      map_Ks: None,
-      map_bump: None,
-      use_map_Ka: 0,
-      use_map_Kd: 0,
-      use_map_Ks: 0,
-      use_map_bump: 0,
+      Ka: mtl.Ka,
+      d: mtl.d,
+      Ni: mtl.Ni,
+      ..MaterialBlock::default()
+    };
+    let upload = |img: &Option<RgbImage>, used: &mut u32| {
+      let texture = img.as_ref().map(|img| upload_texture(facade, img));
+      *used = texture.is_some() as u32;
+      texture
     };
 
-    if let Some(img) = &mtl.map_Ka {
-      gpu_mtl.map_Ka = Some(upload_texture(facade, img));
-      gpu_mtl.use_map_Ka = 1;
-    }
-
-    if let Some(img) = &mtl.map_Kd {
-      gpu_mtl.map_Kd = Some(upload_texture(facade, img));
-      gpu_mtl.use_map_Kd = 1;
-    }
-
-    if let Some(img) = &mtl.map_Ks {
-      gpu_mtl.map_Ks = Some(upload_texture(facade, img));
-      gpu_mtl.use_map_Ks = 1;
-    }
-
-    if let Some(img) = &mtl.map_bump {
-      gpu_mtl.map_bump = Some(upload_texture(facade, img));
-      gpu_mtl.use_map_bump = 1;
-    }
-
-    Ok(gpu_mtl)
+    let map_Ka = upload(&mtl.map_Ka, &mut block.use_map_Ka);
+    let map_Kd = upload(&mtl.map_Kd, &mut block.use_map_Kd);
+    let map_Ks = upload(&mtl.map_Ks, &mut block.use_map_Ks);
+    let map_bump = upload(&mtl.map_bump, &mut block.use_map_bump);
+
+    Ok(Self {
+      block: BlockBuffer::new(facade, block)?,
+      map_Ka,
+      map_Kd,
+      map_Ks,
+      map_bump,
+    })
   }
 
   fn to_uniforms(&self) -> impl glium::uniforms::Uniforms + '_ {
     let mut uniforms = DynUniforms::new();
 
-    uniforms.add("Ns", &self.Ns);
-    uniforms.add("Ni", &self.Ni);
-    uniforms.add("d", &self.d);
-    uniforms.add("Tr", &self.Tr);
-    uniforms.add("Tf", &self.Tf);
-    uniforms.add("illum", &self.illum);
-    uniforms.add("Ka", &self.Ka);
-    uniforms.add("Kd", &self.Kd);
-    uniforms.add("Ks", &self.Ks);
-    uniforms.add("Ke", &self.Ke);
-    uniforms.add("use_map_Ka", &self.use_map_Ka);
-    uniforms.add("use_map_Kd", &self.use_map_Kd);
-    uniforms.add("use_map_Ks", &self.use_map_Ks);
-    uniforms.add("use_map_bump", &self.use_map_bump);
-
     if let Some(map_Ka) = &self.map_Ka {
       uniforms.add_raw(
         "map_Ka",
@@ -253,7 +226,7 @@ impl GPUMtl {
       );
     }
 
-    uniforms
+    OwnedMergedUniform::new(&self.block, uniforms)
   }
 }
 
@@ -262,7 +235,8 @@ pub struct GPUMesh {
   ibo: glium::IndexBuffer<u32>,
   groups: Vec<Group>,
   mtls: HashMap<String, GPUMtl>,
-  bounding_box: [[f32; 2]; 3],
+  bvh: Bvh,
+  bounding_box: Aabb,
 }
 
 impl GPUMesh {
@@ -293,18 +267,18 @@ impl GPUMesh {
     }
   }
 
-  pub fn bounding_box(&self) -> [[f32; 2]; 3] {
+  pub fn bounding_box(&self) -> Aabb {
     self.bounding_box
   }
 
+  // model space, for picking
+  pub fn bvh(&self) -> &Bvh {
+    &self.bvh
+  }
+
   #[allow(dead_code)]
   pub fn center(&self) -> [f32; 3] {
-    let [[xmin, xmax], [ymin, ymax], [zmin, zmax]] = self.bounding_box;
-    [
-      (xmin + xmax) / 2.0,
-      (ymin + ymax) / 2.0,
-      (zmin + zmax) / 2.0,
-    ]
+    self.bounding_box.center().to_array()
   }
 }
 
@@ -345,20 +319,3 @@ fn upload_texture(facade: &impl Facade, image: &RgbImage) -> Texture2d {
   unsafe { texture.generate_mipmaps() };
   texture
 }
-
-fn calc_bounding_box(vertices: &[Vertex]) -> [[f32; 2]; 3] {
-  let (mut xmin, mut ymin, mut zmin) = (f32::MAX, f32::MAX, f32::MAX);
-  let (mut xmax, mut ymax, mut zmax) = (f32::MIN, f32::MIN, f32::MIN);
-
-  for v in vertices {
-    let [x, y, z] = v.pos;
-    xmin = xmin.min(x);
-    ymin = ymin.min(y);
-    zmin = zmin.min(z);
-    xmax = xmax.max(x);
-    ymax = ymax.max(y);
-    zmax = zmax.max(z);
-  }
-
-  [[xmin, xmax], [ymin, ymax], [zmin, zmax]]
-}
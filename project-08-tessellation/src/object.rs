@@ -1,37 +1,41 @@
-use std::path::{Path, PathBuf};
+use std::cell::Ref;
+use std::path::Path;
 
+use common::math::Aabb;
+use common::mesh::Bvh;
 use common::{
-  asset_path, load_program, teapot_path, Draw, HasProgram, HasShadow,
-  MergedUniform,
+  asset_path, teapot_path, CameraLike, HasProgram, HasShadow, MergedUniform,
+  Preprocessor, ProgramVariants, Transform,
 };
 use glam::{Mat3, Mat4, Quat, Vec3};
 use glium::backend::Facade;
 use glium::uniforms::Uniforms;
 use glium::{uniform, DrawParameters, Program, Surface};
 
-use crate::light::Light;
 use crate::mesh::{GPUMesh, Mesh};
-use crate::transform::Transform;
 use crate::{Camera, Result};
 
 const SHADER_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/object");
+// object.frag leaves the shadow map out with NO_SHADOW
+const VARIANTS: &[(&str, &[&str])] =
+  &[("shadowed", &[]), ("unshadowed", &["NO_SHADOW"])];
 
 #[allow(unused)]
 pub struct Teapot;
 
 impl Teapot {
   pub fn load(facade: &impl Facade) -> Result<Object> {
-    let mut object = Object::load(&teapot_path(), &SHADER_PATH, facade)?;
+    Object::load(&teapot_path(), &SHADER_PATH, facade)
+  }
 
-    object.transform = Transform {
+  pub fn transform() -> Transform {
+    Transform {
       scale: Vec3::splat(0.05),
       // the object itself is rotated 90 degrees to the front, let's
       // rotate it back a little.
       rotation: Quat::from_rotation_x(-90f32.to_radians()),
       ..Transform::default()
-    };
-
-    Ok(object)
+    }
   }
 }
 
@@ -54,81 +58,33 @@ pub struct LightObject;
 impl LightObject {
   pub fn load(facade: &impl Facade) -> Result<Object> {
     let model_path = asset_path("light.obj");
-    let mut object = Object::load(&model_path, &SHADER_PATH, facade)?;
+    Object::load(&model_path, &SHADER_PATH, facade)
+  }
 
-    object.transform = Transform {
+  pub fn transform() -> Transform {
+    Transform {
       scale: Vec3::splat(0.1),
       ..Transform::default()
-    };
-
-    Ok(object)
+    }
   }
 }
 
 pub struct Object {
-  shader_path: Option<PathBuf>,
-  program: Option<Program>,
+  programs: ProgramVariants,
   mesh: GPUMesh,
-  transform: Transform,
   cast_shadow: bool,
   receive_shadow: bool,
 }
 
 impl Object {
-  pub fn reload_shader(&mut self, facade: &impl Facade) -> Result<()> {
-    let Some(shader_path) = &self.shader_path else {
-      return Ok(());
-    };
-
-    match load_program(shader_path, facade) {
-      Ok(program) => {
-        self.program = Some(program);
-        println!("Shader reloaded: {:?}", shader_path);
-      }
-      Err(e) => {
-        eprintln!("Failed to reload shader: {}", e);
-      }
-    }
-
-    Ok(())
+  // model space
+  pub fn bounding_box(&self) -> Aabb {
+    self.mesh.bounding_box()
   }
 
-  pub fn set_transform(&mut self, transform: Transform) {
-    self.transform = transform;
-  }
-
-  // world space
-  pub fn bounding_box(&self) -> [[f32; 2]; 3] {
-    let [[x1, x2], [y1, y2], [z1, z2]] = self.mesh.bounding_box();
-    let mut vertices = [
-      [x1, y1, z1],
-      [x1, y1, z2],
-      [x1, y2, z1],
-      [x1, y2, z2],
-      [x2, y1, z1],
-      [x2, y1, z2],
-      [x2, y2, z1],
-      [x2, y2, z2],
-    ];
-
-    let mat = self.transform.to_mat4();
-    for vert in &mut vertices {
-      *vert = mat.transform_point3((*vert).into()).into();
-    }
-
-    let (mut xmin, mut ymin, mut zmin) = (f32::MAX, f32::MAX, f32::MAX);
-    let (mut xmax, mut ymax, mut zmax) = (f32::MIN, f32::MIN, f32::MIN);
-
-    for [x, y, z] in vertices {
-      xmin = xmin.min(x);
-      ymin = ymin.min(y);
-      zmin = zmin.min(z);
-      xmax = xmax.max(x);
-      ymax = ymax.max(y);
-      zmax = zmax.max(z);
-    }
-
-    [[xmin, xmax], [ymin, ymax], [zmin, zmax]]
+  // model space
+  pub fn bvh(&self) -> &Bvh {
+    self.mesh.bvh()
   }
 
   pub fn load(
@@ -138,15 +94,16 @@ impl Object {
   ) -> Result<Self> {
     let mesh = Mesh::load(obj_path)?;
     let mesh = mesh.upload(facade)?;
-    let shader_path = shader_path.as_ref().to_path_buf();
-    let program = Some(load_program(&shader_path, facade)?);
-    let shader_path = Some(shader_path);
+    let programs = ProgramVariants::load(
+      shader_path,
+      &Preprocessor::new(),
+      VARIANTS,
+      facade,
+    )?;
 
     Ok(Self {
-      shader_path,
-      program,
+      programs,
       mesh,
-      transform: Transform::default(),
       cast_shadow: true,
       receive_shadow: true,
     })
@@ -154,19 +111,23 @@ impl Object {
 
   pub fn update(&mut self, _dt: &std::time::Duration) {}
 
-  pub fn model(&self) -> Mat4 {
-    self.transform.to_mat4()
-  }
-
+  // the uniforms of the frame, the camera and light blocks
   pub fn draw(
     &self,
     frame: &mut impl Surface,
     camera: &Camera,
-    light: &Light,
+    model: Mat4,
+    frame_uniforms: impl Uniforms,
   ) -> Result<()> {
-    if let Some(program) = &self.program {
-      let light_uniforms = light.uniforms(camera);
-      self.draw_raw(frame, camera, program, light_uniforms, None)?;
+    if let Some(program) = self.current_program() {
+      self.draw_with_program(
+        frame,
+        camera,
+        model,
+        &program,
+        frame_uniforms,
+        None,
+      )?;
     } else {
       eprintln!("GPUObject::draw: program is not loaded");
     }
@@ -174,23 +135,14 @@ impl Object {
     Ok(())
   }
 
-  pub fn world_pos(&self) -> Vec3 {
-    self.transform.translation
-  }
-
-  pub fn translated(mut self, d: [f32; 3]) -> Self {
-    self.transform.translation = Vec3::from(d);
-    self
-  }
-
-  pub fn rotated_y(mut self, angle: f32) -> Self {
-    self.transform.rotation = Quat::from_rotation_y(angle);
-    self
-  }
-
-  pub fn scaled(mut self, sx: f32, sy: f32, sz: f32) -> Self {
-    self.transform.scale = Vec3::new(sx, sy, sz);
-    self
+  // the variant for whether the object receives shadows
+  fn current_program(&self) -> Option<Ref<'_, Program>> {
+    let variant = if self.receive_shadow {
+      "shadowed"
+    } else {
+      "unshadowed"
+    };
+    self.programs.get(variant)
   }
 
   pub fn cast_no_shadow(mut self) -> Self {
@@ -205,23 +157,23 @@ impl Object {
 }
 
 impl HasProgram for Object {
-  fn program(&self) -> &glium::Program {
-    self.program.as_ref().unwrap()
+  fn program(&self) -> Ref<'_, glium::Program> {
+    self.current_program().unwrap()
   }
 }
 
 impl HasShadow for Object {}
 
-impl Draw for Object {
-  fn draw_raw(
+impl Object {
+  pub fn draw_with_program(
     &self,
     frame: &mut impl glium::Surface,
-    camera: &impl common::CameraLike,
+    camera: &impl CameraLike,
+    m: Mat4,
     program: &glium::Program,
     uniforms: impl Uniforms,
     draw_params: Option<DrawParameters>,
   ) -> Result<()> {
-    let m: Mat4 = self.model();
     let v = Mat4::from_cols_array_2d(&camera.view());
     let p = Mat4::from_cols_array_2d(&camera.projection());
 
@@ -232,8 +184,6 @@ impl Draw for Object {
 
     let model_uniforms = uniform! {
       m: m.to_cols_array_2d(),
-      m: m.to_cols_array_2d(),
-      v: v.to_cols_array_2d(),
       mvp: mvp.to_cols_array_2d(),
       mv: mv.to_cols_array_2d(),
       mv3: mv3.to_cols_array_2d(),
@@ -242,14 +192,9 @@ impl Draw for Object {
     let uniforms = MergedUniform::new(&uniforms, &model_uniforms);
 
     let draw_params = draw_params.unwrap_or_else(|| DrawParameters {
-      depth: glium::Depth {
-        test: glium::draw_parameters::DepthTest::IfLess,
-        write: true,
-        ..Default::default()
-      },
       backface_culling:
         glium::draw_parameters::BackfaceCullingMode::CullClockwise,
-      ..Default::default()
+      ..camera.depth_mode().draw_parameters()
     });
 
     self.mesh.draw(frame, program, &uniforms, &draw_params);
@@ -1,20 +1,64 @@
-use std::{rc::Rc, time::Duration};
+use std::{path::Path, rc::Rc, time::Duration};
 
-use glam::Mat4;
-use glium::backend::Context;
+use common::{
+  camera::{CameraController, CameraPaths, OrbitController, Playback},
+  capture,
+  math::Aabb,
+  scene_file::LightDesc,
+  session::CameraState,
+  BlockBuffer, CameraBlock, CameraLike, MergedUniform, NodeId, SceneGraph,
+};
+use glam::{Mat4, Vec2, Vec3};
+use glium::{backend::Context, uniforms::Uniforms};
+use serde::{Deserialize, Serialize};
 use winit::keyboard::{ModifiersState, NamedKey};
 
 use crate::{
-  light::ShadowMapVisual, object::LightObject, teapot_quad::TeapotQuad, Camera,
-  Light, Object, Result,
+  light::ShadowMapVisual,
+  object::LightObject,
+  teapot_quad::{QuadState, TeapotQuad},
+  Camera, Light, Object, Projection, Result,
+};
+
+const PROJECTION: Projection = Projection::Perspective {
+  fov: 90.0,
+  near: 0.1,
+  far: 100.0,
 };
 
+const CAMERA_PATH: &str = common::project_asset_path!("camera_path.ron");
+
+// what is kept between runs
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SceneState {
+  camera: CameraState,
+  light: LightDesc,
+  shadow_map_visual: bool,
+  quad: Option<QuadState>,
+}
+
+// what was clicked last
+#[derive(Clone, Copy, Debug)]
+enum Selected {
+  Quad,
+  Object(NodeId),
+}
+
 pub struct Scene {
   pub light: Light,
   pub camera: Camera,
+  // written at the start of each frame
+  camera_block: BlockBuffer<CameraBlock>,
+  controller: OrbitController,
+  // recorded into CAMERA_PATH and played back from there. a path being
+  // played overrides the controller until it ends.
+  paths: CameraPaths,
+  viewport: Vec2,
   pub teapot_quad: Option<TeapotQuad>,
-  // tracking the position and orientation of the light
-  pub light_obj: Object,
+  pub graph: SceneGraph<Object>,
+  selected: Option<Selected>,
+  // follows the light, with the light object attached
+  light_rig: NodeId,
   // the boolean is used to toggle the shadow map visual
   shadow_map_visual: (bool, ShadowMapVisual),
   // used internally
@@ -24,49 +68,88 @@ pub struct Scene {
 // Event handling
 impl Scene {
   pub fn new(facade: &impl glium::backend::Facade) -> Result<Self> {
-    Ok(Self {
-      light: Light::new(facade)?,
-      camera: Camera::default(),
+    let light = Light::new(facade)?;
+    let mut graph = SceneGraph::new();
+    let light_rig = graph.add_group(None, light.pose());
+    graph.add(
+      Some(light_rig),
+      LightObject::transform(),
+      LightObject::load(facade)?,
+    );
+
+    let mut scene = Self {
+      light,
+      camera: Camera::new(Mat4::IDENTITY, PROJECTION),
+      camera_block: BlockBuffer::new(facade, CameraBlock::default())?,
+      controller: OrbitController::looking_at(
+        Vec3::new(0.5, 1.0, 1.0),
+        Vec3::ZERO,
+      ),
+      paths: CameraPaths::new(CAMERA_PATH),
+      viewport: Vec2::ONE,
       teapot_quad: None,
-      light_obj: LightObject::load(facade)?,
+      graph,
+      selected: None,
+      light_rig,
       shadow_map_visual: (false, ShadowMapVisual::new(facade)?),
       context: facade.get_context().clone(),
-    })
+    };
+    scene.update_view();
+    Ok(scene)
   }
 
-  pub fn handle_drag(&mut self, dx: f32, dy: f32, modifiers: ModifiersState) {
+  pub fn handle_drag(
+    &mut self,
+    from: Vec2,
+    to: Vec2,
+    modifiers: ModifiersState,
+  ) {
     if modifiers.shift_key() {
-      self.light.rotate(dx, dy);
-      self.sync_light_obj();
+      let delta = to - from;
+      self.light.rotate(delta.x, delta.y);
+      self.update_light_rig();
     } else {
-      self.camera.rotate(dx, dy);
+      self.controller.drag(from, to, self.viewport);
     }
-    self.camera.update_view();
+    self.update_view();
   }
 
-  fn sync_light_obj(&mut self) {
-    self
-      .light_obj
-      .set_transform(self.light.light_object_transform());
+  // directional or spot
+  pub fn toggle_light_variant(&mut self) {
+    self.light.toggle_light_variant();
+    self.update_light_rig();
+  }
+
+  fn update_light_rig(&mut self) {
+    self.graph.set_local(self.light_rig, self.light.pose());
   }
 
   pub fn handle_resize(&mut self, width: f32, height: f32) {
     self.camera.handle_resize(width, height);
-    self.camera.update_view();
+    self.viewport = Vec2::new(width, height);
+    self.update_view();
   }
 
   pub fn handle_scroll(&mut self, _dx: f32, dy: f32) {
-    self.camera.change_distance(dy);
-    self.camera.update_view();
+    // 30 per notch
+    self.controller.zoom(dy / 30.0);
+    self.update_view();
   }
 
   pub fn handle_key(&mut self, key: winit::event::KeyEvent) {
+    if self.paths.handle_key(&key) {
+      self.update_view();
+      return;
+    }
+
     let key = key.logical_key;
 
     if key == "s" {
       self.shadow_map_visual.0 = !self.shadow_map_visual.0;
     } else if key == "x" {
-      self.light.toggle_light_variant();
+      self.toggle_light_variant();
+    } else if key == "f" {
+      self.frame_selected();
     }
 
     let Some(quad) = &mut self.teapot_quad else {
@@ -79,33 +162,129 @@ impl Scene {
       quad.adjust_detail_level(1.0);
     } else if key == NamedKey::ArrowUp {
       quad.adjust_displacement_scale(0.01);
+      self.update_view();
     } else if key == NamedKey::ArrowDown {
       quad.adjust_displacement_scale(-0.01);
+      self.update_view();
     } else if key == "w" {
       quad.cycle_draw_mode();
     }
   }
 
+  // the closest of the quad and the objects under the cursor
+  pub fn pick(&mut self, pixel: Vec2) {
+    let ray = self.camera.pixel_ray(pixel, self.viewport);
+    let quad = self
+      .teapot_quad
+      .as_ref()
+      .and_then(|quad| Some((Selected::Quad, quad.hit(&ray)?)));
+    let objects = self.graph.iter().filter_map(|(id, model, object)| {
+      let hit = object.bvh().closest_hit_placed(&ray, model, f32::MAX)?;
+      Some((Selected::Object(id), hit.t))
+    });
+    self.selected = quad
+      .into_iter()
+      .chain(objects)
+      .min_by(|a, b| a.1.total_cmp(&b.1))
+      .map(|(selected, _)| selected);
+
+    match self.selected {
+      Some(selected) => println!("Selected {:?}", selected),
+      None => println!("Selection cleared"),
+    }
+  }
+
+  // the selected quad or object, or the whole scene if nothing is
+  // selected
+  fn frame_selected(&mut self) {
+    let bounds = match self.selected {
+      Some(Selected::Quad) => self.bounds(),
+      Some(Selected::Object(id)) => {
+        self.graph.subtree_bounds(id, Object::bounding_box)
+      }
+      None => self
+        .bounds()
+        .union(&self.graph.bounds(Object::bounding_box)),
+    };
+    self.controller.frame(&self.camera, &bounds);
+    self.update_view();
+  }
+
+  // world space, without the light
+  fn bounds(&self) -> Aabb {
+    self
+      .teapot_quad
+      .as_ref()
+      .map_or(Aabb::EMPTY, TeapotQuad::bounding_box)
+  }
+
   pub fn update_view(&mut self) {
-    self.camera.update_view()
+    match self.paths.player() {
+      Some(player) => player.apply(&mut self.camera),
+      None => self.controller.apply(&mut self.camera),
+    }
+    // tight near and far planes keep the shadow map small as well
+    let light_bounds = self.graph.bounds(Object::bounding_box);
+    let bounds = self.bounds().union(&light_bounds);
+    self.camera.fit_near_far(&bounds);
   }
 
   pub fn update(&mut self, dt: &Duration) {
+    match self.paths.update(*dt, &self.camera) {
+      Playback::Moved => self.update_view(),
+      Playback::Ended => {
+        self.update_view();
+        // the orbit picks up where the path left the camera
+        self.controller = OrbitController::from_camera(&self.camera);
+        self.paths.stop();
+      }
+      Playback::Idle => {}
+    }
+
     if let Some(quad) = &mut self.teapot_quad {
       quad.update(dt);
     }
 
-    self.sync_light_obj();
-    self.light_obj.update(dt);
+    for object in self.graph.values_mut() {
+      object.update(dt);
+    }
+  }
+
+  pub fn state(&self) -> SceneState {
+    SceneState {
+      camera: CameraState::from_camera(&self.camera),
+      light: self.light.to_desc(),
+      shadow_map_visual: self.shadow_map_visual.0,
+      quad: self.teapot_quad.as_ref().map(TeapotQuad::state),
+    }
+  }
+
+  pub fn restore(&mut self, state: &SceneState) -> Result<()> {
+    self.light.set_from_desc(&state.light)?;
+    self.update_light_rig();
+    self.shadow_map_visual.0 = state.shadow_map_visual;
+    if let (Some(quad), Some(quad_state)) = (&mut self.teapot_quad, &state.quad)
+    {
+      quad.restore(quad_state);
+    }
+
+    state.camera.apply(&mut self.camera);
+    self.controller = OrbitController::from_camera(&self.camera);
+    self.paths.stop();
+    self.update_view();
+    Ok(())
   }
 
   pub fn set_quad(&mut self, quad: TeapotQuad) {
     self.teapot_quad = Some(quad);
+    self.update_view();
   }
 }
 
 impl Scene {
-  pub fn draw(&self, frame: &mut glium::Frame) -> Result<()> {
+  pub fn draw(&self, frame: &mut impl glium::Surface) -> Result<()> {
+    self.camera_block.write(&CameraBlock::new(&self.camera));
+    self.light.update_blocks(&self.camera);
     self.shadow_pass()?;
 
     if self.shadow_map_visual.0 {
@@ -113,15 +292,24 @@ impl Scene {
       return Ok(());
     }
 
-    self.draw_objects(frame)?;
-    self.light_obj.draw(frame, &self.camera, &self.light)?;
+    let light_uniforms = self.light.uniforms();
+    let frame_uniforms =
+      MergedUniform::new(&self.camera_block, &light_uniforms);
+    self.draw_objects(frame, frame_uniforms)?;
+    for (_, model, object) in self.graph.iter() {
+      object.draw(frame, &self.camera, model, frame_uniforms)?;
+    }
 
     Ok(())
   }
 
-  fn draw_objects(&self, frame: &mut glium::Frame) -> Result<()> {
+  fn draw_objects(
+    &self,
+    frame: &mut impl glium::Surface,
+    frame_uniforms: impl Uniforms + Copy,
+  ) -> Result<()> {
     if let Some(quad) = &self.teapot_quad {
-      quad.draw(frame, &self.camera, &self.light)?;
+      quad.draw(frame, &self.camera, frame_uniforms)?;
     }
 
     Ok(())
@@ -143,14 +331,10 @@ impl Scene {
     self.camera.view_projection()
   }
 
-  pub fn reload_shader(
-    &mut self,
-    facade: &impl glium::backend::Facade,
-  ) -> Result<()> {
-    if let Some(quad) = &mut self.teapot_quad {
-      quad.reload_shader(facade)?;
-    }
-    self.shadow_map_visual.1.reload_shader(facade)?;
-    Ok(())
+  // the pass is run again first, so the map matches the current view
+  pub fn export_shadow_map(&self, path: impl AsRef<Path>) -> Result<()> {
+    self.shadow_pass()?;
+    let depth = capture::read_depth(&self.context, self.light.shadow_map())?;
+    capture::save_depth(path, &depth)
   }
 }
@@ -1,22 +1,24 @@
-use std::time::Duration;
+use std::{cell::Ref, time::Duration};
 
 use common::{
+  math::{Aabb, Ray},
+  mesh::Bvh,
   to_raw_image, CameraLike, Draw, DynUniforms, HasShadow, MergedUniform,
-  OwnedMergedUniform,
+  OwnedMergedUniform, Preprocessor, ProgramHandle,
 };
 use glam::{Mat3, Mat4, Vec3};
 use glium::{
   backend::Facade,
   implement_vertex,
   index::{NoIndices, PrimitiveType},
-  program::SourceCode,
   uniform,
   uniforms::{SamplerWrapFunction, UniformValue, Uniforms},
   Depth, DepthTest, DrawParameters, Program, Surface, Texture2d, VertexBuffer,
 };
 use image::RgbImage;
+use serde::{Deserialize, Serialize};
 
-use crate::{Camera, Light, Result, Transform};
+use crate::{Camera, Result, Transform};
 
 #[derive(Copy, Clone)]
 struct Vertex {
@@ -28,7 +30,7 @@ implement_vertex!(Vertex, pos, uv);
 
 const LOCAL_ASSETS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets");
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum DrawMode {
   Tessellation,
   TessellationWireframe,
@@ -36,16 +38,26 @@ pub enum DrawMode {
   Parallax,
 }
 
+// the settings adjusted with the keyboard
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct QuadState {
+  detail_level: f32,
+  displacement_scale: f32,
+  draw_mode: DrawMode,
+}
+
 pub struct TeapotQuad {
   vbo: VertexBuffer<Vertex>,
+  // the flat quad, the displacement is left out of picking
+  bvh: Bvh,
   model: Transform,
   normal_map: Texture2d,
   displacement_map: Texture2d,
   color_texture: Option<Texture2d>,
-  program: Program,
-  shadow_program: Program,
-  parallax_program: Program,
-  wireframe_program: Program,
+  program: ProgramHandle,
+  shadow_program: ProgramHandle,
+  parallax_program: ProgramHandle,
+  wireframe_program: ProgramHandle,
   detail_level: f32,
   displacement_scale: f32,
   draw_mode: DrawMode,
@@ -72,6 +84,8 @@ impl TeapotQuad {
       },
     ];
     let vbo = VertexBuffer::new(facade, &verts)?;
+    let positions = verts.map(|v| v.pos);
+    let bvh = Bvh::new(&positions, &[0, 1, 2, 2, 1, 3], &[]);
 
     let program = Self::load_program(facade)?;
     let shadow_program = Self::load_shadow_program(facade)?;
@@ -94,6 +108,7 @@ impl TeapotQuad {
     Ok(Self {
       model: Transform::default(),
       vbo,
+      bvh,
       program,
       shadow_program,
       wireframe_program,
@@ -107,22 +122,27 @@ impl TeapotQuad {
     })
   }
 
+  // the uniforms of the frame, the camera and light blocks
   pub fn draw(
     &self,
     target: &mut impl Surface,
     camera: &Camera,
-    light: &Light,
+    frame_uniforms: impl Uniforms + Copy,
   ) -> Result<()> {
     match self.draw_mode {
-      DrawMode::Tessellation => self.draw_normal(target, camera, light)?,
+      DrawMode::Tessellation => {
+        self.draw_normal(target, camera, frame_uniforms)?
+      }
       DrawMode::TessellationWireframe => {
-        self.draw_wireframe(target, camera, light)?
+        self.draw_wireframe(target, camera, frame_uniforms)?
       }
       DrawMode::TessellationWithWireframe => {
-        self.draw_normal(target, camera, light)?;
-        self.draw_wireframe(target, camera, light)?;
+        self.draw_normal(target, camera, frame_uniforms)?;
+        self.draw_wireframe(target, camera, frame_uniforms)?;
+      }
+      DrawMode::Parallax => {
+        self.draw_parallax(target, camera, frame_uniforms)?
       }
-      DrawMode::Parallax => self.draw_parallax(target, camera, light)?,
     }
 
     Ok(())
@@ -132,15 +152,9 @@ impl TeapotQuad {
     &self,
     target: &mut impl Surface,
     camera: &Camera,
-    light: &Light,
+    frame_uniforms: impl Uniforms,
   ) -> Result<()> {
-    self.draw_raw(
-      target,
-      camera,
-      &self.program,
-      light.uniforms(camera),
-      None,
-    )?;
+    self.draw_raw(target, camera, &self.program.get(), frame_uniforms, None)?;
 
     Ok(())
   }
@@ -149,11 +163,10 @@ impl TeapotQuad {
     &self,
     target: &mut impl Surface,
     camera: &Camera,
-    light: &Light,
+    frame_uniforms: impl Uniforms,
   ) -> Result<()> {
-    let program = &self.parallax_program;
-    let own_uniforms = self.uniforms(camera, program);
-    let light_uniforms = light.uniforms(camera);
+    let program = self.parallax_program.get();
+    let own_uniforms = self.uniforms(camera, &program);
 
     // maps from model space to tangent space.  transpose == inverse
     // for orthogonal matrices
@@ -170,14 +183,14 @@ impl TeapotQuad {
     let extra_uniforms = uniform! {
       tbn_matrix: tbn_matrix.to_cols_array_2d(),
     };
-    let uniforms = MergedUniform::new(&light_uniforms, &own_uniforms);
+    let uniforms = MergedUniform::new(&frame_uniforms, &own_uniforms);
     let uniforms = MergedUniform::new(&uniforms, &extra_uniforms);
-    let params = default_draw_params();
+    let params = camera.depth_mode().draw_parameters();
 
     target.draw(
       &self.vbo,
       NoIndices(PrimitiveType::TriangleStrip),
-      program,
+      &program,
       &uniforms,
       &params,
     )?;
@@ -189,9 +202,9 @@ impl TeapotQuad {
     &self,
     target: &mut impl Surface,
     camera: &Camera,
-    light: &Light,
+    frame_uniforms: impl Uniforms,
   ) -> Result<()> {
-    let mut params = default_draw_params();
+    let mut params = camera.depth_mode().draw_parameters();
     params.depth = Depth {
       test: DepthTest::Overwrite,
       write: false,
@@ -203,8 +216,8 @@ impl TeapotQuad {
     self.draw_raw(
       target,
       camera,
-      &self.wireframe_program,
-      light.uniforms(camera),
+      &self.wireframe_program.get(),
+      frame_uniforms,
       Some(params),
     )?;
 
@@ -216,38 +229,24 @@ impl TeapotQuad {
     camera: &'a impl CameraLike,
     program: &Program,
   ) -> impl Uniforms + 'a {
-    fn mat4_uniform(mat: &Mat4) -> UniformValue<'static> {
-      glium::uniforms::UniformValue::Mat4(mat.to_cols_array_2d())
-    }
-    fn mat3_uniform(mat3: &Mat3) -> UniformValue<'static> {
-      glium::uniforms::UniformValue::Mat3(mat3.to_cols_array_2d())
-    }
-
+    // view, projection and view_projection are in the Camera block, which
+    // glium also lists as uniforms without a location
     let mut dynamic_uniforms = DynUniforms::new();
     let model = self.model.to_mat4();
     let view = Mat4::from_cols_array_2d(&camera.view());
     let proj = Mat4::from_cols_array_2d(&camera.projection());
 
-    dynamic_uniforms.add_raw("model", mat4_uniform(&model));
-    dynamic_uniforms.add_raw("view", mat4_uniform(&view));
-    dynamic_uniforms.add_raw("projection", mat4_uniform(&proj));
-
-    if program.get_uniform("view_projection").is_some() {
-      let view_proj = proj * view;
-      dynamic_uniforms.add_raw("view_projection", mat4_uniform(&view_proj));
-    }
+    dynamic_uniforms.add("model", model);
 
     if program.get_uniform("model_view_projection").is_some() {
       let model_view_proj = proj * view * model;
-      dynamic_uniforms
-        .add_raw("model_view_projection", mat4_uniform(&model_view_proj));
+      dynamic_uniforms.add("model_view_projection", model_view_proj);
     }
 
     if program.get_uniform("model_view_normal").is_some() {
       let model_view = view * model;
       let model_view_normal = Mat3::from_mat4(model_view).inverse().transpose();
-      dynamic_uniforms
-        .add_raw("model_view_normal", mat3_uniform(&model_view_normal));
+      dynamic_uniforms.add("model_view_normal", model_view_normal);
     }
 
     if let Some(color_texture) = self.color_texture.as_ref() {
@@ -258,7 +257,7 @@ impl TeapotQuad {
         .wrap_function(SamplerWrapFunction::Clamp);
       dynamic_uniforms.add_raw(
         "color_texture",
-        glium::uniforms::UniformValue::Texture2d(sampler.0, Some(sampler.1)),
+        UniformValue::Texture2d(sampler.0, Some(sampler.1)),
       );
     }
 
@@ -285,81 +284,74 @@ impl TeapotQuad {
     OwnedMergedUniform::new(dynamic_uniforms, extra_uniforms)
   }
 
-  pub fn load_program(facade: &impl Facade) -> Result<Program> {
-    use std::fs::read_to_string;
-    let vert = read_to_string(format!("{LOCAL_ASSETS}/tess_obj.vert"))?;
-    let tcs = read_to_string(format!("{LOCAL_ASSETS}/tess_obj.tcs"))?;
-    let tes = read_to_string(format!("{LOCAL_ASSETS}/tess_obj.tes"))?;
-    let frag = read_to_string(format!("{LOCAL_ASSETS}/tess_obj.frag"))?;
-
-    let program = SourceCode {
-      vertex_shader: &vert,
-      tessellation_control_shader: Some(&tcs),
-      tessellation_evaluation_shader: Some(&tes),
-      fragment_shader: &frag,
-      geometry_shader: None,
-    };
-
-    let program = Program::new(facade, program)?;
-    Ok(program)
+  fn load_program(facade: &impl Facade) -> Result<ProgramHandle> {
+    Self::load_stages(
+      &[
+        "tess_obj.vert",
+        "tess_obj.tcs",
+        "tess_obj.tes",
+        "tess_obj.frag",
+      ],
+      facade,
+    )
   }
 
-  fn load_parallax_program(facade: &impl Facade) -> Result<Program> {
-    use std::fs::read_to_string;
-    let vert = read_to_string(format!("{LOCAL_ASSETS}/quad_parallax.vert"))?;
-    let frag = read_to_string(format!("{LOCAL_ASSETS}/quad_parallax.frag"))?;
-
-    Ok(Program::from_source(facade, &vert, &frag, None)?)
+  fn load_parallax_program(facade: &impl Facade) -> Result<ProgramHandle> {
+    Self::load_stages(&["quad_parallax.vert", "quad_parallax.frag"], facade)
   }
 
-  fn load_wireframe_program(facade: &impl Facade) -> Result<Program> {
-    use std::fs::read_to_string;
-    let vert = read_to_string(format!("{LOCAL_ASSETS}/tess_obj.vert"))?;
-    let tcs = read_to_string(format!("{LOCAL_ASSETS}/tess_obj.tcs"))?;
-    let tes = read_to_string(format!("{LOCAL_ASSETS}/tess_obj.tes"))?;
-    let geom = read_to_string(format!("{LOCAL_ASSETS}/tess_obj.geom"))?;
-    let frag = read_to_string(format!("{LOCAL_ASSETS}/tess_obj_wf.frag"))?;
-
-    let program = SourceCode {
-      vertex_shader: &vert,
-      tessellation_control_shader: Some(&tcs),
-      tessellation_evaluation_shader: Some(&tes),
-      fragment_shader: &frag,
-      geometry_shader: Some(&geom),
-    };
-
-    Ok(Program::new(facade, program)?)
+  fn load_wireframe_program(facade: &impl Facade) -> Result<ProgramHandle> {
+    Self::load_stages(
+      &[
+        "tess_obj.vert",
+        "tess_obj.tcs",
+        "tess_obj.tes",
+        "tess_obj.geom",
+        "tess_obj_wf.frag",
+      ],
+      facade,
+    )
   }
 
-  fn load_shadow_program(facade: &impl Facade) -> Result<Program> {
-    use std::fs::read_to_string;
-    let vert = read_to_string(format!("{LOCAL_ASSETS}/tess_obj.vert"))?;
-    let tcs = read_to_string(format!("{LOCAL_ASSETS}/tess_obj.tcs"))?;
-    let tes = read_to_string(format!("{LOCAL_ASSETS}/tess_obj.tes"))?;
-    let frag = r#"
-      #version 330 core
-      void main() {}
-    "#;
-
-    let program = SourceCode {
-      vertex_shader: &vert,
-      tessellation_control_shader: Some(&tcs),
-      tessellation_evaluation_shader: Some(&tes),
-      fragment_shader: frag,
-      geometry_shader: None,
-    };
+  // the tessellated surface with a fragment shader that does nothing,
+  // only the depth matters
+  fn load_shadow_program(facade: &impl Facade) -> Result<ProgramHandle> {
+    Self::load_stages(
+      &[
+        "tess_obj.vert",
+        "tess_obj.tcs",
+        "tess_obj.tes",
+        "tess_shadow.frag",
+      ],
+      facade,
+    )
+  }
 
-    Ok(Program::new(facade, program)?)
+  fn load_stages(
+    files: &[&str],
+    facade: &impl Facade,
+  ) -> Result<ProgramHandle> {
+    let stages: Vec<String> = files
+      .iter()
+      .map(|file| format!("{LOCAL_ASSETS}/{file}"))
+      .collect();
+    ProgramHandle::load_stages(&stages, &Preprocessor::new(), facade)
   }
 
   pub fn update(&self, _dt: &Duration) {}
 
-  pub fn reload_shader(&mut self, facade: &impl Facade) -> Result<()> {
-    self.program = Self::load_program(facade)?;
-    self.wireframe_program = Self::load_wireframe_program(facade)?;
-    self.shadow_program = Self::load_shadow_program(facade)?;
-    self.parallax_program = Self::load_parallax_program(facade)?;
-    Ok(())
+  // world space, with room for the largest displacement either way
+  pub fn bounding_box(&self) -> Aabb {
+    let d = self.displacement_scale;
+    Aabb::new(Vec3::new(-1.0, -1.0, -d), Vec3::new(1.0, 1.0, d))
+      .transform(self.model.to_mat4())
+  }
+
+  // how far along the ray the quad is
+  pub fn hit(&self, ray: &Ray) -> Option<f32> {
+    let model = self.model.to_mat4();
+    let hit = self.bvh.closest_hit_placed(ray, model, f32::MAX)?;
+    Some(hit.t)
   }
 
   pub fn adjust_detail_level(&mut self, delta: f32) {
@@ -370,6 +362,20 @@ impl TeapotQuad {
     self.displacement_scale = (self.displacement_scale + delta).clamp(0.0, 1.0);
   }
 
+  pub fn state(&self) -> QuadState {
+    QuadState {
+      detail_level: self.detail_level,
+      displacement_scale: self.displacement_scale,
+      draw_mode: self.draw_mode,
+    }
+  }
+
+  pub fn restore(&mut self, state: &QuadState) {
+    self.detail_level = state.detail_level.clamp(1.0, 100.0);
+    self.displacement_scale = state.displacement_scale.clamp(0.0, 1.0);
+    self.draw_mode = state.draw_mode;
+  }
+
   pub fn cycle_draw_mode(&mut self) {
     let new_draw_mode = match self.draw_mode {
       DrawMode::Tessellation => DrawMode::TessellationWireframe,
@@ -394,7 +400,8 @@ impl common::Draw for TeapotQuad {
   ) -> Result<()> {
     let own_uniforms = self.uniforms(camera, program);
     let uniforms = MergedUniform::new(&uniforms, &own_uniforms);
-    let params = draw_params.unwrap_or(default_draw_params());
+    let params =
+      draw_params.unwrap_or_else(|| camera.depth_mode().draw_parameters());
 
     frame.draw(
       &self.vbo,
@@ -411,8 +418,8 @@ impl common::Draw for TeapotQuad {
 }
 
 impl HasShadow for TeapotQuad {
-  fn shadow_program(&self) -> Option<&Program> {
-    Some(&self.shadow_program)
+  fn shadow_program(&self) -> Option<Ref<'_, Program>> {
+    Some(self.shadow_program.get())
   }
 }
 
@@ -429,15 +436,3 @@ fn upload_texture(facade: &impl Facade, image: &RgbImage) -> Texture2d {
   unsafe { texture.generate_mipmaps() };
   texture
 }
-
-fn default_draw_params() -> DrawParameters<'static> {
-  DrawParameters {
-    depth: Depth {
-      test: DepthTest::IfLess,
-      write: true,
-      ..Default::default()
-    },
-    // backface_culling: BackfaceCullingMode::CullClockwise,
-    ..Default::default()
-  }
-}
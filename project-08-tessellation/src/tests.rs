@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use common::{
+  app::App,
+  golden::{Golden, HEIGHT, WIDTH},
+  shader_check::ShaderCheck,
+  CameraBlock, LightBlock, MaterialBlock, ShadowBlock,
+};
+
+use crate::World;
+
+const GOLDEN_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/golden");
+
+fn world(golden: &Golden) -> anyhow::Result<World> {
+  let mut world = World::new();
+  world.load_scene(golden.facade())?;
+  world.resize(WIDTH, HEIGHT);
+  world.update(Duration::ZERO);
+  Ok(world)
+}
+
+// tessellation, wireframe, both and parallax, in the order 'w' cycles
+#[test]
+fn draw_modes() -> anyhow::Result<()> {
+  let golden = Golden::new(GOLDEN_DIR)?;
+  let mut world = world(&golden)?;
+  for i in 0..4 {
+    golden.check(&format!("draw_mode_{}", i), |frame| world.render(frame))?;
+    if let Some(quad) =
+      world.scene.as_mut().and_then(|s| s.teapot_quad.as_mut())
+    {
+      quad.cycle_draw_mode();
+    }
+  }
+  Ok(())
+}
+
+#[test]
+fn spot_light() -> anyhow::Result<()> {
+  let golden = Golden::new(GOLDEN_DIR)?;
+  let mut world = world(&golden)?;
+  if let Some(scene) = &mut world.scene {
+    scene.toggle_light_variant();
+  }
+  golden.check("spot", |frame| world.render(frame))
+}
+
+// every shader in assets, grouped into the programs the code builds
+#[test]
+fn shaders() -> anyhow::Result<()> {
+  ShaderCheck::new(concat!(env!("CARGO_MANIFEST_DIR"), "/assets"))
+    .program(&["object.vert", "object.frag"])
+    .variant(&["object.vert", "object.frag"], &["NO_SHADOW"])
+    .program(&["shadow.vert", "shadow.frag"])
+    .program(&["preview.vert", "preview.frag"])
+    .program(&[
+      "tess_obj.vert",
+      "tess_obj.tcs",
+      "tess_obj.tes",
+      "tess_obj.frag",
+    ])
+    .program(&[
+      "tess_obj.vert",
+      "tess_obj.tcs",
+      "tess_obj.tes",
+      "tess_shadow.frag",
+    ])
+    .program(&[
+      "tess_obj.vert",
+      "tess_obj.tcs",
+      "tess_obj.tes",
+      "tess_obj.geom",
+      "tess_obj_wf.frag",
+    ])
+    .program(&["quad_parallax.vert", "quad_parallax.frag"])
+    // std140 in GLSL, #[repr(C)] in Rust
+    .uniform_block::<CameraBlock>()
+    .uniform_block::<LightBlock>()
+    .uniform_block::<ShadowBlock>()
+    .uniform_block::<MaterialBlock>()
+    // no shader samples the optional color texture yet
+    .allow_undeclared(&["color_texture"])
+    .rust_uniforms(concat!(env!("CARGO_MANIFEST_DIR"), "/src"))
+    .finish()
+}